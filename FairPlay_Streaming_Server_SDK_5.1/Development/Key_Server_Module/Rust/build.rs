@@ -5,6 +5,11 @@
 use std::env;
 
 fn main() {
+    // The mock key payload backend replaces everything libfpscrypto provides
+    if env::var("CARGO_FEATURE_MOCK_KEY_PAYLOAD").is_ok() {
+        return;
+    }
+
     // Must use environment variables instead of #[cfg(...)] in build.rs
     // https://doc.rust-lang.org/cargo/reference/environment-variables.html#environment-variables-cargo-sets-for-build-scripts
     let os = env::var("CARGO_CFG_TARGET_OS").unwrap();
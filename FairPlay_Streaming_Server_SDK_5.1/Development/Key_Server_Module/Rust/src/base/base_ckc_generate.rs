@@ -10,11 +10,13 @@ use crate::base::base_constants::AESEncryptionMode;
 use crate::base::base_constants::AES128_IV_SZ;
 use crate::base::structures::base_fps_structures::Base;
 use crate::base::structures::base_server_structures::{FPSServerCKCContainer, FPSServerCtx};
+use crate::base::structures::secret::SecretBytes;
 use crate::base::Utils::FPSServerUtils::VectorHelperUtils;
 use crate::requireAction;
 use crate::validate::{FPSStatus, Result};
 use crate::Extension;
 use openssl::symm::{Cipher, Crypter, Mode};
+use zeroize::Zeroize;
 
 use super::base_constants;
 
@@ -27,7 +29,7 @@ impl Base {
         // Serialize CKC data
         Base::serializeCKCData(serverCtx)?;
 
-        let mut key: Vec<u8> = Vec::with_capacity(16);
+        let mut key = SecretBytes::zeroed(16);
         // Derive encryption key from anti replay seed and R1
         Base::deriveAntiReplayKey(
             &serverCtx.spcContainer.spcData.antiReplay,
@@ -60,12 +62,13 @@ impl Base {
 
         let cipher = Cipher::aes_128_ecb();
         let mut crypter = Crypter::new(cipher, Mode::Encrypt, &hashOfR1[..16], None).unwrap();
-        let mut ciphertext = vec![0_u8; arSeed.len() + cipher.block_size()];
+        let mut ciphertext = SecretBytes::zeroed(arSeed.len() + cipher.block_size());
 
         let _encryptionResult = crypter.update(arSeed, &mut ciphertext);
 
         // Only return the first 16B
-        *ek = ciphertext[..16].to_vec();
+        ek.clear();
+        ek.extend_from_slice(&ciphertext[..16]);
 
         // log::debug!("AntiReplay Key: 0x{}", hex::encode(ek));
 
@@ -85,7 +88,8 @@ impl Base {
             &mut tempCKC,
         )?;
 
-        // Replace original data with the encrypted version
+        // Replace original data with the encrypted version, clearing the content key payload and R1
+        ckcContainer.ckcDataPtr.zeroize();
         ckcContainer.ckcDataPtr = tempCKC;
 
         Ok(())
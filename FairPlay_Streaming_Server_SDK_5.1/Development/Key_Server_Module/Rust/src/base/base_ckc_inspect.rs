@@ -0,0 +1,438 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+use crate::base::base_constants::{
+    AESEncryptionCipher, AESEncryptionMode, FPSHDCPRequirement, FPSKeyDurationType, FPSTLLVTagValue, AES128_IV_SZ,
+    AES128_KEY_SZ, FPS_KEY_DURATION_RESERVED_FIELD_VALUE, FPS_MAX_STREAM_ID_LENGTH, FPS_MAX_TITLE_ID_LENGTH,
+    FPS_TLLV_OFFLINEKEY_TLLV_VERSION_2, FPS_V1_R1_SZ,
+};
+use crate::base::base_spc_inspect::{inlineValue, tagString, InspectedTLLV, SPCInspection};
+use crate::base::structures::base_fps_structures::{Base, FPSResult};
+use crate::base::structures::base_server_structures::{FPSServerCKCContainer, FPSServerCtx, FPSServerTLLV};
+use crate::base::structures::secret::SecretBytes;
+use crate::base::Utils::FPSServerUtils::{readBigEndianU32, readBigEndianU64, readBytes};
+use crate::extension::extension_constants::FPSSecurityLevel;
+use crate::validate::{FPSStatus, Result};
+use crate::{fpsLogError, requireAction, returnErrorStatus, Extension};
+use serde_jsonrc::{json, Map, Value};
+use std::fmt;
+use std::sync::Arc;
+use std::mem::size_of;
+
+/// Tags the server adds to the CKC, as opposed to tags returned from the SPC.
+const CKC_SERVER_TAGS: [u64; 5] = [
+    FPSTLLVTagValue::r1Tag as u64,
+    FPSTLLVTagValue::hdcpInformationTag as u64,
+    FPSTLLVTagValue::securityLevelTag as u64,
+    FPSTLLVTagValue::keyDurationTag as u64,
+    FPSTLLVTagValue::offlineKeyTag as u64,
+];
+
+/// Comparison of the TLLVs returned in a CKC with the SPC's return request.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReturnTagCheck {
+    /// Tags listed in the SPC's return request
+    pub requested: Vec<u64>,
+    /// Requested tags that are not in the CKC
+    pub missing: Vec<u64>,
+    /// Requested tags whose value differs from the one sent in the SPC
+    pub mismatched: Vec<u64>,
+    /// Tags returned in the CKC that the SPC did not request
+    pub unexpected: Vec<u64>,
+}
+
+impl ReturnTagCheck {
+    pub fn isValid(&self) -> bool {
+        self.missing.is_empty() && self.mismatched.is_empty() && self.unexpected.is_empty()
+    }
+}
+
+/// Everything that could be read from a CKC, given the SPC it answers.
+///
+/// The CKC payload is encrypted with a key derived from the SPC's anti-replay seed and R1, so
+/// it can only be opened when the SPC can be decrypted too. The TLLVs are listed in CKC order;
+/// the first one is always the content key payload.
+#[derive(Debug, Clone, Default)]
+pub struct CKCInspection {
+    /// Inspection of the originating SPC
+    pub spc: SPCInspection,
+    pub ckcContainer: FPSServerCKCContainer,
+    /// Reserved field of the CKC header, filled in by `reportServerInformation`
+    pub serverInformation: u32,
+    /// R1 used to derive the anti-replay key
+    pub r1: SecretBytes,
+    pub tllvs: Vec<InspectedTLLV>,
+    /// Error that stopped the inspection
+    pub error: Option<FPSStatus>,
+}
+
+impl Base {
+    /// Decrypts `ckc` with the anti-replay key of `spc` and decodes every TLLV in it.
+    ///
+    /// When `r1` is `None`, it is recovered by running `createContentKeyPayloadCustom` on the
+    /// SPC again, which requires the same credentials as the server that created the CKC.
+    pub fn inspectCKC(spc: &[u8], ckc: &[u8], r1: Option<&[u8]>) -> CKCInspection {
+        let mut inspection = CKCInspection {
+            spc: Base::inspectSPC(spc),
+            ..Default::default()
+        };
+
+        if let Err(e) = Base::inspectCKCPayload(ckc, r1, &mut inspection) {
+            inspection.error = Some(e);
+        }
+
+        inspection
+    }
+
+    /// Parses the unencrypted fields of a CKC. `ckcDataPtr` is left encrypted.
+    pub fn parseCKCContainer(ckc: &[u8], ckcContainer: &mut FPSServerCKCContainer, serverInformation: &mut u32) -> Result<()> {
+        let mut offset = 0;
+
+        // 4B Version
+        ckcContainer.version = readBigEndianU32(ckc, offset)?;
+        offset += size_of::<u32>();
+
+        // 4B Reserved
+        *serverInformation = readBigEndianU32(ckc, offset)?;
+        offset += size_of::<u32>();
+
+        // 16B IV
+        ckcContainer.aesKeyIV = readBytes(ckc, offset, AES128_IV_SZ)?;
+        offset += AES128_IV_SZ;
+
+        // 4B CKC Data size
+        let ckcDataSize = readBigEndianU32(ckc, offset)? as usize;
+        offset += size_of::<u32>();
+
+        if ckc.len() != offset + ckcDataSize {
+            fpsLogError!(
+                FPSStatus::parserErr,
+                "CKC data size {} does not match the {} bytes after the header",
+                ckcDataSize,
+                ckc.len() - offset
+            );
+            returnErrorStatus!(FPSStatus::parserErr);
+        }
+
+        // CKC Data
+        ckcContainer.ckcDataPtr = ckc[offset..].to_vec();
+        ckcContainer.ckc = ckc.to_vec();
+
+        Ok(())
+    }
+
+    /// Decrypts CKC data, the reverse of `encryptCKCData`
+    pub fn decryptCKCData(ckcContainer: &mut FPSServerCKCContainer, key: &[u8]) -> Result<()> {
+        let mut tempCKC: Vec<u8> = Vec::new();
+
+        Base::encryptDecryptWithAES(
+            ckcContainer.ckcDataPtr.as_slice(),
+            key,
+            &ckcContainer.aesKeyIV,
+            AESEncryptionMode::aesDecrypt,
+            AESEncryptionCipher::aesCBC,
+            &mut tempCKC,
+        )?;
+
+        ckcContainer.ckcDataPtr = tempCKC;
+
+        Ok(())
+    }
+
+    /// Gets R1 back from the content key payload hook, with a throwaway content key.
+    fn recoverR1(spcInspection: &SPCInspection) -> Result<SecretBytes> {
+        let mut serverCtx = FPSServerCtx {
+            spcContainer: Arc::new(spcInspection.spcContainer.clone()),
+            ..Default::default()
+        };
+        serverCtx.ckcContainer.ckcData.ck = SecretBytes::zeroed(AES128_KEY_SZ);
+        serverCtx.ckcContainer.ckcData.iv = SecretBytes::zeroed(AES128_IV_SZ);
+
+        Extension::createContentKeyPayloadCustom(&mut serverCtx, 0, &mut FPSResult::default())?;
+
+        Ok(std::mem::take(&mut serverCtx.ckcContainer.ckcData.r1))
+    }
+
+    fn inspectCKCPayload(ckc: &[u8], r1: Option<&[u8]>, inspection: &mut CKCInspection) -> Result<()> {
+        Base::parseCKCContainer(ckc, &mut inspection.ckcContainer, &mut inspection.serverInformation)?;
+
+        // The SPC must have been read up to its anti-replay seed
+        if let Some(e) = inspection.spc.error {
+            returnErrorStatus!(e);
+        }
+        requireAction!(
+            !inspection.spc.spcContainer.spcData.antiReplay.is_empty(),
+            return Err(FPSStatus::missingRequiredTagErr)
+        );
+
+        inspection.r1 = match r1 {
+            Some(r1) => r1.into(),
+            None => Base::recoverR1(&inspection.spc)?,
+        };
+
+        let mut key = SecretBytes::zeroed(AES128_KEY_SZ);
+        Base::deriveAntiReplayKey(&inspection.spc.spcContainer.spcData.antiReplay, &inspection.r1, &mut key)?;
+
+        let ckcContainer = &mut inspection.ckcContainer;
+        Base::decryptCKCData(ckcContainer, &key)?;
+
+        // A wrong R1 gives random data, which fails here
+        let mut offset = 0;
+        while offset < ckcContainer.ckcDataPtr.len() {
+            let mut tllv = FPSServerTLLV::default();
+            Base::readNextTLLV(&ckcContainer.ckcDataPtr, ckcContainer.ckcDataPtr.len(), &mut offset, &mut tllv)?;
+
+            let isContentKey = inspection.tllvs.is_empty();
+            let (decoded, error) = match decodeCKCTLLV(&tllv, isContentKey, &inspection.r1) {
+                Ok(decoded) => (decoded, None),
+                Err(e) => (Value::Null, Some(e)),
+            };
+            inspection.tllvs.push(InspectedTLLV { tllv, decoded, error });
+        }
+
+        Ok(())
+    }
+}
+
+fn hdcpName(hdcp: u64) -> String {
+    match hdcp {
+        x if x == FPSHDCPRequirement::hdcpNotRequired as u64 => "hdcpNotRequired".to_string(),
+        x if x == FPSHDCPRequirement::hdcpType0 as u64 => "hdcpType0".to_string(),
+        x if x == FPSHDCPRequirement::hdcpType1 as u64 => "hdcpType1".to_string(),
+        x => format!("0x{:016x}", x),
+    }
+}
+
+fn keyDurationTypeName(keyType: u32) -> String {
+    match keyType {
+        x if x == FPSKeyDurationType::none as u32 => "none".to_string(),
+        x if x == FPSKeyDurationType::lease as u32 => "lease".to_string(),
+        x if x == FPSKeyDurationType::rental as u32 => "rental".to_string(),
+        x if x == FPSKeyDurationType::leaseAndRental as u32 => "leaseAndRental".to_string(),
+        x if x == FPSKeyDurationType::persistence as u32 => "persistence".to_string(),
+        x if x == FPSKeyDurationType::persistenceAndDuration as u32 => "persistenceAndDuration".to_string(),
+        x => format!("0x{:08x}", x),
+    }
+}
+
+fn securityLevelName(securityLevel: u64) -> String {
+    match securityLevel {
+        x if x == FPSSecurityLevel::audio as u64 => "audio".to_string(),
+        x if x == FPSSecurityLevel::baseline as u64 => "baseline".to_string(),
+        x if x == FPSSecurityLevel::main as u64 => "main".to_string(),
+        x => format!("0x{:016x}", x),
+    }
+}
+
+/// Decodes a TLLV written by the `populateTag*` functions. Returned SPC tags are left to the
+/// return tag check.
+fn decodeCKCTLLV(tllv: &FPSServerTLLV, isContentKey: bool, r1: &[u8]) -> Result<Value> {
+    let value = &tllv.value;
+    let requireLength = |length: usize| -> Result<()> {
+        requireAction!(value.len() == length, return Err(FPSStatus::parserErr));
+        Ok(())
+    };
+
+    let decoded = match tllv.tag {
+        // Encrypted for the client, only its size is meaningful
+        _ if isContentKey => json!({ "content-key-payload": value.len() }),
+        x if x == FPSTLLVTagValue::r1Tag as u64 => {
+            requireLength(FPS_V1_R1_SZ)?;
+            if value.as_slice() != r1 {
+                fpsLogError!(FPSStatus::paramErr, "R1 in the CKC differs from the one used to decrypt it");
+                returnErrorStatus!(FPSStatus::paramErr);
+            }
+            json!(hex::encode_upper(value))
+        }
+        x if x == FPSTLLVTagValue::hdcpInformationTag as u64 => {
+            requireLength(2 * size_of::<u64>())?;
+            json!(hdcpName(readBigEndianU64(value, 0)?))
+        }
+        x if x == FPSTLLVTagValue::securityLevelTag as u64 => {
+            requireLength(2 * size_of::<u32>() + size_of::<u64>())?;
+            json!({
+                "version": readBigEndianU32(value, 0)?,
+                "security-level": securityLevelName(readBigEndianU64(value, 8)?),
+            })
+        }
+        x if x == FPSTLLVTagValue::keyDurationTag as u64 => {
+            requireLength(4 * size_of::<u32>())?;
+            requireAction!(
+                readBigEndianU32(value, 12)? == FPS_KEY_DURATION_RESERVED_FIELD_VALUE,
+                return Err(FPSStatus::parserErr)
+            );
+            json!({
+                "lease-duration": readBigEndianU32(value, 0)?,
+                "rental-duration": readBigEndianU32(value, 4)?,
+                "key-type": keyDurationTypeName(readBigEndianU32(value, 8)?),
+            })
+        }
+        x if x == FPSTLLVTagValue::offlineKeyTag as u64 => {
+            let v1Length = 2 * size_of::<u32>() + FPS_MAX_STREAM_ID_LENGTH + 2 * size_of::<u32>();
+            requireAction!(value.len() >= v1Length, return Err(FPSStatus::parserErr));
+
+            let version = readBigEndianU32(value, 0)?;
+            let mut offlineKey = json!({
+                "version": version,
+                "content-id": hex::encode_upper(&value[8..8 + FPS_MAX_STREAM_ID_LENGTH]),
+                "storage-duration": readBigEndianU32(value, 8 + FPS_MAX_STREAM_ID_LENGTH)?,
+                "playback-duration": readBigEndianU32(value, 12 + FPS_MAX_STREAM_ID_LENGTH)?,
+            });
+            if version == FPS_TLLV_OFFLINEKEY_TLLV_VERSION_2 {
+                requireLength(v1Length + FPS_MAX_TITLE_ID_LENGTH)?;
+                offlineKey["title-id"] = json!(hex::encode_upper(&value[v1Length..]));
+            } else {
+                requireLength(v1Length)?;
+            }
+            offlineKey
+        }
+        _ => Value::Null,
+    };
+
+    Ok(decoded)
+}
+
+impl CKCInspection {
+    /// Checks that the CKC returns exactly the SPC TLLVs listed in the return request, unchanged.
+    pub fn returnTagCheck(&self) -> ReturnTagCheck {
+        let requested = self.spc.returnRequest();
+        let returned: Vec<&FPSServerTLLV> = self
+            .tllvs
+            .iter()
+            .skip(1)
+            .map(|inspected| &inspected.tllv)
+            .filter(|tllv| !CKC_SERVER_TAGS.contains(&tllv.tag))
+            .collect();
+
+        let mut check = ReturnTagCheck::default();
+        for tag in &requested {
+            let sent = self.spc.tllvs.iter().find(|inspected| inspected.tllv.tag == *tag);
+            match returned.iter().find(|tllv| tllv.tag == *tag) {
+                None => check.missing.push(*tag),
+                Some(tllv) if sent.is_none_or(|sent| sent.tllv.value != tllv.value) => check.mismatched.push(*tag),
+                Some(_) => {}
+            }
+        }
+        check.unexpected = returned
+            .iter()
+            .map(|tllv| tllv.tag)
+            .filter(|tag| !requested.contains(tag))
+            .collect();
+        check.requested = requested;
+
+        check
+    }
+
+    pub fn toJson(&self) -> Value {
+        let mut root = Map::new();
+
+        // Major (7 bits), minor (4 bits), language (2 bits) and platform (3 bits) of the server SDK
+        let serverInformation = self.serverInformation;
+        let language = match (serverInformation >> 3) & 0x3 {
+            1 => "swift",
+            2 => "rust",
+            _ => "unknown",
+        };
+        let platform = match serverInformation & 0x7 {
+            1 => "x86_64",
+            2 => "aarch64",
+            _ => "unknown",
+        };
+        root.insert(
+            "header".to_string(),
+            json!({
+                "version": self.ckcContainer.version,
+                "server-information": {
+                    "sdk-version": format!("{}.{}", serverInformation >> 9, (serverInformation >> 5) & 0xF),
+                    "language": language,
+                    "platform": platform,
+                },
+                "iv": hex::encode_upper(&self.ckcContainer.aesKeyIV),
+                "payload-size": self.ckcContainer.ckcDataPtr.len(),
+            }),
+        );
+
+        let tllvs: Vec<Value> = self
+            .tllvs
+            .iter()
+            .enumerate()
+            .map(|(i, inspected)| {
+                let name = match i {
+                    0 => Some("contentKeyPayloadTag"),
+                    _ => FPSTLLVTagValue::name(inspected.tllv.tag),
+                };
+                let mut tllv = json!({
+                    "tag": format!("0x{:016x}", inspected.tllv.tag),
+                    "name": name,
+                    "length": inspected.tllv.value.len(),
+                    "value": hex::encode_upper(&inspected.tllv.value),
+                });
+                if !inspected.decoded.is_null() {
+                    tllv["decoded"] = inspected.decoded.clone();
+                }
+                if let Some(e) = inspected.error {
+                    tllv["error"] = json!(format!("{:?} ({})", e, e));
+                }
+                tllv
+            })
+            .collect();
+        root.insert("tllvs".to_string(), Value::Array(tllvs));
+
+        // Nothing to compare until the payload was decrypted
+        let returnTags = if self.tllvs.is_empty() {
+            Value::Null
+        } else {
+            let check = self.returnTagCheck();
+            let tags = |tags: &[u64]| json!(tags.iter().copied().map(tagString).collect::<Vec<_>>());
+            json!({
+                "requested": tags(&check.requested),
+                "missing": tags(&check.missing),
+                "mismatched": tags(&check.mismatched),
+                "unexpected": tags(&check.unexpected),
+                "valid": check.isValid(),
+            })
+        };
+        root.insert("return-tags".to_string(), returnTags);
+
+        if let Some(e) = self.error {
+            root.insert("error".to_string(), json!(format!("{:?} ({})", e, e)));
+        }
+
+        Value::Object(root)
+    }
+}
+
+impl fmt::Display for CKCInspection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let json = self.toJson();
+
+        writeln!(f, "CKC header: {}", inlineValue(&json["header"]))?;
+        writeln!(f, "TLLVs ({}):", self.tllvs.len())?;
+        for (i, inspected) in self.tllvs.iter().enumerate() {
+            match i {
+                0 => write!(f, "  0x{:016x} (contentKeyPayloadTag)", inspected.tllv.tag)?,
+                _ => write!(f, "  {}", tagString(inspected.tllv.tag))?,
+            }
+            write!(f, " length={}", inspected.tllv.value.len())?;
+            if inspected.decoded.is_null() {
+                write!(f, " value={}", hex::encode_upper(&inspected.tllv.value))?;
+            } else {
+                write!(f, " {}", inlineValue(&inspected.decoded))?;
+            }
+            if let Some(e) = inspected.error {
+                write!(f, " error={:?} ({})", e, e)?;
+            }
+            writeln!(f)?;
+        }
+
+        if !json["return-tags"].is_null() {
+            writeln!(f, "return-tags: {}", inlineValue(&json["return-tags"]))?;
+        }
+        if let Some(e) = self.error {
+            writeln!(f, "error: {:?} ({})", e, e)?;
+        }
+
+        Ok(())
+    }
+}
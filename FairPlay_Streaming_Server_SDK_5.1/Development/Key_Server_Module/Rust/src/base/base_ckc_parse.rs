@@ -16,9 +16,12 @@ use crate::base::structures::base_fps_structures::Base;
 use crate::base::structures::base_fps_structures::{FPSOperation, FPSResult, FPSResults};
 use crate::base::structures::base_server_structures::FPSServerCtx;
 use crate::base::Utils::FPSServerUtils::readBigEndianU32;
-use crate::validate::{FPSStatus, Result};
+use crate::validate::{self, ErrorDetails, FPSStatus, Result};
 use crate::Extension;
-use crate::{requireAction, returnErrorStatus};
+use crate::SDKExtension;
+use crate::logging::LogContext;
+use crate::metrics::{OperationLabels, Stage};
+use crate::{fpsLogError, requireAction, returnErrorStatus};
 use base64::engine::general_purpose;
 use base64::Engine;
 use hex::ToHex;
@@ -33,13 +36,25 @@ impl Base {
         // Set the result id
         fpsResult.id = fpsOperation.id;
 
-        // Custom handling (if needed)
-        Extension::createResultsCustom(fpsOperation, &mut keyTypeRequested)?;
+        // Log lines of this operation carry its id, then what is read from the SPC
+        let _logContext = LogContext::enter(fpsOperation.id);
 
-        // Generate CKC and other result fields
-        Base::genCKCWithCKAndIV(fpsOperation, keyTypeRequested, fpsResult)?;
+        let (status, error) = validate::captureError(|| {
+            // Custom handling (if needed)
+            Extension::createResultsCustom(fpsOperation, &mut keyTypeRequested)?;
 
-        Ok(())
+            // Generate CKC and other result fields
+            Base::genCKCWithCKAndIV(fpsOperation, keyTypeRequested, fpsResult)
+        });
+        fpsResult.error = error;
+
+        if let Err(e) = status {
+            fpsLogError!(e, "create-ckc operation failed");
+        }
+
+        SDKExtension::metrics().recordOperation(OperationLabels::new(fpsOperation, fpsResult, &status));
+
+        status
     }
 
     /// Generates CKC and other result fields
@@ -55,7 +70,9 @@ impl Base {
                 let mut serverCtx: FPSServerCtx = Default::default();
 
                 // Parse SPC
-                Base::parseSPC(fpsOperation, &mut serverCtx)?;
+                let status = Base::parseSPC(fpsOperation, &mut serverCtx);
+                LogContext::update(|context| context.setSPC(&serverCtx.spcContainer));
+                status?;
 
                 // Optional: if querying a database for more information outside of JSON, that is
                 // done here
@@ -72,7 +89,7 @@ impl Base {
                 Extension::createContentKeyPayloadCustom(&mut serverCtx, keyTypeRequested, fpsResult)?;
 
                 // Generate the CKC
-                Base::generateCKC(&mut serverCtx)?;
+                SDKExtension::metrics().time(Stage::ckcGeneration, || Base::generateCKC(&mut serverCtx))?;
 
                 fpsResult.ckc = serverCtx.ckcContainer.ckc.to_owned();
 
@@ -98,9 +115,24 @@ impl Base {
         // Report movie ID (session ID)
         result.sessionId = serverCtx.spcContainer.spcData.playInfo.playbackId;
 
-        // Set the key and IV from the input json
-        serverCtx.ckcContainer.ckcData.ck = operation.assetInfo.key[0..AES128_KEY_SZ].to_owned();
-        serverCtx.ckcContainer.ckcData.iv = operation.assetInfo.iv[0..AES128_IV_SZ].to_owned();
+        // Set the key and IV from the input json (or the key store).
+        // Without them the CKC would carry the all-zero placeholder key. Check-ins and
+        // lease renewals do not need one.
+        if (operation.deliversContentKey() && !operation.assetInfo.isCKProvided)
+            || operation.assetInfo.key.len() != AES128_KEY_SZ
+            || operation.assetInfo.iv.len() != AES128_IV_SZ
+        {
+            fpsLogError!(
+                FPSStatus::paramErr,
+                reason = "missing-content-key",
+                "no {}-byte content key and {}-byte IV for the asset",
+                AES128_KEY_SZ,
+                AES128_IV_SZ
+            );
+            returnErrorStatus!(FPSStatus::paramErr);
+        }
+        serverCtx.ckcContainer.ckcData.ck = operation.assetInfo.key[0..AES128_KEY_SZ].into();
+        serverCtx.ckcContainer.ckcData.iv = operation.assetInfo.iv[0..AES128_IV_SZ].into();
 
         // Offline HLS or Online HLS rental
         if operation.assetInfo.licenseType == FPSLicenseType::offlineHLS as u32 {
@@ -218,6 +250,20 @@ impl Base {
         let statusObj = Value::Number(Number::from(result.status as i32));
         ckcArrayNode.insert(base_constants::STATUS_STR.to_string(), statusObj);
 
+        // Error details, if the caller is trusted with them
+        if let Some(error) = &result.error {
+            let errorDetails = SDKExtension::errorDetails();
+            if errorDetails != ErrorDetails::hidden {
+                let mut errorObj = Map::new();
+                errorObj.insert(base_constants::STATUS_STR.to_string(), Value::Number(Number::from(error.status as i32)));
+                errorObj.insert(base_constants::REASON_STR.to_string(), Value::String(error.reason.clone()));
+                if errorDetails == ErrorDetails::full {
+                    errorObj.insert(base_constants::MESSAGE_STR.to_string(), Value::String(error.message.clone()));
+                }
+                ckcArrayNode.insert(base_constants::ERROR_STR.to_string(), Value::Object(errorObj));
+            }
+        }
+
         // Rest of the fields are printed only if status is no error
         if result.status == FPSStatus::noErr {
             // Player HU
@@ -73,6 +73,9 @@ pub const CHECK_IN_STR: &str = "check-in";
 
 // Output
 pub const STATUS_STR: &str = "status";
+pub const ERROR_STR: &str = "error"; // Returned depending on SDKExtension::errorDetails()
+pub const REASON_STR: &str = "reason";
+pub const MESSAGE_STR: &str = "message";
 pub const HU_STR: &str = "hu";
 pub const CKC_STR: &str = "ckc";
 pub const CHECK_IN_SERVER_CHALLENGE_STR: &str = "check-in-server-challenge";
@@ -176,7 +179,42 @@ pub enum FPSTLLVTagValue {
     vmDeviceInfoTag = 0x756440e240499f70,
 }
 
+impl FPSTLLVTagValue {
+    /// Returns the variant name of a known TLLV tag, for diagnostics.
+    pub fn name(tag: u64) -> Option<&'static str> {
+        let name = match tag {
+            x if x == FPSTLLVTagValue::r2tag as u64 => "r2tag",
+            x if x == FPSTLLVTagValue::antiReplayTag as u64 => "antiReplayTag",
+            x if x == FPSTLLVTagValue::sessionKeyR1Tag as u64 => "sessionKeyR1Tag",
+            x if x == FPSTLLVTagValue::sessionKeyR1IntegrityTag as u64 => "sessionKeyR1IntegrityTag",
+            x if x == FPSTLLVTagValue::assetIDTag as u64 => "assetIDTag",
+            x if x == FPSTLLVTagValue::transactionIDTag as u64 => "transactionIDTag",
+            x if x == FPSTLLVTagValue::protocolVersionUsedTag as u64 => "protocolVersionUsedTag",
+            x if x == FPSTLLVTagValue::protocolVersionsSupportedTag as u64 => "protocolVersionsSupportedTag",
+            x if x == FPSTLLVTagValue::returnRequestTag as u64 => "returnRequestTag",
+            x if x == FPSTLLVTagValue::r1Tag as u64 => "r1Tag",
+            x if x == FPSTLLVTagValue::streamingIndicatorTag as u64 => "streamingIndicatorTag",
+            x if x == FPSTLLVTagValue::mediaPlaybackStateTag as u64 => "mediaPlaybackStateTag",
+            x if x == FPSTLLVTagValue::offlineSyncTag as u64 => "offlineSyncTag",
+            x if x == FPSTLLVTagValue::capabilitiesTag as u64 => "capabilitiesTag",
+            x if x == FPSTLLVTagValue::keyDurationTag as u64 => "keyDurationTag",
+            x if x == FPSTLLVTagValue::offlineKeyTag as u64 => "offlineKeyTag",
+            x if x == FPSTLLVTagValue::hdcpInformationTag as u64 => "hdcpInformationTag",
+            x if x == FPSTLLVTagValue::securityLevelTag as u64 => "securityLevelTag",
+            x if x == FPSTLLVTagValue::supportedKeyFormatTag as u64 => "supportedKeyFormatTag",
+            x if x == FPSTLLVTagValue::securityLevelReportTag as u64 => "securityLevelReportTag",
+            x if x == FPSTLLVTagValue::deviceInfoTag as u64 => "deviceInfoTag",
+            x if x == FPSTLLVTagValue::deviceIdentityTag as u64 => "deviceIdentityTag",
+            x if x == FPSTLLVTagValue::kdlVersionReportTag as u64 => "kdlVersionReportTag",
+            x if x == FPSTLLVTagValue::vmDeviceInfoTag as u64 => "vmDeviceInfoTag",
+            _ => return None,
+        };
+        Some(name)
+    }
+}
+
 /// Content Type used for KSMKeyPayload structure
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum KSMKeyPayloadContentType {
     unknown = 0,
     video = 1,
@@ -221,7 +259,7 @@ pub enum FPSAppleDeviceType {
 }
 
 /// FairPlay Streaming Device Class
-#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum FPSDeviceClass {
     #[default]
     unknown = 0,
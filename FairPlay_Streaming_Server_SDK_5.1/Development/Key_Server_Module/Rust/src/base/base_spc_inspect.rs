@@ -0,0 +1,354 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+use crate::base::base_constants::{
+    self, FPSAppleDeviceType, FPSDeviceClass, FPSDevicePlaybackState, FPSTLLVTagValue, FPS_CAPABILITIES_FLAGS_LENGTH,
+    FPS_OFFLINE_CONTENTID_LENGTH, FPS_TLLV_TAG_SZ, KD_SYNC_SPC_FLAG_TITLEID_VALID,
+};
+use crate::base::structures::base_fps_structures::Base;
+use crate::base::structures::base_server_structures::{
+    FPSServerCtx, FPSServerSPCContainer, FPSServerSPCData, FPSServerTLLV, VMDeviceInfo,
+};
+use crate::base::Utils::FPSServerUtils::readBigEndianU64;
+use crate::extension::extension_constants::FPSSecurityLevel;
+use crate::extension::key_store::formatAssetId;
+use crate::validate::{FPSStatus, Result};
+use serde_jsonrc::{json, Map, Value};
+use std::fmt;
+use std::sync::Arc;
+
+/// A TLLV found in the SPC payload, with its value decoded when the tag is known.
+#[derive(Debug, Clone)]
+pub struct InspectedTLLV {
+    pub tllv: FPSServerTLLV,
+    pub decoded: Value,
+    /// Error returned while parsing this TLLV
+    pub error: Option<FPSStatus>,
+}
+
+/// Everything that could be read from an SPC, for troubleshooting.
+///
+/// Inspection goes as far as the SPC allows: if the payload cannot be decrypted the header
+/// is still reported, and a malformed TLLV does not hide the ones after it.
+#[derive(Debug, Clone, Default)]
+pub struct SPCInspection {
+    pub spcContainer: FPSServerSPCContainer,
+    pub tllvs: Vec<InspectedTLLV>,
+    /// Error that stopped the inspection
+    pub error: Option<FPSStatus>,
+}
+
+impl Base {
+    /// Parses and decrypts `spc` and decodes every TLLV in it.
+    pub fn inspectSPC(spc: &[u8]) -> SPCInspection {
+        let mut inspection = SPCInspection::default();
+
+        if let Err(e) = Base::inspectSPCPayload(spc, &mut inspection) {
+            inspection.error = Some(e);
+        }
+
+        inspection
+    }
+
+    fn inspectSPCPayload(spc: &[u8], inspection: &mut SPCInspection) -> Result<()> {
+        let spcContainer = &mut inspection.spcContainer;
+
+        Base::parseSPCContainer(spc, spcContainer)?;
+        Base::decryptSPCData(spc, spcContainer)?;
+
+        // Same loop as parseSPCData, but keep going after TLLVs that fail to parse
+        let mut offset = 0;
+        while offset < spcContainer.spcDataSize {
+            let mut tllv = FPSServerTLLV::default();
+            Base::readNextTLLV(&spcContainer.spcDecryptedData, spcContainer.spcDataSize, &mut offset, &mut tllv)?;
+
+            let error = Base::parseTLLV(&tllv, spcContainer).err();
+            let decoded = match error {
+                None => decodeTLLV(&tllv, &spcContainer.spcData),
+                Some(_) => Value::Null,
+            };
+            spcContainer.spcData.spcDataParser.TLLVs.push(tllv.clone());
+            inspection.tllvs.push(InspectedTLLV { tllv, decoded, error });
+        }
+
+        // Derive the client features from the capabilities TLLV
+        let mut serverCtx = FPSServerCtx {
+            spcContainer: Arc::new(std::mem::take(spcContainer)),
+            ..Default::default()
+        };
+        let status = Base::checkSupportedFeatures(&mut serverCtx);
+        *spcContainer = Arc::unwrap_or_clone(serverCtx.spcContainer);
+
+        status
+    }
+}
+
+pub(crate) fn tagString(tag: u64) -> String {
+    match FPSTLLVTagValue::name(tag) {
+        Some(name) => format!("0x{:016x} ({})", tag, name),
+        None => format!("0x{:016x}", tag),
+    }
+}
+
+fn deviceClassName(deviceClass: FPSDeviceClass) -> String {
+    format!("{:?}", deviceClass)
+}
+
+fn returnRequestTags(spcData: &FPSServerSPCData) -> Vec<u64> {
+    (0..spcData.returnRequest.value.len() / FPS_TLLV_TAG_SZ)
+        .filter_map(|i| readBigEndianU64(&spcData.returnRequest.value, i * FPS_TLLV_TAG_SZ).ok())
+        .collect()
+}
+
+fn vmDeviceInfoJson(vmDeviceInfo: &VMDeviceInfo) -> Value {
+    json!({
+        base_constants::HOST_DEVICE_CLASS_STR: deviceClassName(vmDeviceInfo.hostDeviceClass),
+        base_constants::HOST_OS_VERSION_STR: format!("{:08X}", vmDeviceInfo.hostOSVersion),
+        base_constants::HOST_VM_PROTOCOL_VERSION: vmDeviceInfo.hostVMProtocolVersion,
+        base_constants::GUEST_DEVICE_CLASS_STR: deviceClassName(vmDeviceInfo.guestDeviceClass),
+        base_constants::GUEST_OS_VERSION_STR: format!("{:08X}", vmDeviceInfo.guestOSVersion),
+        base_constants::GUEST_VM_PROTOCOL_VERSION: vmDeviceInfo.guestVMProtocolVersion,
+    })
+}
+
+/// Describes the fields `parseTLLV` read from `tllv`. Values without more structure than their
+/// bytes (R2, anti-replay seed, encrypted session key, ...) are `Null`.
+fn decodeTLLV(tllv: &FPSServerTLLV, spcData: &FPSServerSPCData) -> Value {
+    match tllv.tag {
+        x if x == FPSTLLVTagValue::assetIDTag as u64 => json!(formatAssetId(&spcData.assetId)),
+        x if x == FPSTLLVTagValue::transactionIDTag as u64 => json!(format!("0x{:016x}", spcData.transactionId)),
+        x if x == FPSTLLVTagValue::protocolVersionUsedTag as u64 => json!(spcData.versionUsed),
+        x if x == FPSTLLVTagValue::protocolVersionsSupportedTag as u64 => json!(spcData.versionsSupported),
+        x if x == FPSTLLVTagValue::returnRequestTag as u64 => {
+            json!(returnRequestTags(spcData).into_iter().map(tagString).collect::<Vec<_>>())
+        }
+        x if x == FPSTLLVTagValue::streamingIndicatorTag as u64 => {
+            json!(format!("0x{:016x}", spcData.streamingIndicator))
+        }
+        x if x == FPSTLLVTagValue::mediaPlaybackStateTag as u64 => {
+            let playbackState = match spcData.playInfo.playbackState {
+                x if x == FPSDevicePlaybackState::firstPlaybackCKRequired as u32 => "firstPlaybackCKRequired".to_string(),
+                x if x == FPSDevicePlaybackState::currentlyPlayingCKRequired as u32 => {
+                    "currentlyPlayingCKRequired".to_string()
+                }
+                x if x == FPSDevicePlaybackState::currentlyPlayingCKNotRequired as u32 => {
+                    "currentlyPlayingCKNotRequired".to_string()
+                }
+                x => format!("0x{:08x}", x),
+            };
+            json!({
+                "date": spcData.playInfo.date,
+                "playback-state": playbackState,
+                "playback-id": format!("0x{:016x}", spcData.playInfo.playbackId),
+            })
+        }
+        x if x == FPSTLLVTagValue::offlineSyncTag as u64 => {
+            let deletedContentIDs: Vec<String> = spcData
+                .deletedContentIDs
+                .chunks(FPS_OFFLINE_CONTENTID_LENGTH)
+                .map(hex::encode_upper)
+                .collect();
+            let mut sync = json!({
+                base_constants::CHECK_IN_SERVER_CHALLENGE_STR: spcData.syncServerChallenge,
+                base_constants::CHECK_IN_FLAGS_STR: format!("{:X}", spcData.syncFlags),
+                base_constants::DURATION_LEFT_STR: spcData.durationToRentalExpiry,
+                base_constants::CHECK_IN_STREAM_ID_STR: deletedContentIDs,
+            });
+            if (spcData.syncFlags & KD_SYNC_SPC_FLAG_TITLEID_VALID) != 0 {
+                sync[base_constants::CHECK_IN_TITLE_ID_STR] = json!(hex::encode_upper(&spcData.syncTitleId));
+            }
+            sync
+        }
+        x if x == FPSTLLVTagValue::capabilitiesTag as u64 => {
+            let flags = readBigEndianU64(&spcData.clientCapabilities, 8).unwrap_or(0);
+            json!(format!("0x{:016x}", flags))
+        }
+        x if x == FPSTLLVTagValue::securityLevelReportTag as u64 => {
+            if !spcData.isSecurityLevelTLLVValid {
+                return json!("encrypted");
+            }
+            let securityLevel = match spcData.supportedSecurityLevel {
+                x if x == FPSSecurityLevel::audio as u64 => "audio".to_string(),
+                x if x == FPSSecurityLevel::baseline as u64 => "baseline".to_string(),
+                x if x == FPSSecurityLevel::main as u64 => "main".to_string(),
+                x => format!("0x{:016x}", x),
+            };
+            json!({ "security-level": securityLevel, "kdl-version": spcData.clientKextDenyListVersion })
+        }
+        x if x == FPSTLLVTagValue::kdlVersionReportTag as u64 => {
+            json!({ "kdl-version": spcData.clientKextDenyListVersion })
+        }
+        x if x == FPSTLLVTagValue::supportedKeyFormatTag as u64 => {
+            let keyFormats = &spcData.supportedKeyFormats[..spcData.numberOfSupportedKeyFormats as usize];
+            json!(keyFormats.iter().map(|format| format!("0x{:016x}", format)).collect::<Vec<_>>())
+        }
+        x if x == FPSTLLVTagValue::deviceInfoTag as u64 => {
+            let deviceType = match spcData.deviceInfo.deviceType {
+                x if x == FPSAppleDeviceType::mac as u64 => "mac".to_string(),
+                x if x == FPSAppleDeviceType::tv as u64 => "tv".to_string(),
+                x if x == FPSAppleDeviceType::iOS as u64 => "iOS".to_string(),
+                x if x == FPSAppleDeviceType::watch as u64 => "watch".to_string(),
+                x => format!("0x{:016x}", x),
+            };
+            json!({
+                "device-type": deviceType,
+                base_constants::OS_VERSION_STR: format!("{:08X}", spcData.deviceInfo.osVersion),
+            })
+        }
+        x if x == FPSTLLVTagValue::deviceIdentityTag as u64 => {
+            let deviceIdentity = &spcData.deviceIdentity;
+            json!({
+                base_constants::FPDI_VERSION_STR: deviceIdentity.fpdiVersion,
+                base_constants::DEVICE_CLASS_STR: deviceClassName(FPSDeviceClass::from(deviceIdentity.deviceClass)),
+                base_constants::VENDOR_HASH_STR: hex::encode_upper(&deviceIdentity.vendorHash),
+                base_constants::PRODUCT_HASH_STR: hex::encode_upper(&deviceIdentity.productHash),
+                base_constants::FPS_REE_VERSION_STR: format!("{:08X}", deviceIdentity.fpVersionREE),
+                base_constants::FPS_TEE_VERSION_STR: format!("{:08X}", deviceIdentity.fpVersionTEE),
+                base_constants::OS_VERSION_STR: format!("{:08X}", deviceIdentity.osVersion),
+            })
+        }
+        x if x == FPSTLLVTagValue::vmDeviceInfoTag as u64 => match &spcData.vmDeviceInfo {
+            Some(vmDeviceInfo) => vmDeviceInfoJson(vmDeviceInfo),
+            None => Value::Null,
+        },
+        _ => Value::Null,
+    }
+}
+
+impl SPCInspection {
+    /// Tags of TLLVs that are not defined by the SDK
+    pub fn unknownTags(&self) -> Vec<u64> {
+        self.tllvs
+            .iter()
+            .map(|inspected| inspected.tllv.tag)
+            .filter(|tag| FPSTLLVTagValue::name(*tag).is_none())
+            .collect()
+    }
+
+    /// Tags the client asked to be returned in the CKC
+    pub fn returnRequest(&self) -> Vec<u64> {
+        returnRequestTags(&self.spcContainer.spcData)
+    }
+
+    pub fn toJson(&self) -> Value {
+        let spcContainer = &self.spcContainer;
+        let spcData = &spcContainer.spcData;
+        let mut root = Map::new();
+
+        root.insert(
+            "header".to_string(),
+            json!({
+                "version": spcContainer.version,
+                "certificate-hash": hex::encode_upper(&spcContainer.certificateHash),
+                "wrapped-key-size": spcContainer.aesWrappedKeySize,
+                "payload-size": spcContainer.spcDataSize,
+            }),
+        );
+
+        let tllvs: Vec<Value> = self
+            .tllvs
+            .iter()
+            .map(|inspected| {
+                let mut tllv = json!({
+                    "tag": format!("0x{:016x}", inspected.tllv.tag),
+                    "name": FPSTLLVTagValue::name(inspected.tllv.tag),
+                    "length": inspected.tllv.value.len(),
+                    "value": hex::encode_upper(&inspected.tllv.value),
+                });
+                if !inspected.decoded.is_null() {
+                    tllv["decoded"] = inspected.decoded.clone();
+                }
+                if let Some(e) = inspected.error {
+                    tllv["error"] = json!(format!("{:?} ({})", e, e));
+                }
+                tllv
+            })
+            .collect();
+        root.insert("tllvs".to_string(), Value::Array(tllvs));
+
+        let returnRequest: Vec<String> = self.returnRequest().into_iter().map(tagString).collect();
+        root.insert("return-request".to_string(), json!(returnRequest));
+
+        // Client features are only known once the whole payload was read
+        let clientFeatures = &spcData.clientFeatures;
+        let capabilities = match spcData.clientCapabilities.len() {
+            FPS_CAPABILITIES_FLAGS_LENGTH => json!({
+                "flags": format!("0x{:016x}", readBigEndianU64(&spcData.clientCapabilities, 8).unwrap_or(0)),
+                "hdcp-type1": clientFeatures.supportsHDCPTypeOne,
+                "dual-expiry": clientFeatures.supportsDualExpiry,
+                "check-in": clientFeatures.supportsCheckIn,
+                "offline-key-v1": clientFeatures.supportsOfflineKeyTLLV,
+                "offline-key-v2": clientFeatures.supportsOfflineKeyTLLVV2,
+                "security-level-baseline": clientFeatures.supportsSecurityLevelBaseline,
+                "security-level-main": clientFeatures.supportsSecurityLevelMain,
+            }),
+            _ => Value::Null,
+        };
+        root.insert("capabilities".to_string(), capabilities);
+
+        let deviceIdentity = self
+            .tllvs
+            .iter()
+            .find(|inspected| inspected.tllv.tag == FPSTLLVTagValue::deviceIdentityTag as u64)
+            .map_or(Value::Null, |inspected| inspected.decoded.clone());
+        root.insert("device-identity".to_string(), deviceIdentity);
+
+        let vmDeviceInfo = spcData.vmDeviceInfo.as_ref().map_or(Value::Null, vmDeviceInfoJson);
+        root.insert("vm-device-info".to_string(), vmDeviceInfo);
+
+        let unknownTags: Vec<String> = self.unknownTags().into_iter().map(tagString).collect();
+        root.insert("unknown-tags".to_string(), json!(unknownTags));
+
+        if let Some(e) = self.error {
+            root.insert("error".to_string(), json!(format!("{:?} ({})", e, e)));
+        }
+
+        Value::Object(root)
+    }
+}
+
+/// Renders a decoded value on one line: objects as `key=value` pairs, arrays comma separated.
+pub(crate) fn inlineValue(value: &Value) -> String {
+    match value {
+        Value::Object(map) => map
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, inlineValue(value)))
+            .collect::<Vec<_>>()
+            .join(" "),
+        Value::Array(array) => array.iter().map(inlineValue).collect::<Vec<_>>().join(", "),
+        Value::String(string) => string.clone(),
+        Value::Null => "-".to_string(),
+        other => other.to_string(),
+    }
+}
+
+impl fmt::Display for SPCInspection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let json = self.toJson();
+
+        writeln!(f, "SPC header: {}", inlineValue(&json["header"]))?;
+        writeln!(f, "TLLVs ({}):", self.tllvs.len())?;
+        for inspected in &self.tllvs {
+            write!(f, "  {} length={}", tagString(inspected.tllv.tag), inspected.tllv.value.len())?;
+            if inspected.decoded.is_null() {
+                write!(f, " value={}", hex::encode_upper(&inspected.tllv.value))?;
+            } else {
+                write!(f, " {}", inlineValue(&inspected.decoded))?;
+            }
+            if let Some(e) = inspected.error {
+                write!(f, " error={:?} ({})", e, e)?;
+            }
+            writeln!(f)?;
+        }
+
+        for key in ["return-request", "capabilities", "device-identity", "vm-device-info", "unknown-tags"] {
+            writeln!(f, "{}: {}", key, inlineValue(&json[key]))?;
+        }
+        if let Some(e) = self.error {
+            writeln!(f, "error: {:?} ({})", e, e)?;
+        }
+
+        Ok(())
+    }
+}
@@ -3,31 +3,46 @@
 //
 
 use std::mem::size_of;
+use std::sync::Arc;
 
 use crate::base::base_constants;
 use crate::base::base_constants::{AESEncryptionCipher, AESEncryptionMode, SPCVersion};
 use crate::base::base_constants::{FPS_TLLV_TAG_SZ, FPS_TLLV_TOTAL_LENGTH_SZ, FPS_TLLV_VALUE_LENGTH_SZ};
 use crate::base::structures::base_fps_structures::Base;
 use crate::base::structures::base_fps_structures::FPSOperation;
+use crate::base::structures::secret::SecretBytes;
 use crate::base::structures::base_server_structures::{
     FPSServerCtx, FPSServerSPCContainer, FPSServerTLLV,
 };
 use crate::base::Utils::FPSServerUtils::{readBigEndianU32, readBigEndianU64, readBytes};
 use crate::requireAction;
 use crate::validate::{FPSStatus, Result};
+use crate::metrics::Stage;
 use crate::Extension;
+use crate::SDKExtension;
 
 impl Base {
     /// Parses, decrypts, and validates received SPC.
     pub fn parseSPC(fpsOperation: &FPSOperation, serverCtx: &mut FPSServerCtx) -> Result<()> {
+        // Operations of one request sharing this SPC decrypt it only once (see `extension::spc_cache`)
+        match SDKExtension::spcCache() {
+            Some(spcCache) => spcCache.parse(&fpsOperation.spc, serverCtx, Base::parseSPCUncached),
+            None => Base::parseSPCUncached(&fpsOperation.spc, serverCtx),
+        }
+    }
+
+    /// Decrypts and parses `spc` into `serverCtx.spcContainer`.
+    pub fn parseSPCUncached(spc: &[u8], serverCtx: &mut FPSServerCtx) -> Result<()> {
+        let spcContainer = Arc::make_mut(&mut serverCtx.spcContainer);
+
         // Parse SPC container
-        Base::parseSPCContainer(&fpsOperation.spc, &mut serverCtx.spcContainer)?;
+        Base::parseSPCContainer(spc, spcContainer)?;
 
         // Open SPC Data
-        Base::decryptSPCData(&fpsOperation.spc, &mut serverCtx.spcContainer)?;
+        SDKExtension::metrics().time(Stage::spcDecrypt, || Base::decryptSPCData(spc, spcContainer))?;
 
         // Parse SPC data
-        Base::parseSPCData(&mut serverCtx.spcContainer)?;
+        Base::parseSPCData(spcContainer)?;
 
         // Create set of flags of client supported features (lease, rental, persistence, etc)
         Base::checkSupportedFeatures(serverCtx)?;
@@ -67,11 +82,12 @@ impl Base {
         offset += spcContainer.aesWrappedKeySize;
 
         // 20B Certificate Hash
-        // This is where we should check the certificateHash and fail if it is not what is expected.
-        // Also, this is where the private RSA key would be selected if more than one was provisioned.
         spcContainer.certificateHash = readBytes(spc, offset, base_constants::FPS_V1_HASH_SZ)?;
         offset += base_constants::FPS_V1_HASH_SZ;
 
+        // Select the credentials for this certificate (fails if it is not provisioned)
+        Extension::selectCredentialsCustom(spcContainer)?;
+
         // 4B SPC Size
         spcContainer.spcDataSize = readBigEndianU32(spc, offset)? as usize;
         offset += size_of::<u32>();
@@ -96,7 +112,7 @@ impl Base {
     pub fn decryptSPCData(spc: &[u8], spcContainer: &mut FPSServerSPCContainer) -> Result<()> {
         requireAction!(!spc.is_empty(), return Err(FPSStatus::paramErr));
 
-        let mut localKey: Vec<u8> = Vec::new();
+        let mut localKey = SecretBytes::default();
 
         // Decrypt the encrypted AES key using the RSA private key
         Extension::decryptKeyRSACustom(spcContainer, &mut localKey)?;
@@ -219,50 +235,40 @@ impl Base {
     /// This is done after parsing of the Capabilities TLLV so that any custom
     /// handling knows what the client capabilities are.
     pub fn checkSupportedFeatures(serverCtx: &mut FPSServerCtx) -> Result<()> {
+        let spcData = &mut Arc::make_mut(&mut serverCtx.spcContainer).spcData;
+
         // Defaults
-        serverCtx.spcContainer.spcData.clientFeatures.supportsOfflineKeyTLLV = false;
-        serverCtx.spcContainer.spcData.clientFeatures.supportsOfflineKeyTLLVV2 = false;
-        serverCtx
-            .spcContainer
-            .spcData
-            .clientFeatures
-            .supportsSecurityLevelBaseline = false;
-        serverCtx.spcContainer.spcData.clientFeatures.supportsSecurityLevelMain = false;
-        serverCtx.spcContainer.spcData.clientFeatures.supportsHDCPTypeOne = false;
-        serverCtx.spcContainer.spcData.clientFeatures.supportsDualExpiry = false;
-        serverCtx.spcContainer.spcData.clientFeatures.supportsCheckIn = false;
+        spcData.clientFeatures.supportsOfflineKeyTLLV = false;
+        spcData.clientFeatures.supportsOfflineKeyTLLVV2 = false;
+        spcData.clientFeatures.supportsSecurityLevelBaseline = false;
+        spcData.clientFeatures.supportsSecurityLevelMain = false;
+        spcData.clientFeatures.supportsHDCPTypeOne = false;
+        spcData.clientFeatures.supportsDualExpiry = false;
+        spcData.clientFeatures.supportsCheckIn = false;
 
         // In case we did not receive client capabilities, fill with zeros
-        serverCtx
-            .spcContainer
-            .spcData
-            .clientCapabilities
-            .resize(base_constants::FPS_CAPABILITIES_FLAGS_LENGTH, 0);
+        spcData.clientCapabilities.resize(base_constants::FPS_CAPABILITIES_FLAGS_LENGTH, 0);
 
-        let capabilitiesLVbits = readBigEndianU64(&serverCtx.spcContainer.spcData.clientCapabilities, 8)?;
+        let capabilitiesLVbits = readBigEndianU64(&spcData.clientCapabilities, 8)?;
 
         if (capabilitiesLVbits & base_constants::FPS_CAPABILITY_OFFLINE_KEY_V2_SUPPORTED) != 0 {
-            serverCtx.spcContainer.spcData.clientFeatures.supportsOfflineKeyTLLVV2 = true;
-            serverCtx.spcContainer.spcData.clientFeatures.supportsOfflineKeyTLLV = true;
+            spcData.clientFeatures.supportsOfflineKeyTLLVV2 = true;
+            spcData.clientFeatures.supportsOfflineKeyTLLV = true;
         }
         if (capabilitiesLVbits & base_constants::FPS_CAPABILITY_SECURITY_LEVEL_BASELINE_SUPPORTED) != 0 {
-            serverCtx
-                .spcContainer
-                .spcData
-                .clientFeatures
-                .supportsSecurityLevelBaseline = true;
+            spcData.clientFeatures.supportsSecurityLevelBaseline = true;
         }
         if (capabilitiesLVbits & base_constants::FPS_CAPABILITY_SECURITY_LEVEL_MAIN_SUPPORTED) != 0 {
-            serverCtx.spcContainer.spcData.clientFeatures.supportsSecurityLevelMain = true;
+            spcData.clientFeatures.supportsSecurityLevelMain = true;
         }
         if (capabilitiesLVbits & base_constants::FPS_CAPABILITY_HDCP_TYPE1_ENFORCEMENT_SUPPORTED) != 0 {
-            serverCtx.spcContainer.spcData.clientFeatures.supportsHDCPTypeOne = true;
+            spcData.clientFeatures.supportsHDCPTypeOne = true;
         }
         if (capabilitiesLVbits & base_constants::FPS_CAPABILITY_OFFLINE_KEY_SUPPORTED) != 0 {
-            serverCtx.spcContainer.spcData.clientFeatures.supportsDualExpiry = true;
+            spcData.clientFeatures.supportsDualExpiry = true;
         }
         if (capabilitiesLVbits & base_constants::FPS_CAPABILITY_CHECK_IN_SUPPORTED) != 0 {
-            serverCtx.spcContainer.spcData.clientFeatures.supportsCheckIn = true;
+            spcData.clientFeatures.supportsCheckIn = true;
         }
 
         // Custom handling (if needed)
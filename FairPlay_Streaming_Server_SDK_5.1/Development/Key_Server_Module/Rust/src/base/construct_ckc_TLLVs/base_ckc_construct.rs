@@ -6,6 +6,7 @@ use crate::base::base_constants::AES128_BLOCK_SIZE;
 use crate::base::structures::base_fps_structures::Base;
 use crate::base::structures::base_server_structures::{FPSServerCKCContainer, FPSServerCtx};
 use crate::base::Utils::FPSServerUtils::VectorHelperUtils;
+use crate::logging::Redacted;
 use crate::validate::Result;
 use crate::Extension;
 
@@ -56,7 +57,7 @@ impl Base {
         log::debug!("Adding TLLV Tag -- 0x{:x}", tag);
         log::debug!("    Block Length: 0x{:x}", valueSize + paddingSize);
         log::debug!("    Value Length: 0x{:x}", valueSize);
-        log::debug!("    Value: 0x{}", hex::encode(value));
+        log::debug!("    Value: {}", Redacted(value));
 
         // Write TLLV Tag
         ckcContainer.ckcDataPtr.appendBigEndianU64(tag);
@@ -8,10 +8,11 @@ use crate::validate::Result;
 
 impl Base {
     pub fn populateTagCK(tag: u64, serverCtx: &mut FPSServerCtx) -> Result<()> {
-        Base::serializeTLLV(
-            tag,
-            &serverCtx.ckcContainer.ckcData.contentKeyTLLVPayload.to_owned(),
-            &mut serverCtx.ckcContainer,
-        )
+        // Moved out rather than copied, the payload carries the content key
+        let payload = std::mem::take(&mut serverCtx.ckcContainer.ckcData.contentKeyTLLVPayload);
+        let result = Base::serializeTLLV(tag, &payload, &mut serverCtx.ckcContainer);
+        serverCtx.ckcContainer.ckcData.contentKeyTLLVPayload = payload;
+
+        result
     }
 }
@@ -9,10 +9,11 @@ use crate::validate::Result;
 
 impl Base {
     pub fn populateTagR1(serverCtx: &mut FPSServerCtx) -> Result<()> {
-        Base::serializeTLLV(
-            FPSTLLVTagValue::r1Tag as u64,
-            &serverCtx.ckcContainer.ckcData.r1.to_owned(),
-            &mut serverCtx.ckcContainer,
-        )
+        // Moved out rather than copied, R1 is secret
+        let r1 = std::mem::take(&mut serverCtx.ckcContainer.ckcData.r1);
+        let result = Base::serializeTLLV(FPSTLLVTagValue::r1Tag as u64, &r1, &mut serverCtx.ckcContainer);
+        serverCtx.ckcContainer.ckcData.r1 = r1;
+
+        result
     }
 }
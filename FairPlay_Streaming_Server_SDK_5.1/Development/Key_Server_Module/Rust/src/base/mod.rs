@@ -6,10 +6,12 @@
 
 pub mod Utils;
 pub mod base_ckc_generate;
+pub mod base_ckc_inspect;
 pub mod base_ckc_parse;
 pub mod base_constants;
 pub mod base_parse_verification;
 pub mod base_spc_decrypt;
+pub mod base_spc_inspect;
 pub mod base_spc_parse;
 pub mod construct_ckc_TLLVs;
 pub mod parse_json;
@@ -12,30 +12,55 @@ use crate::base::structures::base_fps_structures::AssetInfo;
 use crate::base::structures::base_fps_structures::FPSOperation;
 use crate::base::structures::base_fps_structures::FPSResult;
 use crate::base::structures::base_fps_structures::{Base, FPSOperations, FPSResults};
+use crate::base::structures::secret::SecretBytes;
+use crate::base::parse_json::base_parse_json_helper::{invalidFieldType, requireType};
 use crate::fpsLogError;
+use crate::logging::Redacted;
 use crate::returnErrorStatus;
-use crate::validate::{FPSStatus, Result};
+use crate::validate::{self, FPSErrorDetail, FPSStatus, Result};
+use crate::extension::spc_cache::SPCCache;
+use crate::extension::extension_constants::CONTENT_KEY_WRAPPED_STR;
+use crate::extension::{parallel, schema, wrapped_key};
+use crate::extension::structures::extension_structures::SDKExtension;
 use crate::Extension;
 use base64::engine::general_purpose;
 use base64::Engine;
 use serde_jsonrc::{Map, Value};
 use std::fs::File;
+use std::sync::Arc;
 extern crate hex;
 use crate::base::base_constants::FPSHDCPRequirement;
 
 impl Base {
-    pub fn parseRootFromJson(file: File) -> Value {
+    pub fn parseRootFromJson(file: File) -> Result<Value> {
         // Initialize generic logging with no extra information from json.
         // This will be called again later to add new information after json parsing.
         Extension::logInitCustom(None);
 
-        serde_jsonrc::from_reader(file).expect("error while reading")
+        serde_jsonrc::from_reader(file).map_err(Base::jsonSyntaxError)
     }
 
-    pub fn parseRootFromString(string: &str) -> Value {
+    pub fn parseRootFromString(string: &str) -> Result<Value> {
         Extension::logInitCustom(None);
 
-        serde_jsonrc::from_str(string).expect("error while reading")
+        serde_jsonrc::from_str(string).map_err(Base::jsonSyntaxError)
+    }
+
+    /// Logs where the input stopped being valid JSON.
+    fn jsonSyntaxError(e: serde_jsonrc::Error) -> FPSStatus {
+        if e.is_io() {
+            fpsLogError!(FPSStatus::parserErr, reason = "unreadable-json", "Unable to read JSON input: {}", e);
+        } else {
+            fpsLogError!(
+                FPSStatus::parserErr,
+                reason = "invalid-json",
+                "Invalid JSON at line {} column {}: {}",
+                e.line(),
+                e.column(),
+                e
+            );
+        }
+        FPSStatus::parserErr
     }
 
     /// Parses the initial parameters in the root of the JSON (id, create-ckc array, etc.)
@@ -43,17 +68,42 @@ impl Base {
         // Get root object from JSON
         let mut root: Map<String, Value> = Default::default();
 
+        if SDKExtension::strictSchema() {
+            schema::REQUEST_SCHEMA.validate(json, "")?;
+        }
+
         Extension::parseOperationsCustom(json, &mut root)?;
 
         // Parse create-ckc object array
-        if let Some(create_ckc_obj_array) = root[base_constants::CREATE_CKC_STR].as_array() {
-            for ckc_obj in create_ckc_obj_array.iter() {
-                //log::debug!("ckc_obj: {:#}", ckc_obj);
-
-                Base::parseCreateCKCOperation(ckc_obj, fpsOperations, &mut &root)?;
+        match root.get(base_constants::CREATE_CKC_STR) {
+            Some(Value::Array(create_ckc_obj_array)) => {
+                for (index, ckc_obj) in create_ckc_obj_array.iter().enumerate() {
+                    //log::debug!("ckc_obj: {:#}", ckc_obj);
+                    let field = format!("{}[{}]", base_constants::CREATE_CKC_STR, index);
+
+                    // An operation that does not parse fails alone, the others are still processed
+                    let (status, error) = validate::captureError(|| {
+                        requireType(&field, ckc_obj, ckc_obj.is_object(), "an object")?;
+                        Base::parseCreateCKCOperation(ckc_obj, fpsOperations, &mut &root)
+                    });
+                    if status.is_err() {
+                        if fpsOperations.operationsPtr.len() == index {
+                            fpsOperations.operationsPtr.push(FPSOperation::default());
+                        }
+                        fpsOperations.operationsPtr[index].parseError = error;
+                    }
+                }
+            }
+            Some(value) => return Err(invalidFieldType(base_constants::CREATE_CKC_STR, "an array", value)),
+            None => {
+                fpsLogError!(
+                    FPSStatus::paramErr,
+                    reason = "missing-field",
+                    "{} not found",
+                    base_constants::CREATE_CKC_STR
+                );
+                returnErrorStatus!(FPSStatus::paramErr);
             }
-        } else {
-            returnErrorStatus!(FPSStatus::paramErr);
         }
 
         Ok(())
@@ -75,36 +125,55 @@ impl Base {
         } else if let Some(id) = ckcObj[base_constants::ID_STR].as_str() {
             // Try again as a string instead
             operation.id = id.parse::<u64>().unwrap_or(0);
+        } else if let Some(id) = ckcObj.get(base_constants::ID_STR) {
+            status = Err(invalidFieldType(base_constants::ID_STR, "an unsigned integer or a string", id));
         } else {
             operation.id = 0;
         }
 
         // SPC - required
-        if let Some(spc) = ckcObj[base_constants::SPC_STR].as_str() {
-            let base64Request = spc.to_string();
-            if let Err(e) = general_purpose::STANDARD.decode_vec(base64Request, &mut operation.spc) {
-                fpsLogError!(FPSStatus::parserErr, "Error decoding base64 SPC: {}", e);
-                status = Err(FPSStatus::parserErr);
+        match ckcObj.get(base_constants::SPC_STR) {
+            Some(Value::String(spc)) => {
+                if let Err(e) = general_purpose::STANDARD.decode_vec(spc, &mut operation.spc) {
+                    fpsLogError!(FPSStatus::parserErr, reason = "spc-not-base64", "Error decoding base64 SPC: {}", e);
+                    status = Err(FPSStatus::parserErr);
+                }
+            }
+            Some(spc) => status = Err(invalidFieldType(base_constants::SPC_STR, "a base64 string", spc)),
+            None => {
+                fpsLogError!(FPSStatus::paramErr, reason = "spc-missing", "SPC not found");
+                status = Err(FPSStatus::paramErr);
             }
-        } else {
-            fpsLogError!(FPSStatus::paramErr, "SPC not found");
-            status = Err(FPSStatus::paramErr);
         }
 
         // Check-in - optional
-        if let Some(check) = ckcObj[base_constants::CHECK_IN_STR].as_bool() {
-            operation.isCheckIn = check;
+        if let Some(check) = ckcObj.get(base_constants::CHECK_IN_STR) {
+            match check.as_bool() {
+                Some(check) => operation.isCheckIn = check,
+                None => status = Err(invalidFieldType(base_constants::CHECK_IN_STR, "a boolean", check)),
+            }
         }
 
         // asset-info - optional
-        if let Some(asset_info_obj_array) = ckcObj[base_constants::ASSET_INFO_STR].as_array() {
-            if asset_info_obj_array.len() == 1 {
-                Base::parseAssetInfo(&asset_info_obj_array[0], &mut operation.assetInfo)?;
-            } else {
+        match ckcObj.get(base_constants::ASSET_INFO_STR) {
+            Some(Value::Array(asset_info_obj_array)) if asset_info_obj_array.len() == 1 => {
+                let field = format!("{}[0]", base_constants::ASSET_INFO_STR);
+                let assetInfoObj = &asset_info_obj_array[0];
+                let parsed = requireType(&field, assetInfoObj, assetInfoObj.is_object(), "an object")
+                    .and_then(|_| Base::parseAssetInfo(assetInfoObj, &mut operation.assetInfo));
+                if let Err(e) = parsed {
+                    status = Err(e);
+                }
+            }
+            Some(Value::Array(_)) => {
                 // We currently only expect one entry
                 fpsLogError!(FPSStatus::paramErr, "Unexpected multiple asset-info entries");
                 status = Err(FPSStatus::paramErr);
             }
+            Some(assetInfo) => {
+                status = Err(invalidFieldType(base_constants::ASSET_INFO_STR, "an array with one object", assetInfo));
+            }
+            None => {}
         }
 
         // Custom handling (if needed)
@@ -125,6 +194,18 @@ impl Base {
         // Keep track if all CK parameters provided (CK, IV)
         assetInfo.isCKProvided = true;
 
+        if assetInfoObj.get(base_constants::CONTENT_KEY_STR).is_some()
+            && assetInfoObj.get(CONTENT_KEY_WRAPPED_STR).is_some()
+        {
+            fpsLogError!(
+                FPSStatus::paramErr,
+                "{} and {} cannot both be given",
+                base_constants::CONTENT_KEY_STR,
+                CONTENT_KEY_WRAPPED_STR
+            );
+            status = Err(FPSStatus::paramErr);
+        }
+
         // content-key - optional for lease renewals
         if let Some(mut contentKey) = assetInfoObj[base_constants::CONTENT_KEY_STR].as_str() {
             // Remove any initial "0x"
@@ -132,27 +213,37 @@ impl Base {
                 (_, contentKey) = contentKey.split_at(2);
             }
 
+            // Decoded straight into a buffer of the final size, so no copy of the key is left behind
+            assetInfo.key = SecretBytes::zeroed(AES128_KEY_SZ);
             if !contentKey.is_empty() && contentKey != "0" {
-                if let Ok(key) = hex::decode(contentKey) {
-                    assetInfo.key = key;
-                } else {
-                    // Try again with an extra 0 in the front
+                if contentKey.len() % 2 == 1 {
+                    // Read with an extra 0 in the front
                     fpsLogError!(
                         FPSStatus::paramErr,
-                        "Warning! content key invalid length: \"{}\"",
-                        contentKey
+                        "Warning! content key invalid length: {}",
+                        Redacted(contentKey.as_bytes())
                     );
-                    let mut tmp = contentKey.to_string();
-                    tmp.insert(0, '0');
-                    if let Ok(key) = hex::decode(tmp) {
-                        assetInfo.key = key;
-                    } else {
-                        fpsLogError!(FPSStatus::paramErr, "unable to decode content key: \"{}\"", contentKey);
+                }
+                match SecretBytes::fromHex(contentKey, AES128_KEY_SZ) {
+                    Some(key) => assetInfo.key = key,
+                    None => {
+                        fpsLogError!(
+                            FPSStatus::paramErr,
+                            "unable to decode content key: {}",
+                            Redacted(contentKey.as_bytes())
+                        );
                         status = Err(FPSStatus::paramErr);
                     }
                 }
             }
-            assetInfo.key.resize(AES128_KEY_SZ, 0);
+        } else if let Some(value) = assetInfoObj.get(base_constants::CONTENT_KEY_STR) {
+            status = Err(invalidFieldType(base_constants::CONTENT_KEY_STR, "a hex string", value));
+        } else if let Some(wrapped) = assetInfoObj.get(CONTENT_KEY_WRAPPED_STR) {
+            // content-key-wrapped - content key encrypted by the CMS
+            match wrapped_key::unwrapContentKey(wrapped) {
+                Ok(key) => assetInfo.key = key,
+                Err(e) => status = Err(e),
+            }
         } else {
             assetInfo.isCKProvided = false;
         }
@@ -164,27 +255,31 @@ impl Base {
                 (_, contentIV) = contentIV.split_at(2);
             }
 
+            // Decoded straight into a buffer of the final size, so no copy of the iv is left behind
+            assetInfo.iv = SecretBytes::zeroed(AES128_IV_SZ);
             if !contentIV.is_empty() && contentIV != "0" {
-                if let Ok(iv) = hex::decode(contentIV) {
-                    assetInfo.iv = iv;
-                } else {
-                    // Try again with an extra 0 in the front
+                if contentIV.len() % 2 == 1 {
+                    // Read with an extra 0 in the front
                     fpsLogError!(
                         FPSStatus::paramErr,
-                        "Warning! content IV invalid length: \"{}\"",
-                        contentIV
+                        "Warning! content IV invalid length: {}",
+                        Redacted(contentIV.as_bytes())
                     );
-                    let mut tmp = contentIV.to_string();
-                    tmp.insert(0, '0');
-                    if let Ok(iv) = hex::decode(tmp) {
-                        assetInfo.iv = iv;
-                    } else {
-                        fpsLogError!(FPSStatus::paramErr, "unable to decode content iv: \"{}\"", contentIV);
+                }
+                match SecretBytes::fromHex(contentIV, AES128_IV_SZ) {
+                    Some(iv) => assetInfo.iv = iv,
+                    None => {
+                        fpsLogError!(
+                            FPSStatus::paramErr,
+                            "unable to decode content iv: {}",
+                            Redacted(contentIV.as_bytes())
+                        );
                         status = Err(FPSStatus::paramErr);
                     }
                 }
             }
-            assetInfo.iv.resize(AES128_IV_SZ, 0);
+        } else if let Some(value) = assetInfoObj.get(base_constants::CONTENT_IV_STR) {
+            status = Err(invalidFieldType(base_constants::CONTENT_IV_STR, "a hex string", value));
         } else {
             assetInfo.isCKProvided = false;
         }
@@ -195,6 +290,8 @@ impl Base {
             if assetInfo.leaseDuration == 0 {
                 assetInfo.leaseDuration = base_constants::NO_LEASE_DURATION;
             }
+        } else if let Some(value) = assetInfoObj.get(base_constants::LEASE_DURATION_STR) {
+            status = Err(invalidFieldType(base_constants::LEASE_DURATION_STR, "an unsigned integer", value));
         } else {
             assetInfo.leaseDuration = base_constants::NO_LEASE_DURATION;
         }
@@ -206,6 +303,8 @@ impl Base {
                 fpsLogError!(e, "parseOfflineHLS failed");
                 status = Err(e);
             }
+        } else if let Some(value) = assetInfoObj.get(base_constants::OFFLINE_HLS_STR) {
+            status = Err(invalidFieldType(base_constants::OFFLINE_HLS_STR, "an object", value));
         }
 
         // HDCP Requirement - optional
@@ -223,6 +322,8 @@ impl Base {
                 fpsLogError!(e, "Error parsing HDCP type: {}", hdcpTypeString);
                 status = Err(e);
             }
+        } else if let Some(value) = assetInfoObj.get(base_constants::HDCP_TYPE_STR) {
+            status = Err(invalidFieldType(base_constants::HDCP_TYPE_STR, "an integer or a string", value));
         } else {
             log::debug!("Warning! HDCP Type not provided, defaulting to Type 0");
             assetInfo.hdcpReq = FPSHDCPRequirement::hdcpType0 as u64;
@@ -245,33 +346,79 @@ impl Base {
         let mut fpsResults: FPSResults = FPSResults::default();
 
         // Parse json and put results into operation
-        status = Base::parseOperations(&json, &mut fpsOperations);
+        let (parseStatus, mut error) = validate::captureError(|| Base::parseOperations(&json, &mut fpsOperations));
+        status = parseStatus;
 
         if let Err(e) = Extension::processOperationsCustom(&json, output, &fpsOperations, &mut fpsResults) {
             fpsLogError!(e, "processOperationsCustom failed");
             status = Err(e);
+            error = Some(FPSErrorDetail::new(e));
         }
 
         if status.is_ok() {
-            for mut fpsOperation in fpsOperations.operationsPtr {
+            // Operations are independent: process them concurrently, results stay in request order.
+            // SPCs sent in several operations are only decrypted once.
+            let spcCache = Arc::new(SPCCache::new());
+            let results = parallel::processInParallel(fpsOperations.operationsPtr, move |mut fpsOperation| {
+                if let Some(error) = fpsOperation.parseError.take() {
+                    return FPSResult {id: fpsOperation.id, status: error.status, error: Some(error), ..Default::default()};
+                }
+
                 let mut fpsResult: FPSResult = FPSResult::default();
                 // Process operations only if parseOperations() call succeeded (as indicated by status)
-                fpsResult.status = match Base::createResults(&mut fpsOperation, &mut fpsResult) {
+                let status = SDKExtension::withSPCCache(spcCache.clone(), || {
+                    Base::createResults(&mut fpsOperation, &mut fpsResult)
+                });
+                fpsResult.status = match status {
                     Ok(_) => FPSStatus::noErr,
                     Err(e) => e,
                 };
 
-                fpsResults.resultPtr.push(fpsResult);
-            }
+                fpsResult
+            });
+            fpsResults.resultPtr.extend(results);
         } else {
-            let fpsResult: FPSResult = FPSResult {status: status.unwrap_err(), ..Default::default()};
+            let fpsResult: FPSResult = FPSResult {status: status.unwrap_err(), error, ..Default::default()};
             fpsResults.resultPtr.push(fpsResult);
         }
 
         // Convert results from structure into JSON format
         let _ = Base::serializeResults(fpsResults, &mut resultJson);
-        *output = resultJson.parse().unwrap();
+        *output = Base::parseSerializedResults(&resultJson)?;
 
         Ok(())
     }
+
+    /// Parses the JSON written by `serializeResults`.
+    fn parseSerializedResults(resultJson: &str) -> Result<Value> {
+        match resultJson.parse() {
+            Ok(output) => Ok(output),
+            Err(e) => {
+                fpsLogError!(FPSStatus::internalErr, "Unable to parse serialized results: {}", e);
+                returnErrorStatus!(FPSStatus::internalErr);
+            }
+        }
+    }
+
+    /// Parses `string` and processes its operations.
+    ///
+    /// Input that is not valid JSON gets a single `parserErr` result, as other request-level errors do.
+    pub fn processOperationsFromString(string: &str, output: &mut Value) -> Result<()> {
+        let (root, error) = validate::captureError(|| Base::parseRootFromString(string));
+
+        match root {
+            Ok(json) => Base::processOperations(json, output),
+            Err(status) => {
+                let mut resultJson = "".to_string();
+                let fpsResults = FPSResults {
+                    resultPtr: vec![FPSResult {status, error, ..Default::default()}],
+                    ..Default::default()
+                };
+                let _ = Base::serializeResults(fpsResults, &mut resultJson);
+                *output = Base::parseSerializedResults(&resultJson)?;
+
+                Ok(())
+            }
+        }
+    }
 }
@@ -8,10 +8,51 @@ use crate::base::base_constants;
 use crate::base::base_constants::FPSHDCPRequirement;
 use crate::base::structures::base_fps_structures::AssetInfo;
 use crate::base::structures::base_fps_structures::Base;
-use crate::validate::Result;
-use crate::Extension;
+use crate::validate::{FPSStatus, Result};
+use crate::{fpsLogError, Extension};
 use serde_jsonrc::Value;
 
+/// Name of the JSON type of `value`, for error messages.
+pub fn jsonTypeName(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+/// Logs that `field` of the input JSON is not `expected`, and returns the status to fail with.
+pub fn invalidFieldType(field: &str, expected: &str, value: &Value) -> FPSStatus {
+    fpsLogError!(
+        FPSStatus::paramErr,
+        reason = "invalid-field-type",
+        "{} must be {}, not {}",
+        field,
+        expected,
+        jsonTypeName(value)
+    );
+    FPSStatus::paramErr
+}
+
+/// Fails with `invalidFieldType` unless `isExpected`.
+pub fn requireType(field: &str, value: &Value, isExpected: bool, expected: &str) -> Result<()> {
+    if !isExpected {
+        return Err(invalidFieldType(field, expected, value));
+    }
+    Ok(())
+}
+
+/// Decodes the hex string `field` of an `offline-hls` object.
+fn decodeHexField(field: &str, value: &str) -> Result<Vec<u8>> {
+    hex::decode(value).map_err(|e| {
+        fpsLogError!(FPSStatus::paramErr, reason = "invalid-hex", "unable to decode {}: {}", field, e);
+        FPSStatus::paramErr
+    })
+}
+
 impl Base {
     /// Assigns HDCP requirement based on input integer.
     /// -1 = HDCP not required
@@ -40,7 +81,7 @@ impl Base {
         if ckcObj.contains_key(base_constants::STREAM_ID_STR) {
             if let Some(streamId) = ckcObj.get(base_constants::STREAM_ID_STR).unwrap().as_str() {
                 if !streamId.is_empty() {
-                    assetInfo.streamId = Some(hex::decode(streamId).unwrap());
+                    assetInfo.streamId = Some(decodeHexField(base_constants::STREAM_ID_STR, streamId)?);
                 }
             }
         }
@@ -49,7 +90,7 @@ impl Base {
         if ckcObj.contains_key(base_constants::TITLE_ID_STR) {
             if let Some(titleId) = ckcObj.get(base_constants::TITLE_ID_STR).unwrap().as_str() {
                 if !titleId.is_empty() {
-                    assetInfo.titleId = Some(hex::decode(titleId).unwrap());
+                    assetInfo.titleId = Some(decodeHexField(base_constants::TITLE_ID_STR, titleId)?);
                 }
             }
         }
@@ -14,7 +14,7 @@ impl Base {
         // Check that size matches expected size exactly
         requireAction!(tllv.value.len() == AES128_KEY_SZ, return Err(FPSStatus::parserErr));
 
-        spcContainer.spcData.antiReplay = readBytes(&tllv.value, 0, AES128_KEY_SZ)?;
+        spcContainer.spcData.antiReplay = readBytes(&tllv.value, 0, AES128_KEY_SZ)?.into();
 
         Ok(())
     }
@@ -68,6 +68,7 @@ impl Base {
             if !tagAdded {
                 fpsLogError!(
                     FPSStatus::missingRequiredTagErr,
+                    reason = "return-tag-missing",
                     "Return tag missing from SPC 0x{:x}",
                     tag
                 );
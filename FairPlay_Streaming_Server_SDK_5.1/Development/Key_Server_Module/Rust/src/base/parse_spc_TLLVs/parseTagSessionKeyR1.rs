@@ -15,7 +15,7 @@ impl Base {
         requireAction!(tllv.value.len() == FPS_V1_SKR1_SZ, return Err(FPSStatus::parserErr));
 
         // Entire TLLV value is the SK R1 value
-        spcContainer.spcData.skR1 = readBytes(&tllv.value, 0, FPS_V1_SKR1_SZ)?;
+        spcContainer.spcData.skR1 = readBytes(&tllv.value, 0, FPS_V1_SKR1_SZ)?.into();
 
         Ok(())
     }
@@ -8,8 +8,9 @@ use crate::base::base_constants;
 use crate::base::base_constants::FPSHDCPRequirement;
 use crate::base::base_constants::FPS_V1_HU_SZ;
 use super::base_server_structures::VMDeviceInfo;
+use super::secret::SecretBytes;
 use crate::extension_structures;
-use crate::validate::FPSStatus;
+use crate::validate::{FPSErrorDetail, FPSStatus};
 use std::fmt::Debug;
 
 /// Base container where common code is implemented.
@@ -33,16 +34,29 @@ pub struct FPSOperation {
     /// True when input SPC is a SyncSPC with check-in
     pub isCheckIn: bool,
     pub assetInfo: AssetInfo,
+    /// Why the create-ckc object could not be parsed. The operation is answered with this error
+    /// instead of being processed.
+    pub parseError: Option<FPSErrorDetail>,
 
     // Extension
     pub extension: extension_structures::FPSOperationExtension,
 }
 
+impl FPSOperation {
+    /// False for check-ins and lease renewals (a lease requested without a content key), which
+    /// return no content key to the client.
+    pub fn deliversContentKey(&self) -> bool {
+        let assetInfo = &self.assetInfo;
+        let isLease = assetInfo.leaseDuration != base_constants::NO_LEASE_DURATION && assetInfo.leaseDuration != 0;
+        !self.isCheckIn && (assetInfo.isCKProvided || !isLease)
+    }
+}
+
 /// Protection requirements related to a particular asset.
 #[derive(Debug, Clone)]
 pub struct AssetInfo {
-    pub key: Vec<u8>,
-    pub iv: Vec<u8>,
+    pub key: SecretBytes,
+    pub iv: SecretBytes,
     pub isCKProvided: bool,     // true if key and iv are valid
 
     pub hdcpReq: u64,           // one of the FPSHDCPRequirement enums. Using type u64 so we can test with invalid values.
@@ -65,8 +79,8 @@ impl Default for AssetInfo {
     fn default() -> AssetInfo {
         AssetInfo {
             isCKProvided: false,
-            key: vec![0; base_constants::AES128_KEY_SZ],
-            iv: vec![0; base_constants::AES128_IV_SZ],
+            key: SecretBytes::zeroed(base_constants::AES128_KEY_SZ),
+            iv: SecretBytes::zeroed(base_constants::AES128_IV_SZ),
             leaseDuration: 0,
             rentalDuration: 0,
             playbackDuration: 0,
@@ -86,6 +100,8 @@ impl Default for AssetInfo {
 pub struct FPSResult {
     pub id: u64,
     pub status: FPSStatus,
+    /// Why the operation failed, if it did
+    pub error: Option<FPSErrorDetail>,
     pub hu: Vec<u8>,
     pub ckc: Vec<u8>,
 
@@ -120,6 +136,7 @@ impl Default for FPSResult {
         FPSResult {
             id: 0,
             status: FPSStatus::noErr,
+            error: None,
             hu: vec![0; FPS_V1_HU_SZ],
             ckc: Vec::new(),
 
@@ -5,17 +5,22 @@
 use crate::base::base_constants::FPS_MAX_KEY_FORMATS;
 use crate::base::base_constants::{self, FPSDeviceClass};
 use crate::extension_structures;
+use super::secret::SecretBytes;
 use derivative::Derivative;
 use std::fmt::Debug;
+use std::sync::Arc;
 
 /// Contains both the SPC and CKC containers along with stream and title IDs.
 /// This is the base structure that is most commonly sent to functions.
 #[derive(Debug, Default, Clone)]
 pub struct FPSServerCtx {
-    pub spcContainer: FPSServerSPCContainer,
+    /// Read-only once parsed, shared by the operations of a request that send the same SPC
+    pub spcContainer: Arc<FPSServerSPCContainer>,
     pub ckcContainer: FPSServerCKCContainer,
     pub streamId: Option<Vec<u8>>,
     pub titleId: Option<Vec<u8>>,
+    /// Device identifier returned with the content key payload
+    pub hu: Vec<u8>,
 
     // Extension
     pub extension: extension_structures::ServerCtxExtension,
@@ -31,7 +36,7 @@ pub struct FPSServerSPCContainer {
     pub aesWrappedKey: Vec<u8>,
     pub aesWrappedKeySize: usize,
     pub certificateHash: Vec<u8>,
-    pub spcDecryptedData: Vec<u8>,
+    pub spcDecryptedData: SecretBytes,
     pub spcDataSize: usize,
     pub spcDataOffset: usize,
     pub spcData: FPSServerSPCData,
@@ -69,14 +74,13 @@ impl Default for FPSServerCKCContainer {
 /// Contains information parsed out of the SPC TLLVs after decryption.
 #[derive(Debug, Clone)]
 pub struct FPSServerSPCData {
-    pub antiReplay: Vec<u8>,
-    pub sk: Vec<u8>,
-    pub hu: Vec<u8>,
+    pub antiReplay: SecretBytes,
+    pub sk: SecretBytes,
     pub r2: Vec<u8>,
-    pub r1: Vec<u8>,
+    pub r1: SecretBytes,
     pub skR1IntegrityTag: Vec<u8>,
     pub skR1Integrity: Vec<u8>,
-    pub skR1: Vec<u8>,
+    pub skR1: SecretBytes,
     pub assetId: Vec<u8>,
     pub versionUsed: u32,
     pub versionsSupported: Vec<u32>,
@@ -121,14 +125,13 @@ pub struct FPSServerSPCData {
 impl Default for FPSServerSPCData {
     fn default() -> FPSServerSPCData {
         FPSServerSPCData {
-            antiReplay: vec![0; base_constants::AES128_KEY_SZ],
-            sk: vec![0; base_constants::AES128_KEY_SZ],
-            hu: vec![0; base_constants::FPS_V1_HU_SZ],
+            antiReplay: SecretBytes::zeroed(base_constants::AES128_KEY_SZ),
+            sk: SecretBytes::zeroed(base_constants::AES128_KEY_SZ),
             r2: vec![0; base_constants::FPS_V1_R2_SZ],
-            r1: vec![0; base_constants::FPS_V1_R1_SZ],
+            r1: SecretBytes::zeroed(base_constants::FPS_V1_R1_SZ),
             skR1IntegrityTag: vec![0; base_constants::FPS_V1_SKR1_INTEGRITY_SZ],
             skR1Integrity: vec![0; base_constants::FPS_V1_SKR1_INTEGRITY_SZ],
-            skR1: vec![0; base_constants::FPS_V1_SKR1_SZ],
+            skR1: SecretBytes::zeroed(base_constants::FPS_V1_SKR1_SZ),
 
             assetId: Default::default(),
             versionUsed: 0,
@@ -199,15 +202,15 @@ pub struct FPSDeviceInfo {
 /// Data that will be added to the CKC TLLVs.
 #[derive(Derivative, Debug, Clone, Default)]
 pub struct FPSServerCKCData {
-    pub ck: Vec<u8>,
-    pub iv: Vec<u8>,
-    pub r1: Vec<u8>,
+    pub ck: SecretBytes,
+    pub iv: SecretBytes,
+    pub r1: SecretBytes,
     pub keyDuration: FPSServerKeyDuration,
     pub hdcpTypeTLLVValue: u64,
 
     // For new FPS crypto lib this is content key tag and content key TLLV payload to use
     pub contentKeyTLLVTag: u64,
-    pub contentKeyTLLVPayload: Vec<u8>,
+    pub contentKeyTLLVPayload: SecretBytes,
 
     // Extension
     pub extension: extension_structures::CKCDataExtension,
@@ -4,3 +4,4 @@
 
 pub mod base_fps_structures;
 pub mod base_server_structures;
+pub mod secret;
@@ -0,0 +1,112 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+//! Key material that is zeroed when dropped and redacted in `Debug` output.
+
+use crate::logging::Redacted;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use zeroize::Zeroize;
+
+/// Bytes of a content key, IV, session key or other secret.
+///
+/// Derefs to `Vec<u8>`, so it is filled and read like the vector it replaces. The whole
+/// allocation is zeroed on drop. Growing it past its capacity moves the bytes and leaves the old
+/// allocation behind, so secrets are created at their final size (`SecretBytes::zeroed`).
+#[derive(Clone, Default)]
+pub struct SecretBytes(Vec<u8>);
+
+impl SecretBytes {
+    /// `len` zero bytes, to be overwritten in place.
+    pub fn zeroed(len: usize) -> SecretBytes {
+        SecretBytes(vec![0; len])
+    }
+
+    /// Decodes hex `text` into `len` bytes, without any intermediate copy of the secret.
+    ///
+    /// Text of odd length is read as if it had a leading `0`. Shorter values are padded with zero
+    /// bytes and longer ones truncated. `None` if `text` is not hex.
+    pub fn fromHex(text: &str, len: usize) -> Option<SecretBytes> {
+        let digits = text.as_bytes();
+        let digit = |index: usize| match index.checked_sub(digits.len() % 2) {
+            Some(index) => (digits[index] as char).to_digit(16),
+            None => Some(0),
+        };
+
+        let mut secret = SecretBytes::zeroed(len);
+        for index in 0..digits.len().div_ceil(2) {
+            let byte = (digit(2 * index)? << 4 | digit(2 * index + 1)?) as u8;
+            if let Some(value) = secret.get_mut(index) {
+                *value = byte;
+            }
+        }
+        Some(secret)
+    }
+}
+
+impl Drop for SecretBytes {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl fmt::Debug for SecretBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&Redacted(&self.0), f)
+    }
+}
+
+/// Compares in constant time for equal lengths.
+impl PartialEq for SecretBytes {
+    fn eq(&self, other: &SecretBytes) -> bool {
+        self.0.len() == other.0.len() && openssl::memcmp::eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for SecretBytes {}
+
+impl PartialEq<[u8]> for SecretBytes {
+    fn eq(&self, other: &[u8]) -> bool {
+        self.0.len() == other.len() && openssl::memcmp::eq(&self.0, other)
+    }
+}
+
+impl PartialEq<Vec<u8>> for SecretBytes {
+    fn eq(&self, other: &Vec<u8>) -> bool {
+        self == other.as_slice()
+    }
+}
+
+impl Deref for SecretBytes {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.0
+    }
+}
+
+impl DerefMut for SecretBytes {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.0
+    }
+}
+
+impl AsRef<[u8]> for SecretBytes {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Takes ownership of `bytes` without copying them.
+impl From<Vec<u8>> for SecretBytes {
+    fn from(bytes: Vec<u8>) -> SecretBytes {
+        SecretBytes(bytes)
+    }
+}
+
+impl From<&[u8]> for SecretBytes {
+    fn from(bytes: &[u8]) -> SecretBytes {
+        SecretBytes(bytes.to_vec())
+    }
+}
@@ -7,20 +7,279 @@
 use fpssdk::base::base_constants;
 use fpssdk::base::structures::base_fps_structures::Base;
 use fpssdk::extension::extension_constants;
+use fpssdk::extension::key_store::{formatAssetId, parseAssetId, KeyStore, KEY_STORE_PATH_ENV};
+use fpssdk::extension::schema;
 use fpssdk::extension::validate::{FPSStatus, Result};
 use fpssdk::fpsLogError;
+use base64::engine::general_purpose;
+use base64::Engine;
 use serde_jsonrc::Value;
 use std::env;
-use std::fs::File;
 use std::panic;
 use std::path::Path;
 
+const USAGE: &str = "Usage: fpssdk_local <input.json>
+       fpssdk_local keys import [--db PATH] <keys.json>
+       fpssdk_local keys list [--db PATH] [--show-keys]
+       fpssdk_local keys rotate [--db PATH] <asset-id> [--content-key HEX] [--content-iv HEX]
+       fpssdk_local inspect-spc [--json] <spc-file>
+       fpssdk_local inspect-ckc [--json] [--r1 HEX] <spc-file> <ckc-file>
+       fpssdk_local schema <request|response>
+
+The key store defaults to $FPS_KEY_STORE_PATH.
+An SPC file holds the raw SPC, the SPC in base64, or a request JSON (every create-ckc SPC is inspected).
+A CKC file holds the raw CKC, the CKC in base64, or a response JSON; CKCs are matched to SPCs in order.
+Without --r1, R1 is recovered from the SPC with the configured credentials.
+schema prints the JSON Schema of the request or response JSON; set FPS_STRICT_SCHEMA=1 to enforce it.";
+
 fn main() -> Result<()> {
+    let args: Vec<String> = env::args().collect();
+    if args.get(1).map(String::as_str) == Some("keys") {
+        return keys_command(&args[2..]);
+    }
+    if args.get(1).map(String::as_str) == Some("inspect-spc") {
+        return inspect_spc_command(&args[2..]);
+    }
+    if args.get(1).map(String::as_str) == Some("inspect-ckc") {
+        return inspect_ckc_command(&args[2..]);
+    }
+    if args.get(1).map(String::as_str) == Some("schema") {
+        return schema_command(&args[2..]);
+    }
+
     launch_process()?;
 
     Ok(())
 }
 
+/// Manages the content key store used by `queryDatabaseCustom`.
+fn keys_command(args: &[String]) -> Result<()> {
+    let mut dbPath = env::var(KEY_STORE_PATH_ENV).ok();
+    let mut showKeys = false;
+    let mut contentKey = None;
+    let mut contentIV = None;
+    let mut positional = Vec::new();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let mut value = || match iter.next() {
+            Some(value) => Ok(value.clone()),
+            None => {
+                println!("Error: missing value for {}\n{}", arg, USAGE);
+                Err(FPSStatus::paramErr)
+            }
+        };
+        match arg.as_str() {
+            "--db" => dbPath = Some(value()?),
+            "--show-keys" => showKeys = true,
+            "--content-key" => contentKey = Some(decode_hex(&value()?)?),
+            "--content-iv" => contentIV = Some(decode_hex(&value()?)?),
+            _ => positional.push(arg.as_str()),
+        }
+    }
+
+    let Some(dbPath) = dbPath else {
+        println!("Error: no key store given (--db or {})\n{}", KEY_STORE_PATH_ENV, USAGE);
+        return Err(FPSStatus::paramErr);
+    };
+    let keyStore = KeyStore::open(&dbPath)?;
+
+    match positional.as_slice() {
+        ["import", importPath] => {
+            let json = std::fs::read_to_string(importPath).map_err(|e| {
+                println!("Error: unable to read {}: {}", importPath, e);
+                FPSStatus::paramErr
+            })?;
+            let count = keyStore.importJson(&json)?;
+            println!("Imported {} key(s) into {}", count, dbPath);
+        }
+        ["list"] => {
+            for record in keyStore.list()? {
+                let mut line = format!(
+                    "{}\tv{}\tcontent-type={}\thdcp-type={}\tlease={}\trental={}\tplayback={}\tupdated={}",
+                    formatAssetId(&record.assetId),
+                    record.version,
+                    record.contentType.map_or("-", |contentType| contentType.name()),
+                    optional(record.hdcpType),
+                    optional(record.leaseDuration),
+                    optional(record.rentalDuration),
+                    optional(record.playbackDuration),
+                    chrono::DateTime::from_timestamp(record.updatedAt, 0).map_or_else(String::new, |t| t.to_rfc3339()),
+                );
+                if showKeys {
+                    line.push_str(&format!("\tkey={}\tiv={}", hex::encode(&record.key), hex::encode(&record.iv)));
+                }
+                println!("{}", line);
+            }
+        }
+        ["rotate", assetId] => {
+            let record = keyStore.rotate(&parseAssetId(assetId)?, contentKey.map(Into::into), contentIV.map(Into::into))?;
+            println!(
+                "{}\tv{}\tkey={}\tiv={}",
+                formatAssetId(&record.assetId),
+                record.version,
+                hex::encode(&record.key),
+                hex::encode(&record.iv)
+            );
+        }
+        _ => {
+            println!("{}", USAGE);
+            return Err(FPSStatus::paramErr);
+        }
+    }
+
+    Ok(())
+}
+
+/// Decodes and prints every TLLV of one or more SPCs.
+fn inspect_spc_command(args: &[String]) -> Result<()> {
+    let (asJson, path) = match args {
+        [path] => (false, path),
+        [flag, path] if flag == "--json" => (true, path),
+        _ => {
+            println!("{}", USAGE);
+            return Err(FPSStatus::paramErr);
+        }
+    };
+
+    let mut results = Vec::new();
+    for spc in read_spcs(&read_file(path)?)? {
+        let inspection = Base::inspectSPC(&spc);
+        if asJson {
+            results.push(inspection.toJson());
+        } else {
+            println!("{}", inspection);
+        }
+    }
+    if asJson {
+        println!("{}", serde_jsonrc::to_string_pretty(&Value::Array(results)).unwrap());
+    }
+
+    Ok(())
+}
+
+/// Prints a published JSON Schema.
+fn schema_command(args: &[String]) -> Result<()> {
+    let schema = match args {
+        [document] if document == "request" => schema::requestJsonSchema(),
+        [document] if document == "response" => schema::responseJsonSchema(),
+        _ => {
+            println!("{}", USAGE);
+            return Err(FPSStatus::paramErr);
+        }
+    };
+    println!("{}", serde_jsonrc::to_string_pretty(&schema).unwrap());
+
+    Ok(())
+}
+
+/// Decrypts CKCs with their originating SPCs and prints every TLLV.
+fn inspect_ckc_command(args: &[String]) -> Result<()> {
+    let mut asJson = false;
+    let mut r1 = None;
+    let mut paths = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => asJson = true,
+            "--r1" => match args.next() {
+                Some(value) => r1 = Some(decode_hex(value)?),
+                None => {
+                    println!("Error: missing value for {}\n{}", arg, USAGE);
+                    return Err(FPSStatus::paramErr);
+                }
+            },
+            _ => paths.push(arg),
+        }
+    }
+    let [spcPath, ckcPath] = paths[..] else {
+        println!("{}", USAGE);
+        return Err(FPSStatus::paramErr);
+    };
+
+    let spcs = read_spcs(&read_file(spcPath)?)?;
+    let ckcs = read_messages(
+        &read_file(ckcPath)?,
+        extension_constants::FAIRPLAY_STREAMING_RESPONSE_STR,
+        base_constants::CKC_STR,
+    )?;
+    if spcs.len() != ckcs.len() {
+        println!("Error: {} SPCs but {} CKCs", spcs.len(), ckcs.len());
+        return Err(FPSStatus::paramErr);
+    }
+
+    let mut results = Vec::new();
+    for (spc, ckc) in spcs.iter().zip(&ckcs) {
+        let inspection = Base::inspectCKC(spc, ckc, r1.as_deref());
+        if asJson {
+            results.push(inspection.toJson());
+        } else {
+            println!("{}", inspection);
+        }
+    }
+    if asJson {
+        println!("{}", serde_jsonrc::to_string_pretty(&Value::Array(results)).unwrap());
+    }
+
+    Ok(())
+}
+
+fn read_file(path: &str) -> Result<Vec<u8>> {
+    std::fs::read(path).map_err(|e| {
+        println!("Error: unable to read {}: {}", path, e);
+        FPSStatus::paramErr
+    })
+}
+
+/// Returns the SPCs in an SPC file (see `USAGE`).
+fn read_spcs(contents: &[u8]) -> Result<Vec<Vec<u8>>> {
+    read_messages(contents, extension_constants::FAIRPLAY_STREAMING_REQUEST_STR, base_constants::SPC_STR)
+}
+
+/// Returns the raw or base64 message in `contents`, or the `field` of every create-ckc entry
+/// under `root` when `contents` is JSON.
+fn read_messages(contents: &[u8], root: &str, field: &str) -> Result<Vec<Vec<u8>>> {
+    let text = std::str::from_utf8(contents).map(str::trim).unwrap_or_default();
+
+    if let Ok(json) = serde_jsonrc::from_str::<Value>(text) {
+        let operations = json[root][base_constants::CREATE_CKC_STR]
+            .as_array()
+            .cloned()
+            .unwrap_or_default();
+        if operations.is_empty() {
+            println!("Error: no {} entries in {} JSON", base_constants::CREATE_CKC_STR, root);
+            return Err(FPSStatus::paramErr);
+        }
+        return operations
+            .iter()
+            .map(|operation| {
+                let message = operation[field].as_str().unwrap_or_default();
+                general_purpose::STANDARD.decode(message).map_err(|e| {
+                    println!("Error: invalid base64 {} in {} JSON: {}", field, root, e);
+                    FPSStatus::paramErr
+                })
+            })
+            .collect();
+    }
+
+    match general_purpose::STANDARD.decode(text) {
+        Ok(message) if !text.is_empty() => Ok(vec![message]),
+        _ => Ok(vec![contents.to_vec()]),
+    }
+}
+
+fn decode_hex(value: &str) -> Result<Vec<u8>> {
+    hex::decode(value.strip_prefix("0x").unwrap_or(value)).map_err(|e| {
+        println!("Error: invalid hex value {}: {}", value, e);
+        FPSStatus::paramErr
+    })
+}
+
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map_or_else(|| "-".to_string(), |value| value.to_string())
+}
+
 fn launch_process() -> Result<Value> {
     let result = panic::catch_unwind(|| -> Result<Value> {
         let mut _status: FPSStatus = FPSStatus::noErr;
@@ -31,16 +290,19 @@ fn launch_process() -> Result<Value> {
         if args.len() > 1 {
             jsonFilePath = Path::new(&args[1]);
         } else {
-            println!("Error: Input json file not provided");
+            println!("Error: Input json file not provided\n{}", USAGE);
             return Err(FPSStatus::paramErr);
         }
 
-        let file = File::open(jsonFilePath).expect("file not found");
+        let contents = read_file(&jsonFilePath.to_string_lossy())?;
+        let Ok(json) = String::from_utf8(contents) else {
+            println!("Error: {} is not UTF-8", jsonFilePath.display());
+            return Err(FPSStatus::parserErr);
+        };
 
         let mut output: Value = Default::default();
 
-        let root = Base::parseRootFromJson(file);
-        Base::processOperations(root, &mut output)?;
+        Base::processOperationsFromString(&json, &mut output)?;
 
         println!("{}", output);
         Ok(output)
@@ -0,0 +1,406 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+//! Minimal HTTP/1.1 request reader and response writer used by `fpssdk_server`.
+//!
+//! Only what a key server needs is supported: `Content-Length` delimited bodies,
+//! `Expect: 100-continue`, and persistent connections. Chunked request bodies are
+//! rejected with `501 Not Implemented`.
+
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+/// Maximum size of the request line plus all header lines.
+pub const MAX_HEADER_BYTES: u64 = 16 * 1024;
+
+/// Maximum number of header lines in a single request.
+pub const MAX_HEADER_COUNT: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+impl Version {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Version::Http10 => "HTTP/1.0",
+            Version::Http11 => "HTTP/1.1",
+        }
+    }
+}
+
+/// A fully read HTTP request.
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    pub target: String,
+    pub version: Version,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// Returns the value of the first header called `name` (case-insensitive).
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Request target without any query string.
+    pub fn path(&self) -> &str {
+        self.target.split('?').next().unwrap_or("")
+    }
+
+    /// True if the client asked for (or, for HTTP/1.1, did not opt out of) a persistent connection.
+    pub fn keep_alive(&self) -> bool {
+        let connection = self.header("Connection").unwrap_or("");
+        let has_token = |token: &str| connection.split(',').any(|t| t.trim().eq_ignore_ascii_case(token));
+
+        match self.version {
+            Version::Http11 => !has_token("close"),
+            Version::Http10 => has_token("keep-alive"),
+        }
+    }
+}
+
+/// Reasons a request could not be read off the connection.
+#[derive(Debug)]
+pub enum ReadError {
+    /// The peer closed the connection, or it sat idle past the keep-alive timeout,
+    /// before a new request started. Nothing should be sent back.
+    Closed,
+    /// The request is malformed or violates a limit. Respond with this status and close.
+    Status(u16),
+    /// Unrecoverable socket error.
+    Io(io::Error),
+}
+
+/// Read side of a connection.
+///
+/// Between requests each read waits up to the idle timeout. While a request is being read, reads
+/// share the deadline set by `read_request`, so a client trickling bytes cannot hold a worker
+/// for longer than the request timeout.
+#[derive(Debug)]
+pub struct Connection {
+    stream: TcpStream,
+    idle_timeout: Duration,
+    deadline: Option<Instant>,
+}
+
+impl Connection {
+    pub fn new(stream: TcpStream, idle_timeout: Duration) -> Connection {
+        Connection {
+            stream,
+            idle_timeout,
+            deadline: None,
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let timeout = match self.deadline {
+            Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+            None => self.idle_timeout,
+        };
+        if timeout.is_zero() {
+            return Err(io::Error::from(ErrorKind::TimedOut));
+        }
+        self.stream.set_read_timeout(Some(timeout))?;
+        self.stream.read(buf)
+    }
+}
+
+fn is_timeout(error: &io::Error) -> bool {
+    matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
+/// Reads one request from `reader`.
+///
+/// Waiting for the request to start is bounded by the idle timeout of the connection. Once its
+/// first byte arrives, the whole header and body must be read within `request_timeout`.
+///
+/// `writer` is only used to send `100 Continue` when the client asks for it.
+pub fn read_request(
+    reader: &mut BufReader<Connection>,
+    writer: &mut impl Write,
+    max_body_bytes: usize,
+    request_timeout: Duration,
+) -> Result<Request, ReadError> {
+    reader.get_mut().deadline = None;
+    match reader.fill_buf() {
+        Ok([]) => return Err(ReadError::Closed),
+        Ok(_) => {}
+        Err(e) if is_timeout(&e) => return Err(ReadError::Closed),
+        Err(e) => return Err(ReadError::Io(e)),
+    }
+    reader.get_mut().deadline = Some(Instant::now() + request_timeout);
+
+    let mut head = reader.by_ref().take(MAX_HEADER_BYTES);
+    let mut line = String::new();
+
+    // Request line. Tolerate empty lines left over from a previous request (RFC 9112 section 2.2).
+    loop {
+        line.clear();
+        match head.read_line(&mut line) {
+            Ok(0) => return Err(ReadError::Closed),
+            Ok(_) if line.trim_end().is_empty() && line.ends_with('\n') => continue,
+            Ok(_) if !line.ends_with('\n') => return Err(ReadError::Status(431)),
+            Ok(_) => break,
+            Err(e) if is_timeout(&e) && line.is_empty() => return Err(ReadError::Closed),
+            Err(e) if is_timeout(&e) => return Err(ReadError::Status(408)),
+            Err(e) if e.kind() == ErrorKind::InvalidData => return Err(ReadError::Status(400)),
+            Err(e) => return Err(ReadError::Io(e)),
+        }
+    }
+
+    let mut parts = line.trim_end().split(' ');
+    let (method, target, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(target), Some(version), None) if !method.is_empty() && target.starts_with('/') => {
+            (method.to_string(), target.to_string(), version)
+        }
+        _ => return Err(ReadError::Status(400)),
+    };
+    let version = match version {
+        "HTTP/1.1" => Version::Http11,
+        "HTTP/1.0" => Version::Http10,
+        v if v.starts_with("HTTP/") => return Err(ReadError::Status(505)),
+        _ => return Err(ReadError::Status(400)),
+    };
+
+    // Header lines
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        match head.read_line(&mut line) {
+            Ok(0) => return Err(ReadError::Status(400)),
+            Ok(_) if !line.ends_with('\n') => return Err(ReadError::Status(431)),
+            Ok(_) => {}
+            Err(e) if is_timeout(&e) => return Err(ReadError::Status(408)),
+            Err(e) if e.kind() == ErrorKind::InvalidData => return Err(ReadError::Status(400)),
+            Err(e) => return Err(ReadError::Io(e)),
+        }
+
+        let trimmed = line.trim_end_matches(['\r', '\n']);
+        if trimmed.is_empty() {
+            break;
+        }
+        if headers.len() == MAX_HEADER_COUNT {
+            return Err(ReadError::Status(431));
+        }
+        match trimmed.split_once(':') {
+            Some((name, value)) if !name.is_empty() && !name.ends_with(char::is_whitespace) => {
+                headers.push((name.to_string(), value.trim().to_string()));
+            }
+            _ => return Err(ReadError::Status(400)),
+        }
+    }
+
+    let mut request = Request {
+        method,
+        target,
+        version,
+        headers,
+        body: Vec::new(),
+    };
+
+    if request.header("Transfer-Encoding").is_some() {
+        return Err(ReadError::Status(501));
+    }
+
+    // A repeated Content-Length, even with the same value, is rejected rather than merged: a proxy
+    // in front of the server may have picked a different one.
+    let mut content_lengths = request
+        .headers
+        .iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("Content-Length"))
+        .map(|(_, value)| value.as_str());
+    let content_length = match (content_lengths.next(), content_lengths.next()) {
+        (Some(_), Some(_)) => return Err(ReadError::Status(400)),
+        (Some(value), None) => match value.parse::<usize>() {
+            Ok(length) if value.bytes().all(|b| b.is_ascii_digit()) => length,
+            _ => return Err(ReadError::Status(400)),
+        },
+        (None, _) if request.method == "POST" || request.method == "PUT" => return Err(ReadError::Status(411)),
+        (None, _) => 0,
+    };
+
+    if content_length > max_body_bytes {
+        return Err(ReadError::Status(413));
+    }
+
+    if content_length > 0 {
+        let expects_continue = request
+            .header("Expect")
+            .is_some_and(|value| value.eq_ignore_ascii_case("100-continue"));
+        if expects_continue && request.version == Version::Http11 {
+            writer
+                .write_all(b"HTTP/1.1 100 Continue\r\n\r\n")
+                .map_err(ReadError::Io)?;
+        }
+
+        request.body = vec![0; content_length];
+        match reader.read_exact(&mut request.body) {
+            Ok(()) => {}
+            Err(e) if is_timeout(&e) => return Err(ReadError::Status(408)),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Err(ReadError::Closed),
+            Err(e) => return Err(ReadError::Io(e)),
+        }
+    }
+
+    Ok(request)
+}
+
+/// An HTTP response waiting to be written.
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: &str, body: Vec<u8>) -> Response {
+        Response {
+            status,
+            headers: vec![("Content-Type".to_string(), content_type.to_string())],
+            body,
+        }
+    }
+
+    pub fn json(status: u16, body: String) -> Response {
+        Response::new(status, "application/json", body.into_bytes())
+    }
+
+    /// Plain-text response whose body is the reason phrase of `status`.
+    pub fn error(status: u16) -> Response {
+        Response::new(status, "text/plain; charset=utf-8", format!("{}\n", reason_phrase(status)).into_bytes())
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Response {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Serializes the response onto `stream`.
+    ///
+    /// `keep_alive` controls the `Connection` header; the caller is responsible for
+    /// actually closing the connection when it is false.
+    pub fn write_to(&self, stream: &mut impl Write, version: Version, keep_alive: bool) -> io::Result<()> {
+        let mut head = format!("{} {} {}\r\n", version.as_str(), self.status, reason_phrase(self.status));
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        head.push_str(if keep_alive { "Connection: keep-alive\r\n" } else { "Connection: close\r\n" });
+        head.push_str("\r\n");
+
+        stream.write_all(head.as_bytes())?;
+        stream.write_all(&self.body)?;
+        stream.flush()
+    }
+}
+
+pub fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        411 => "Length Required",
+        413 => "Content Too Large",
+        415 => "Unsupported Media Type",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        505 => "HTTP Version Not Supported",
+        _ => "Unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Shutdown, TcpListener};
+
+    /// Sends `raw` over a loopback connection, and reads it back as a request.
+    fn read(raw: &[u8], request_timeout: Duration) -> Result<Request, ReadError> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+
+        client.write_all(raw).unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let mut reader = BufReader::new(Connection::new(stream, Duration::from_secs(5)));
+        let result = read_request(&mut reader, &mut writer, 16, request_timeout);
+        let _ = client.shutdown(Shutdown::Both);
+        result
+    }
+
+    fn status(result: Result<Request, ReadError>) -> Option<u16> {
+        match result {
+            Err(ReadError::Status(status)) => Some(status),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn reads_a_request_with_a_body() {
+        let raw = b"POST /license?x=1 HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello";
+        let request = read(raw, Duration::from_secs(5)).unwrap();
+
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path(), "/license");
+        assert_eq!(request.version, Version::Http11);
+        assert_eq!(request.header("content-length"), Some("5"));
+        assert_eq!(request.body, b"hello");
+        assert!(!request.keep_alive());
+    }
+
+    #[test]
+    fn rejects_a_body_over_the_limit() {
+        let raw = b"POST / HTTP/1.1\r\nContent-Length: 17\r\n\r\n";
+        assert_eq!(status(read(raw, Duration::from_secs(5))), Some(413));
+    }
+
+    #[test]
+    fn rejects_transfer_encoding() {
+        let raw = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n";
+        assert_eq!(status(read(raw, Duration::from_secs(5))), Some(501));
+    }
+
+    #[test]
+    fn rejects_bad_or_repeated_content_length() {
+        let bodies: [&[u8]; 5] = [
+            b"POST / HTTP/1.1\r\nContent-Length: five\r\n\r\nhello",
+            b"POST / HTTP/1.1\r\nContent-Length: +5\r\n\r\nhello",
+            b"POST / HTTP/1.1\r\nContent-Length: 5, 5\r\n\r\nhello",
+            b"POST / HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 5\r\n\r\nhello",
+            b"POST / HTTP/1.1\r\nContent-Length: 5\r\ncontent-length: 3\r\n\r\nhello",
+        ];
+        for raw in bodies {
+            assert_eq!(
+                status(read(raw, Duration::from_secs(5))),
+                Some(400),
+                "{}",
+                String::from_utf8_lossy(raw)
+            );
+        }
+    }
+
+    #[test]
+    fn times_out_a_request_that_never_finishes() {
+        // The client stays connected without sending the rest of the body
+        let raw = b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nhello";
+        assert_eq!(status(read(raw, Duration::from_millis(200))), Some(408));
+    }
+}
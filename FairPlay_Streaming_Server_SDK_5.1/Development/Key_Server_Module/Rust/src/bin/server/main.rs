@@ -0,0 +1,682 @@
+//
+// Copyright © 2023-2025 Apple Inc. All rights reserved.
+//
+
+//! HTTP front end for the key server module.
+//!
+//! Routes:
+//! - `POST /` and `POST /fps`: `fairplay-streaming-request` JSON in, `fairplay-streaming-response` JSON out
+//! - `POST /license`: an SPC as sent by `AVContentKeySession` and the sample clients in `Development/Client`
+//!   in, the CKC out in the format of the request (see `LicenseRequest`)
+//! - `GET /metrics`: library metrics in the Prometheus text format
+//!
+//! Usage: fpssdk_server [--bind ADDR] [--workers N] [--max-body-bytes N] [--keep-alive-timeout SECS]
+//!
+//! Each option can also be set with the `FPS_SERVER_*` variables listed in `ServerConfig::load`.
+//! The library reads its own settings from the environment: `FPS_POLICY_FILE`, `FPS_KEY_STORE_PATH`,
+//! `FPS_REPLAY_CACHE`, `FPS_ERROR_DETAILS`, `FPS_STRICT_SCHEMA`, `FPS_PARALLELISM`, `FPS_OFFLINE_LEDGER`
+//! and `FPS_CHECK_IN_CHALLENGE_KEY` among others; each is documented in the module that reads it.
+//!
+//! SIGTERM or SIGINT stops accepting new connections and waits for in-flight requests to finish.
+
+mod http;
+mod pool;
+
+#[cfg(test)]
+#[path = "../../../tests/common/mod.rs"]
+mod common;
+
+use base64::engine::general_purpose;
+use base64::Engine;
+use fpssdk::base::base_constants;
+use fpssdk::extension::extension_constants;
+use fpssdk::extension::structures::extension_structures::SDKExtension;
+use fpssdk::extension::validate::FPSStatus;
+use fpssdk::metrics::METRICS_CONTENT_TYPE;
+use http::{Connection, ReadError, Request, Response};
+use pool::ThreadPool;
+use serde_jsonrc::{json, Value};
+use std::ffi::{c_char, CString};
+use std::io::{BufReader, ErrorKind};
+use std::net::{TcpListener, TcpStream};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use std::{env, thread};
+
+/// How often the accept loop wakes up to check for a shutdown signal.
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Write timeout for responses, so a stalled client cannot pin a worker.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone)]
+struct ServerConfig {
+    bind: String,
+    workers: usize,
+    queue_depth: usize,
+    max_body_bytes: usize,
+    keep_alive_timeout: Duration,
+    request_timeout: Duration,
+    max_requests_per_connection: usize,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        let workers = thread::available_parallelism().map(|n| n.get()).unwrap_or(4);
+        ServerConfig {
+            bind: "127.0.0.1:8080".to_string(),
+            workers,
+            queue_depth: workers * 16,
+            max_body_bytes: 1024 * 1024,
+            keep_alive_timeout: Duration::from_secs(5),
+            request_timeout: Duration::from_secs(30),
+            max_requests_per_connection: 1000,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Builds the configuration from defaults, then environment variables, then command line options.
+    fn load() -> Result<ServerConfig, String> {
+        let mut config = ServerConfig::default();
+
+        let vars = [
+            ("FPS_SERVER_BIND", "--bind"),
+            ("FPS_SERVER_WORKERS", "--workers"),
+            ("FPS_SERVER_QUEUE_DEPTH", "--queue-depth"),
+            ("FPS_SERVER_MAX_BODY_BYTES", "--max-body-bytes"),
+            ("FPS_SERVER_KEEP_ALIVE_TIMEOUT", "--keep-alive-timeout"),
+            ("FPS_SERVER_REQUEST_TIMEOUT", "--request-timeout"),
+            ("FPS_SERVER_MAX_REQUESTS_PER_CONNECTION", "--max-requests-per-connection"),
+        ];
+        for (var, option) in vars {
+            if let Ok(value) = env::var(var) {
+                config.set(option, &value).map_err(|e| format!("{var}: {e}"))?;
+            }
+        }
+
+        let mut args = env::args().skip(1);
+        while let Some(option) = args.next() {
+            if option == "-h" || option == "--help" {
+                return Err(String::new());
+            }
+            let value = args.next().ok_or_else(|| format!("missing value for {option}"))?;
+            config.set(&option, &value)?;
+        }
+
+        if config.workers == 0 {
+            return Err("worker count must be at least 1".to_string());
+        }
+        if config.request_timeout.is_zero() {
+            return Err("request timeout must be at least 1 second".to_string());
+        }
+        if config.max_requests_per_connection == 0 {
+            return Err("max requests per connection must be at least 1".to_string());
+        }
+        Ok(config)
+    }
+
+    fn set(&mut self, option: &str, value: &str) -> Result<(), String> {
+        let number = || value.parse::<usize>().map_err(|_| format!("invalid value for {option}: {value}"));
+        match option {
+            "--bind" => self.bind = value.to_string(),
+            "--workers" => self.workers = number()?,
+            "--queue-depth" => self.queue_depth = number()?,
+            "--max-body-bytes" => self.max_body_bytes = number()?,
+            "--keep-alive-timeout" => self.keep_alive_timeout = Duration::from_secs(number()? as u64),
+            "--request-timeout" => self.request_timeout = Duration::from_secs(number()? as u64),
+            "--max-requests-per-connection" => self.max_requests_per_connection = number()?,
+            _ => return Err(format!("unknown option: {option}")),
+        }
+        Ok(())
+    }
+}
+
+fn usage() {
+    eprintln!(
+        "Usage: fpssdk_server [--bind ADDR] [--workers N] [--queue-depth N] [--max-body-bytes N]\n\
+         \x20                    [--keep-alive-timeout SECS] [--request-timeout SECS]\n\
+         \x20                    [--max-requests-per-connection N]"
+    );
+}
+
+fn main() -> ExitCode {
+    let config = match ServerConfig::load() {
+        Ok(config) => Arc::new(config),
+        Err(e) => {
+            if !e.is_empty() {
+                eprintln!("fpssdk_server: {e}");
+            }
+            usage();
+            return ExitCode::FAILURE;
+        }
+    };
+
+    // Load the business rules policy and replay cache up front so a broken configuration stops startup
+    if let Err(status) = SDKExtension::policy() {
+        eprintln!("fpssdk_server: unable to load policy ({status})");
+        return ExitCode::FAILURE;
+    }
+    if let Err(status) = SDKExtension::replayCache() {
+        eprintln!("fpssdk_server: unable to configure replay cache ({status})");
+        return ExitCode::FAILURE;
+    }
+
+    let listener = match TcpListener::bind(&config.bind) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("fpssdk_server: unable to bind {}: {}", config.bind, e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    // Flip `shutdown` on SIGTERM/SIGINT. The accept loop polls it between connections.
+    let shutdown = Arc::new(AtomicBool::new(false));
+    for signal in [signal_hook::consts::SIGTERM, signal_hook::consts::SIGINT] {
+        if let Err(e) = signal_hook::flag::register(signal, Arc::clone(&shutdown)) {
+            eprintln!("fpssdk_server: unable to install signal handler: {e}");
+            return ExitCode::FAILURE;
+        }
+    }
+
+    if let Err(e) = listener.set_nonblocking(true) {
+        eprintln!("fpssdk_server: unable to configure listener: {e}");
+        return ExitCode::FAILURE;
+    }
+
+    let pool = {
+        let config = Arc::clone(&config);
+        let shutdown = Arc::clone(&shutdown);
+        ThreadPool::new(config.workers, config.queue_depth, move |stream| {
+            handle_connection(stream, &config, &shutdown)
+        })
+    };
+    let pool = match pool {
+        Ok(pool) => pool,
+        Err(e) => {
+            eprintln!("fpssdk_server: unable to start workers: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    eprintln!(
+        "fpssdk_server {} listening on {} with {} workers",
+        env!("CARGO_PKG_VERSION"),
+        listener.local_addr().map(|a| a.to_string()).unwrap_or_else(|_| config.bind.clone()),
+        config.workers
+    );
+
+    while !shutdown.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, _peer)) => {
+                if let Err(stream) = pool.execute(stream) {
+                    // Every worker is busy and the queue is full: shed load
+                    reject_connection(stream, 503);
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(ACCEPT_POLL_INTERVAL),
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => {
+                // Typically EMFILE/ENFILE. Back off instead of spinning.
+                eprintln!("fpssdk_server: accept failed: {e}");
+                thread::sleep(ACCEPT_POLL_INTERVAL);
+            }
+        }
+    }
+
+    eprintln!("fpssdk_server: shutting down, waiting for in-flight requests");
+    drop(listener);
+    pool.join();
+
+    ExitCode::SUCCESS
+}
+
+/// Writes a bare error response on a connection that will not be served.
+fn reject_connection(mut stream: TcpStream, status: u16) {
+    let _ = stream.set_nonblocking(false);
+    let _ = stream.set_write_timeout(Some(WRITE_TIMEOUT));
+    let _ = Response::error(status).write_to(&mut stream, http::Version::Http11, false);
+}
+
+/// Serves requests on `stream` until the client closes it, asks for it to be closed,
+/// an error occurs, or the server is shutting down.
+fn handle_connection(stream: TcpStream, config: &ServerConfig, shutdown: &AtomicBool) {
+    // Accepted sockets may inherit non-blocking mode from the listener on some platforms
+    let configured = stream
+        .set_nonblocking(false)
+        .and_then(|_| stream.set_write_timeout(Some(WRITE_TIMEOUT)))
+        .and_then(|_| stream.set_nodelay(true));
+    if configured.is_err() {
+        return;
+    }
+
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(_) => return,
+    };
+    let mut reader = BufReader::new(Connection::new(stream, config.keep_alive_timeout));
+    let mut served = 0;
+
+    loop {
+        let request = match http::read_request(&mut reader, &mut writer, config.max_body_bytes, config.request_timeout) {
+            Ok(request) => request,
+            Err(ReadError::Closed) => return,
+            Err(ReadError::Status(status)) => {
+                log::debug!("Rejecting request with HTTP {}", status);
+                let _ = Response::error(status).write_to(&mut writer, http::Version::Http11, false);
+                return;
+            }
+            Err(ReadError::Io(e)) => {
+                log::debug!("Connection error: {}", e);
+                return;
+            }
+        };
+        served += 1;
+
+        let keep_alive = request.keep_alive()
+            && served < config.max_requests_per_connection
+            && !shutdown.load(Ordering::Relaxed);
+
+        let response = route(&request);
+        if response.write_to(&mut writer, request.version, keep_alive).is_err() || !keep_alive {
+            return;
+        }
+    }
+}
+
+fn route(request: &Request) -> Response {
+    match request.path() {
+        "/" | "/fps" => match request.method.as_str() {
+            "POST" => process_json(&request.body),
+            _ => Response::error(405).with_header("Allow", "POST"),
+        },
+        "/license" => match request.method.as_str() {
+            "POST" => process_spc(request),
+            _ => Response::error(405).with_header("Allow", "POST"),
+        },
+        "/metrics" => match request.method.as_str() {
+            "GET" => Response::new(200, METRICS_CONTENT_TYPE, SDKExtension::metrics().render().into_bytes()),
+            _ => Response::error(405).with_header("Allow", "GET"),
+        },
+        _ => Response::error(404),
+    }
+}
+
+/// Runs a `fairplay-streaming-request` body through the key server module.
+fn process_json(body: &[u8]) -> Response {
+    // fpsProcessOperations takes a C string: reject bodies that cannot be represented as one
+    let body = match CString::new(body) {
+        Ok(body) if body.to_str().is_ok() => body,
+        _ => return Response::error(400),
+    };
+
+    match process_operations(body) {
+        Ok(content) => Response::json(200, content),
+        Err(response) => response,
+    }
+}
+
+/// Calls `fpsProcessOperations` and returns the `fairplay-streaming-response` JSON.
+fn process_operations(body: CString) -> Result<String, Response> {
+    let len = body.as_bytes().len();
+
+    let mut out_body: *mut c_char = std::ptr::null_mut();
+    let mut out_body_length: usize = 0;
+
+    // Call library to generate the output JSON
+    let status = fpssdk::fpsProcessOperations(body.as_ptr(), len, &mut out_body, &mut out_body_length);
+    if out_body.is_null() {
+        return Err(Response::error(500));
+    }
+    let content = unsafe { CString::from_raw(out_body) }.into_string().unwrap_or_default();
+
+    // Per-operation failures are reported inside the JSON. A failing status here means the
+    // library itself failed (e.g. recovered from a panic), which is a server error.
+    if status != FPSStatus::noErr {
+        return Err(Response::json(500, content));
+    }
+    Ok(content)
+}
+
+/// Key of the license list in the `fairplay-streaming-request` the Safari sample client sends.
+const STREAMING_KEYS_STR: &str = "streaming-keys";
+
+/// A `POST /license` body, answered in the format its client expects.
+#[derive(Debug, PartialEq)]
+enum LicenseRequest {
+    /// Binary or base64 SPC, or a form with the base64 SPC in its `spc` field: answered with the
+    /// binary CKC
+    Spc(Vec<u8>),
+    /// `{"spc": "<base64>"}`, as sent by the TVML sample client: answered with the base64 CKC as text
+    JsonSpc(Vec<u8>),
+    /// `fairplay-streaming-request` with a `streaming-keys` list of `id` and base64 `spc`, as sent by
+    /// the Safari sample client: answered with a `fairplay-streaming-response` carrying the same list
+    /// with the base64 `ckc` of each key
+    StreamingKeys(Vec<(Value, Vec<u8>)>),
+}
+
+/// Answers a `POST /license` request (see `LicenseRequest`) with the CKC.
+///
+/// Each SPC is processed as a `create-ckc` operation without `asset-info`, so the content key of
+/// its asset ID comes from the key store or `queryDatabaseCustom`.
+///
+/// Failures use `FPSStatus::httpStatus` and carry the status code in `X-FPS-Status`. The body is
+/// the `error` object of the result when `FPS_ERROR_DETAILS` allows it.
+fn process_spc(request: &Request) -> Response {
+    let Some(license_request) = read_license_request(request) else {
+        return Response::error(400);
+    };
+
+    match license_request {
+        LicenseRequest::Spc(spc) => match create_ckcs(&[spc]) {
+            Ok(mut ckcs) => Response::new(200, "application/octet-stream", ckcs.remove(0)),
+            Err(response) => response,
+        },
+        LicenseRequest::JsonSpc(spc) => match create_ckcs(&[spc]) {
+            Ok(ckcs) => Response::new(
+                200,
+                "text/plain; charset=utf-8",
+                general_purpose::STANDARD.encode(&ckcs[0]).into_bytes(),
+            ),
+            Err(response) => response,
+        },
+        LicenseRequest::StreamingKeys(keys) => {
+            let (ids, spcs): (Vec<Value>, Vec<Vec<u8>>) = keys.into_iter().unzip();
+            match create_ckcs(&spcs) {
+                Ok(ckcs) => {
+                    let keys: Vec<Value> = ids
+                        .into_iter()
+                        .zip(ckcs)
+                        .map(|(id, ckc)| {
+                            json!({
+                                base_constants::ID_STR: id,
+                                base_constants::CKC_STR: general_purpose::STANDARD.encode(ckc),
+                            })
+                        })
+                        .collect();
+                    let output = json!({
+                        extension_constants::FAIRPLAY_STREAMING_RESPONSE_STR: { STREAMING_KEYS_STR: keys }
+                    });
+                    Response::json(200, output.to_string())
+                }
+                Err(response) => response,
+            }
+        }
+    }
+}
+
+/// Processes `spcs` as the operations of one request, and returns their CKCs in the same order.
+///
+/// The first operation that fails gives the error response.
+fn create_ckcs(spcs: &[Vec<u8>]) -> Result<Vec<Vec<u8>>, Response> {
+    let operations: Vec<Value> = spcs
+        .iter()
+        .enumerate()
+        .map(|(index, spc)| {
+            json!({
+                base_constants::ID_STR: index + 1,
+                base_constants::SPC_STR: general_purpose::STANDARD.encode(spc),
+            })
+        })
+        .collect();
+    let envelope = json!({
+        extension_constants::FAIRPLAY_STREAMING_REQUEST_STR: { base_constants::CREATE_CKC_STR: operations }
+    });
+    let content = process_operations(CString::new(envelope.to_string()).unwrap_or_default())?;
+
+    let output: Value = content.parse().unwrap_or_default();
+    let results = output[extension_constants::FAIRPLAY_STREAMING_RESPONSE_STR][base_constants::CREATE_CKC_STR]
+        .as_array()
+        .cloned()
+        .unwrap_or_default();
+    if results.len() != spcs.len() {
+        // A request-level failure has a single result
+        return Err(error_response(results.first().unwrap_or(&Value::Null)));
+    }
+
+    results
+        .iter()
+        .map(|result| {
+            let ckc = result[base_constants::CKC_STR]
+                .as_str()
+                .and_then(|ckc| general_purpose::STANDARD.decode(ckc).ok());
+            match (result_status(result), ckc) {
+                (FPSStatus::noErr, Some(ckc)) => Ok(ckc),
+                (FPSStatus::noErr, None) => Err(Response::error(500)),
+                _ => Err(error_response(result)),
+            }
+        })
+        .collect()
+}
+
+fn result_code(result: &Value) -> i64 {
+    result[base_constants::STATUS_STR].as_i64().unwrap_or(FPSStatus::internalErr as i64)
+}
+
+fn result_status(result: &Value) -> FPSStatus {
+    // Codes of the precompiled library are not FPSStatus values: those are server errors
+    FPSStatus::try_from(result_code(result)).unwrap_or(FPSStatus::internalErr)
+}
+
+/// Error response for a failed `create-ckc` result.
+fn error_response(result: &Value) -> Response {
+    let http_status = result_status(result).httpStatus();
+    let response = match result.get(base_constants::ERROR_STR) {
+        Some(error) => Response::json(http_status, json!({ base_constants::ERROR_STR: error }).to_string()),
+        None => Response::error(http_status),
+    };
+    response.with_header("X-FPS-Status", &result_code(result).to_string())
+}
+
+/// Reads the body of a `POST /license` request. JSON bodies are recognized whatever their
+/// `Content-Type`, since the Safari sample client sends its JSON as a form.
+fn read_license_request(request: &Request) -> Option<LicenseRequest> {
+    let is_binary = media_type(request) == "application/octet-stream";
+    let json = match std::str::from_utf8(&request.body) {
+        Ok(text) if !is_binary && text.trim_start().starts_with('{') => text.parse::<Value>().ok()?,
+        _ => return read_spc(request).map(LicenseRequest::Spc),
+    };
+
+    let decode = |spc: &Value| general_purpose::STANDARD.decode(spc.as_str()?.trim()).ok();
+    if let Some(keys) = json[extension_constants::FAIRPLAY_STREAMING_REQUEST_STR][STREAMING_KEYS_STR].as_array() {
+        let keys = keys
+            .iter()
+            .map(|key| Some((key[base_constants::ID_STR].clone(), decode(&key[base_constants::SPC_STR])?)))
+            .collect::<Option<Vec<_>>>()?;
+        return (!keys.is_empty()).then_some(LicenseRequest::StreamingKeys(keys));
+    }
+    decode(&json[base_constants::SPC_STR]).map(LicenseRequest::JsonSpc)
+}
+
+fn media_type(request: &Request) -> String {
+    let content_type = request.header("Content-Type").unwrap_or("");
+    content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase()
+}
+
+/// Returns the SPC in a binary, base64 or form `POST /license` body (see `LicenseRequest::Spc`).
+fn read_spc(request: &Request) -> Option<Vec<u8>> {
+    let base64_spc = match media_type(request).as_str() {
+        "application/octet-stream" => return Some(request.body.clone()),
+        "application/x-www-form-urlencoded" => {
+            let form = std::str::from_utf8(&request.body).ok()?;
+            let value = form.split('&').find_map(|pair| pair.strip_prefix("spc="))?;
+            percent_decode(value)?
+        }
+        // Anything else is base64 text, unless it is not (clients that do not set a type)
+        _ => match std::str::from_utf8(&request.body) {
+            Ok(text) => text.to_string(),
+            Err(_) => return Some(request.body.clone()),
+        },
+    };
+
+    // Base64 '+' often arrives as a space when the client did not encode the form value
+    let base64_spc: String = base64_spc
+        .trim_end_matches(['\r', '\n'])
+        .chars()
+        .filter(|c| !matches!(c, '\r' | '\n' | '\t'))
+        .map(|c| if c == ' ' { '+' } else { c })
+        .collect();
+    general_purpose::STANDARD.decode(base64_spc).ok()
+}
+
+/// Decodes a form value (`%XX` escapes and `+` for space).
+fn percent_decode(value: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut iter = value.bytes();
+    while let Some(byte) = iter.next() {
+        match byte {
+            b'%' => {
+                let hex = [iter.next()?, iter.next()?];
+                bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+            }
+            b'+' => bytes.push(b' '),
+            _ => bytes.push(byte),
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn post(content_type: Option<&str>, body: impl Into<Vec<u8>>) -> Request {
+        Request {
+            method: "POST".to_string(),
+            target: "/license".to_string(),
+            version: http::Version::Http11,
+            headers: content_type
+                .map(|content_type| vec![("Content-Type".to_string(), content_type.to_string())])
+                .unwrap_or_default(),
+            body: body.into(),
+        }
+    }
+
+    fn header<'a>(response: &'a Response, name: &str) -> Option<&'a str> {
+        response
+            .headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    #[test]
+    fn percent_decode_handles_escapes_and_plus() {
+        assert_eq!(percent_decode("a%2Bb+c%3d").as_deref(), Some("a+b c="));
+        assert_eq!(percent_decode("plain").as_deref(), Some("plain"));
+        assert_eq!(percent_decode("%2"), None);
+        assert_eq!(percent_decode("%zz"), None);
+        assert_eq!(percent_decode("%FF"), None);
+    }
+
+    #[test]
+    fn read_spc_accepts_binary_base64_and_form_bodies() {
+        let spc = vec![0xFB, 0xEF, 0x00, 0x01];
+        let base64 = general_purpose::STANDARD.encode(&spc);
+        assert_eq!(base64, "++8AAQ==");
+
+        assert_eq!(read_spc(&post(Some("application/octet-stream"), spc.clone())), Some(spc.clone()));
+        assert_eq!(read_spc(&post(Some("text/plain"), format!("{base64}\r\n"))), Some(spc.clone()));
+        assert_eq!(read_spc(&post(None, base64.clone())), Some(spc.clone()));
+        // Clients that do not set a type and send binary
+        assert_eq!(read_spc(&post(None, vec![0, 0, 0, 2, 0xFF])), Some(vec![0, 0, 0, 2, 0xFF]));
+
+        let form = "application/x-www-form-urlencoded; charset=UTF-8";
+        assert_eq!(read_spc(&post(Some(form), "id=1&spc=%2B%2B8AAQ%3D%3D")), Some(spc.clone()));
+        // An unescaped '+' decodes to a space, which is read back as '+'
+        assert_eq!(read_spc(&post(Some(form), format!("spc={base64}"))), Some(spc.clone()));
+        assert_eq!(read_spc(&post(Some(form), "id=1")), None);
+        assert_eq!(read_spc(&post(Some("text/plain"), "not base64!")), None);
+    }
+
+    #[test]
+    fn read_license_request_recognizes_the_sample_client_json() {
+        let spc = vec![0, 0, 0, 1, 0xAB];
+        let base64 = general_purpose::STANDARD.encode(&spc);
+
+        // TVML sample
+        let body = json!({ "spc": base64 }).to_string();
+        assert_eq!(
+            read_license_request(&post(Some("application/json"), body)),
+            Some(LicenseRequest::JsonSpc(spc.clone()))
+        );
+
+        // Safari sample, which labels its JSON as a form
+        let body = json!({ "fairplay-streaming-request": { "version": 1, "streaming-keys": [
+            { "id": 1, "uri": "skd://movie", "spc": base64 }
+        ]}})
+        .to_string();
+        assert_eq!(
+            read_license_request(&post(Some("application/x-www-form-urlencoded"), body)),
+            Some(LicenseRequest::StreamingKeys(vec![(json!(1), spc.clone())]))
+        );
+
+        assert_eq!(read_license_request(&post(Some("application/json"), "{\"spc\": 5}")), None);
+        assert_eq!(read_license_request(&post(Some("application/json"), "{\"spc\"")), None);
+        let empty = json!({ "fairplay-streaming-request": { "streaming-keys": [] } }).to_string();
+        assert_eq!(read_license_request(&post(None, empty)), None);
+        assert_eq!(
+            read_license_request(&post(Some("application/octet-stream"), spc.clone())),
+            Some(LicenseRequest::Spc(spc))
+        );
+    }
+
+    #[test]
+    fn process_spc_maps_failures_to_http_statuses() {
+        let response = process_spc(&post(Some("text/plain"), "not base64!"));
+        assert_eq!(response.status, 400);
+        assert_eq!(header(&response, "X-FPS-Status"), None);
+
+        // Unsupported SPC version
+        let response = process_spc(&post(Some("application/octet-stream"), vec![0, 0, 0, 9, 0, 0, 0, 0]));
+        assert_eq!(response.status, FPSStatus::spcVersionErr.httpStatus());
+        assert_eq!(
+            header(&response, "X-FPS-Status"),
+            Some((FPSStatus::spcVersionErr as i32).to_string().as_str())
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "mock_key_payload")]
+    fn process_spc_answers_each_client_in_its_format() {
+        use crate::common::{buildSPC, requiredTLLVs, serverKey};
+        use fpssdk::extension::key_payload::MockKeyPayloadBackend;
+        use fpssdk::extension::key_store::KeyStore;
+
+        serverKey();
+        SDKExtension::setKeyPayloadBackend(Arc::new(MockKeyPayloadBackend));
+        let key_store = KeyStore::openInMemory().unwrap();
+        let keys = json!([{
+            "asset-id": "movie",
+            "content-key": "3C3C3C3C3C3C3C3C3C3C3C3C3C3C3C3C",
+            "content-iv": "D5D5D5D5D5D5D5D5D5D5D5D5D5D5D5D5"
+        }]);
+        assert_eq!(key_store.importJson(&keys.to_string()), Ok(1));
+        SDKExtension::setKeyStore(Arc::new(key_store));
+        let spc = buildSPC(serverKey(), &requiredTLLVs(b"movie"));
+        let base64 = general_purpose::STANDARD.encode(&spc);
+
+        let response = process_spc(&post(Some("application/octet-stream"), spc.clone()));
+        assert_eq!(response.status, 200);
+        assert_eq!(header(&response, "Content-Type"), Some("application/octet-stream"));
+        assert!(!response.body.is_empty());
+
+        let response = process_spc(&post(Some("application/json"), json!({ "spc": base64 }).to_string()));
+        assert_eq!(response.status, 200);
+        let ckc = general_purpose::STANDARD.decode(&response.body).unwrap();
+        assert!(!ckc.is_empty());
+
+        let body = json!({ "fairplay-streaming-request": { "version": 1, "streaming-keys": [
+            { "id": 7, "uri": "skd://movie", "spc": base64 }
+        ]}})
+        .to_string();
+        let response = process_spc(&post(Some("application/x-www-form-urlencoded"), body));
+        assert_eq!(response.status, 200);
+        let output: Value = std::str::from_utf8(&response.body).unwrap().parse().unwrap();
+        let key = &output["fairplay-streaming-response"]["streaming-keys"][0];
+        assert_eq!(key["id"], 7);
+        assert!(general_purpose::STANDARD.decode(key["ckc"].as_str().unwrap()).is_ok());
+    }
+}
@@ -0,0 +1,81 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+//! Fixed-size worker pool with a bounded hand-off queue.
+
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+pub struct ThreadPool<T: Send + 'static> {
+    sender: Option<SyncSender<T>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl<T: Send + 'static> ThreadPool<T> {
+    /// Spawns `size` workers that each run `handler` on queued jobs.
+    ///
+    /// At most `queue_depth` jobs wait for a free worker; see `execute`.
+    pub fn new<F>(size: usize, queue_depth: usize, handler: F) -> io::Result<ThreadPool<T>>
+    where
+        F: Fn(T) + Send + Sync + 'static,
+    {
+        let (sender, receiver) = sync_channel::<T>(queue_depth);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let handler = Arc::new(handler);
+
+        let mut workers = Vec::with_capacity(size);
+        for index in 0..size {
+            let receiver = Arc::clone(&receiver);
+            let handler = Arc::clone(&handler);
+            let worker = thread::Builder::new()
+                .name(format!("fpssdk-worker-{index}"))
+                .spawn(move || worker_loop(&receiver, handler.as_ref()))?;
+            workers.push(worker);
+        }
+
+        Ok(ThreadPool {
+            sender: Some(sender),
+            workers,
+        })
+    }
+
+    /// Queues `job` for the next free worker.
+    ///
+    /// Hands the job back if every worker is busy and the queue is full so the
+    /// caller can shed load instead of blocking the accept loop.
+    pub fn execute(&self, job: T) -> Result<(), T> {
+        match self.sender.as_ref() {
+            Some(sender) => sender.try_send(job).map_err(|e| match e {
+                TrySendError::Full(job) | TrySendError::Disconnected(job) => job,
+            }),
+            None => Err(job),
+        }
+    }
+
+    /// Stops accepting jobs and waits for the workers to drain the queue.
+    pub fn join(mut self) {
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn worker_loop<T, F: Fn(T)>(receiver: &Mutex<Receiver<T>>, handler: &F) {
+    loop {
+        // Hold the lock only while waiting for the next job
+        let job = match receiver.lock().unwrap_or_else(|e| e.into_inner()).recv() {
+            Ok(job) => job,
+            Err(_) => return, // Sender dropped: pool is shutting down
+        };
+
+        // A panic while handling one job must not take the worker down with it
+        if panic::catch_unwind(AssertUnwindSafe(|| handler(job))).is_err() {
+            eprintln!("fpssdk_server: worker {:?} recovered from panic", thread::current().name());
+        }
+    }
+}
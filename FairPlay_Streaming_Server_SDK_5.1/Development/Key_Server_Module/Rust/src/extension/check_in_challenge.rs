@@ -0,0 +1,306 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+//! Server challenges for offline license check-ins.
+//!
+//! Before a check-in, the app asks its server for a challenge for the device (the HU of an
+//! earlier license response) and title, and passes it to the client, which returns it in the
+//! SyncTLLV of the check-in SPC. A challenge is 64 bits:
+//!
+//! - issue time, Unix seconds (32 bits)
+//! - random nonce (8 bits)
+//! - HMAC-SHA256 over the above, the HU and the title ID, truncated to 24 bits
+//!
+//! A check-in is verified (`check-in-verified` in the response) only if its challenge was minted
+//! with the same key for that HU and title, less than the challenge lifetime ago. Only verified
+//! check-ins return licenses to the offline ledger (see `extension::offline_ledger`), which also
+//! accepts each challenge once.
+//!
+//! The 24-bit tag bounds forgery: each guessed challenge verifies with probability 2^-24 (1 in
+//! about 16.7 million). To keep guessing from adding up, a device whose check-ins carry
+//! `MAX_FORGED_CHALLENGES` forged challenges within `FORGED_CHALLENGE_WINDOW_SECS` is logged and
+//! its check-ins are not verified (`throttled`) until the window ends, which limits an attacker
+//! to 1 in about 2.1 million per device and hour. The 8-bit nonce tells apart challenges minted
+//! for the same HU and title in the same second. Above 256 of those, two are always equal and
+//! only the first check-in using them is accepted; collisions become likely from about 20.
+//!
+//! - `FPS_CHECK_IN_CHALLENGE_KEY`: HMAC key, at least 16 bytes in hex. Without it a random key is
+//!   used, so challenges only verify in the process that minted them.
+//! - `FPS_CHECK_IN_CHALLENGE_LIFETIME_SECS`: how long a challenge is accepted (default 600)
+
+use crate::base::base_constants::FPS_MAX_TITLE_ID_LENGTH;
+use crate::base::structures::base_fps_structures::FPSResult;
+use crate::base::structures::secret::SecretBytes;
+use crate::extension::structures::extension_structures::SDKExtension;
+use crate::validate::{FPSStatus, Result};
+use crate::{fpsLogError, returnErrorStatus};
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use rand::Rng;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+pub const CHECK_IN_CHALLENGE_KEY_ENV: &str = "FPS_CHECK_IN_CHALLENGE_KEY";
+pub const CHECK_IN_CHALLENGE_LIFETIME_ENV: &str = "FPS_CHECK_IN_CHALLENGE_LIFETIME_SECS";
+
+pub const DEFAULT_CHECK_IN_CHALLENGE_LIFETIME: Duration = Duration::from_secs(600);
+
+/// Challenges issued by servers whose clocks are slightly ahead are still accepted.
+const CLOCK_SKEW_SECS: i64 = 60;
+
+const MIN_KEY_LENGTH: usize = 16;
+const TAG_BITS: u32 = 24;
+
+/// Forged challenges a device may send within `FORGED_CHALLENGE_WINDOW_SECS` before it is throttled.
+pub const MAX_FORGED_CHALLENGES: u32 = 8;
+pub const FORGED_CHALLENGE_WINDOW_SECS: i64 = 3600;
+/// Devices tracked at most; devices outside their window are dropped first.
+const MAX_TRACKED_DEVICES: usize = 100_000;
+
+/// Outcome of verifying the server challenge of a check-in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChallengeVerification {
+    valid,
+    /// The SyncTLLV carries no challenge (zero)
+    missing,
+    /// Not minted with this key for the HU and title of the check-in
+    forged,
+    /// Minted longer than the challenge lifetime ago
+    expired,
+    /// Not checked, the device sent too many forged challenges recently
+    throttled,
+}
+
+/// Forged challenges sent by one device since `windowStart`.
+#[derive(Debug, Clone, Copy)]
+struct ForgedChallenges {
+    windowStart: i64,
+    count: u32,
+}
+
+/// Mints and verifies check-in challenges with one HMAC key.
+pub struct CheckInChallenges {
+    key: SecretBytes,
+    lifetime: Duration,
+    /// Keyed by HU
+    forgedChallenges: Mutex<HashMap<Vec<u8>, ForgedChallenges>>,
+}
+
+impl CheckInChallenges {
+    pub fn new(key: impl Into<SecretBytes>, lifetime: Duration) -> Result<CheckInChallenges> {
+        let key = key.into();
+        if key.len() < MIN_KEY_LENGTH {
+            fpsLogError!(
+                FPSStatus::paramErr,
+                "check-in challenge key must be at least {} bytes (got {})",
+                MIN_KEY_LENGTH,
+                key.len()
+            );
+            returnErrorStatus!(FPSStatus::paramErr);
+        }
+        Ok(CheckInChallenges {
+            key,
+            lifetime,
+            forgedChallenges: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Uses `FPS_CHECK_IN_CHALLENGE_KEY` and `FPS_CHECK_IN_CHALLENGE_LIFETIME_SECS`, or a random key.
+    pub fn fromEnvironment() -> Result<CheckInChallenges> {
+        let lifetime = match std::env::var(CHECK_IN_CHALLENGE_LIFETIME_ENV) {
+            Ok(value) => match value.parse() {
+                Ok(seconds) => Duration::from_secs(seconds),
+                Err(_) => {
+                    fpsLogError!(
+                        FPSStatus::paramErr,
+                        "invalid {}: {}",
+                        CHECK_IN_CHALLENGE_LIFETIME_ENV,
+                        value
+                    );
+                    returnErrorStatus!(FPSStatus::paramErr);
+                }
+            },
+            Err(_) => DEFAULT_CHECK_IN_CHALLENGE_LIFETIME,
+        };
+
+        let key = match std::env::var(CHECK_IN_CHALLENGE_KEY_ENV) {
+            Ok(value) => match hex::decode(value.trim()) {
+                Ok(key) => SecretBytes::from(key),
+                Err(e) => {
+                    fpsLogError!(FPSStatus::paramErr, "invalid {}: {}", CHECK_IN_CHALLENGE_KEY_ENV, e);
+                    returnErrorStatus!(FPSStatus::paramErr);
+                }
+            },
+            Err(_) => {
+                log::warn!(
+                    "{} is not set, check-in challenges only verify in this process",
+                    CHECK_IN_CHALLENGE_KEY_ENV
+                );
+                let mut key = SecretBytes::zeroed(32);
+                rand::thread_rng().fill(&mut key[..]);
+                key
+            }
+        };
+
+        CheckInChallenges::new(key, lifetime)
+    }
+
+    fn tag(&self, issuedAt: u32, nonce: u8, hu: &[u8], titleId: &[u8]) -> Result<u32> {
+        let mut title = [0; FPS_MAX_TITLE_ID_LENGTH];
+        let length = titleId.len().min(FPS_MAX_TITLE_ID_LENGTH);
+        title[..length].copy_from_slice(&titleId[..length]);
+
+        let mac = PKey::hmac(&self.key)
+            .and_then(|key| {
+                let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+                signer.update(&issuedAt.to_be_bytes())?;
+                signer.update(&[nonce])?;
+                signer.update(hu)?;
+                signer.update(&title)?;
+                signer.sign_to_vec()
+            })
+            .map_err(|e| {
+                fpsLogError!(FPSStatus::internalErr, "unable to compute check-in challenge: {}", e);
+                FPSStatus::internalErr
+            })?;
+
+        Ok(u32::from_be_bytes([0, mac[0], mac[1], mac[2]]))
+    }
+
+    /// Mints a challenge for the device `hu` and `titleId` (zero-padded to 16 bytes), at Unix time `now`.
+    pub fn mint(&self, hu: &[u8], titleId: &[u8], now: i64) -> Result<u64> {
+        let issuedAt = now as u32;
+        let nonce = rand::thread_rng().gen::<u8>();
+        let tag = self.tag(issuedAt, nonce, hu, titleId)?;
+
+        Ok((u64::from(issuedAt) << 32) | (u64::from(nonce) << TAG_BITS) | u64::from(tag))
+    }
+
+    /// Checks that `challenge` was minted by `mint` for `hu` and `titleId`, and is still fresh at `now`.
+    pub fn verify(&self, challenge: u64, hu: &[u8], titleId: &[u8], now: i64) -> Result<ChallengeVerification> {
+        if challenge == 0 {
+            return Ok(ChallengeVerification::missing);
+        }
+
+        let issuedAt = (challenge >> 32) as u32;
+        let nonce = (challenge >> TAG_BITS) as u8;
+        let tag = (challenge as u32) & ((1 << TAG_BITS) - 1);
+        let expected = self.tag(issuedAt, nonce, hu, titleId)?;
+        if !openssl::memcmp::eq(&tag.to_be_bytes(), &expected.to_be_bytes()) {
+            return Ok(ChallengeVerification::forged);
+        }
+
+        let age = now - i64::from(issuedAt);
+        if age < -CLOCK_SKEW_SECS {
+            return Ok(ChallengeVerification::forged);
+        }
+        if age > self.lifetime.as_secs() as i64 {
+            return Ok(ChallengeVerification::expired);
+        }
+
+        Ok(ChallengeVerification::valid)
+    }
+
+    /// Verifies the challenge of a check-in like `verify`, throttling devices that send forged ones.
+    pub fn verifyCheckIn(&self, challenge: u64, hu: &[u8], titleId: &[u8], now: i64) -> Result<ChallengeVerification> {
+        let mut forgedChallenges = self.forgedChallenges.lock().unwrap_or_else(|e| e.into_inner());
+        let inWindow = |forged: &ForgedChallenges| now - forged.windowStart < FORGED_CHALLENGE_WINDOW_SECS;
+
+        let previous = forgedChallenges.get(hu).copied().filter(inWindow);
+        if previous.is_some_and(|forged| forged.count >= MAX_FORGED_CHALLENGES) {
+            return Ok(ChallengeVerification::throttled);
+        }
+
+        let verification = self.verify(challenge, hu, titleId, now)?;
+        if verification != ChallengeVerification::forged {
+            return Ok(verification);
+        }
+
+        let forged = ForgedChallenges {
+            windowStart: previous.map_or(now, |forged| forged.windowStart),
+            count: previous.map_or(0, |forged| forged.count) + 1,
+        };
+        if forged.count > 1 {
+            log::warn!(
+                "{} forged check-in challenges from HU {} in {}s",
+                forged.count,
+                hex::encode(hu),
+                now - forged.windowStart
+            );
+        }
+        if forged.count == MAX_FORGED_CHALLENGES {
+            log::warn!(
+                "Check-ins from HU {} are not verified until {}",
+                hex::encode(hu),
+                forged.windowStart + FORGED_CHALLENGE_WINDOW_SECS
+            );
+        }
+
+        if forgedChallenges.len() >= MAX_TRACKED_DEVICES && !forgedChallenges.contains_key(hu) {
+            forgedChallenges.retain(|_, forged| inWindow(forged));
+        }
+        if forgedChallenges.len() < MAX_TRACKED_DEVICES || forgedChallenges.contains_key(hu) {
+            forgedChallenges.insert(hu.to_vec(), forged);
+        }
+
+        Ok(verification)
+    }
+}
+
+/// Challenges installed with `SDKExtension::setCheckInChallenges`, or configured from the environment once first used.
+static CHECK_IN_CHALLENGES: RwLock<Option<Arc<CheckInChallenges>>> = RwLock::new(None);
+
+impl SDKExtension {
+    /// Mints and verifies check-in challenges with `checkInChallenges` from now on.
+    pub fn setCheckInChallenges(checkInChallenges: Arc<CheckInChallenges>) {
+        *CHECK_IN_CHALLENGES.write().unwrap_or_else(|e| e.into_inner()) = Some(checkInChallenges);
+    }
+
+    /// Returns the installed challenges, configuring them from `FPS_CHECK_IN_CHALLENGE_KEY` on first use.
+    pub fn checkInChallenges() -> Result<Arc<CheckInChallenges>> {
+        if let Some(checkInChallenges) = CHECK_IN_CHALLENGES.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+            return Ok(checkInChallenges.clone());
+        }
+
+        let mut installed = CHECK_IN_CHALLENGES.write().unwrap_or_else(|e| e.into_inner());
+        if let Some(checkInChallenges) = installed.as_ref() {
+            return Ok(checkInChallenges.clone());
+        }
+
+        let checkInChallenges = Arc::new(CheckInChallenges::fromEnvironment()?);
+        *installed = Some(checkInChallenges.clone());
+
+        Ok(checkInChallenges)
+    }
+
+    /// Mints a challenge for the app to pass to the client before it checks in `titleId`.
+    pub fn mintCheckInChallenge(hu: &[u8], titleId: &[u8]) -> Result<u64> {
+        SDKExtension::checkInChallenges()?.mint(hu, titleId, chrono::Utc::now().timestamp())
+    }
+
+    /// Sets `checkInVerified` on the result of a check-in.
+    pub fn verifyCheckIn(fpsResult: &mut FPSResult) -> Result<()> {
+        if !fpsResult.isCheckIn {
+            return Ok(());
+        }
+
+        let verification = SDKExtension::checkInChallenges()?.verifyCheckIn(
+            fpsResult.syncServerChallenge,
+            &fpsResult.hu,
+            &fpsResult.syncTitleId,
+            chrono::Utc::now().timestamp(),
+        )?;
+        if verification != ChallengeVerification::valid {
+            log::warn!(
+                "Check-in server challenge is {:?}, the check-in is not trusted",
+                verification
+            );
+        }
+        fpsResult.extension.checkInVerified = verification == ChallengeVerification::valid;
+
+        Ok(())
+    }
+}
@@ -2,99 +2,140 @@
 // Copyright © 2023-2025 Apple Inc. All rights reserved.
 //
 
-use crate::base::base_constants::{
-    KSMKeyPayloadContentType, AES128_IV_SZ, AES128_KEY_SZ, FPS_KEY_PAYLOAD_STRUCT_VERSION, FPS_V1_HASH_SZ, FPS_V1_R1_SZ,
-};
-use crate::base::structures::base_fps_structures::KSMKeyPayload;
+use crate::base::base_constants::{KSMKeyPayloadContentType, AES128_KEY_SZ};
 use crate::base::structures::base_server_structures::FPSServerCtx;
 use crate::extension::extension_constants::{ContentType, FPSKeyFormatTag};
+use crate::extension::key_payload::KeyPayloadRequest;
+use crate::metrics::Stage;
 use crate::validate::Result;
-use crate::{fpsLogError, returnErrorStatus, FPSStatus, SDKExtension};
+use crate::{returnErrorStatus, FPSStatus, SDKExtension};
 
-pub const FPS_CONTENT_KEY_TLLV_MAX_PAYLOAD: u32 = 1024;
+#[cfg(not(feature = "mock_key_payload"))]
+pub use self::fpscrypto::{FpsCryptoBackend, KSMCreateKeyPayload, FPS_CONTENT_KEY_TLLV_MAX_PAYLOAD};
 
-extern "C" {
-    pub fn KSMCreateKeyPayload(keyPayload: &mut KSMKeyPayload) -> FPSStatus;
-}
-
-impl SDKExtension {
-    /// Calls cryptographic library to generate content key payload
-    pub fn createContentKeyPayloadCustomImpl(serverCtx: &mut FPSServerCtx, _keyTypeRequested: u32) -> Result<()> {
-        let mut provData: Vec<u8> = Default::default();
-        let mut provDataLength: usize = 0;
+#[cfg(not(feature = "mock_key_payload"))]
+mod fpscrypto {
+    use crate::base::base_constants::{
+        AES128_IV_SZ, AES128_KEY_SZ, FPS_KEY_PAYLOAD_STRUCT_VERSION, FPS_V1_HASH_SZ, FPS_V1_HU_SZ, FPS_V1_R1_SZ,
+    };
+    use crate::base::structures::base_fps_structures::KSMKeyPayload;
+    use crate::base::structures::secret::SecretBytes;
+    use crate::extension::key_payload::{KeyPayload, KeyPayloadBackend, KeyPayloadRequest};
+    use crate::validate::Result;
+    use crate::{fpsLogError, returnErrorStatus, FPSStatus};
 
-        // Get provisioning data
-        SDKExtension::getProvisioningData(&mut provData, &mut provDataLength)?;
+    pub const FPS_CONTENT_KEY_TLLV_MAX_PAYLOAD: u32 = 1024;
 
-        // Older devices may not send this information so default to 16 byte key
-        if serverCtx.spcContainer.spcData.numberOfSupportedKeyFormats == 0 {
-            serverCtx.spcContainer.spcData.numberOfSupportedKeyFormats = 1;
-            serverCtx.spcContainer.spcData.supportedKeyFormats[0] = FPSKeyFormatTag::buf16Byte as u64;
-        }
-
-        // Convert from our custom ContentType to KSMKeyPayloadContentType
-        let ksmKeyPayloadContentType: KSMKeyPayloadContentType = match serverCtx.extension.contentType {
-            ContentType::uhd | ContentType::hd | ContentType::sd => KSMKeyPayloadContentType::video,
-            ContentType::audio => KSMKeyPayloadContentType::audio,
-            _ => KSMKeyPayloadContentType::unknown
-        };
+    extern "C" {
+        pub fn KSMCreateKeyPayload(keyPayload: &mut KSMKeyPayload) -> FPSStatus;
+    }
 
-        let mut r1 = vec![0_u8; FPS_V1_R1_SZ];
+    /// Creates content key payloads with the precompiled `libfpscrypto` library.
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct FpsCryptoBackend;
 
-        serverCtx.ckcContainer.ckcData.contentKeyTLLVPayload = vec![0_u8; FPS_CONTENT_KEY_TLLV_MAX_PAYLOAD as usize];
+    impl KeyPayloadBackend for FpsCryptoBackend {
+        fn createKeyPayload(&self, request: &KeyPayloadRequest) -> Result<KeyPayload> {
+            let mut hu = vec![0_u8; FPS_V1_HU_SZ];
+            let mut r1 = SecretBytes::zeroed(FPS_V1_R1_SZ);
+            let mut contentKeyTLLVPayload = SecretBytes::zeroed(FPS_CONTENT_KEY_TLLV_MAX_PAYLOAD as usize);
 
-        unsafe {
             let mut keyPayload = KSMKeyPayload {
                 version: FPS_KEY_PAYLOAD_STRUCT_VERSION,
-                contentKey: serverCtx.ckcContainer.ckcData.ck.as_ptr(),
+                contentKey: request.contentKey.as_ptr(),
                 contentKeyLength: AES128_KEY_SZ as u64,
-                contentIV: serverCtx.ckcContainer.ckcData.iv.as_ptr(),
+                contentIV: request.contentIV.as_ptr(),
                 contentIVLength: AES128_IV_SZ as u64,
-                contentType: ksmKeyPayloadContentType as u64,
-                SK_R1: serverCtx.spcContainer.spcData.skR1.as_ptr(),
-                SK_R1Length: serverCtx.spcContainer.spcData.skR1.len() as u64,
-                R2: serverCtx.spcContainer.spcData.r2.as_ptr(),
-                R2Length: serverCtx.spcContainer.spcData.r2.len() as u64,
-                R1Integrity: serverCtx.spcContainer.spcData.skR1IntegrityTag.as_ptr(),
-                R1IntegrityLength: serverCtx.spcContainer.spcData.skR1IntegrityTag.len() as u64,
-                supportedKeyFormats: serverCtx.spcContainer.spcData.supportedKeyFormats.as_ptr(),
-                numberOfSupportedKeyFormats: serverCtx.spcContainer.spcData.numberOfSupportedKeyFormats as u64,
-                cryptoVersionUsed: serverCtx.spcContainer.spcData.versionUsed as u64,
-                provisioningData: provData.as_ptr(),
-                provisioningDataLength: provDataLength as u64,
-                certHash: serverCtx.spcContainer.certificateHash.as_ptr(),
+                contentType: request.contentType as u64,
+                SK_R1: request.skR1.as_ptr(),
+                SK_R1Length: request.skR1.len() as u64,
+                R2: request.r2.as_ptr(),
+                R2Length: request.r2.len() as u64,
+                R1Integrity: request.r1Integrity.as_ptr(),
+                R1IntegrityLength: request.r1Integrity.len() as u64,
+                supportedKeyFormats: request.supportedKeyFormats.as_ptr(),
+                numberOfSupportedKeyFormats: request.supportedKeyFormats.len() as u64,
+                cryptoVersionUsed: request.cryptoVersionUsed as u64,
+                provisioningData: request.provisioningData.as_ptr(),
+                provisioningDataLength: request.provisioningData.len() as u64,
+                certHash: request.certHash.as_ptr(),
                 certHashLength: FPS_V1_HASH_SZ as u64,
-                clientHU: serverCtx.spcContainer.spcData.hu.as_mut_ptr(),
-                clientHULength: serverCtx.spcContainer.spcData.hu.len() as u64,
-                contentKeyTLLVTag: serverCtx.ckcContainer.ckcData.contentKeyTLLVTag,
-                contentKeyTLLVPayload: serverCtx.ckcContainer.ckcData.contentKeyTLLVPayload.as_mut_ptr(),
-                contentKeyTLLVPayloadLength: serverCtx.ckcContainer.ckcData.contentKeyTLLVPayload.len() as u64,
+                clientHU: hu.as_mut_ptr(),
+                clientHULength: hu.len() as u64,
+                contentKeyTLLVTag: 0,
+                contentKeyTLLVPayload: contentKeyTLLVPayload.as_mut_ptr(),
+                contentKeyTLLVPayloadLength: contentKeyTLLVPayload.len() as u64,
                 R1: r1.as_mut_ptr(),
                 R1Length: r1.len() as u64,
             };
-            // Call to precompiled cryptographic library
-            let status = KSMCreateKeyPayload(&mut keyPayload);
 
-            // These returned values have type UInt64 instead of pointer, so copy out of the structure
-            serverCtx.ckcContainer.ckcData.contentKeyTLLVTag = keyPayload.contentKeyTLLVTag;
-            serverCtx
-                .ckcContainer
-                .ckcData
-                .contentKeyTLLVPayload
-                .resize(keyPayload.contentKeyTLLVPayloadLength as usize, 0);
+            // Call to precompiled cryptographic library
+            let status = unsafe { KSMCreateKeyPayload(&mut keyPayload) };
 
             if status != FPSStatus::noErr {
                 fpsLogError!(status, "KSMCreateKeyPayload failed");
                 returnErrorStatus!(status);
             }
+
+            // These returned values have type UInt64 instead of pointer, so copy out of the structure
+            contentKeyTLLVPayload.truncate(keyPayload.contentKeyTLLVPayloadLength as usize);
+
+            Ok(KeyPayload {
+                hu,
+                contentKeyTLLVTag: keyPayload.contentKeyTLLVTag,
+                contentKeyTLLVPayload,
+                r1,
+            })
         }
+    }
+}
+
+impl SDKExtension {
+    /// Calls the key payload backend (the cryptographic library by default) to generate content key payload
+    pub fn createContentKeyPayloadCustomImpl(serverCtx: &mut FPSServerCtx, _keyTypeRequested: u32) -> Result<()> {
+        // Get provisioning data
+        let provData = SDKExtension::getProvisioningData(&serverCtx.spcContainer)?;
+
+        // Older devices may not send this information so default to 16 byte key
+        let spcData = &serverCtx.spcContainer.spcData;
+        let supportedKeyFormats = match spcData.numberOfSupportedKeyFormats {
+            0 => &[FPSKeyFormatTag::buf16Byte as u64][..],
+            n => &spcData.supportedKeyFormats[..n as usize],
+        };
+
+        // Convert from our custom ContentType to KSMKeyPayloadContentType
+        let ksmKeyPayloadContentType: KSMKeyPayloadContentType = match serverCtx.extension.contentType {
+            ContentType::uhd | ContentType::hd | ContentType::sd => KSMKeyPayloadContentType::video,
+            ContentType::audio => KSMKeyPayloadContentType::audio,
+            _ => KSMKeyPayloadContentType::unknown
+        };
+
+        let request = KeyPayloadRequest {
+            contentKey: &serverCtx.ckcContainer.ckcData.ck,
+            contentIV: &serverCtx.ckcContainer.ckcData.iv,
+            contentType: ksmKeyPayloadContentType,
+            skR1: &spcData.skR1,
+            r2: &spcData.r2,
+            r1Integrity: &spcData.skR1IntegrityTag,
+            supportedKeyFormats,
+            cryptoVersionUsed: spcData.versionUsed,
+            provisioningData: &provData,
+            certHash: &serverCtx.spcContainer.certificateHash,
+        };
 
-        if serverCtx.ckcContainer.ckcData.contentKeyTLLVPayload.len() <= AES128_KEY_SZ {
+        let backend = SDKExtension::keyPayloadBackend();
+        let keyPayload = SDKExtension::metrics().time(Stage::keyPayload, || backend.createKeyPayload(&request))?;
+
+        if keyPayload.contentKeyTLLVPayload.len() <= AES128_KEY_SZ {
             returnErrorStatus!(FPSStatus::internalErr);
         }
 
+        serverCtx.hu = keyPayload.hu;
+        serverCtx.ckcContainer.ckcData.contentKeyTLLVTag = keyPayload.contentKeyTLLVTag;
+        serverCtx.ckcContainer.ckcData.contentKeyTLLVPayload = keyPayload.contentKeyTLLVPayload;
+
         // Store R1 returned from the crypto lib
-        serverCtx.ckcContainer.ckcData.r1 = r1.to_vec();
+        serverCtx.ckcContainer.ckcData.r1 = keyPayload.r1;
 
         Ok(())
     }
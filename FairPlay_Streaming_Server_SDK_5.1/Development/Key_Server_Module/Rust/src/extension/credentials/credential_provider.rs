@@ -0,0 +1,421 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+use crate::base::structures::secret::SecretBytes;
+use crate::extension::credentials::credentials::{
+    KEY_ENCRYPTION_KEY, KEY_ENCRYPTION_KEY_ID, PROVISIONING_DATA, RSA_1024_PRIVATE_KEY_PEM, RSA_2048_PRIVATE_KEY_PEM,
+};
+use crate::validate::{FPSStatus, Result};
+use crate::{fpsLogError, returnErrorStatus};
+use base64::engine::general_purpose;
+use base64::Engine;
+use openssl::pkey::{PKey, Private};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+/// Environment variable holding the 1024-bit RSA private key (PEM) for `EnvCredentialProvider`.
+pub const RSA_1024_PRIVATE_KEY_PEM_ENV: &str = "FPS_RSA_1024_PRIVATE_KEY_PEM";
+/// Environment variable holding the 2048-bit RSA private key (PEM) for `EnvCredentialProvider`.
+pub const RSA_2048_PRIVATE_KEY_PEM_ENV: &str = "FPS_RSA_2048_PRIVATE_KEY_PEM";
+/// Environment variable holding the base64 encoded provisioning data for `EnvCredentialProvider`.
+pub const PROVISIONING_DATA_ENV: &str = "FPS_PROVISIONING_DATA";
+/// Environment variable holding the key-encryption key (PEM or hex) for `EnvCredentialProvider`.
+pub const KEY_ENCRYPTION_KEY_ENV: &str = "FPS_KEY_ENCRYPTION_KEY";
+/// Environment variable holding the optional kid of `FPS_KEY_ENCRYPTION_KEY`.
+pub const KEY_ENCRYPTION_KEY_ID_ENV: &str = "FPS_KEY_ENCRYPTION_KEY_ID";
+
+/// Key the CMS wraps content keys with before sending them in `content-key-wrapped`.
+#[derive(Debug, Clone)]
+pub enum KeyEncryptionKey {
+    /// 16, 24 or 32-byte key for AES key wrap
+    aes(SecretBytes),
+    /// Private key for RSA-OAEP
+    rsa(PKey<Private>),
+}
+
+impl KeyEncryptionKey {
+    /// Parses a PEM encoded RSA private key, or an AES key given as raw bytes or as hex text.
+    pub fn parse(bytes: &[u8], source: &str) -> Result<KeyEncryptionKey> {
+        if bytes.trim_ascii_start().starts_with(b"-----BEGIN") {
+            return Ok(KeyEncryptionKey::rsa(parsePrivateKey(bytes, source)?));
+        }
+
+        let key: SecretBytes = match std::str::from_utf8(bytes).map(|text| hex::decode(text.trim())) {
+            Ok(Ok(decoded)) => decoded.into(),
+            _ => bytes.into(),
+        };
+        if ![16, 24, 32].contains(&key.len()) {
+            fpsLogError!(
+                FPSStatus::internalErr,
+                "Key-encryption key from {} must be a PEM RSA private key or a 16, 24 or 32-byte AES key",
+                source
+            );
+            returnErrorStatus!(FPSStatus::internalErr);
+        }
+
+        Ok(KeyEncryptionKey::aes(key))
+    }
+}
+
+/// Source of the partner credentials used to open SPCs and create content key payloads.
+///
+/// Implementations must be cheap to call: the SDK asks for the key and provisioning
+/// data on every `create-ckc` operation.
+pub trait CredentialProvider: Send + Sync {
+    /// Returns the RSA private key for the SPC version (1 = 1024-bit, 2 = 2048-bit certificate).
+    fn getPrivateKey(&self, spcVersion: u32) -> Result<PKey<Private>>;
+
+    /// Returns the provisioning data generated for the FPS certificate.
+    fn getProvisioningData(&self) -> Result<Arc<Vec<u8>>>;
+
+    /// Returns the key-encryption key `content-key-wrapped` values are unwrapped with.
+    ///
+    /// `kid` is the key ID given with the wrapped key, if any. Fails with `paramErr` for a kid the
+    /// provider has no key for, and for every kid if the provider has no key-encryption key.
+    fn getKeyEncryptionKey(&self, kid: Option<&str>) -> Result<KeyEncryptionKey> {
+        missingKeyEncryptionKey(kid)
+    }
+}
+
+fn parsePrivateKey(pem: &[u8], source: &str) -> Result<PKey<Private>> {
+    match PKey::private_key_from_pem(pem) {
+        Ok(key) => Ok(key),
+        Err(e) => {
+            fpsLogError!(FPSStatus::internalErr, "Unable to load private key from {}: {}", source, e);
+            returnErrorStatus!(FPSStatus::internalErr);
+        }
+    }
+}
+
+fn missingPrivateKey(spcVersion: u32) -> Result<PKey<Private>> {
+    fpsLogError!(
+        FPSStatus::invalidCertificateErr,
+        "No private key provisioned for SPC version {}",
+        spcVersion
+    );
+    returnErrorStatus!(FPSStatus::invalidCertificateErr);
+}
+
+fn missingKeyEncryptionKey(kid: Option<&str>) -> Result<KeyEncryptionKey> {
+    match kid {
+        Some(kid) => {
+            fpsLogError!(
+                FPSStatus::paramErr,
+                reason = "unknown-kid",
+                "No key-encryption key provisioned with kid {}",
+                kid
+            );
+            returnErrorStatus!(FPSStatus::paramErr);
+        }
+        None => {
+            fpsLogError!(FPSStatus::internalErr, "No key-encryption key provisioned");
+            returnErrorStatus!(FPSStatus::internalErr);
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// In-memory credentials
+////////////////////////////////////////////////////////////////////////////////
+
+/// Credentials that were parsed up front and never change.
+#[derive(Clone)]
+pub struct MemoryCredentialProvider {
+    rsa1024: Option<PKey<Private>>,
+    rsa2048: Option<PKey<Private>>,
+    provisioningData: Arc<Vec<u8>>,
+    /// Key-encryption keys by kid, `None` for the one used when the kid is absent
+    keyEncryptionKeys: HashMap<Option<String>, KeyEncryptionKey>,
+}
+
+impl MemoryCredentialProvider {
+    pub fn new(
+        rsa1024: Option<PKey<Private>>,
+        rsa2048: Option<PKey<Private>>,
+        provisioningData: Vec<u8>,
+    ) -> MemoryCredentialProvider {
+        MemoryCredentialProvider {
+            rsa1024,
+            rsa2048,
+            provisioningData: Arc::new(provisioningData),
+            keyEncryptionKeys: HashMap::new(),
+        }
+    }
+
+    /// Adds a key-encryption key for `content-key-wrapped`, under `kid` or as the default one.
+    pub fn withKeyEncryptionKey(mut self, kid: Option<&str>, key: KeyEncryptionKey) -> MemoryCredentialProvider {
+        self.keyEncryptionKeys.insert(kid.map(str::to_string), key);
+        self
+    }
+
+    /// Parses PEM encoded private keys. Either key may be omitted if that certificate size is not provisioned.
+    pub fn fromPem(
+        rsa1024Pem: Option<&[u8]>,
+        rsa2048Pem: Option<&[u8]>,
+        provisioningData: Vec<u8>,
+    ) -> Result<MemoryCredentialProvider> {
+        let rsa1024 = rsa1024Pem.map(|pem| parsePrivateKey(pem, "1024-bit PEM")).transpose()?;
+        let rsa2048 = rsa2048Pem.map(|pem| parsePrivateKey(pem, "2048-bit PEM")).transpose()?;
+
+        Ok(MemoryCredentialProvider::new(rsa1024, rsa2048, provisioningData))
+    }
+}
+
+impl CredentialProvider for MemoryCredentialProvider {
+    fn getPrivateKey(&self, spcVersion: u32) -> Result<PKey<Private>> {
+        let key = match spcVersion {
+            1 => self.rsa1024.as_ref(),
+            2 => self.rsa2048.as_ref(),
+            _ => None,
+        };
+        match key {
+            Some(key) => Ok(key.clone()),
+            None => missingPrivateKey(spcVersion),
+        }
+    }
+
+    fn getProvisioningData(&self) -> Result<Arc<Vec<u8>>> {
+        Ok(self.provisioningData.clone())
+    }
+
+    fn getKeyEncryptionKey(&self, kid: Option<&str>) -> Result<KeyEncryptionKey> {
+        match self.keyEncryptionKeys.get(&kid.map(str::to_string)) {
+            Some(key) => Ok(key.clone()),
+            None => missingKeyEncryptionKey(kid),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Environment variable credentials
+////////////////////////////////////////////////////////////////////////////////
+
+/// Credentials read once from `FPS_RSA_1024_PRIVATE_KEY_PEM`, `FPS_RSA_2048_PRIVATE_KEY_PEM`
+/// (PEM text), `FPS_PROVISIONING_DATA` (base64) and the optional `FPS_KEY_ENCRYPTION_KEY`, used for
+/// wrapped keys without a kid or with the kid in `FPS_KEY_ENCRYPTION_KEY_ID`.
+pub struct EnvCredentialProvider {
+    inner: MemoryCredentialProvider,
+}
+
+impl EnvCredentialProvider {
+    pub fn new() -> Result<EnvCredentialProvider> {
+        let rsa1024 = std::env::var(RSA_1024_PRIVATE_KEY_PEM_ENV).ok();
+        let rsa2048 = std::env::var(RSA_2048_PRIVATE_KEY_PEM_ENV).ok();
+
+        let provisioningData = match std::env::var(PROVISIONING_DATA_ENV) {
+            Ok(encoded) => match general_purpose::STANDARD.decode(encoded.trim()) {
+                Ok(data) => data,
+                Err(e) => {
+                    fpsLogError!(FPSStatus::internalErr, "Unable to decode {}: {}", PROVISIONING_DATA_ENV, e);
+                    returnErrorStatus!(FPSStatus::internalErr);
+                }
+            },
+            Err(_) => {
+                fpsLogError!(FPSStatus::internalErr, "{} is not set", PROVISIONING_DATA_ENV);
+                returnErrorStatus!(FPSStatus::internalErr);
+            }
+        };
+
+        let mut inner = MemoryCredentialProvider::fromPem(
+            rsa1024.as_deref().map(str::as_bytes),
+            rsa2048.as_deref().map(str::as_bytes),
+            provisioningData,
+        )?;
+        if let Ok(keyEncryptionKey) = std::env::var(KEY_ENCRYPTION_KEY_ENV) {
+            let keyEncryptionKey = KeyEncryptionKey::parse(keyEncryptionKey.as_bytes(), KEY_ENCRYPTION_KEY_ENV)?;
+            if let Ok(kid) = std::env::var(KEY_ENCRYPTION_KEY_ID_ENV) {
+                inner = inner.withKeyEncryptionKey(Some(kid.trim()), keyEncryptionKey.clone());
+            }
+            inner = inner.withKeyEncryptionKey(None, keyEncryptionKey);
+        }
+
+        Ok(EnvCredentialProvider { inner })
+    }
+
+    /// True if any of the credential environment variables is set.
+    pub fn isConfigured() -> bool {
+        [RSA_1024_PRIVATE_KEY_PEM_ENV, RSA_2048_PRIVATE_KEY_PEM_ENV, PROVISIONING_DATA_ENV]
+            .iter()
+            .any(|var| std::env::var_os(var).is_some())
+    }
+}
+
+impl CredentialProvider for EnvCredentialProvider {
+    fn getPrivateKey(&self, spcVersion: u32) -> Result<PKey<Private>> {
+        self.inner.getPrivateKey(spcVersion)
+    }
+
+    fn getProvisioningData(&self) -> Result<Arc<Vec<u8>>> {
+        self.inner.getProvisioningData()
+    }
+
+    fn getKeyEncryptionKey(&self, kid: Option<&str>) -> Result<KeyEncryptionKey> {
+        self.inner.getKeyEncryptionKey(kid)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// File credentials
+////////////////////////////////////////////////////////////////////////////////
+
+/// Modification time and size of a file when it was last loaded.
+type FileStamp = (Option<SystemTime>, u64);
+
+/// A parsed file that is reloaded when the file on disk changes.
+///
+/// The new contents are fully parsed before being swapped in, so readers see
+/// either the previous value or the new one, never a partially loaded file.
+struct ReloadingFile<T: Clone> {
+    path: PathBuf,
+    state: RwLock<ReloadState<T>>,
+}
+
+struct ReloadState<T> {
+    /// Last contents that parsed, and the stamp they were read at
+    loaded: Option<(FileStamp, T)>,
+    /// Stamp of contents that did not parse, and why, so they are not read again until the file changes
+    failed: Option<(FileStamp, FPSStatus)>,
+}
+
+impl<T: Clone> ReloadState<T> {
+    /// The value to serve for a file at `stamp`, if it needs no reload.
+    fn current(&self, stamp: &FileStamp) -> Option<Result<T>> {
+        match (&self.loaded, &self.failed) {
+            (Some((loadedStamp, value)), _) if loadedStamp == stamp => Some(Ok(value.clone())),
+            // A half-written replacement must not take the server down: keep using the old copy
+            (Some((_, value)), Some((failedStamp, _))) if failedStamp == stamp => Some(Ok(value.clone())),
+            (None, Some((failedStamp, status))) if failedStamp == stamp => Some(Err(*status)),
+            _ => None,
+        }
+    }
+}
+
+impl<T: Clone> ReloadingFile<T> {
+    fn new(path: PathBuf) -> ReloadingFile<T> {
+        ReloadingFile {
+            path,
+            state: RwLock::new(ReloadState {
+                loaded: None,
+                failed: None,
+            }),
+        }
+    }
+
+    fn get(&self, parse: impl Fn(&[u8]) -> Result<T>) -> Result<T> {
+        let stamp = match std::fs::metadata(&self.path) {
+            Ok(metadata) => (metadata.modified().ok(), metadata.len()),
+            Err(e) => {
+                // Keep serving the last good copy if the file is briefly missing (e.g. during a replace)
+                if let Some((_, value)) = self.state.read().unwrap_or_else(|e| e.into_inner()).loaded.as_ref() {
+                    return Ok(value.clone());
+                }
+                fpsLogError!(FPSStatus::internalErr, "Unable to read credentials {}: {}", self.path.display(), e);
+                returnErrorStatus!(FPSStatus::internalErr);
+            }
+        };
+
+        if let Some(value) = self.state.read().unwrap_or_else(|e| e.into_inner()).current(&stamp) {
+            return value;
+        }
+
+        let mut state = self.state.write().unwrap_or_else(|e| e.into_inner());
+
+        // Another thread may have reloaded while we waited for the lock
+        if let Some(value) = state.current(&stamp) {
+            return value;
+        }
+
+        let contents = match std::fs::read(&self.path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                fpsLogError!(FPSStatus::internalErr, "Unable to read credentials {}: {}", self.path.display(), e);
+                returnErrorStatus!(FPSStatus::internalErr);
+            }
+        };
+
+        match parse(&contents) {
+            Ok(value) => {
+                log::debug!("Loaded credentials from {}", self.path.display());
+                state.loaded = Some((stamp, value.clone()));
+                state.failed = None;
+                Ok(value)
+            }
+            Err(e) => {
+                state.failed = Some((stamp, e));
+                match state.loaded.as_ref() {
+                    Some((_, value)) => {
+                        log::warn!(
+                            "Credentials {} changed but could not be loaded, keeping the previous copy",
+                            self.path.display()
+                        );
+                        Ok(value.clone())
+                    }
+                    None => Err(e),
+                }
+            }
+        }
+    }
+}
+
+/// Credentials read from a directory and cached until the files change.
+///
+/// Uses the same file names as the original SDK layout (`priv_key_1024.pem`,
+/// `priv_key_2048.pem`, `provisioning_data.bin`, or their `test_` variants when
+/// the `test_credentials` feature is enabled). The optional `key_encryption_key` file holds
+/// the one key-encryption key, used for wrapped keys without a kid or with the kid in the
+/// optional `key_encryption_key_id` file.
+pub struct FileCredentialProvider {
+    rsa1024: ReloadingFile<PKey<Private>>,
+    rsa2048: ReloadingFile<PKey<Private>>,
+    provisioningData: ReloadingFile<Arc<Vec<u8>>>,
+    keyEncryptionKey: ReloadingFile<KeyEncryptionKey>,
+    keyEncryptionKeyId: ReloadingFile<String>,
+}
+
+impl FileCredentialProvider {
+    pub fn new(directory: impl AsRef<Path>) -> FileCredentialProvider {
+        let directory = directory.as_ref();
+        FileCredentialProvider {
+            rsa1024: ReloadingFile::new(directory.join(RSA_1024_PRIVATE_KEY_PEM)),
+            rsa2048: ReloadingFile::new(directory.join(RSA_2048_PRIVATE_KEY_PEM)),
+            provisioningData: ReloadingFile::new(directory.join(PROVISIONING_DATA)),
+            keyEncryptionKey: ReloadingFile::new(directory.join(KEY_ENCRYPTION_KEY)),
+            keyEncryptionKeyId: ReloadingFile::new(directory.join(KEY_ENCRYPTION_KEY_ID)),
+        }
+    }
+}
+
+impl CredentialProvider for FileCredentialProvider {
+    fn getPrivateKey(&self, spcVersion: u32) -> Result<PKey<Private>> {
+        let file = match spcVersion {
+            1 => &self.rsa1024,
+            2 => &self.rsa2048,
+            _ => return missingPrivateKey(spcVersion),
+        };
+        let source = file.path.display().to_string();
+        file.get(|pem| parsePrivateKey(pem, &source))
+    }
+
+    fn getProvisioningData(&self) -> Result<Arc<Vec<u8>>> {
+        self.provisioningData.get(|data| Ok(Arc::new(data.to_vec())))
+    }
+
+    fn getKeyEncryptionKey(&self, kid: Option<&str>) -> Result<KeyEncryptionKey> {
+        if let Some(kid) = kid {
+            let knownKid = self.keyEncryptionKeyId.path.exists()
+                && self
+                    .keyEncryptionKeyId
+                    .get(|id| Ok(String::from_utf8_lossy(id).trim().to_string()))
+                    .is_ok_and(|id| id == kid);
+            if !knownKid {
+                return missingKeyEncryptionKey(Some(kid));
+            }
+        }
+
+        let source = self.keyEncryptionKey.path.display().to_string();
+        self.keyEncryptionKey.get(|key| KeyEncryptionKey::parse(key, &source))
+    }
+}
@@ -4,6 +4,9 @@
 
 pub const CREDENTIALS_PATH: &str = "src/extension/credentials/";
 
+/// Overrides `CREDENTIALS_PATH` for the default file based credential provider.
+pub const CREDENTIALS_PATH_ENV: &str = "FPS_CREDENTIALS_PATH";
+
 // **********************************************************************************************
 // RSA 1024-bit Private Key (PEM format)
 // **********************************************************************************************
@@ -32,4 +35,13 @@ pub const RSA_2048_PRIVATE_KEY_PEM: &str = "priv_key_2048.pem";
 pub const PROVISIONING_DATA: &str = "test_provisioning_data.bin";
 
 #[cfg(not(feature = "test_credentials"))]
-pub const PROVISIONING_DATA: &str = "provisioning_data.bin";
\ No newline at end of file
+pub const PROVISIONING_DATA: &str = "provisioning_data.bin";
+
+// **********************************************************************************************
+// Key-encryption key for content-key-wrapped (PEM RSA private key, or raw or hex AES key)
+// **********************************************************************************************
+
+pub const KEY_ENCRYPTION_KEY: &str = "key_encryption_key";
+
+// Optional kid of the key-encryption key (text)
+pub const KEY_ENCRYPTION_KEY_ID: &str = "key_encryption_key_id";
\ No newline at end of file
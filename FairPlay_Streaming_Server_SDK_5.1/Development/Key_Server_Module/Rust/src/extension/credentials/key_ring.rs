@@ -0,0 +1,143 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+use crate::base::base_constants;
+use crate::extension::credentials::credential_provider::{CredentialProvider, FileCredentialProvider};
+use crate::validate::{FPSStatus, Result};
+use crate::{fpsLogError, returnErrorStatus};
+use openssl::hash::{hash, MessageDigest};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+/// Environment variable pointing at a key ring directory for `KeyRing::fromDirectory`.
+pub const KEY_RING_PATH_ENV: &str = "FPS_KEY_RING_PATH";
+
+/// Credentials for several FPS certificates, selected by the certificate hash in the SPC header.
+///
+/// Lets a server hold an old and a new certificate side by side while rotating, or serve
+/// several apps with separate credentials. SPCs for certificates that are not in the ring are
+/// rejected with `invalidCertificateErr` before any RSA operation is attempted.
+#[derive(Default)]
+pub struct KeyRing {
+    entries: RwLock<HashMap<Vec<u8>, Arc<dyn CredentialProvider>>>,
+}
+
+impl KeyRing {
+    pub fn new() -> KeyRing {
+        KeyRing::default()
+    }
+
+    /// Returns the certificate hash an SPC carries for `certificateDer` (SHA-1 of the DER certificate).
+    pub fn certificateHash(certificateDer: &[u8]) -> Result<Vec<u8>> {
+        match hash(MessageDigest::sha1(), certificateDer) {
+            Ok(digest) => Ok(digest.to_vec()),
+            Err(e) => {
+                fpsLogError!(FPSStatus::internalErr, "Unable to hash certificate: {}", e);
+                returnErrorStatus!(FPSStatus::internalErr);
+            }
+        }
+    }
+
+    /// Adds (or replaces) the credentials used for SPCs carrying `certificateHash`.
+    pub fn insert(&self, certificateHash: &[u8], provider: Arc<dyn CredentialProvider>) -> Result<()> {
+        if certificateHash.len() != base_constants::FPS_V1_HASH_SZ {
+            fpsLogError!(
+                FPSStatus::paramErr,
+                "Certificate hash must be {} bytes, got {}",
+                base_constants::FPS_V1_HASH_SZ,
+                certificateHash.len()
+            );
+            returnErrorStatus!(FPSStatus::paramErr);
+        }
+
+        self.entries
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(certificateHash.to_vec(), provider);
+        Ok(())
+    }
+
+    /// Retires a certificate, e.g. at the end of a rotation window. Returns false if it was not present.
+    pub fn remove(&self, certificateHash: &[u8]) -> bool {
+        self.entries
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(certificateHash)
+            .is_some()
+    }
+
+    /// Returns the credentials for `certificateHash`, or `invalidCertificateErr` if it is unknown.
+    pub fn get(&self, certificateHash: &[u8]) -> Result<Arc<dyn CredentialProvider>> {
+        match self.entries.read().unwrap_or_else(|e| e.into_inner()).get(certificateHash) {
+            Some(provider) => Ok(provider.clone()),
+            None => {
+                fpsLogError!(
+                    FPSStatus::invalidCertificateErr,
+                    "No credentials provisioned for certificate hash {}",
+                    hex::encode(certificateHash)
+                );
+                returnErrorStatus!(FPSStatus::invalidCertificateErr);
+            }
+        }
+    }
+
+    /// Hashes of all certificates currently in the ring, in no particular order.
+    pub fn certificateHashes(&self) -> Vec<Vec<u8>> {
+        self.entries.read().unwrap_or_else(|e| e.into_inner()).keys().cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.read().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Builds a ring from a directory with one subdirectory per certificate.
+    ///
+    /// Each subdirectory is named after the hex encoded certificate hash and uses the same
+    /// layout as a single credentials directory (see `FileCredentialProvider`):
+    ///
+    /// ```text
+    /// keyring/
+    ///   3f0a...e1/   priv_key_2048.pem, provisioning_data.bin
+    ///   9bc4...07/   priv_key_1024.pem, priv_key_2048.pem, provisioning_data.bin
+    /// ```
+    ///
+    /// Files are reloaded when they change; adding or removing a certificate needs a new ring.
+    pub fn fromDirectory(directory: impl AsRef<Path>) -> Result<KeyRing> {
+        let directory = directory.as_ref();
+        let entries = match std::fs::read_dir(directory) {
+            Ok(entries) => entries,
+            Err(e) => {
+                fpsLogError!(FPSStatus::internalErr, "Unable to read key ring {}: {}", directory.display(), e);
+                returnErrorStatus!(FPSStatus::internalErr);
+            }
+        };
+
+        let keyRing = KeyRing::new();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if !path.is_dir() {
+                continue;
+            }
+
+            let name = entry.file_name();
+            let certificateHash = match name.to_str().map(hex::decode) {
+                Some(Ok(certificateHash)) if certificateHash.len() == base_constants::FPS_V1_HASH_SZ => certificateHash,
+                _ => {
+                    log::debug!("Skipping {}: not named after a certificate hash", path.display());
+                    continue;
+                }
+            };
+
+            log::debug!("Adding certificate {} to key ring", hex::encode(&certificateHash));
+            keyRing.insert(&certificateHash, Arc::new(FileCredentialProvider::new(&path)))?;
+        }
+
+        Ok(keyRing)
+    }
+}
@@ -2,4 +2,6 @@
 // Copyright © 2023-2024 Apple Inc. All rights reserved.
 //
 
+pub mod credential_provider;
 pub mod credentials;
+pub mod key_ring;
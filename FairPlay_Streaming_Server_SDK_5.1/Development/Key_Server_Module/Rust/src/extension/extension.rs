@@ -2,409 +2,266 @@
 // Copyright © 2023-2025 Apple Inc. All rights reserved.
 //
 
-use crate::base::base_constants;
-use crate::base::base_constants::{FPSKeyDurationType, FPSTLLVTagValue};
 use crate::base::structures::base_fps_structures::{AssetInfo, FPSOperation, FPSOperations, FPSResult, FPSResults};
 use crate::base::structures::base_server_structures::{FPSServerCtx, FPSServerSPCContainer, FPSServerTLLV};
-use crate::extension::credentials::credentials::{
-    CREDENTIALS_PATH, PROVISIONING_DATA, RSA_1024_PRIVATE_KEY_PEM, RSA_2048_PRIVATE_KEY_PEM,
+use crate::extension::credentials::credential_provider::{
+    CredentialProvider, EnvCredentialProvider, FileCredentialProvider,
 };
-use crate::extension::extension_constants::{self, ContentType, FairPlayStreamingVersion};
+use crate::extension::credentials::credentials::{CREDENTIALS_PATH, CREDENTIALS_PATH_ENV};
+use crate::extension::credentials::key_ring::{KeyRing, KEY_RING_PATH_ENV};
 use crate::extension::structures::extension_structures::SDKExtension;
 use crate::extension_structures::FPSOperationExtension;
 use crate::validate::{FPSStatus, Result};
-use crate::Base;
-use crate::{fpsLogError, requireAction, returnErrorStatus};
-use rand::Rng;
-use std::path::Path;
+use crate::{fpsLogError, returnErrorStatus};
+use openssl::pkey::{PKey, Private};
 use serde_jsonrc::{Map, Value};
-use std::io::Write;
+use std::sync::{Arc, RwLock};
 
 ////////////////////////////////////////////////////////////////////////////////
 // Utility Functions
 ////////////////////////////////////////////////////////////////////////////////
 
-/// Initializes custom log output formatting. Change the format here to match
-/// whatever log formatting works best for your tools.
-pub fn logInitCustom(_extension: Option<&FPSOperationExtension>) {
-    let env = env_logger::Env::new()
-        .filter_or("RUST_LOG", "trace")
-        .write_style("RUST_LOG_STYLE");
-
-    // Example configuration of log::Debug!() style prints:
-    env_logger::Builder::from_env(env)
-        .format(move |buf, record| writeln!(buf, "[DEBUG] {}", record.args()))
-        .try_init()
-        .unwrap_or(());
-    // or match the fpsLogError!() style prints:
-    // env_logger::Builder::from_env(env)
-    //     .format(move |buf, record| {
-    //         writeln!(
-    //             buf,
-    //             "timestamp=\"{}\",FP_TOOLN=\"{}\",FP_TOOLV=\"{}\",FP_PID=\"{}\",FP_FL=\"{}\",FP_LN=\"{}\",{}",
-    //             chrono::Utc::now().format("%Y-%m-%d %T,%3f"),
-    //             env!("CARGO_PKG_NAME"),
-    //             env!("CARGO_PKG_VERSION"),
-    //             std::process::id(),
-    //             record.file().unwrap_or("unknown file"),
-    //             record.line().unwrap_or(0),
-    //             record.args()
-    //             )
-    //     })
-    // .init();
-    // or use default format:
-    // env_logger::Builder::from_env(env).try_init().unwrap_or(());
-
-    // Example configuration of fpsLogError!() style prints:
-    crate::logging::LOG_FORMAT.with(|a| {
-        let _ = a.replace(Box::new(|line, file| {
-            format!(
-                "timestamp=\"{}\",FP_TOOLN=\"{}\",FP_TOOLV=\"{}\",FP_PID=\"{}\",FP_FL=\"{}\",FP_LN=\"{}\"",
-                chrono::Local::now().format("%Y-%m-%d %T,%3f"),
-                env!("CARGO_PKG_NAME"),
-                env!("CARGO_PKG_VERSION"),
-                std::process::id(),
-                file,
-                line,
-            )
-        }));
-    });
-}
-
-/// Fills buffer with random numbers
+/// Fills buffer with random numbers from the active `RandomSource` (see `extension::random`)
 pub fn genRandom(out: &mut [u8], length: usize) {
-    let mut rng = rand::thread_rng();
-    rng.fill(&mut out[0..length]);
+    SDKExtension::randomSource().fill(&mut out[0..length]);
 }
 
 ////////////////////////////////////////////////////////////////////////////////
-// Input Parsing and Verification Functions
+// Extension Hooks
+//
+// Called by Base at each customization point. They forward to the active
+// `FpsExtension` (see `extension::fps_extension`), where the SDK's default
+// behavior for each hook is implemented and documented.
 ////////////////////////////////////////////////////////////////////////////////
 
-/// Performs any custom input json top-level parsing operations
+pub fn logInitCustom(extension: Option<&FPSOperationExtension>) {
+    SDKExtension::fpsExtension().logInitCustom(extension)
+}
+
 pub fn parseOperationsCustom(json: &Value, root: &mut Map<String, Value>) -> Result<()> {
-    if let Some(rootObj) = json[extension_constants::FAIRPLAY_STREAMING_REQUEST_STR].as_object() {
-        *root = rootObj.clone();
-    } else {
-        returnErrorStatus!(FPSStatus::paramErr);
-    }
-    Ok(())
+    SDKExtension::fpsExtension().parseOperationsCustom(json, root)
 }
 
-/// Performs custom parsing of account info JSON input.
-///
-/// Use this function to handle any values outside of what the Base code parses
-/// for json input `offline-hls`.
-pub fn parseOfflineHLSCustom(_ckcObj: &serde_jsonrc::Map<String, Value>, _assetInfo: &mut AssetInfo) -> Result<()> {
-    Ok(())
+pub fn parseOfflineHLSCustom(ckcObj: &Map<String, Value>, assetInfo: &mut AssetInfo) -> Result<()> {
+    SDKExtension::fpsExtension().parseOfflineHLSCustom(ckcObj, assetInfo)
 }
 
-/// Performs custom verification of account info JSON input.
-pub fn verifyOfflineHLSCustom(_assetInfo: &mut AssetInfo) -> Result<()> {
-    Ok(())
+pub fn verifyOfflineHLSCustom(assetInfo: &mut AssetInfo) -> Result<()> {
+    SDKExtension::fpsExtension().verifyOfflineHLSCustom(assetInfo)
 }
 
-/// Performs custom parsing of `hdcp-type` JSON input.
-///
-/// Use this function to handle any values outside of what the Base code parses.
-pub fn parseHDCPTypeCustom(_hdcpType: i32) -> Result<u64> {
-    // Base code already handled all known values. Treat unknown values as an error.
-    Err(FPSStatus::paramErr)
+pub fn parseHDCPTypeCustom(hdcpType: i32) -> Result<u64> {
+    SDKExtension::fpsExtension().parseHDCPTypeCustom(hdcpType)
 }
 
-/// Performs parsing of any custom fields within the `asset-info` object of the input JSON
 pub fn parseAssetInfoCustom(assetInfoObj: &Value, assetInfo: &mut AssetInfo) -> Result<()> {
-    // Parse "content-type" from input json
-    if let Some(contentType) = assetInfoObj[extension_constants::CONTENT_TYPE_STR].as_str() {
-        match contentType {
-            extension_constants::CONTENT_TYPE_UHD_STR => {
-                assetInfo.extension.contentType = ContentType::uhd;
-            }
-            extension_constants::CONTENT_TYPE_HD_STR => {
-                assetInfo.extension.contentType = ContentType::hd;
-            }
-            extension_constants::CONTENT_TYPE_SD_STR => {
-                assetInfo.extension.contentType = ContentType::sd;
-            }
-            extension_constants::CONTENT_TYPE_AUDIO_STR => {
-                assetInfo.extension.contentType = ContentType::audio;
-            }
-            _ => {
-                assetInfo.extension.contentType = ContentType::unknown;
-            }
-        }
-    } else {
-        assetInfo.extension.contentType = ContentType::unknown;
-    }
-
-    Ok(())
+    SDKExtension::fpsExtension().parseAssetInfoCustom(assetInfoObj, assetInfo)
 }
 
-/// Performs parsing of any custom fields within the `create-ckc` object of the input JSON
 pub fn parseCreateCKCOperationCustom(
-    _fpsOperation: &mut FPSOperation,
-    _ckcObj: &Value,
-    _root: &mut &Map<String, Value>,
+    fpsOperation: &mut FPSOperation,
+    ckcObj: &Value,
+    root: &mut &Map<String, Value>,
 ) -> Result<()> {
-    Ok(())
+    SDKExtension::fpsExtension().parseCreateCKCOperationCustom(fpsOperation, ckcObj, root)
 }
 
-/// Performs any remaining parsing of the top level input JSON after FPSOperation structure has been filled
 pub fn processOperationsCustom(
-    _json: &Value,
-    _output: &mut Value,
-    _fpsOperations: &FPSOperations,
-    _fpsResults: &mut FPSResults,
+    json: &Value,
+    output: &mut Value,
+    fpsOperations: &FPSOperations,
+    fpsResults: &mut FPSResults,
 ) -> Result<()> {
-    Ok(())
+    SDKExtension::fpsExtension().processOperationsCustom(json, output, fpsOperations, fpsResults)
 }
 
-// Custom handling inside createResults() if needed
-pub fn createResultsCustom(_fpsOperation: &mut FPSOperation, _keyTypeRequested: &mut u32) -> Result<()> {
-    Ok(())
+pub fn createResultsCustom(fpsOperation: &mut FPSOperation, keyTypeRequested: &mut u32) -> Result<()> {
+    SDKExtension::fpsExtension().createResultsCustom(fpsOperation, keyTypeRequested)
 }
 
-/// Decrypts `spcContainer.aesWrappedKey` into `aesKey`.
-///
-/// Uses partner-specific private key for the RSA decyrption.
-pub fn decryptKeyRSACustom(spcContainer: &mut FPSServerSPCContainer, aesKey: &mut Vec<u8>) -> Result<()> {
-    let mut keyPem: Vec<u8> = Default::default();
-    let aesWrappedKeySize = spcContainer.aesWrappedKeySize;
-    let aesWrappedKey: &Vec<u8> = &spcContainer.aesWrappedKey;
-
-    // Sanity check inputs
-    requireAction!(!aesWrappedKey.is_empty(), return Err(FPSStatus::paramErr));
-
-    SDKExtension::getPrivateKey(spcContainer, &mut keyPem)?;
-
-    let result = openssl::rsa::Rsa::private_key_from_pem(keyPem.as_slice());
-    if result.is_err() {
-        fpsLogError!(
-            FPSStatus::internalErr,
-            "Unable to load private key: {}",
-            result.unwrap_err()
-        );
-        returnErrorStatus!(FPSStatus::internalErr);
-    }
-    let rsa = result.unwrap();
-
-    *aesKey = vec![0_u8; rsa.size() as usize];
-
-    if aesWrappedKeySize == base_constants::FPS_V1_WRAPPED_KEY_SZ {
-        if rsa.private_decrypt(aesWrappedKey, aesKey, openssl::rsa::Padding::PKCS1_OAEP).is_err() {
-            // If decryption failed, it is likely the data was encrypted for another key.
-            fpsLogError!(FPSStatus::invalidCertificateErr, "RSA Decryption Failed");
-            returnErrorStatus!(FPSStatus::invalidCertificateErr);
-        }
-    } else if aesWrappedKeySize == base_constants::FPS_V2_WRAPPED_KEY_SZ {
-        let pkey = openssl::pkey::PKey::from_rsa(rsa).unwrap();
-
-        let mut decrypter = openssl::encrypt::Decrypter::new(&pkey).unwrap();
-
-        decrypter.set_rsa_padding(openssl::rsa::Padding::PKCS1_OAEP).unwrap();
-        decrypter
-            .set_rsa_mgf1_md(openssl::hash::MessageDigest::sha256())
-            .unwrap();
-        decrypter
-            .set_rsa_oaep_md(openssl::hash::MessageDigest::sha256())
-            .unwrap();
-
-        // Get the length of the output buffer
-        let bufferLen = decrypter.decrypt_len(aesWrappedKey).unwrap();
-        let mut decoded = vec![0u8; bufferLen];
-
-        // Decrypt the data
-        if decrypter.decrypt(aesWrappedKey, &mut decoded).is_err() {
-            // If decryption failed, it is likely the data was encrypted for another key.
-            fpsLogError!(FPSStatus::invalidCertificateErr, "RSA Decryption Failed");
-            returnErrorStatus!(FPSStatus::invalidCertificateErr);
-        }
-
-        *aesKey = decoded.to_vec();
-    }
+pub fn selectCredentialsCustom(spcContainer: &mut FPSServerSPCContainer) -> Result<()> {
+    SDKExtension::fpsExtension().selectCredentialsCustom(spcContainer)
+}
 
-    Ok(())
+pub fn decryptKeyRSACustom(spcContainer: &mut FPSServerSPCContainer, aesKey: &mut Vec<u8>) -> Result<()> {
+    SDKExtension::fpsExtension().decryptKeyRSACustom(spcContainer, aesKey)
 }
 
-/// Perform any custom steps needed directly after SPC decryption
-pub fn decryptSPCDataCustom(_spc: &[u8], _spcContainer: &mut FPSServerSPCContainer) -> Result<()> {
-    Ok(())
+pub fn decryptSPCDataCustom(spc: &[u8], spcContainer: &mut FPSServerSPCContainer) -> Result<()> {
+    SDKExtension::fpsExtension().decryptSPCDataCustom(spc, spcContainer)
 }
 
-/// Performs parsing of any TLLVs not handled in Base
-pub fn parseTLLVCustom(_tllv: &FPSServerTLLV, _value: &mut FPSServerSPCContainer) -> Result<()> {
-    Ok(())
+pub fn parseTLLVCustom(tllv: &FPSServerTLLV, value: &mut FPSServerSPCContainer) -> Result<()> {
+    SDKExtension::fpsExtension().parseTLLVCustom(tllv, value)
 }
 
-/// Performs any custom validation after all TLLVs have been parsed.
-pub fn validateTLLVsCustom(_spcContainer: &mut FPSServerSPCContainer) -> Result<()> {
-    Ok(())
+pub fn validateTLLVsCustom(spcContainer: &mut FPSServerSPCContainer) -> Result<()> {
+    SDKExtension::fpsExtension().validateTLLVsCustom(spcContainer)
 }
 
-/// Performs parsing of any capabilities flags not handled in Base
-pub fn checkSupportedFeaturesCustom(_serverCtx: &mut FPSServerCtx) -> Result<()> {
-    Ok(())
+pub fn checkSupportedFeaturesCustom(serverCtx: &mut FPSServerCtx) -> Result<()> {
+    SDKExtension::fpsExtension().checkSupportedFeaturesCustom(serverCtx)
 }
 
-/// Performs validation of SPC after SPC data is parsed.
 pub fn validateSPCCustom(fpsOperation: &mut FPSOperation, serverCtx: &mut FPSServerCtx) -> Result<()> {
-    // Check that business rules are satisfied
-    SDKExtension::checkBusinessRules(fpsOperation, serverCtx)
+    SDKExtension::fpsExtension().validateSPCCustom(fpsOperation, serverCtx)
 }
 
-/// Optional query of database to get asset information.
-///
-/// If asset information is not passed in the JSON input, now is the time to use
-/// the asset id found inside the request (`serverCtx.spcContainer.spcData.assetId`)
-/// to query your database and fill in `fpsOperation.assetInfo`.
-pub fn queryDatabaseCustom(_fpsOperation: &mut FPSOperation, _serverCtx: &mut FPSServerCtx) -> Result<()> {
-    Ok(())
+pub fn queryDatabaseCustom(fpsOperation: &mut FPSOperation, serverCtx: &mut FPSServerCtx) -> Result<()> {
+    SDKExtension::fpsExtension().queryDatabaseCustom(fpsOperation, serverCtx)
 }
 
-////////////////////////////////////////////////////////////////////////////////
-// Output Creation Functions
-////////////////////////////////////////////////////////////////////////////////
-
-/// Populates `serverCtx.ckcContainer` and `fpsResult` structure with any custom fields that will be returned to the caller
-pub fn populateResultsCustom(
-    serverCtx: &mut FPSServerCtx,
-    operation: &FPSOperation,
-    _result: &mut FPSResult,
-) -> Result<()> {
-    // Copy content type to the server context
-    serverCtx.extension.contentType = operation.assetInfo.extension.contentType;
-
-    Ok(())
+pub fn populateResultsCustom(serverCtx: &mut FPSServerCtx, operation: &FPSOperation, result: &mut FPSResult) -> Result<()> {
+    SDKExtension::fpsExtension().populateResultsCustom(serverCtx, operation, result)
 }
 
-/// Adds Content Key payload TLLV and related data to the CKC container
 pub fn createContentKeyPayloadCustom(
     serverCtx: &mut FPSServerCtx,
     keyTypeRequested: u32,
     fpsResult: &mut FPSResult,
 ) -> Result<()> {
-    SDKExtension::createContentKeyPayloadCustomImpl(serverCtx, keyTypeRequested)?;
-
-    // player HU
-    fpsResult.hu = serverCtx.spcContainer.spcData.hu.to_owned();
-
-    Ok(())
+    SDKExtension::fpsExtension().createContentKeyPayloadCustom(serverCtx, keyTypeRequested, fpsResult)
 }
 
-/// Fills version and IV of the CKC container
 pub fn fillCKCContainerCustom(serverCtx: &mut FPSServerCtx) -> Result<()> {
-    // Set the version
-    serverCtx.ckcContainer.version = FairPlayStreamingVersion::v1 as u32; // currently, only V1 is supported
-
-    // Generate the CKC container (AR) IV
-    genRandom(&mut serverCtx.ckcContainer.aesKeyIV, 16);
-
-    Ok(())
+    SDKExtension::fpsExtension().fillCKCContainerCustom(serverCtx)
 }
 
-/// Populates hdcpInformationTag Tag
-///
-/// The base code does not add this tag to the CKC by default. It is up to the extension to add it here.
 pub fn HDCPInformationTagPopulateCustom(serverCtx: &mut FPSServerCtx) -> Result<()> {
-    SDKExtension::populateTagServerHDCPInformation(serverCtx)
+    SDKExtension::fpsExtension().HDCPInformationTagPopulateCustom(serverCtx)
 }
 
-/// Populates securityLevelTag Tag
-///
-/// The base code does not add this tag to the CKC by default. It is up to the extension to add it here.
 pub fn securityLevelTagPopulateCustom(serverCtx: &mut FPSServerCtx) -> Result<()> {
-    SDKExtension::populateTagSecurityLevel(serverCtx)
+    SDKExtension::fpsExtension().securityLevelTagPopulateCustom(serverCtx)
 }
 
-/// Populates any custom TLLVs
-///
-/// One of the TLLVs indicating license expiration should be populated here (if required),
-/// because it is not done in Base.
 pub fn serializeCKCDataCustom(serverCtx: &mut FPSServerCtx) -> Result<()> {
-    // Construct and serialize either offline key tag or key duration tag
-    let keyType: u32 = serverCtx.ckcContainer.ckcData.keyDuration.keyType;
-
-    if serverCtx
-        .spcContainer
-        .spcData
-        .spcDataParser
-        .parsedTagValues
-        .contains(&(FPSTLLVTagValue::mediaPlaybackStateTag as u64))
-        && (keyType != FPSKeyDurationType::none as u32)
-    {
-        if ((keyType == FPSKeyDurationType::persistenceAndDuration as u32)
-            || (keyType == FPSKeyDurationType::persistence as u32))
-            && serverCtx.spcContainer.spcData.clientFeatures.supportsOfflineKeyTLLV
-        {
-            Base::populateTagServerOfflineKey(serverCtx)?;
-        } else {
-            Base::populateTagServerKeyDuration(serverCtx)?;
-        }
-    }
-
-    Ok(())
+    SDKExtension::fpsExtension().serializeCKCDataCustom(serverCtx)
 }
 
-/// Populates Content ID for Offline Key TLLV V1
-pub fn offlineKeyTagPopulateContentIDCustom(_serverCtx: &mut FPSServerCtx, offlineKeyTLLV: &mut Vec<u8>) -> Result<()> {
-    // Just add 16B of zeros
-    offlineKeyTLLV.append(&mut vec![0; 16]);
-
-    Ok(())
+pub fn offlineKeyTagPopulateContentIDCustom(serverCtx: &mut FPSServerCtx, offlineKeyTLLV: &mut Vec<u8>) -> Result<()> {
+    SDKExtension::fpsExtension().offlineKeyTagPopulateContentIDCustom(serverCtx, offlineKeyTLLV)
 }
 
-/// Adds any custom items to `FPSResult` after CKC has been generated
-pub fn finalizeResultsCustom(_serverCtx: &FPSServerCtx, _fpsResult: &mut FPSResult) -> Result<()> {
-    Ok(())
+pub fn finalizeResultsCustom(serverCtx: &FPSServerCtx, fpsResult: &mut FPSResult) -> Result<()> {
+    SDKExtension::fpsExtension().finalizeResultsCustom(serverCtx, fpsResult)
 }
 
-/// Adds any custom fields to the 'create-ckc' object of the output JSON
-pub fn serializeCreateCKCNodeCustom(_result: &FPSResult, _ckcNode: &mut Map<String, Value>) -> Result<()> {
-    Ok(())
+pub fn serializeCreateCKCNodeCustom(result: &FPSResult, ckcNode: &mut Map<String, Value>) -> Result<()> {
+    SDKExtension::fpsExtension().serializeCreateCKCNodeCustom(result, ckcNode)
 }
 
-/// Packages `ckcNode` into final JSON output (required).
 pub fn serializeResultsCustom(
     fpsResults: &FPSResults,
     ckcNode: Vec<Value>,
     jsonResults: &mut Map<String, Value>,
 ) -> Result<()> {
-    let mut root = Map::new();
-
-    root.insert(base_constants::CREATE_CKC_STR.to_string(), Value::Array(ckcNode));
-
-    // Add into top level response object
-    jsonResults.insert(
-        extension_constants::FAIRPLAY_STREAMING_RESPONSE_STR.to_string(),
-        Value::Object(root),
-    );
-
-    Ok(())
+    SDKExtension::fpsExtension().serializeResultsCustom(fpsResults, ckcNode, jsonResults)
 }
 
 ////////////////////////////////////////////////////////////////////////////////
 // Creentials Functions
 ////////////////////////////////////////////////////////////////////////////////
 
+/// Provider installed with `SDKExtension::setCredentialProvider`, or the default one once first used.
+static CREDENTIAL_PROVIDER: RwLock<Option<Arc<dyn CredentialProvider>>> = RwLock::new(None);
+
+/// Key ring installed with `SDKExtension::setKeyRing`, or loaded from `FPS_KEY_RING_PATH` once first used.
+/// The inner `None` means no key ring is configured and `CREDENTIAL_PROVIDER` serves every certificate.
+static KEY_RING: RwLock<Option<Option<Arc<KeyRing>>>> = RwLock::new(None);
+
 impl SDKExtension {
 
-    fn getCredentials(fileName : &str) -> Vec<u8> {
-        // Open the file
-        let filePath = CREDENTIALS_PATH.to_owned() + fileName;
-        let path = Path::new(&filePath);
-        std::fs::read(path).expect("Error while reading credentials")
+    /// Replaces the source of private keys and provisioning data for all subsequent requests.
+    pub fn setCredentialProvider(provider: Arc<dyn CredentialProvider>) {
+        *CREDENTIAL_PROVIDER.write().unwrap_or_else(|e| e.into_inner()) = Some(provider);
+    }
+
+    /// Returns the installed credential provider.
+    ///
+    /// If none was installed, uses credentials from the environment when `FPS_RSA_*_PEM` /
+    /// `FPS_PROVISIONING_DATA` are set, otherwise files under `FPS_CREDENTIALS_PATH`
+    /// (default `CREDENTIALS_PATH`).
+    pub fn credentialProvider() -> Result<Arc<dyn CredentialProvider>> {
+        if let Some(provider) = CREDENTIAL_PROVIDER.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+            return Ok(provider.clone());
+        }
+
+        let mut installed = CREDENTIAL_PROVIDER.write().unwrap_or_else(|e| e.into_inner());
+        if let Some(provider) = installed.as_ref() {
+            return Ok(provider.clone());
+        }
+
+        let provider: Arc<dyn CredentialProvider> = if EnvCredentialProvider::isConfigured() {
+            log::debug!("Using credentials from environment variables");
+            Arc::new(EnvCredentialProvider::new()?)
+        } else {
+            let path = std::env::var(CREDENTIALS_PATH_ENV).unwrap_or_else(|_| CREDENTIALS_PATH.to_string());
+            log::debug!("Using credentials from {}", path);
+            Arc::new(FileCredentialProvider::new(path))
+        };
+        *installed = Some(provider.clone());
+
+        Ok(provider)
+    }
+
+    /// Selects credentials by SPC certificate hash for all subsequent requests.
+    ///
+    /// Takes precedence over the single credential provider.
+    pub fn setKeyRing(keyRing: Arc<KeyRing>) {
+        *KEY_RING.write().unwrap_or_else(|e| e.into_inner()) = Some(Some(keyRing));
+    }
+
+    /// Returns the installed key ring, loading it from `FPS_KEY_RING_PATH` if that is set.
+    pub fn keyRing() -> Result<Option<Arc<KeyRing>>> {
+        if let Some(keyRing) = KEY_RING.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+            return Ok(keyRing.clone());
+        }
+
+        let mut installed = KEY_RING.write().unwrap_or_else(|e| e.into_inner());
+        if let Some(keyRing) = installed.as_ref() {
+            return Ok(keyRing.clone());
+        }
+
+        let keyRing = match std::env::var(KEY_RING_PATH_ENV) {
+            Ok(path) => {
+                log::debug!("Using key ring from {}", path);
+                Some(Arc::new(KeyRing::fromDirectory(path)?))
+            }
+            Err(_) => None,
+        };
+        *installed = Some(keyRing.clone());
+
+        Ok(keyRing)
+    }
+
+    /// Returns the credentials for an FPS certificate.
+    ///
+    /// Without a key ring the single credential provider is used for every certificate.
+    pub fn credentialsForCertificate(certificateHash: &[u8]) -> Result<Arc<dyn CredentialProvider>> {
+        match Self::keyRing()? {
+            Some(keyRing) => keyRing.get(certificateHash),
+            None => Self::credentialProvider(),
+        }
+    }
+
+    /// Returns the credentials selected for this SPC, or the single credential provider
+    /// if `selectCredentialsCustom` has not run.
+    fn credentialsForSPC(spcContainer: &FPSServerSPCContainer) -> Result<Arc<dyn CredentialProvider>> {
+        match spcContainer.extension.credentials.as_ref() {
+            Some(provider) => Ok(provider.clone()),
+            None => Self::credentialProvider(),
+        }
     }
 
     /// Returns private key associated with either 1024 or 2048-bit certificate
-    fn getPrivateKey(spcContainer: &FPSServerSPCContainer, keyPem: &mut Vec<u8>) -> Result<()> {
+    pub(crate) fn getPrivateKey(spcContainer: &FPSServerSPCContainer) -> Result<PKey<Private>> {
         match spcContainer.version {
             1 => {
                 log::debug!("Dealing with RSA 1024-bit Certificate");
-                *keyPem = Self::getCredentials(RSA_1024_PRIVATE_KEY_PEM);
             }
             2 => {
                 log::debug!("Dealing with RSA 2048-bit Certificate");
-                *keyPem = Self::getCredentials(RSA_2048_PRIVATE_KEY_PEM);
             }
             _ => {
                 fpsLogError!(
@@ -416,14 +273,11 @@ impl SDKExtension {
             }
         }
 
-        Ok(())
+        Self::credentialsForSPC(spcContainer)?.getPrivateKey(spcContainer.version)
     }
 
-    /// Returns provisioning data
-    pub fn getProvisioningData(provData: &mut Vec<u8>, provDataLength: &mut usize) -> Result<()> {
-        *provData = Self::getCredentials(PROVISIONING_DATA);
-        *provDataLength = provData.len();
-
-        Ok(())
+    /// Returns provisioning data of the certificate the SPC was created for
+    pub fn getProvisioningData(spcContainer: &FPSServerSPCContainer) -> Result<Arc<Vec<u8>>> {
+        Self::credentialsForSPC(spcContainer)?.getProvisioningData()
     }
 }
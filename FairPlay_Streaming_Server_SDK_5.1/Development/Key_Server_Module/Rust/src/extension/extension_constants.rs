@@ -11,6 +11,15 @@ pub const CONTENT_TYPE_UHD_STR: &str = "uhd";
 pub const CONTENT_TYPE_HD_STR: &str = "hd";
 pub const CONTENT_TYPE_SD_STR: &str = "sd";
 pub const CONTENT_TYPE_AUDIO_STR: &str = "audio";
+pub const CONTENT_TYPE_UNKNOWN_STR: &str = "unknown";
+
+pub const CONTENT_KEY_WRAPPED_STR: &str = "content-key-wrapped";
+pub const WRAPPED_KEY_ALG_STR: &str = "alg";
+pub const WRAPPED_KEY_KID_STR: &str = "kid";
+pub const WRAPPED_KEY_VALUE_STR: &str = "value";
+
+pub const ACCOUNT_ID_STR: &str = "account-id";
+pub const CHECK_IN_VERIFIED_STR: &str = "check-in-verified";
 
 /// FairPlay Streaming Version
 pub enum FairPlayStreamingVersion {
@@ -35,6 +44,29 @@ pub enum ContentType {
     uhd,
 }
 
+impl ContentType {
+    /// Parses a `content-type` value. Unrecognized names map to `unknown`.
+    pub fn fromName(name: &str) -> ContentType {
+        match name {
+            CONTENT_TYPE_UHD_STR => ContentType::uhd,
+            CONTENT_TYPE_HD_STR => ContentType::hd,
+            CONTENT_TYPE_SD_STR => ContentType::sd,
+            CONTENT_TYPE_AUDIO_STR => ContentType::audio,
+            _ => ContentType::unknown,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ContentType::uhd => CONTENT_TYPE_UHD_STR,
+            ContentType::hd => CONTENT_TYPE_HD_STR,
+            ContentType::sd => CONTENT_TYPE_SD_STR,
+            ContentType::audio => CONTENT_TYPE_AUDIO_STR,
+            ContentType::unknown => CONTENT_TYPE_UNKNOWN_STR,
+        }
+    }
+}
+
 /// FairPlay Security Levels (sent in SPC and CKC)
 ///
 /// Values are ordered so comparisions are possible
@@ -0,0 +1,573 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+//! Runtime extension hooks.
+//!
+//! Every customization point of the SDK is a method of `FpsExtension`. The default methods
+//! implement the SDK's own behavior, so an implementation only overrides the hooks it needs
+//! and can call `DefaultExtension` to keep the standard behavior around its own:
+//!
+//! ```no_run
+//! use fpssdk::base::structures::base_fps_structures::FPSOperation;
+//! use fpssdk::base::structures::base_server_structures::FPSServerCtx;
+//! use fpssdk::extension::fps_extension::{DefaultExtension, FpsExtension};
+//! use fpssdk::extension::validate::{FPSStatus, Result};
+//! use fpssdk::key_server::KeyServer;
+//! use std::sync::Arc;
+//!
+//! struct BlockedAssets;
+//!
+//! impl FpsExtension for BlockedAssets {
+//!     fn validateSPCCustom(&self, fpsOperation: &mut FPSOperation, serverCtx: &mut FPSServerCtx) -> Result<()> {
+//!         if serverCtx.spcContainer.spcData.assetId == b"blocked" {
+//!             return Err(FPSStatus::paramErr);
+//!         }
+//!         DefaultExtension.validateSPCCustom(fpsOperation, serverCtx)
+//!     }
+//! }
+//!
+//! let keyServer = KeyServer::new().withExtension(Arc::new(BlockedAssets));
+//! ```
+//!
+//! The extension used for a request is the one of the `KeyServer` processing it, otherwise the
+//! one installed with `SDKExtension::setFpsExtension` (used by the C interface and the bundled
+//! server), otherwise `DefaultExtension`.
+
+use crate::base::base_constants;
+use crate::base::base_constants::{FPSKeyDurationType, FPSTLLVTagValue};
+use crate::base::structures::base_fps_structures::{AssetInfo, FPSOperation, FPSOperations, FPSResult, FPSResults};
+use crate::base::parse_json::base_parse_json_helper::invalidFieldType;
+use crate::base::structures::base_server_structures::{FPSServerCtx, FPSServerSPCContainer, FPSServerTLLV};
+use crate::extension::extension_constants::{self, ContentType, FairPlayStreamingVersion};
+use crate::extension::key_store::formatAssetId;
+use crate::extension::structures::extension_structures::SDKExtension;
+use crate::extension_structures::FPSOperationExtension;
+use crate::logging::{jsonLogLine, LogOutput};
+use crate::validate::{FPSStatus, Result};
+use crate::Base;
+use crate::Extension;
+use crate::{fpsLogError, requireAction, returnErrorStatus};
+use serde_jsonrc::{Map, Value};
+use std::cell::RefCell;
+use std::io::Write;
+use std::sync::{Arc, RwLock};
+
+/// Customization points of the SDK. The default methods are the SDK's standard behavior.
+pub trait FpsExtension: Send + Sync {
+    ////////////////////////////////////////////////////////////////////////////////
+    // Utility Functions
+    ////////////////////////////////////////////////////////////////////////////////
+
+    /// Initializes custom log output formatting. Change the format here to match
+    /// whatever log formatting works best for your tools.
+    fn logInitCustom(&self, _extension: Option<&FPSOperationExtension>) {
+        // Release builds only print errors unless RUST_LOG (or `SDKExtension::setLogLevel`) asks for more
+        let defaultLevel = if cfg!(debug_assertions) { "trace" } else { "error" };
+        let env = env_logger::Env::new()
+            .filter_or("RUST_LOG", defaultLevel)
+            .write_style("RUST_LOG_STYLE");
+
+        // Example configuration of log::Debug!() style prints, as JSON lines when
+        // `SDKExtension::logOutput()` is `LogOutput::json`:
+        env_logger::Builder::from_env(env)
+            .format(move |buf, record| match SDKExtension::logOutput() {
+                LogOutput::text => writeln!(buf, "[DEBUG] {}", record.args()),
+                LogOutput::json => {
+                    let line = jsonLogLine(
+                        record.level().as_str(),
+                        record.file().unwrap_or("unknown file"),
+                        record.line().unwrap_or(0),
+                        &record.args().to_string(),
+                    );
+                    writeln!(buf, "{}", Value::Object(line))
+                }
+            })
+            .try_init()
+            .unwrap_or(());
+        // or match the fpsLogError!() style prints:
+        // env_logger::Builder::from_env(env)
+        //     .format(move |buf, record| {
+        //         writeln!(
+        //             buf,
+        //             "timestamp=\"{}\",FP_TOOLN=\"{}\",FP_TOOLV=\"{}\",FP_PID=\"{}\",FP_FL=\"{}\",FP_LN=\"{}\",{}",
+        //             chrono::Utc::now().format("%Y-%m-%d %T,%3f"),
+        //             env!("CARGO_PKG_NAME"),
+        //             env!("CARGO_PKG_VERSION"),
+        //             std::process::id(),
+        //             record.file().unwrap_or("unknown file"),
+        //             record.line().unwrap_or(0),
+        //             record.args()
+        //             )
+        //     })
+        // .init();
+        // or use default format:
+        // env_logger::Builder::from_env(env).try_init().unwrap_or(());
+
+        // Example configuration of fpsLogError!() style prints:
+        crate::logging::LOG_FORMAT.with(|a| {
+            let _ = a.replace(Box::new(|line, file| {
+                format!(
+                    "timestamp=\"{}\",FP_TOOLN=\"{}\",FP_TOOLV=\"{}\",FP_PID=\"{}\",FP_FL=\"{}\",FP_LN=\"{}\"",
+                    chrono::Local::now().format("%Y-%m-%d %T,%3f"),
+                    env!("CARGO_PKG_NAME"),
+                    env!("CARGO_PKG_VERSION"),
+                    std::process::id(),
+                    file,
+                    line,
+                )
+            }));
+        });
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+    // Input Parsing and Verification Functions
+    ////////////////////////////////////////////////////////////////////////////////
+
+    /// Performs any custom input json top-level parsing operations
+    fn parseOperationsCustom(&self, json: &Value, root: &mut Map<String, Value>) -> Result<()> {
+        match json.get(extension_constants::FAIRPLAY_STREAMING_REQUEST_STR) {
+            Some(Value::Object(rootObj)) => *root = rootObj.clone(),
+            Some(value) => {
+                return Err(invalidFieldType(extension_constants::FAIRPLAY_STREAMING_REQUEST_STR, "an object", value));
+            }
+            None => {
+                fpsLogError!(
+                    FPSStatus::paramErr,
+                    reason = "missing-field",
+                    "{} not found",
+                    extension_constants::FAIRPLAY_STREAMING_REQUEST_STR
+                );
+                returnErrorStatus!(FPSStatus::paramErr);
+            }
+        }
+        Ok(())
+    }
+
+    /// Performs custom parsing of account info JSON input.
+    ///
+    /// Use this function to handle any values outside of what the Base code parses
+    /// for json input `offline-hls`.
+    fn parseOfflineHLSCustom(&self, ckcObj: &Map<String, Value>, assetInfo: &mut AssetInfo) -> Result<()> {
+        // Parse "account-id" from input json
+        match ckcObj.get(extension_constants::ACCOUNT_ID_STR) {
+            Some(Value::String(accountId)) if !accountId.is_empty() => {
+                assetInfo.extension.accountId = Some(accountId.clone());
+            }
+            Some(Value::String(_)) | None => {}
+            Some(value) => return Err(invalidFieldType(extension_constants::ACCOUNT_ID_STR, "a string", value)),
+        }
+
+        Ok(())
+    }
+
+    /// Performs custom verification of account info JSON input.
+    fn verifyOfflineHLSCustom(&self, _assetInfo: &mut AssetInfo) -> Result<()> {
+        Ok(())
+    }
+
+    /// Performs custom parsing of `hdcp-type` JSON input.
+    ///
+    /// Use this function to handle any values outside of what the Base code parses.
+    fn parseHDCPTypeCustom(&self, _hdcpType: i32) -> Result<u64> {
+        // Base code already handled all known values. Treat unknown values as an error.
+        Err(FPSStatus::paramErr)
+    }
+
+    /// Performs parsing of any custom fields within the `asset-info` object of the input JSON
+    fn parseAssetInfoCustom(&self, assetInfoObj: &Value, assetInfo: &mut AssetInfo) -> Result<()> {
+        // Parse "content-type" from input json
+        if let Some(contentType) = assetInfoObj[extension_constants::CONTENT_TYPE_STR].as_str() {
+            match contentType {
+                extension_constants::CONTENT_TYPE_UHD_STR => {
+                    assetInfo.extension.contentType = ContentType::uhd;
+                }
+                extension_constants::CONTENT_TYPE_HD_STR => {
+                    assetInfo.extension.contentType = ContentType::hd;
+                }
+                extension_constants::CONTENT_TYPE_SD_STR => {
+                    assetInfo.extension.contentType = ContentType::sd;
+                }
+                extension_constants::CONTENT_TYPE_AUDIO_STR => {
+                    assetInfo.extension.contentType = ContentType::audio;
+                }
+                _ => {
+                    assetInfo.extension.contentType = ContentType::unknown;
+                }
+            }
+        } else if let Some(value) = assetInfoObj.get(extension_constants::CONTENT_TYPE_STR) {
+            return Err(invalidFieldType(extension_constants::CONTENT_TYPE_STR, "a string", value));
+        } else {
+            assetInfo.extension.contentType = ContentType::unknown;
+        }
+
+        Ok(())
+    }
+
+    /// Performs parsing of any custom fields within the `create-ckc` object of the input JSON
+    fn parseCreateCKCOperationCustom(
+        &self,
+        _fpsOperation: &mut FPSOperation,
+        _ckcObj: &Value,
+        _root: &mut &Map<String, Value>,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    /// Performs any remaining parsing of the top level input JSON after FPSOperation structure has been filled
+    fn processOperationsCustom(
+        &self,
+        _json: &Value,
+        _output: &mut Value,
+        _fpsOperations: &FPSOperations,
+        _fpsResults: &mut FPSResults,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    /// Custom handling inside createResults() if needed
+    fn createResultsCustom(&self, _fpsOperation: &mut FPSOperation, _keyTypeRequested: &mut u32) -> Result<()> {
+        Ok(())
+    }
+
+    /// Selects the credentials for the certificate named by `spcContainer.certificateHash`.
+    ///
+    /// Called right after the SPC header is parsed, before any RSA operation. When a key ring is
+    /// configured, SPCs for certificates that are not in it fail with `invalidCertificateErr`.
+    fn selectCredentialsCustom(&self, spcContainer: &mut FPSServerSPCContainer) -> Result<()> {
+        spcContainer.extension.credentials =
+            Some(SDKExtension::credentialsForCertificate(&spcContainer.certificateHash)?);
+
+        Ok(())
+    }
+
+    /// Decrypts `spcContainer.aesWrappedKey` into `aesKey`.
+    ///
+    /// Uses partner-specific private key for the RSA decyrption.
+    fn decryptKeyRSACustom(&self, spcContainer: &mut FPSServerSPCContainer, aesKey: &mut Vec<u8>) -> Result<()> {
+        let aesWrappedKeySize = spcContainer.aesWrappedKeySize;
+        let aesWrappedKey: &Vec<u8> = &spcContainer.aesWrappedKey;
+
+        // Sanity check inputs
+        requireAction!(!aesWrappedKey.is_empty(), return Err(FPSStatus::paramErr));
+
+        let pkey = SDKExtension::getPrivateKey(spcContainer)?;
+
+        if aesWrappedKeySize == base_constants::FPS_V1_WRAPPED_KEY_SZ {
+            let rsa = match pkey.rsa() {
+                Ok(rsa) => rsa,
+                Err(e) => {
+                    fpsLogError!(FPSStatus::internalErr, "Unable to load private key: {}", e);
+                    returnErrorStatus!(FPSStatus::internalErr);
+                }
+            };
+
+            *aesKey = vec![0_u8; rsa.size() as usize];
+
+            if rsa.private_decrypt(aesWrappedKey, aesKey, openssl::rsa::Padding::PKCS1_OAEP).is_err() {
+                // If decryption failed, it is likely the data was encrypted for another key.
+                fpsLogError!(FPSStatus::invalidCertificateErr, "RSA Decryption Failed");
+                returnErrorStatus!(FPSStatus::invalidCertificateErr);
+            }
+        } else if aesWrappedKeySize == base_constants::FPS_V2_WRAPPED_KEY_SZ {
+            let mut decrypter = openssl::encrypt::Decrypter::new(&pkey).unwrap();
+
+            decrypter.set_rsa_padding(openssl::rsa::Padding::PKCS1_OAEP).unwrap();
+            decrypter
+                .set_rsa_mgf1_md(openssl::hash::MessageDigest::sha256())
+                .unwrap();
+            decrypter
+                .set_rsa_oaep_md(openssl::hash::MessageDigest::sha256())
+                .unwrap();
+
+            // Get the length of the output buffer
+            let bufferLen = decrypter.decrypt_len(aesWrappedKey).unwrap();
+            let mut decoded = vec![0u8; bufferLen];
+
+            // Decrypt the data
+            match decrypter.decrypt(aesWrappedKey, &mut decoded) {
+                Ok(decodedLen) => decoded.truncate(decodedLen),
+                Err(_) => {
+                    // If decryption failed, it is likely the data was encrypted for another key.
+                    fpsLogError!(FPSStatus::invalidCertificateErr, "RSA Decryption Failed");
+                    returnErrorStatus!(FPSStatus::invalidCertificateErr);
+                }
+            }
+
+            *aesKey = decoded;
+        }
+
+        Ok(())
+    }
+
+    /// Perform any custom steps needed directly after SPC decryption
+    fn decryptSPCDataCustom(&self, _spc: &[u8], _spcContainer: &mut FPSServerSPCContainer) -> Result<()> {
+        Ok(())
+    }
+
+    /// Performs parsing of any TLLVs not handled in Base
+    fn parseTLLVCustom(&self, _tllv: &FPSServerTLLV, _value: &mut FPSServerSPCContainer) -> Result<()> {
+        Ok(())
+    }
+
+    /// Performs any custom validation after all TLLVs have been parsed.
+    fn validateTLLVsCustom(&self, _spcContainer: &mut FPSServerSPCContainer) -> Result<()> {
+        Ok(())
+    }
+
+    /// Performs parsing of any capabilities flags not handled in Base
+    fn checkSupportedFeaturesCustom(&self, _serverCtx: &mut FPSServerCtx) -> Result<()> {
+        Ok(())
+    }
+
+    /// Performs validation of SPC after SPC data is parsed.
+    fn validateSPCCustom(&self, fpsOperation: &mut FPSOperation, serverCtx: &mut FPSServerCtx) -> Result<()> {
+        // Check that business rules are satisfied
+        SDKExtension::checkBusinessRules(fpsOperation, serverCtx)?;
+
+        // Reject SPCs that were already answered (only if replay detection is configured).
+        // Done last so requests denied above do not use up the SPC.
+        // Operations of one request sharing an SPC use it up once.
+        if let Some(replayCache) = SDKExtension::replayCache()? {
+            match SDKExtension::spcCache() {
+                Some(spcCache) => spcCache.checkReplay(&replayCache, &serverCtx.spcContainer.spcData)?,
+                None => replayCache.check(&serverCtx.spcContainer.spcData)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Optional query of database to get asset information.
+    ///
+    /// If asset information is not passed in the JSON input, now is the time to use
+    /// the asset id found inside the request (`serverCtx.spcContainer.spcData.assetId`)
+    /// to query your database and fill in `fpsOperation.assetInfo`.
+    ///
+    /// When a key store is configured (see `extension::key_store`), requests without
+    /// `content-key`/`content-iv` get them from the store, and fail if it has none for the asset
+    /// (check-ins and lease renewals do not need one).
+    fn queryDatabaseCustom(&self, fpsOperation: &mut FPSOperation, serverCtx: &mut FPSServerCtx) -> Result<()> {
+        if fpsOperation.assetInfo.isCKProvided {
+            return Ok(());
+        }
+
+        let Some(keyStore) = SDKExtension::keyStore()? else {
+            return Ok(());
+        };
+
+        let assetId = &serverCtx.spcContainer.spcData.assetId;
+        match keyStore.get(assetId)? {
+            Some(record) => {
+                log::debug!("Using stored content key v{} for asset {}", record.version, formatAssetId(assetId));
+                record.applyTo(&mut fpsOperation.assetInfo);
+            }
+            None if !fpsOperation.deliversContentKey() => {}
+            None => {
+                fpsLogError!(
+                    FPSStatus::paramErr,
+                    reason = "no-stored-content-key",
+                    "no content key stored for asset {}",
+                    formatAssetId(assetId)
+                );
+                returnErrorStatus!(FPSStatus::paramErr);
+            }
+        }
+
+        Ok(())
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+    // Output Creation Functions
+    ////////////////////////////////////////////////////////////////////////////////
+
+    /// Populates `serverCtx.ckcContainer` and `fpsResult` structure with any custom fields that will be returned to the caller
+    fn populateResultsCustom(
+        &self,
+        serverCtx: &mut FPSServerCtx,
+        operation: &FPSOperation,
+        _result: &mut FPSResult,
+    ) -> Result<()> {
+        // Copy content type and account to the server context
+        serverCtx.extension.contentType = operation.assetInfo.extension.contentType;
+        serverCtx.extension.accountId = operation.assetInfo.extension.accountId.clone();
+
+        Ok(())
+    }
+
+    /// Adds Content Key payload TLLV and related data to the CKC container
+    fn createContentKeyPayloadCustom(
+        &self,
+        serverCtx: &mut FPSServerCtx,
+        keyTypeRequested: u32,
+        fpsResult: &mut FPSResult,
+    ) -> Result<()> {
+        SDKExtension::createContentKeyPayloadCustomImpl(serverCtx, keyTypeRequested)?;
+
+        // player HU
+        fpsResult.hu = serverCtx.hu.to_owned();
+
+        // Offline download limits are per device. The HU is only known from the content key
+        // payload, so the license is checked against them and reserved here.
+        SDKExtension::reserveOfflineLicense(serverCtx, fpsResult)?;
+
+        Ok(())
+    }
+
+    /// Fills version and IV of the CKC container
+    fn fillCKCContainerCustom(&self, serverCtx: &mut FPSServerCtx) -> Result<()> {
+        // Set the version
+        serverCtx.ckcContainer.version = FairPlayStreamingVersion::v1 as u32; // currently, only V1 is supported
+
+        // Generate the CKC container (AR) IV
+        Extension::genRandom(&mut serverCtx.ckcContainer.aesKeyIV, 16);
+
+        Ok(())
+    }
+
+    /// Populates hdcpInformationTag Tag
+    ///
+    /// The base code does not add this tag to the CKC by default. It is up to the extension to add it here.
+    fn HDCPInformationTagPopulateCustom(&self, serverCtx: &mut FPSServerCtx) -> Result<()> {
+        SDKExtension::populateTagServerHDCPInformation(serverCtx)
+    }
+
+    /// Populates securityLevelTag Tag
+    ///
+    /// The base code does not add this tag to the CKC by default. It is up to the extension to add it here.
+    fn securityLevelTagPopulateCustom(&self, serverCtx: &mut FPSServerCtx) -> Result<()> {
+        SDKExtension::populateTagSecurityLevel(serverCtx)
+    }
+
+    /// Populates any custom TLLVs
+    ///
+    /// One of the TLLVs indicating license expiration should be populated here (if required),
+    /// because it is not done in Base.
+    fn serializeCKCDataCustom(&self, serverCtx: &mut FPSServerCtx) -> Result<()> {
+        // Construct and serialize either offline key tag or key duration tag
+        let keyType: u32 = serverCtx.ckcContainer.ckcData.keyDuration.keyType;
+
+        if serverCtx
+            .spcContainer
+            .spcData
+            .spcDataParser
+            .parsedTagValues
+            .contains(&(FPSTLLVTagValue::mediaPlaybackStateTag as u64))
+            && (keyType != FPSKeyDurationType::none as u32)
+        {
+            if ((keyType == FPSKeyDurationType::persistenceAndDuration as u32)
+                || (keyType == FPSKeyDurationType::persistence as u32))
+                && serverCtx.spcContainer.spcData.clientFeatures.supportsOfflineKeyTLLV
+            {
+                Base::populateTagServerOfflineKey(serverCtx)?;
+            } else {
+                Base::populateTagServerKeyDuration(serverCtx)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Populates Content ID for Offline Key TLLV V1
+    fn offlineKeyTagPopulateContentIDCustom(&self, _serverCtx: &mut FPSServerCtx, offlineKeyTLLV: &mut Vec<u8>) -> Result<()> {
+        // Just add 16B of zeros
+        offlineKeyTLLV.append(&mut vec![0; 16]);
+
+        Ok(())
+    }
+
+    /// Adds any custom items to `FPSResult` after CKC has been generated
+    ///
+    /// Check-in server challenges are verified (see `extension::check_in_challenge`), then when an
+    /// offline ledger is configured (see `extension::offline_ledger`), offline licenses are recorded
+    /// and check-ins reconciled here, once the CKC is known to be good.
+    fn finalizeResultsCustom(&self, serverCtx: &FPSServerCtx, fpsResult: &mut FPSResult) -> Result<()> {
+        SDKExtension::verifyCheckIn(fpsResult)?;
+        SDKExtension::updateOfflineLedger(serverCtx, fpsResult)
+    }
+
+    /// Adds any custom fields to the 'create-ckc' object of the output JSON
+    fn serializeCreateCKCNodeCustom(&self, result: &FPSResult, ckcNode: &mut Map<String, Value>) -> Result<()> {
+        if result.isCheckIn {
+            ckcNode.insert(
+                extension_constants::CHECK_IN_VERIFIED_STR.to_string(),
+                Value::Bool(result.extension.checkInVerified),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Packages `ckcNode` into final JSON output (required).
+    fn serializeResultsCustom(
+        &self,
+        _fpsResults: &FPSResults,
+        ckcNode: Vec<Value>,
+        jsonResults: &mut Map<String, Value>,
+    ) -> Result<()> {
+        let mut root = Map::new();
+
+        root.insert(base_constants::CREATE_CKC_STR.to_string(), Value::Array(ckcNode));
+
+        // Add into top level response object
+        jsonResults.insert(
+            extension_constants::FAIRPLAY_STREAMING_RESPONSE_STR.to_string(),
+            Value::Object(root),
+        );
+
+        Ok(())
+    }
+}
+
+/// The SDK's standard behavior, with no hook overridden.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DefaultExtension;
+
+impl FpsExtension for DefaultExtension {}
+
+/// Extension installed with `SDKExtension::setFpsExtension`. `None` means `DefaultExtension`.
+static FPS_EXTENSION: RwLock<Option<Arc<dyn FpsExtension>>> = RwLock::new(None);
+
+thread_local! {
+    /// Extension of the `KeyServer` currently processing a request on this thread
+    static SCOPED_FPS_EXTENSION: RefCell<Option<Arc<dyn FpsExtension>>> = const { RefCell::new(None) };
+}
+
+/// Restores the previously scoped extension, also when the request panics.
+struct ScopedExtensionGuard {
+    previous: Option<Arc<dyn FpsExtension>>,
+}
+
+impl Drop for ScopedExtensionGuard {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        SCOPED_FPS_EXTENSION.with(|scoped| *scoped.borrow_mut() = previous);
+    }
+}
+
+impl SDKExtension {
+    /// Replaces the extension used for requests not processed by a `KeyServer` with its own.
+    pub fn setFpsExtension(fpsExtension: Arc<dyn FpsExtension>) {
+        *FPS_EXTENSION.write().unwrap_or_else(|e| e.into_inner()) = Some(fpsExtension);
+    }
+
+    /// Returns the extension hooks apply to on the calling thread.
+    pub fn fpsExtension() -> Arc<dyn FpsExtension> {
+        if let Some(fpsExtension) = SCOPED_FPS_EXTENSION.with(|scoped| scoped.borrow().clone()) {
+            return fpsExtension;
+        }
+
+        match FPS_EXTENSION.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+            Some(fpsExtension) => fpsExtension.clone(),
+            None => Arc::new(DefaultExtension),
+        }
+    }
+
+    /// Runs `f` with `fpsExtension` handling every hook called on this thread.
+    pub fn withFpsExtension<R>(fpsExtension: Arc<dyn FpsExtension>, f: impl FnOnce() -> R) -> R {
+        let previous = SCOPED_FPS_EXTENSION.with(|scoped| scoped.borrow_mut().replace(fpsExtension));
+        let _guard = ScopedExtensionGuard { previous };
+
+        f()
+    }
+}
@@ -0,0 +1,144 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+//! Creation of the content key payload, the step that needs the FairPlay Streaming crypto library.
+//!
+//! `createContentKeyPayloadCustomImpl` hands the SPC values to the installed `KeyPayloadBackend`.
+//! By default that is `FpsCryptoBackend`, which calls `KSMCreateKeyPayload` in `libfpscrypto`.
+//! Building with the `mock_key_payload` feature does not link `libfpscrypto` at all and uses
+//! `MockKeyPayloadBackend` instead, so the rest of the pipeline can be tested anywhere. The mock
+//! only exists with that feature, which release builds refuse.
+
+use crate::base::base_constants::KSMKeyPayloadContentType;
+#[cfg(feature = "mock_key_payload")]
+use crate::base::base_constants::{AES128_IV_SZ, AES128_KEY_SZ, FPS_V1_HU_SZ, FPS_V1_R1_SZ};
+use crate::base::structures::secret::SecretBytes;
+use crate::extension::structures::extension_structures::SDKExtension;
+use crate::validate::Result;
+#[cfg(feature = "mock_key_payload")]
+use crate::{fpsLogError, returnErrorStatus, validate::FPSStatus};
+#[cfg(feature = "mock_key_payload")]
+use std::mem::size_of;
+use std::sync::{Arc, RwLock};
+
+/// Content key TLLV tag used by `MockKeyPayloadBackend`.
+#[cfg(feature = "mock_key_payload")]
+pub const MOCK_CONTENT_KEY_TLLV_TAG: u64 = 0x4d4f434b434b544c;
+
+/// Prefix of every content key TLLV payload created by `MockKeyPayloadBackend`.
+#[cfg(feature = "mock_key_payload")]
+pub const MOCK_CONTENT_KEY_PAYLOAD_MAGIC: &[u8; 8] = b"FPSMOCK1";
+
+/// Inputs of `KSMCreateKeyPayload`, taken from the asset info and the SPC.
+#[derive(Debug, Clone, Copy)]
+pub struct KeyPayloadRequest<'a> {
+    pub contentKey: &'a [u8],
+    pub contentIV: &'a [u8],
+    pub contentType: KSMKeyPayloadContentType,
+    /// Value of the sessionKeyR1Tag TLLV
+    pub skR1: &'a [u8],
+    /// Value of the r2Tag TLLV
+    pub r2: &'a [u8],
+    /// Value of the sessionKeyR1IntegrityTag TLLV
+    pub r1Integrity: &'a [u8],
+    pub supportedKeyFormats: &'a [u64],
+    pub cryptoVersionUsed: u32,
+    pub provisioningData: &'a [u8],
+    /// Certificate hash from the SPC header
+    pub certHash: &'a [u8],
+}
+
+/// Outputs of `KSMCreateKeyPayload`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyPayload {
+    /// Client HU
+    pub hu: Vec<u8>,
+    pub contentKeyTLLVTag: u64,
+    pub contentKeyTLLVPayload: SecretBytes,
+    /// R1, from which the CKC encryption key is derived
+    pub r1: SecretBytes,
+}
+
+/// Creates the content key TLLV for a request.
+pub trait KeyPayloadBackend: Send + Sync {
+    fn createKeyPayload(&self, request: &KeyPayloadRequest) -> Result<KeyPayload>;
+}
+
+/// Deterministic stand-in for `libfpscrypto`, for tests only.
+///
+/// HU and R1 are derived from SK_R1, so the same SPC always gives the same values. The content
+/// key payload is `MOCK_CONTENT_KEY_PAYLOAD_MAGIC`, the content key, the content IV and the
+/// 8-byte content type, in the clear. No client can use the resulting CKC.
+#[cfg(feature = "mock_key_payload")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MockKeyPayloadBackend;
+
+#[cfg(feature = "mock_key_payload")]
+impl KeyPayloadBackend for MockKeyPayloadBackend {
+    fn createKeyPayload(&self, request: &KeyPayloadRequest) -> Result<KeyPayload> {
+        if request.contentKey.len() != AES128_KEY_SZ || request.contentIV.len() != AES128_IV_SZ {
+            fpsLogError!(FPSStatus::paramErr, "content key and IV must be 16 bytes");
+            returnErrorStatus!(FPSStatus::paramErr);
+        }
+        if request.skR1.is_empty() {
+            fpsLogError!(FPSStatus::paramErr, "SK_R1 is missing");
+            returnErrorStatus!(FPSStatus::paramErr);
+        }
+
+        let mut contentKeyTLLVPayload = SecretBytes::from(Vec::with_capacity(
+            MOCK_CONTENT_KEY_PAYLOAD_MAGIC.len() + AES128_KEY_SZ + AES128_IV_SZ + size_of::<u64>(),
+        ));
+        contentKeyTLLVPayload.extend_from_slice(MOCK_CONTENT_KEY_PAYLOAD_MAGIC);
+        contentKeyTLLVPayload.extend_from_slice(request.contentKey);
+        contentKeyTLLVPayload.extend_from_slice(request.contentIV);
+        contentKeyTLLVPayload.extend_from_slice(&(request.contentType as u64).to_be_bytes());
+
+        let mut sha = openssl::sha::Sha512::new();
+        sha.update(b"R1");
+        sha.update(request.skR1);
+        let r1 = SecretBytes::from(&sha.finish()[..FPS_V1_R1_SZ]);
+
+        let mut sha = openssl::sha::Sha1::new();
+        sha.update(b"HU");
+        sha.update(request.skR1);
+        let hu = sha.finish()[..FPS_V1_HU_SZ].to_vec();
+
+        Ok(KeyPayload {
+            hu,
+            contentKeyTLLVTag: MOCK_CONTENT_KEY_TLLV_TAG,
+            contentKeyTLLVPayload,
+            r1,
+        })
+    }
+}
+
+/// Backend installed with `SDKExtension::setKeyPayloadBackend`, or the default one once first used.
+static KEY_PAYLOAD_BACKEND: RwLock<Option<Arc<dyn KeyPayloadBackend>>> = RwLock::new(None);
+
+impl SDKExtension {
+    /// Replaces the backend that creates content key payloads for all subsequent requests.
+    pub fn setKeyPayloadBackend(backend: Arc<dyn KeyPayloadBackend>) {
+        *KEY_PAYLOAD_BACKEND.write().unwrap_or_else(|e| e.into_inner()) = Some(backend);
+    }
+
+    /// Returns the installed backend, `FpsCryptoBackend` (or `MockKeyPayloadBackend` with the
+    /// `mock_key_payload` feature) if none was installed.
+    pub fn keyPayloadBackend() -> Arc<dyn KeyPayloadBackend> {
+        if let Some(backend) = KEY_PAYLOAD_BACKEND.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+            return backend.clone();
+        }
+
+        #[cfg(feature = "mock_key_payload")]
+        let backend: Arc<dyn KeyPayloadBackend> = Arc::new(MockKeyPayloadBackend);
+        #[cfg(not(feature = "mock_key_payload"))]
+        let backend: Arc<dyn KeyPayloadBackend> =
+            Arc::new(crate::extension::construct_ckc_TLLVs::createContentKeyPayload::FpsCryptoBackend);
+
+        KEY_PAYLOAD_BACKEND
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .get_or_insert(backend)
+            .clone()
+    }
+}
@@ -0,0 +1,418 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+//! Content keys stored in a local SQLite database, keyed by the asset ID the client puts in the SPC.
+//!
+//! When a `create-ckc` request does not carry `content-key`/`content-iv`, `queryDatabaseCustom`
+//! looks the asset up here and fills in `AssetInfo`. Packagers register keys once with
+//! `fpssdk_local keys import` instead of sending them with every license request.
+
+use crate::base::base_constants::{self, FPSHDCPRequirement};
+use crate::base::structures::base_fps_structures::AssetInfo;
+use crate::base::structures::secret::SecretBytes;
+use crate::extension::extension_constants::{self, ContentType};
+use crate::extension::structures::extension_structures::SDKExtension;
+use crate::validate::{FPSStatus, Result};
+use crate::{fpsLogError, returnErrorStatus};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde_jsonrc::Value;
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+
+/// Environment variable naming the key store database opened by `SDKExtension::keyStore`.
+pub const KEY_STORE_PATH_ENV: &str = "FPS_KEY_STORE_PATH";
+
+/// JSON field naming the asset in key import files.
+pub const ASSET_ID_STR: &str = "asset-id";
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS content_keys (
+        asset_id          BLOB PRIMARY KEY NOT NULL,
+        content_key       BLOB NOT NULL,
+        content_iv        BLOB NOT NULL,
+        content_type      TEXT,
+        hdcp_type         INTEGER,
+        lease_duration    INTEGER,
+        rental_duration   INTEGER,
+        playback_duration INTEGER,
+        version           INTEGER NOT NULL,
+        updated_at        INTEGER NOT NULL
+    );
+";
+
+/// A content key and the asset information that goes with it.
+///
+/// Optional fields override the values parsed from `asset-info` when set.
+#[derive(Debug, Clone, Default)]
+pub struct KeyRecord {
+    pub assetId: Vec<u8>,
+    pub key: SecretBytes,
+    pub iv: SecretBytes,
+    pub contentType: Option<ContentType>,
+    /// Same values as `hdcp-type` in `asset-info` (-1 = not required, 0 = type 0, 1 = type 1)
+    pub hdcpType: Option<i32>,
+    pub leaseDuration: Option<u32>,
+    pub rentalDuration: Option<u32>,
+    pub playbackDuration: Option<u32>,
+    /// Starts at 1 and is incremented each time the key is replaced
+    pub version: u32,
+    /// Unix time of the last change
+    pub updatedAt: i64,
+}
+
+/// Parses an asset ID as written on the command line or in an import file.
+///
+/// Asset IDs are usually the text of the `skd://` URI; binary IDs can be given as `0x` + hex.
+pub fn parseAssetId(assetId: &str) -> Result<Vec<u8>> {
+    match assetId.strip_prefix("0x") {
+        Some(hexId) => decodeHex(hexId, ASSET_ID_STR),
+        None if !assetId.is_empty() => Ok(assetId.as_bytes().to_vec()),
+        None => {
+            fpsLogError!(FPSStatus::paramErr, "empty asset ID");
+            returnErrorStatus!(FPSStatus::paramErr);
+        }
+    }
+}
+
+/// Formats an asset ID the way `parseAssetId` reads it.
+pub fn formatAssetId(assetId: &[u8]) -> String {
+    match std::str::from_utf8(assetId) {
+        Ok(text) if !text.starts_with("0x") && text.chars().all(|c| !c.is_control()) => text.to_string(),
+        _ => format!("0x{}", hex::encode(assetId)),
+    }
+}
+
+fn decodeHex(value: &str, field: &str) -> Result<Vec<u8>> {
+    match hex::decode(value.strip_prefix("0x").unwrap_or(value)) {
+        Ok(bytes) => Ok(bytes),
+        Err(e) => {
+            fpsLogError!(FPSStatus::paramErr, "unable to decode {}: {}", field, e);
+            returnErrorStatus!(FPSStatus::paramErr);
+        }
+    }
+}
+
+fn checkKeyAndIV(key: &[u8], iv: &[u8]) -> Result<()> {
+    if key.len() != base_constants::AES128_KEY_SZ || iv.len() != base_constants::AES128_IV_SZ {
+        fpsLogError!(
+            FPSStatus::paramErr,
+            "content key and IV must be {} and {} bytes (got {} and {})",
+            base_constants::AES128_KEY_SZ,
+            base_constants::AES128_IV_SZ,
+            key.len(),
+            iv.len()
+        );
+        returnErrorStatus!(FPSStatus::paramErr);
+    }
+    Ok(())
+}
+
+/// Content keys are drawn from the OpenSSL CSPRNG directly, so no installed `RandomSource` can influence them.
+fn randomSecret(length: usize) -> Result<SecretBytes> {
+    let mut secret = SecretBytes::zeroed(length);
+    if let Err(e) = openssl::rand::rand_bytes(&mut secret) {
+        fpsLogError!(FPSStatus::internalErr, "unable to generate content key: {}", e);
+        returnErrorStatus!(FPSStatus::internalErr);
+    }
+    Ok(secret)
+}
+
+fn databaseError(e: rusqlite::Error) -> FPSStatus {
+    fpsLogError!(FPSStatus::internalErr, "key store error: {}", e);
+    FPSStatus::internalErr
+}
+
+impl KeyRecord {
+    /// Parses one entry of a key import file. Field names match `asset-info`, plus `asset-id`.
+    pub fn fromJson(entry: &Value) -> Result<KeyRecord> {
+        let text = |field: &str| entry[field].as_str();
+        let number = |field: &str| -> Result<Option<u32>> {
+            match &entry[field] {
+                Value::Null => Ok(None),
+                value => match value.as_u64().and_then(|n| u32::try_from(n).ok()) {
+                    Some(n) => Ok(Some(n)),
+                    None => {
+                        fpsLogError!(FPSStatus::paramErr, "invalid {}: {}", field, value);
+                        returnErrorStatus!(FPSStatus::paramErr);
+                    }
+                },
+            }
+        };
+
+        let (Some(assetId), Some(key), Some(iv)) = (
+            text(ASSET_ID_STR),
+            text(base_constants::CONTENT_KEY_STR),
+            text(base_constants::CONTENT_IV_STR),
+        ) else {
+            fpsLogError!(
+                FPSStatus::paramErr,
+                "key entries require {}, {} and {}",
+                ASSET_ID_STR,
+                base_constants::CONTENT_KEY_STR,
+                base_constants::CONTENT_IV_STR
+            );
+            returnErrorStatus!(FPSStatus::paramErr);
+        };
+
+        let hdcpType = match &entry[base_constants::HDCP_TYPE_STR] {
+            Value::Null => None,
+            value => match value.as_i64() {
+                Some(hdcpType @ -1..=1) => Some(hdcpType as i32),
+                _ => {
+                    fpsLogError!(FPSStatus::paramErr, "invalid {}: {}", base_constants::HDCP_TYPE_STR, value);
+                    returnErrorStatus!(FPSStatus::paramErr);
+                }
+            },
+        };
+
+        let record = KeyRecord {
+            assetId: parseAssetId(assetId)?,
+            key: decodeHex(key, base_constants::CONTENT_KEY_STR)?.into(),
+            iv: decodeHex(iv, base_constants::CONTENT_IV_STR)?.into(),
+            contentType: text(extension_constants::CONTENT_TYPE_STR).map(ContentType::fromName),
+            hdcpType,
+            leaseDuration: number(base_constants::LEASE_DURATION_STR)?,
+            rentalDuration: number(base_constants::RENTAL_DURATION_STR)?,
+            playbackDuration: number(base_constants::PLAYBACK_DURATION_STR)?,
+            version: 0,
+            updatedAt: 0,
+        };
+        checkKeyAndIV(&record.key, &record.iv)?;
+
+        Ok(record)
+    }
+
+    fn fromRow(row: &Row) -> rusqlite::Result<KeyRecord> {
+        Ok(KeyRecord {
+            assetId: row.get("asset_id")?,
+            key: row.get::<_, Vec<u8>>("content_key")?.into(),
+            iv: row.get::<_, Vec<u8>>("content_iv")?.into(),
+            contentType: row
+                .get::<_, Option<String>>("content_type")?
+                .map(|name| ContentType::fromName(&name)),
+            hdcpType: row.get("hdcp_type")?,
+            leaseDuration: row.get("lease_duration")?,
+            rentalDuration: row.get("rental_duration")?,
+            playbackDuration: row.get("playback_duration")?,
+            version: row.get("version")?,
+            updatedAt: row.get("updated_at")?,
+        })
+    }
+
+    /// Copies the key and every stored field into `assetInfo`.
+    pub fn applyTo(&self, assetInfo: &mut AssetInfo) {
+        assetInfo.key = self.key.clone();
+        assetInfo.iv = self.iv.clone();
+        assetInfo.isCKProvided = true;
+
+        if let Some(contentType) = self.contentType {
+            assetInfo.extension.contentType = contentType;
+        }
+        if let Some(hdcpType) = self.hdcpType {
+            assetInfo.hdcpReq = match hdcpType {
+                0 => FPSHDCPRequirement::hdcpType0 as u64,
+                1 => FPSHDCPRequirement::hdcpType1 as u64,
+                _ => FPSHDCPRequirement::hdcpNotRequired as u64,
+            };
+        }
+        if let Some(leaseDuration) = self.leaseDuration {
+            assetInfo.leaseDuration = if leaseDuration == 0 {
+                base_constants::NO_LEASE_DURATION
+            } else {
+                leaseDuration
+            };
+        }
+        if let Some(rentalDuration) = self.rentalDuration {
+            assetInfo.rentalDuration = rentalDuration;
+        }
+        if let Some(playbackDuration) = self.playbackDuration {
+            assetInfo.playbackDuration = playbackDuration;
+        }
+    }
+}
+
+/// Local content key database.
+pub struct KeyStore {
+    connection: Mutex<Connection>,
+}
+
+impl KeyStore {
+    /// Opens (creating if needed) the database at `path`.
+    pub fn open(path: impl AsRef<Path>) -> Result<KeyStore> {
+        let path = path.as_ref();
+        match Connection::open(path) {
+            Ok(connection) => KeyStore::fromConnection(connection),
+            Err(e) => {
+                fpsLogError!(FPSStatus::internalErr, "Unable to open key store {}: {}", path.display(), e);
+                returnErrorStatus!(FPSStatus::internalErr);
+            }
+        }
+    }
+
+    /// Opens a database that lives only as long as the returned store.
+    pub fn openInMemory() -> Result<KeyStore> {
+        KeyStore::fromConnection(Connection::open_in_memory().map_err(databaseError)?)
+    }
+
+    fn fromConnection(connection: Connection) -> Result<KeyStore> {
+        connection.execute_batch(SCHEMA).map_err(databaseError)?;
+        Ok(KeyStore {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    fn connection(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.connection.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Returns the key registered for `assetId`, if any.
+    pub fn get(&self, assetId: &[u8]) -> Result<Option<KeyRecord>> {
+        self.connection()
+            .query_row(
+                "SELECT * FROM content_keys WHERE asset_id = ?1",
+                params![assetId],
+                KeyRecord::fromRow,
+            )
+            .optional()
+            .map_err(databaseError)
+    }
+
+    /// Returns every registered key, ordered by asset ID.
+    pub fn list(&self) -> Result<Vec<KeyRecord>> {
+        let connection = self.connection();
+        let mut statement = connection
+            .prepare("SELECT * FROM content_keys ORDER BY asset_id")
+            .map_err(databaseError)?;
+        let records = statement
+            .query_map([], KeyRecord::fromRow)
+            .map_err(databaseError)?
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .map_err(databaseError)?;
+        Ok(records)
+    }
+
+    /// Adds or replaces keys. Either every record is stored or none is.
+    pub fn put(&self, records: &[KeyRecord]) -> Result<()> {
+        for record in records {
+            checkKeyAndIV(&record.key, &record.iv)?;
+        }
+
+        let mut connection = self.connection();
+        let transaction = connection.transaction().map_err(databaseError)?;
+        for record in records {
+            transaction
+                .execute(
+                    "INSERT INTO content_keys (asset_id, content_key, content_iv, content_type, hdcp_type,
+                                               lease_duration, rental_duration, playback_duration, version, updated_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, 1, ?9)
+                     ON CONFLICT (asset_id) DO UPDATE SET
+                         content_key = excluded.content_key, content_iv = excluded.content_iv,
+                         content_type = excluded.content_type, hdcp_type = excluded.hdcp_type,
+                         lease_duration = excluded.lease_duration, rental_duration = excluded.rental_duration,
+                         playback_duration = excluded.playback_duration,
+                         version = content_keys.version + 1, updated_at = excluded.updated_at",
+                    params![
+                        record.assetId,
+                        record.key.as_slice(),
+                        record.iv.as_slice(),
+                        record.contentType.map(|contentType| contentType.name()),
+                        record.hdcpType,
+                        record.leaseDuration,
+                        record.rentalDuration,
+                        record.playbackDuration,
+                        chrono::Utc::now().timestamp(),
+                    ],
+                )
+                .map_err(databaseError)?;
+        }
+        transaction.commit().map_err(databaseError)
+    }
+
+    /// Imports a JSON array of key entries (see `KeyRecord::fromJson`). Returns how many were stored.
+    pub fn importJson(&self, json: &str) -> Result<usize> {
+        let entries = match serde_jsonrc::from_str::<Value>(json) {
+            Ok(Value::Array(entries)) => entries,
+            Ok(_) => {
+                fpsLogError!(FPSStatus::paramErr, "key import file must contain a JSON array");
+                returnErrorStatus!(FPSStatus::paramErr);
+            }
+            Err(e) => {
+                fpsLogError!(FPSStatus::paramErr, "invalid key import file: {}", e);
+                returnErrorStatus!(FPSStatus::paramErr);
+            }
+        };
+
+        let records = entries.iter().map(KeyRecord::fromJson).collect::<Result<Vec<_>>>()?;
+        self.put(&records)?;
+        Ok(records.len())
+    }
+
+    /// Replaces the key and IV of an existing asset, keeping its other settings.
+    ///
+    /// Fresh random values are generated for whichever of `key`/`iv` is not given.
+    pub fn rotate(&self, assetId: &[u8], key: Option<SecretBytes>, iv: Option<SecretBytes>) -> Result<KeyRecord> {
+        let Some(mut record) = self.get(assetId)? else {
+            fpsLogError!(FPSStatus::paramErr, "no content key stored for asset {}", formatAssetId(assetId));
+            returnErrorStatus!(FPSStatus::paramErr);
+        };
+
+        record.key = match key {
+            Some(key) => key,
+            None => randomSecret(base_constants::AES128_KEY_SZ)?,
+        };
+        record.iv = match iv {
+            Some(iv) => iv,
+            None => randomSecret(base_constants::AES128_IV_SZ)?,
+        };
+        self.put(std::slice::from_ref(&record))?;
+
+        Ok(self.get(assetId)?.unwrap_or(record))
+    }
+
+    /// Removes the key for `assetId`. Returns false if none was stored.
+    pub fn remove(&self, assetId: &[u8]) -> Result<bool> {
+        self.connection()
+            .execute("DELETE FROM content_keys WHERE asset_id = ?1", params![assetId])
+            .map(|deleted| deleted > 0)
+            .map_err(databaseError)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Installed key store
+////////////////////////////////////////////////////////////////////////////////
+
+/// Key store installed with `SDKExtension::setKeyStore`, or opened from `FPS_KEY_STORE_PATH` once first used.
+/// The inner `None` means no key store is configured.
+static KEY_STORE: RwLock<Option<Option<Arc<KeyStore>>>> = RwLock::new(None);
+
+impl SDKExtension {
+    /// Looks up content keys in `keyStore` for requests that do not carry one.
+    pub fn setKeyStore(keyStore: Arc<KeyStore>) {
+        *KEY_STORE.write().unwrap_or_else(|e| e.into_inner()) = Some(Some(keyStore));
+    }
+
+    /// Returns the installed key store, opening `FPS_KEY_STORE_PATH` if that is set.
+    pub fn keyStore() -> Result<Option<Arc<KeyStore>>> {
+        if let Some(keyStore) = KEY_STORE.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+            return Ok(keyStore.clone());
+        }
+
+        let mut installed = KEY_STORE.write().unwrap_or_else(|e| e.into_inner());
+        if let Some(keyStore) = installed.as_ref() {
+            return Ok(keyStore.clone());
+        }
+
+        let keyStore = match std::env::var(KEY_STORE_PATH_ENV) {
+            Ok(path) => {
+                log::debug!("Using key store {}", path);
+                Some(Arc::new(KeyStore::open(path)?))
+            }
+            Err(_) => None,
+        };
+        *installed = Some(keyStore.clone());
+
+        Ok(keyStore)
+    }
+}
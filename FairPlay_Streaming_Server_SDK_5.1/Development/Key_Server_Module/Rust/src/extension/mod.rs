@@ -7,6 +7,18 @@ pub mod credentials;
 pub mod structures;
 
 pub mod business_rules;
+pub mod check_in_challenge;
 pub mod extension;
 pub mod extension_constants;
+pub mod fps_extension;
+pub mod key_payload;
+pub mod key_store;
+pub mod offline_ledger;
+pub mod parallel;
+pub mod policy;
+pub mod random;
+pub mod replay_cache;
+pub mod schema;
+pub mod spc_cache;
 pub mod validate;
+pub mod wrapped_key;
@@ -0,0 +1,577 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+//! Offline (persistent) licenses issued by the server, kept in a local SQLite database.
+//!
+//! Every successful `offline-hls` license is recorded with the client HU, stream and title IDs,
+//! durations and issue time. A device holds at most one license per stream ID: issuing it
+//! again replaces the earlier one. Check-in SPCs (`check-in: true`) with a verified server
+//! challenge (see `extension::check_in_challenge`) mark the content IDs the client reports as
+//! securely deleted as returned. Each challenge is accepted once, so replaying a check-in SPC
+//! cannot return licenses issued after it.
+//!
+//! The `offline-limits` of the policy (see `extension::policy`) are counted here. A license is
+//! reserved, checked against the limits and recorded in one transaction once the content key
+//! payload gives the HU, so concurrent requests cannot all pass the limits. The reservation is
+//! undone if the CKC is not generated.
+//!
+//! Disabled unless `FPS_OFFLINE_LEDGER` names a database or a ledger is installed with
+//! `SDKExtension::setOfflineLedger`.
+
+use crate::base::base_constants::{FPSKeyDurationType, FPS_OFFLINE_CONTENTID_LENGTH};
+use crate::base::structures::base_fps_structures::FPSResult;
+use crate::base::structures::base_server_structures::FPSServerCtx;
+use crate::extension::policy::OfflineLimits;
+use crate::extension::structures::extension_structures::SDKExtension;
+use crate::validate::{FPSStatus, Result};
+use crate::{fpsLogError, returnErrorStatus};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+/// Environment variable naming the ledger database opened by `SDKExtension::offlineLedger`.
+pub const OFFLINE_LEDGER_ENV: &str = "FPS_OFFLINE_LEDGER";
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS offline_licenses (
+        hu                BLOB NOT NULL,
+        stream_id         BLOB NOT NULL,
+        title_id          BLOB NOT NULL,
+        account_id        TEXT,
+        rental_duration   INTEGER NOT NULL,
+        playback_duration INTEGER NOT NULL,
+        issued_at         INTEGER NOT NULL,
+        returned_at       INTEGER,
+        reservation_id    INTEGER,
+        PRIMARY KEY (hu, stream_id)
+    );
+    CREATE INDEX IF NOT EXISTS offline_licenses_account_id ON offline_licenses (account_id);
+    CREATE TABLE IF NOT EXISTS check_in_challenges (
+        server_challenge  INTEGER PRIMARY KEY NOT NULL,
+        hu                BLOB NOT NULL,
+        checked_in_at     INTEGER NOT NULL
+    );
+";
+
+/// Licenses that are not returned and, for rentals, not past their rental duration.
+const ACTIVE: &str = "returned_at IS NULL AND (rental_duration = 0 OR issued_at + rental_duration > ?2)";
+
+/// One persistent license issued to a device.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OfflineLicenseRecord {
+    pub hu: Vec<u8>,
+    /// Content ID of the persistent key, empty if the request had no `stream-id`
+    pub streamId: Vec<u8>,
+    pub titleId: Vec<u8>,
+    /// `account-id` of the `offline-hls` request object, if any
+    pub accountId: Option<String>,
+    /// Seconds from download, 0 for none
+    pub rentalDuration: u32,
+    /// Seconds from first playback, 0 for none
+    pub playbackDuration: u32,
+    /// Unix time the license was issued
+    pub issuedAt: i64,
+    /// Unix time a check-in reported the license deleted
+    pub returnedAt: Option<i64>,
+}
+
+impl OfflineLicenseRecord {
+    /// Whether the license still counts against the device, as of Unix time `now`.
+    pub fn isActive(&self, now: i64) -> bool {
+        self.returnedAt.is_none() && (self.rentalDuration == 0 || self.issuedAt + i64::from(self.rentalDuration) > now)
+    }
+
+    /// The license `serverCtx` is about to issue at Unix time `now`, or `None` if it is not an offline license.
+    pub fn fromServerCtx(serverCtx: &FPSServerCtx, now: i64) -> Option<OfflineLicenseRecord> {
+        let keyDuration = &serverCtx.ckcContainer.ckcData.keyDuration;
+        let isPersistent = keyDuration.keyType == FPSKeyDurationType::persistence as u32
+            || keyDuration.keyType == FPSKeyDurationType::persistenceAndDuration as u32;
+
+        isPersistent.then(|| OfflineLicenseRecord {
+            hu: serverCtx.hu.clone(),
+            streamId: serverCtx.streamId.clone().unwrap_or_default(),
+            titleId: serverCtx.titleId.clone().unwrap_or_default(),
+            accountId: serverCtx.extension.accountId.clone(),
+            rentalDuration: keyDuration.rentalDuration,
+            playbackDuration: keyDuration.playbackDuration,
+            issuedAt: now,
+            returnedAt: None,
+        })
+    }
+
+    fn fromRow(row: &Row) -> rusqlite::Result<OfflineLicenseRecord> {
+        Ok(OfflineLicenseRecord {
+            hu: row.get("hu")?,
+            streamId: row.get("stream_id")?,
+            titleId: row.get("title_id")?,
+            accountId: row.get("account_id")?,
+            rentalDuration: row.get("rental_duration")?,
+            playbackDuration: row.get("playback_duration")?,
+            issuedAt: row.get("issued_at")?,
+            returnedAt: row.get("returned_at")?,
+        })
+    }
+}
+
+/// Whose licenses to look up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LicenseHolder {
+    /// A device, by HU
+    device(Vec<u8>),
+    /// Every device of an account, by `account-id`
+    account(String),
+}
+
+fn databaseError(e: rusqlite::Error) -> FPSStatus {
+    fpsLogError!(FPSStatus::internalErr, "offline ledger error: {}", e);
+    FPSStatus::internalErr
+}
+
+/// Local database of issued offline licenses.
+pub struct OfflineLedger {
+    connection: Mutex<Connection>,
+}
+
+impl OfflineLedger {
+    /// Opens (creating if needed) the database at `path`.
+    pub fn open(path: impl AsRef<Path>) -> Result<OfflineLedger> {
+        let path = path.as_ref();
+        match Connection::open(path) {
+            Ok(connection) => {
+                // Other processes may hold the write lock briefly
+                connection.busy_timeout(Duration::from_secs(5)).map_err(databaseError)?;
+                OfflineLedger::fromConnection(connection)
+            }
+            Err(e) => {
+                fpsLogError!(
+                    FPSStatus::internalErr,
+                    "Unable to open offline ledger {}: {}",
+                    path.display(),
+                    e
+                );
+                returnErrorStatus!(FPSStatus::internalErr);
+            }
+        }
+    }
+
+    /// Opens a database that lives only as long as the returned ledger.
+    pub fn openInMemory() -> Result<OfflineLedger> {
+        OfflineLedger::fromConnection(Connection::open_in_memory().map_err(databaseError)?)
+    }
+
+    fn fromConnection(connection: Connection) -> Result<OfflineLedger> {
+        connection.execute_batch(SCHEMA).map_err(databaseError)?;
+
+        // Ledgers created before reservations had ids
+        let hasReservationId = connection
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('offline_licenses') WHERE name = 'reservation_id'",
+                [],
+                |row| row.get::<_, i64>(0),
+            )
+            .map_err(databaseError)?
+            > 0;
+        if !hasReservationId {
+            connection
+                .execute("ALTER TABLE offline_licenses ADD COLUMN reservation_id INTEGER", [])
+                .map_err(databaseError)?;
+        }
+        connection
+            .execute(
+                "CREATE INDEX IF NOT EXISTS offline_licenses_reservation_id ON offline_licenses (reservation_id)",
+                [],
+            )
+            .map_err(databaseError)?;
+
+        Ok(OfflineLedger {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    fn connection(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.connection.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Records an issued license, replacing the one the device held for the same stream ID.
+    pub fn record(&self, license: &OfflineLicenseRecord) -> Result<()> {
+        OfflineLedger::recordIn(&self.connection(), license, None)
+    }
+
+    /// `reservationId` identifies the row while the reservation that recorded it is pending.
+    fn recordIn(connection: &Connection, license: &OfflineLicenseRecord, reservationId: Option<i64>) -> Result<()> {
+        connection
+            .execute(
+                "INSERT OR REPLACE INTO offline_licenses (hu, stream_id, title_id, account_id, rental_duration,
+                                                          playback_duration, issued_at, returned_at, reservation_id)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                params![
+                    license.hu,
+                    license.streamId,
+                    license.titleId,
+                    license.accountId,
+                    license.rentalDuration,
+                    license.playbackDuration,
+                    license.issuedAt,
+                    license.returnedAt,
+                    reservationId,
+                ],
+            )
+            .map(|_| ())
+            .map_err(databaseError)
+    }
+
+    /// Reconciles a check-in: marks the licenses of `hu` for `deletedContentIDs` (concatenated
+    /// 16-byte content IDs) as returned. Returns how many active licenses were returned.
+    ///
+    /// Fails with `replayErr` if `serverChallenge` was already used by a check-in. A zero
+    /// challenge (SyncTLLV v1, or no challenge given to the client) cannot be verified, so
+    /// nothing is returned for it.
+    pub fn checkIn(&self, hu: &[u8], serverChallenge: u64, deletedContentIDs: &[u8], now: i64) -> Result<usize> {
+        if serverChallenge == 0 {
+            if !deletedContentIDs.is_empty() {
+                log::warn!("Check-in without a server challenge, deleted licenses are not returned");
+            }
+            return Ok(0);
+        }
+
+        let mut connection = self.connection();
+        let transaction = connection
+            .transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)
+            .map_err(databaseError)?;
+        let inserted = transaction
+            .execute(
+                "INSERT OR IGNORE INTO check_in_challenges (server_challenge, hu, checked_in_at) VALUES (?1, ?2, ?3)",
+                params![serverChallenge as i64, hu, now],
+            )
+            .map_err(databaseError)?;
+        if inserted == 0 {
+            fpsLogError!(
+                FPSStatus::replayErr,
+                reason = "check-in-challenge-reused",
+                "check-in server challenge {} was already used",
+                serverChallenge
+            );
+            returnErrorStatus!(FPSStatus::replayErr);
+        }
+
+        let mut returned = 0;
+        for contentId in deletedContentIDs.chunks(FPS_OFFLINE_CONTENTID_LENGTH) {
+            returned += transaction
+                .execute(
+                    &format!(
+                        "UPDATE offline_licenses SET returned_at = ?2 WHERE {} AND hu = ?1 AND stream_id = ?3",
+                        ACTIVE
+                    ),
+                    params![hu, now, contentId],
+                )
+                .map_err(databaseError)?;
+        }
+        transaction.commit().map_err(databaseError)?;
+
+        Ok(returned)
+    }
+
+    /// Returns the licenses of `holder` that are active at Unix time `now`, oldest first.
+    pub fn activeLicenses(&self, holder: &LicenseHolder, now: i64) -> Result<Vec<OfflineLicenseRecord>> {
+        OfflineLedger::activeLicensesIn(&self.connection(), holder, now)
+    }
+
+    fn activeLicensesIn(
+        connection: &Connection,
+        holder: &LicenseHolder,
+        now: i64,
+    ) -> Result<Vec<OfflineLicenseRecord>> {
+        let (column, key): (&str, &dyn rusqlite::ToSql) = match holder {
+            LicenseHolder::device(hu) => ("hu", hu),
+            LicenseHolder::account(accountId) => ("account_id", accountId),
+        };
+        let mut statement = connection
+            .prepare(&format!(
+                "SELECT * FROM offline_licenses WHERE {} = ?1 AND {} ORDER BY issued_at",
+                column, ACTIVE
+            ))
+            .map_err(databaseError)?;
+        let licenses = statement
+            .query_map(params![key, now], OfflineLicenseRecord::fromRow)
+            .map_err(databaseError)?
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .map_err(databaseError)?;
+        Ok(licenses)
+    }
+
+    /// Returns how many offline licenses `holder` holds right now.
+    pub fn activeLicenseCount(&self, holder: &LicenseHolder) -> Result<usize> {
+        Ok(self.activeLicenses(holder, chrono::Utc::now().timestamp())?.len())
+    }
+
+    /// Fails with `offlineLimitErr` if issuing `license` would go over `limits`.
+    ///
+    /// Downloading a stream the device already holds replaces that license, so it does not
+    /// count again unless `requireCheckInBeforeRedownload` forbids it.
+    pub fn checkLimits(&self, limits: &OfflineLimits, license: &OfflineLicenseRecord) -> Result<()> {
+        OfflineLedger::checkLimitsIn(&self.connection(), limits, license)
+    }
+
+    fn checkLimitsIn(connection: &Connection, limits: &OfflineLimits, license: &OfflineLicenseRecord) -> Result<()> {
+        let held =
+            OfflineLedger::activeLicensesIn(connection, &LicenseHolder::device(license.hu.clone()), license.issuedAt)?;
+        let isRedownload = held.iter().any(|held| held.streamId == license.streamId);
+
+        if isRedownload && limits.requireCheckInBeforeRedownload {
+            fpsLogError!(
+                FPSStatus::offlineLimitErr,
+                reason = "offline-check-in-required",
+                "device already holds stream {}, it must be checked in before downloading it again",
+                hex::encode(&license.streamId)
+            );
+            returnErrorStatus!(FPSStatus::offlineLimitErr);
+        }
+
+        if let Some(maxLicenses) = limits.maxLicensesPerDevice {
+            if !isRedownload && held.len() >= maxLicenses {
+                fpsLogError!(
+                    FPSStatus::offlineLimitErr,
+                    reason = "offline-device-limit",
+                    "device holds {} offline licenses (limit {})",
+                    held.len(),
+                    maxLicenses
+                );
+                returnErrorStatus!(FPSStatus::offlineLimitErr);
+            }
+        }
+
+        if let (Some(maxDevices), Some(accountId)) = (limits.maxDevicesPerAccountTitle, &license.accountId) {
+            let accountLicenses = OfflineLedger::activeLicensesIn(
+                connection,
+                &LicenseHolder::account(accountId.clone()),
+                license.issuedAt,
+            )?;
+            let devices: BTreeSet<&Vec<u8>> = accountLicenses
+                .iter()
+                .filter(|held| held.titleId == license.titleId)
+                .map(|held| &held.hu)
+                .collect();
+            if !devices.contains(&license.hu) && devices.len() >= maxDevices {
+                fpsLogError!(
+                    FPSStatus::offlineLimitErr,
+                    reason = "offline-account-limit",
+                    "account {} holds title {} on {} devices (limit {})",
+                    accountId,
+                    hex::encode(&license.titleId),
+                    devices.len(),
+                    maxDevices
+                );
+                returnErrorStatus!(FPSStatus::offlineLimitErr);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl OfflineLedger {
+    /// Checks `license` against `limits` and records it, in one transaction.
+    ///
+    /// The license counts against the limits of other requests from then on. Dropping the
+    /// reservation without `commit` restores the license the device held for that stream before.
+    pub fn reserve(
+        self: &Arc<OfflineLedger>,
+        limits: &OfflineLimits,
+        license: &OfflineLicenseRecord,
+    ) -> Result<OfflineReservation> {
+        let mut connection = self.connection();
+        let transaction = connection
+            .transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)
+            .map_err(databaseError)?;
+
+        OfflineLedger::checkLimitsIn(&transaction, limits, license)?;
+        let replaced = transaction
+            .query_row(
+                "SELECT * FROM offline_licenses WHERE hu = ?1 AND stream_id = ?2",
+                params![license.hu, license.streamId],
+                |row| Ok((OfflineLicenseRecord::fromRow(row)?, row.get("reservation_id")?)),
+            )
+            .optional()
+            .map_err(databaseError)?;
+        // Requests issued in the same second record identical licenses, so only the id tells
+        // this reservation's row apart
+        let id = rand::random();
+        OfflineLedger::recordIn(&transaction, license, Some(id))?;
+        transaction.commit().map_err(databaseError)?;
+
+        Ok(OfflineReservation {
+            ledger: self.clone(),
+            id,
+            license: license.clone(),
+            replaced,
+            committed: AtomicBool::new(false),
+        })
+    }
+
+    /// Undoes reservation `id`, unless another request issued the stream again in the meantime.
+    fn release(&self, id: i64, replaced: Option<&(OfflineLicenseRecord, Option<i64>)>) -> Result<()> {
+        let mut connection = self.connection();
+        let transaction = connection
+            .transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)
+            .map_err(databaseError)?;
+
+        let deleted = transaction
+            .execute("DELETE FROM offline_licenses WHERE reservation_id = ?1", params![id])
+            .map_err(databaseError)?;
+        if let (1, Some((replaced, replacedId))) = (deleted, replaced) {
+            OfflineLedger::recordIn(&transaction, replaced, *replacedId)?;
+        }
+        transaction.commit().map_err(databaseError)
+    }
+}
+
+/// An offline license recorded by `OfflineLedger::reserve` before its CKC is generated.
+pub struct OfflineReservation {
+    ledger: Arc<OfflineLedger>,
+    /// Random id of the recorded row
+    id: i64,
+    license: OfflineLicenseRecord,
+    /// License the device held for the same stream before, with its reservation id if still pending
+    replaced: Option<(OfflineLicenseRecord, Option<i64>)>,
+    committed: AtomicBool,
+}
+
+impl OfflineReservation {
+    pub fn license(&self) -> &OfflineLicenseRecord {
+        &self.license
+    }
+
+    /// Keeps the license recorded: its CKC was generated.
+    pub fn commit(&self) {
+        self.committed.store(true, Ordering::Release);
+    }
+}
+
+impl Drop for OfflineReservation {
+    fn drop(&mut self) {
+        if self.committed.load(Ordering::Acquire) {
+            return;
+        }
+        log::debug!("CKC not generated, releasing reserved offline license");
+        // Failures are logged by release
+        let _ = self.ledger.release(self.id, self.replaced.as_ref());
+    }
+}
+
+impl std::fmt::Debug for OfflineReservation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OfflineReservation")
+            .field("license", &self.license)
+            .field("committed", &self.committed)
+            .finish()
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Installed ledger
+////////////////////////////////////////////////////////////////////////////////
+
+/// Ledger installed with `SDKExtension::setOfflineLedger`, or opened from `FPS_OFFLINE_LEDGER` once first used.
+/// The inner `None` means no ledger is kept.
+static OFFLINE_LEDGER: RwLock<Option<Option<Arc<OfflineLedger>>>> = RwLock::new(None);
+
+impl SDKExtension {
+    /// Records offline licenses and check-ins in `offlineLedger` (`Some`), or stops recording them (`None`).
+    pub fn setOfflineLedger(offlineLedger: Option<Arc<OfflineLedger>>) {
+        *OFFLINE_LEDGER.write().unwrap_or_else(|e| e.into_inner()) = Some(offlineLedger);
+    }
+
+    /// Returns the installed ledger, opening `FPS_OFFLINE_LEDGER` if that is set.
+    pub fn offlineLedger() -> Result<Option<Arc<OfflineLedger>>> {
+        if let Some(offlineLedger) = OFFLINE_LEDGER.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+            return Ok(offlineLedger.clone());
+        }
+
+        let mut installed = OFFLINE_LEDGER.write().unwrap_or_else(|e| e.into_inner());
+        if let Some(offlineLedger) = installed.as_ref() {
+            return Ok(offlineLedger.clone());
+        }
+
+        let offlineLedger = match std::env::var(OFFLINE_LEDGER_ENV) {
+            Ok(path) => {
+                log::debug!("Using offline ledger {}", path);
+                Some(Arc::new(OfflineLedger::open(path)?))
+            }
+            Err(_) => None,
+        };
+        *installed = Some(offlineLedger.clone());
+
+        Ok(offlineLedger)
+    }
+
+    /// Records the offline license just generated, or reconciles the verified check-in, in the installed ledger.
+    pub fn updateOfflineLedger(serverCtx: &FPSServerCtx, fpsResult: &FPSResult) -> Result<()> {
+        let Some(offlineLedger) = SDKExtension::offlineLedger()? else {
+            return Ok(());
+        };
+        let now = chrono::Utc::now().timestamp();
+
+        if fpsResult.isCheckIn {
+            if !fpsResult.extension.checkInVerified {
+                log::warn!("Check-in is not verified, deleted licenses are not returned");
+                return Ok(());
+            }
+            let returned = offlineLedger.checkIn(
+                &serverCtx.hu,
+                fpsResult.syncServerChallenge,
+                &fpsResult.deletedContentIDs,
+                now,
+            )?;
+            log::debug!("Check-in returned {} offline licenses", returned);
+            return Ok(());
+        }
+
+        match &serverCtx.extension.offlineReservation {
+            Some(reservation) => reservation.commit(),
+            // Extensions that do not reserve licenses record them now
+            None => {
+                if let Some(license) = OfflineLicenseRecord::fromServerCtx(serverCtx, now) {
+                    offlineLedger.record(&license)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reserves the offline license `serverCtx` is about to issue in the installed ledger, failing with
+    /// `offlineLimitErr` if it goes over the `offline-limits` of the policy. Check-ins are never limited.
+    ///
+    /// `updateOfflineLedger` keeps the reservation once the CKC is generated; dropping `serverCtx`
+    /// before that releases it.
+    pub fn reserveOfflineLicense(serverCtx: &mut FPSServerCtx, fpsResult: &FPSResult) -> Result<()> {
+        if fpsResult.isCheckIn {
+            return Ok(());
+        }
+        let Some(license) = OfflineLicenseRecord::fromServerCtx(serverCtx, chrono::Utc::now().timestamp()) else {
+            return Ok(());
+        };
+
+        let policy = SDKExtension::policy()?;
+        let Some(offlineLedger) = SDKExtension::offlineLedger()? else {
+            if policy.offlineLimits.isEmpty() {
+                return Ok(());
+            }
+            fpsLogError!(
+                FPSStatus::internalErr,
+                "offline-limits are set but no offline ledger is configured (see {})",
+                OFFLINE_LEDGER_ENV
+            );
+            returnErrorStatus!(FPSStatus::internalErr);
+        };
+
+        let reservation = offlineLedger.reserve(&policy.offlineLimits, &license)?;
+        serverCtx.extension.offlineReservation = Some(Arc::new(reservation));
+
+        Ok(())
+    }
+}
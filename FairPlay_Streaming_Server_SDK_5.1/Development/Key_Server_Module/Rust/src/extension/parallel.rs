@@ -0,0 +1,191 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+//! Concurrent processing of the `create-ckc` operations of one request.
+//!
+//! `Base::processOperations` spreads its operations over at most `SDKExtension::parallelism()`
+//! workers, since each one spends most of its time in the SPC RSA decryption and the key payload
+//! backend. Results come back in request order. Workers run with the `FpsExtension` and
+//! `RandomSource` of the calling thread, so a `KeyServer`'s own extension applies to all of them.
+//!
+//! The workers are threads of one pool shared by every request of the process. It starts
+//! threads as needed up to the largest `SDKExtension::parallelism()` it has been used with, so
+//! requests processed at the same time never run more threads than that in total.
+
+use crate::extension::structures::extension_structures::SDKExtension;
+use crate::Extension;
+use std::any::Any;
+use std::cell::Cell;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::thread;
+
+/// Default for `SDKExtension::parallelism`, when set to a positive integer.
+pub const PARALLELISM_ENV: &str = "FPS_PARALLELISM";
+
+/// Limit installed with `SDKExtension::setParallelism`, or read from `FPS_PARALLELISM` once first used.
+static PARALLELISM: RwLock<Option<usize>> = RwLock::new(None);
+
+impl SDKExtension {
+    /// Limits the workers processing the operations of one request, for all threads. The shared
+    /// pool starts threads up to the largest limit it is used with, and keeps them.
+    ///
+    /// `1` processes them one at a time on the calling thread. Defaults to the number of CPUs.
+    pub fn setParallelism(parallelism: usize) {
+        *PARALLELISM.write().unwrap_or_else(|e| e.into_inner()) = Some(parallelism.max(1));
+    }
+
+    pub fn parallelism() -> usize {
+        if let Some(parallelism) = *PARALLELISM.read().unwrap_or_else(|e| e.into_inner()) {
+            return parallelism;
+        }
+
+        let parallelism = std::env::var(PARALLELISM_ENV)
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .filter(|parallelism| *parallelism > 0)
+            .unwrap_or_else(|| thread::available_parallelism().map_or(1, |cpus| cpus.get()));
+        *PARALLELISM
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .get_or_insert(parallelism)
+    }
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+thread_local! {
+    static IS_POOL_WORKER: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Threads processing the operations of all requests.
+struct WorkerPool {
+    sender: Mutex<Sender<Job>>,
+    receiver: Arc<Mutex<Receiver<Job>>>,
+    workerCount: Mutex<usize>,
+}
+
+static WORKER_POOL: OnceLock<WorkerPool> = OnceLock::new();
+
+impl WorkerPool {
+    fn shared() -> &'static WorkerPool {
+        WORKER_POOL.get_or_init(|| {
+            let (sender, receiver) = channel();
+            WorkerPool {
+                sender: Mutex::new(sender),
+                receiver: Arc::new(Mutex::new(receiver)),
+                workerCount: Mutex::new(0),
+            }
+        })
+    }
+
+    /// Starts threads until the pool has `size`. Returns how many it has.
+    fn grow(&self, size: usize) -> usize {
+        let mut workerCount = self.workerCount.lock().unwrap_or_else(|e| e.into_inner());
+        while *workerCount < size {
+            let receiver = self.receiver.clone();
+            let spawned = thread::Builder::new()
+                .name(format!("fpssdk-operation-{}", *workerCount))
+                .spawn(move || WorkerPool::run(&receiver));
+            if let Err(e) = spawned {
+                log::warn!("Unable to start operation worker: {}", e);
+                break;
+            }
+            *workerCount += 1;
+        }
+        *workerCount
+    }
+
+    fn run(receiver: &Mutex<Receiver<Job>>) {
+        IS_POOL_WORKER.with(|isPoolWorker| isPoolWorker.set(true));
+        loop {
+            // Hold the lock only while waiting for the next job
+            let job = match receiver.lock().unwrap_or_else(|e| e.into_inner()).recv() {
+                Ok(job) => job,
+                Err(_) => return,
+            };
+            // Panics are reported to the caller by the job itself
+            let _ = panic::catch_unwind(AssertUnwindSafe(job));
+        }
+    }
+
+    fn execute(&self, job: Job) {
+        let sender = self.sender.lock().unwrap_or_else(|e| e.into_inner());
+        // The receiver lives as long as the pool, so sending cannot fail
+        let _ = sender.send(job);
+    }
+}
+
+/// Applies `f` to every item on up to `SDKExtension::parallelism()` workers of the shared pool, and
+/// returns the results in the order of `items`.
+///
+/// Items are processed one at a time on the calling thread when the random source is
+/// `RandomSource::ordered`, so seeded CKCs stay reproducible, and when called from a pool worker.
+/// A panic in `f` is resumed on the calling thread once every item has been processed.
+pub fn processInParallel<T, R, F>(items: Vec<T>, f: F) -> Vec<R>
+where
+    T: Send + 'static,
+    R: Send + 'static,
+    F: Fn(T) -> R + Send + Sync + 'static,
+{
+    let fpsExtension = SDKExtension::fpsExtension();
+    let randomSource = SDKExtension::randomSource();
+    let isPoolWorker = IS_POOL_WORKER.with(|isPoolWorker| isPoolWorker.get());
+    let parallelism = SDKExtension::parallelism().min(items.len());
+    if parallelism <= 1 || randomSource.ordered() || isPoolWorker {
+        return items.into_iter().map(f).collect();
+    }
+
+    let pool = WorkerPool::shared();
+    let workerCount = pool.grow(SDKExtension::parallelism()).min(parallelism);
+    if workerCount == 0 {
+        return items.into_iter().map(f).collect();
+    }
+
+    let itemCount = items.len();
+    let queue = Arc::new(Mutex::new(items.into_iter().enumerate()));
+    let f = Arc::new(f);
+    let (resultSender, resultReceiver) = channel::<(usize, thread::Result<R>)>();
+
+    // Each job takes items from the queue of this call until it is empty
+    for _ in 0..workerCount {
+        let (queue, f, resultSender) = (queue.clone(), f.clone(), resultSender.clone());
+        let (fpsExtension, randomSource) = (fpsExtension.clone(), randomSource.clone());
+        pool.execute(Box::new(move || {
+            SDKExtension::withFpsExtension(fpsExtension, || {
+                SDKExtension::withRandomSource(randomSource, || {
+                    // Log formatting is configured per thread
+                    Extension::logInitCustom(None);
+
+                    loop {
+                        let next = queue.lock().unwrap_or_else(|e| e.into_inner()).next();
+                        let Some((index, item)) = next else {
+                            break;
+                        };
+                        let result = panic::catch_unwind(AssertUnwindSafe(|| f(item)));
+                        let _ = resultSender.send((index, result));
+                    }
+                })
+            })
+        }));
+    }
+    drop(resultSender);
+
+    let mut results: Vec<Option<R>> = (0..itemCount).map(|_| None).collect();
+    let mut panicked: Option<Box<dyn Any + Send>> = None;
+    for (index, result) in resultReceiver {
+        match result {
+            Ok(result) => results[index] = Some(result),
+            Err(panic) => {
+                panicked.get_or_insert(panic);
+            }
+        }
+    }
+    if let Some(panic) = panicked {
+        panic::resume_unwind(panic);
+    }
+
+    results.into_iter().flatten().collect()
+}
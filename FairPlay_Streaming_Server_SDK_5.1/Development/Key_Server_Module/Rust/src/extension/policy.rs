@@ -0,0 +1,529 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+//! Declarative business rules, evaluated by `SDKExtension::checkBusinessRules`.
+//!
+//! A policy file is JSON with a `defaults` rule that applies to every request and one rule per
+//! content type (`uhd`, `hd`, `sd`, `audio`, and `unknown` for requests without a recognized
+//! `content-type`). A request must satisfy both. All fields are optional:
+//!
+//! ```json
+//! {
+//!     "defaults": {
+//!         "min-kdl-version": 31,
+//!         "allow-virtual-machines": false
+//!     },
+//!     "content-types": {
+//!         "uhd": {
+//!             "security-level": "main",
+//!             "min-hdcp": "type1",
+//!             "device-classes": ["apple-living-room", "apple-desktop", "partner-living-room"],
+//!             "min-os-version": "17.0",
+//!             "min-ree-version": "0x00010000",
+//!             "min-tee-version": "0x00010000"
+//!         },
+//!         "hd": { "security-level": "baseline", "min-hdcp": "type0" },
+//!         "sd": { "security-level": "baseline" },
+//!         "audio": { "security-level": "audio" },
+//!         "unknown": { "security-level": "main", "check-client-security-level": false }
+//!     },
+//!     "offline-limits": {
+//!         "max-licenses-per-device": 25,
+//!         "max-devices-per-account-title": 2,
+//!         "require-check-in-before-redownload": true
+//!     }
+//! }
+//! ```
+//!
+//! Checks against values an older client does not report (device identity, KDL version,
+//! security level TLLV) are skipped, as the hard-coded rules always did.
+//!
+//! `offline-limits` are counted in the offline ledger (see `extension::offline_ledger`), which must
+//! be configured when any limit is set. Requests over a limit fail with `FPSStatus::offlineLimitErr`.
+
+use crate::base::base_constants::{self, FPSDeviceClass, FPSHDCPRequirement};
+use crate::base::structures::base_fps_structures::FPSOperation;
+use crate::base::structures::base_server_structures::FPSServerCtx;
+use crate::extension::extension_constants::{self, FPSSecurityLevel};
+use crate::validate::{FPSStatus, Result};
+use crate::{fpsLogError, returnErrorStatus};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Environment variable naming the policy file loaded by `SDKExtension::policy`.
+pub const POLICY_FILE_ENV: &str = "FPS_POLICY_FILE";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PolicySecurityLevel {
+    audio,
+    baseline,
+    main,
+}
+
+impl From<PolicySecurityLevel> for FPSSecurityLevel {
+    fn from(value: PolicySecurityLevel) -> Self {
+        match value {
+            PolicySecurityLevel::audio => FPSSecurityLevel::audio,
+            PolicySecurityLevel::baseline => FPSSecurityLevel::baseline,
+            PolicySecurityLevel::main => FPSSecurityLevel::main,
+        }
+    }
+}
+
+/// HDCP requirement, ordered from least to most strict.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PolicyHDCP {
+    notRequired,
+    type0,
+    type1,
+}
+
+impl PolicyHDCP {
+    /// Custom values are ranked like the hard-coded rules did: the HD rule (HDCP required)
+    /// accepted them, the UHD rule (HDCP type 1) did not.
+    fn fromRequirement(hdcpReq: u64) -> PolicyHDCP {
+        match hdcpReq {
+            x if x == FPSHDCPRequirement::hdcpNotRequired as u64 => PolicyHDCP::notRequired,
+            x if x == FPSHDCPRequirement::hdcpType1 as u64 => PolicyHDCP::type1,
+            _ => PolicyHDCP::type0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PolicyDeviceClass {
+    unknown,
+    appleLivingRoom,
+    appleMobile,
+    appleDesktop,
+    appleSpacial,
+    appleWearable,
+    appleUnknown,
+    partnerLivingRoom,
+    partnerUnknown,
+}
+
+impl From<PolicyDeviceClass> for FPSDeviceClass {
+    fn from(value: PolicyDeviceClass) -> Self {
+        match value {
+            PolicyDeviceClass::unknown => FPSDeviceClass::unknown,
+            PolicyDeviceClass::appleLivingRoom => FPSDeviceClass::appleLivingRoom,
+            PolicyDeviceClass::appleMobile => FPSDeviceClass::appleMobile,
+            PolicyDeviceClass::appleDesktop => FPSDeviceClass::appleDesktop,
+            PolicyDeviceClass::appleSpacial => FPSDeviceClass::appleSpacial,
+            PolicyDeviceClass::appleWearable => FPSDeviceClass::appleWearable,
+            PolicyDeviceClass::appleUnknown => FPSDeviceClass::appleUnknown,
+            PolicyDeviceClass::partnerLivingRoom => FPSDeviceClass::partnerLivingRoom,
+            PolicyDeviceClass::partnerUnknown => FPSDeviceClass::partnerUnknown,
+        }
+    }
+}
+
+fn allowsDeviceClass(allowed: &[PolicyDeviceClass], deviceClass: FPSDeviceClass) -> bool {
+    allowed.iter().any(|&class| FPSDeviceClass::from(class) == deviceClass)
+}
+
+/// A version as reported in the SPC.
+///
+/// Written in the policy as a number, a hex string (`"0x00110400"`), or for OS versions as
+/// `"major.minor.patch"`, which is encoded the way clients report it (`0x00MMmmpp`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(try_from = "PolicyVersionValue")]
+pub struct PolicyVersion(pub u32);
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PolicyVersionValue {
+    number(u32),
+    text(String),
+}
+
+impl TryFrom<PolicyVersionValue> for PolicyVersion {
+    type Error = String;
+
+    fn try_from(value: PolicyVersionValue) -> std::result::Result<Self, Self::Error> {
+        let text = match value {
+            PolicyVersionValue::number(number) => return Ok(PolicyVersion(number)),
+            PolicyVersionValue::text(text) => text,
+        };
+
+        if let Some(hex) = text.strip_prefix("0x") {
+            return u32::from_str_radix(hex, 16)
+                .map(PolicyVersion)
+                .map_err(|_| format!("invalid version: {}", text));
+        }
+
+        let mut encoded = 0u32;
+        for (index, part) in text.split('.').enumerate() {
+            match part.parse::<u8>() {
+                Ok(value) if index < 3 => encoded |= (value as u32) << (16 - 8 * index),
+                _ => return Err(format!("invalid version: {}", text)),
+            }
+        }
+        Ok(PolicyVersion(encoded))
+    }
+}
+
+/// Requirements a request has to meet. Unset fields are not checked.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PolicyRule {
+    /// Security level required in the CKC. Also checked against the level the client reports
+    /// unless `check-client-security-level` is false.
+    #[serde(rename = "security-level")]
+    pub securityLevel: Option<PolicySecurityLevel>,
+    #[serde(rename = "check-client-security-level")]
+    pub checkClientSecurityLevel: Option<bool>,
+
+    /// Weakest HDCP requirement the `asset-info` may ask for. Custom HDCP requirement values rank
+    /// as `type0`: HDCP is required, but type 1 enforcement is not known to be.
+    #[serde(rename = "min-hdcp")]
+    pub minHDCP: Option<PolicyHDCP>,
+
+    #[serde(rename = "min-kdl-version")]
+    pub minKDLVersion: Option<u32>,
+
+    /// Device classes (from the device identity TLLV) that may receive keys.
+    #[serde(rename = "device-classes")]
+    pub deviceClasses: Option<Vec<PolicyDeviceClass>>,
+    #[serde(rename = "min-os-version")]
+    pub minOSVersion: Option<PolicyVersion>,
+    #[serde(rename = "min-ree-version")]
+    pub minREEVersion: Option<PolicyVersion>,
+    #[serde(rename = "min-tee-version")]
+    pub minTEEVersion: Option<PolicyVersion>,
+
+    /// Whether clients running in (or hosting) a virtual machine may receive keys.
+    #[serde(rename = "allow-virtual-machines")]
+    pub allowVirtualMachines: Option<bool>,
+    #[serde(rename = "vm-host-device-classes")]
+    pub vmHostDeviceClasses: Option<Vec<PolicyDeviceClass>>,
+    #[serde(rename = "vm-guest-device-classes")]
+    pub vmGuestDeviceClasses: Option<Vec<PolicyDeviceClass>>,
+    #[serde(rename = "min-vm-host-os-version")]
+    pub minVMHostOSVersion: Option<PolicyVersion>,
+    #[serde(rename = "min-vm-guest-os-version")]
+    pub minVMGuestOSVersion: Option<PolicyVersion>,
+}
+
+/// Logs the denying rule and returns `status`.
+macro_rules! deny {
+    ($status: expr, $rule: expr, $field: expr, $($arg:tt)+) => {
+        fpsLogError!(
+            $status,
+            reason = "policy-denied",
+            "Policy rule {}.{} denied request: {}",
+            $rule,
+            $field,
+            format!($($arg)+)
+        );
+        returnErrorStatus!($status);
+    };
+}
+
+impl PolicyRule {
+    /// Checks the request against this rule. `name` identifies the rule in log messages.
+    fn check(&self, name: &str, operation: &FPSOperation, serverCtx: &FPSServerCtx) -> Result<()> {
+        let spcData = &serverCtx.spcContainer.spcData;
+
+        if let Some(securityLevel) = self.securityLevel {
+            if self.checkClientSecurityLevel.unwrap_or(true) {
+                let required = FPSSecurityLevel::from(securityLevel);
+                if spcData.isSecurityLevelTLLVValid {
+                    if spcData.supportedSecurityLevel < required as u64 {
+                        deny!(
+                            FPSStatus::clientSecurityLevelErr,
+                            name,
+                            "security-level",
+                            "requires security level {:?}. Client supports 0x{:X}",
+                            required,
+                            spcData.supportedSecurityLevel
+                        );
+                    }
+                } else if securityLevel == PolicySecurityLevel::main
+                    && spcData.clientFeatures.supportsSecurityLevelBaseline
+                    && !spcData.clientFeatures.supportsSecurityLevelMain
+                {
+                    // Older devices do not send any supported security fields, so only fail here if
+                    // supportsSecurityLevelBaseline is set but supportsSecurityLevelMain is not
+                    deny!(
+                        FPSStatus::clientSecurityLevelErr,
+                        name,
+                        "security-level",
+                        "requires security level main. Client supports Baseline"
+                    );
+                }
+            }
+        }
+
+        if let Some(minHDCP) = self.minHDCP {
+            let hdcpReq = operation.assetInfo.hdcpReq;
+            if PolicyHDCP::fromRequirement(hdcpReq) < minHDCP {
+                deny!(
+                    FPSStatus::paramErr,
+                    name,
+                    "min-hdcp",
+                    "requires HDCP {:?} or stricter. Asset requests 0x{:X}",
+                    minHDCP,
+                    hdcpReq
+                );
+            }
+        }
+
+        if let Some(minKDLVersion) = self.minKDLVersion {
+            // Only verify the Kext Deny List version if the client reported one
+            let kdlVersion = spcData.clientKextDenyListVersion;
+            if kdlVersion > 0 && kdlVersion < minKDLVersion {
+                deny!(
+                    FPSStatus::clientSecurityLevelErr,
+                    name,
+                    "min-kdl-version",
+                    "KDL version supported by the client ({}) does not meet minimum required ({})",
+                    kdlVersion,
+                    minKDLVersion
+                );
+            }
+        }
+
+        let identity = &spcData.deviceIdentity;
+        if identity.isDeviceIdentitySet {
+            if let Some(deviceClasses) = &self.deviceClasses {
+                let deviceClass = FPSDeviceClass::from(identity.deviceClass);
+                if !allowsDeviceClass(deviceClasses, deviceClass) {
+                    deny!(
+                        FPSStatus::clientSecurityLevelErr,
+                        name,
+                        "device-classes",
+                        "device class {:?} is not allowed",
+                        deviceClass
+                    );
+                }
+            }
+
+            for (field, minimum, reported) in [
+                ("min-ree-version", self.minREEVersion, identity.fpVersionREE),
+                ("min-tee-version", self.minTEEVersion, identity.fpVersionTEE),
+            ] {
+                if let Some(PolicyVersion(minimum)) = minimum {
+                    if reported != 0 && reported < minimum {
+                        deny!(
+                            FPSStatus::clientSecurityLevelErr,
+                            name,
+                            field,
+                            "client version 0x{:08X} is below 0x{:08X}",
+                            reported,
+                            minimum
+                        );
+                    }
+                }
+            }
+        }
+
+        if let Some(PolicyVersion(minOSVersion)) = self.minOSVersion {
+            // Prefer the device identity TLLV, fall back to the older device info TLLV
+            let osVersion = if identity.isDeviceIdentitySet && identity.osVersion != 0 {
+                identity.osVersion
+            } else if spcData.deviceInfo.isDeviceInfoSet {
+                spcData.deviceInfo.osVersion
+            } else {
+                0
+            };
+            if osVersion != 0 && osVersion < minOSVersion {
+                deny!(
+                    FPSStatus::clientSecurityLevelErr,
+                    name,
+                    "min-os-version",
+                    "client OS version 0x{:08X} is below 0x{:08X}",
+                    osVersion,
+                    minOSVersion
+                );
+            }
+        }
+
+        if let Some(vmDeviceInfo) = &spcData.vmDeviceInfo {
+            if self.allowVirtualMachines == Some(false) {
+                deny!(
+                    FPSStatus::clientSecurityLevelErr,
+                    name,
+                    "allow-virtual-machines",
+                    "content cannot be played on a virtual machine"
+                );
+            }
+
+            for (field, allowed, deviceClass) in [
+                ("vm-host-device-classes", &self.vmHostDeviceClasses, vmDeviceInfo.hostDeviceClass),
+                ("vm-guest-device-classes", &self.vmGuestDeviceClasses, vmDeviceInfo.guestDeviceClass),
+            ] {
+                if let Some(allowed) = allowed {
+                    if !allowsDeviceClass(allowed, deviceClass) {
+                        deny!(
+                            FPSStatus::clientSecurityLevelErr,
+                            name,
+                            field,
+                            "device class {:?} is not allowed",
+                            deviceClass
+                        );
+                    }
+                }
+            }
+
+            for (field, minimum, reported) in [
+                ("min-vm-host-os-version", self.minVMHostOSVersion, vmDeviceInfo.hostOSVersion),
+                ("min-vm-guest-os-version", self.minVMGuestOSVersion, vmDeviceInfo.guestOSVersion),
+            ] {
+                if let Some(PolicyVersion(minimum)) = minimum {
+                    if reported < minimum {
+                        deny!(
+                            FPSStatus::clientSecurityLevelErr,
+                            name,
+                            field,
+                            "OS version 0x{:08X} is below 0x{:08X}",
+                            reported,
+                            minimum
+                        );
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Limits on the offline licenses issued to devices and accounts. Unset fields are not checked.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OfflineLimits {
+    /// Active offline licenses one device may hold
+    #[serde(rename = "max-licenses-per-device")]
+    pub maxLicensesPerDevice: Option<usize>,
+    /// Devices of one `account-id` that may hold licenses for the same title
+    #[serde(rename = "max-devices-per-account-title")]
+    pub maxDevicesPerAccountTitle: Option<usize>,
+    /// Whether a device must check in a stream before downloading it again
+    #[serde(rename = "require-check-in-before-redownload")]
+    pub requireCheckInBeforeRedownload: bool,
+}
+
+impl OfflineLimits {
+    pub fn isEmpty(&self) -> bool {
+        *self == OfflineLimits::default()
+    }
+}
+
+/// Business rules for every content type.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+    /// Applies to every request
+    pub defaults: PolicyRule,
+    /// Applies to requests for one content type, keyed by the `content-type` names used in `asset-info`
+    #[serde(rename = "content-types")]
+    pub contentTypes: BTreeMap<String, PolicyRule>,
+    #[serde(rename = "offline-limits")]
+    pub offlineLimits: OfflineLimits,
+}
+
+impl Policy {
+    /// The rules the SDK ships with.
+    pub fn builtIn() -> Policy {
+        let rule = |securityLevel, minHDCP| PolicyRule {
+            securityLevel: Some(securityLevel),
+            minHDCP,
+            ..Default::default()
+        };
+
+        Policy {
+            defaults: PolicyRule {
+                minKDLVersion: Some(base_constants::MIN_KDL_VERSION),
+                ..Default::default()
+            },
+            contentTypes: BTreeMap::from([
+                (
+                    extension_constants::CONTENT_TYPE_UHD_STR.to_string(),
+                    rule(PolicySecurityLevel::main, Some(PolicyHDCP::type1)),
+                ),
+                (
+                    extension_constants::CONTENT_TYPE_HD_STR.to_string(),
+                    rule(PolicySecurityLevel::baseline, Some(PolicyHDCP::type0)),
+                ),
+                (
+                    extension_constants::CONTENT_TYPE_SD_STR.to_string(),
+                    rule(PolicySecurityLevel::baseline, None),
+                ),
+                (
+                    extension_constants::CONTENT_TYPE_AUDIO_STR.to_string(),
+                    rule(PolicySecurityLevel::audio, None),
+                ),
+                (
+                    extension_constants::CONTENT_TYPE_UNKNOWN_STR.to_string(),
+                    PolicyRule {
+                        checkClientSecurityLevel: Some(false),
+                        ..rule(PolicySecurityLevel::main, None)
+                    },
+                ),
+            ]),
+            offlineLimits: Default::default(),
+        }
+    }
+
+    pub fn fromJson(json: &str) -> Result<Policy> {
+        let policy: Policy = match serde_jsonrc::from_str(json) {
+            Ok(policy) => policy,
+            Err(e) => {
+                fpsLogError!(FPSStatus::paramErr, "Invalid policy: {}", e);
+                returnErrorStatus!(FPSStatus::paramErr);
+            }
+        };
+
+        let known = [
+            extension_constants::CONTENT_TYPE_UHD_STR,
+            extension_constants::CONTENT_TYPE_HD_STR,
+            extension_constants::CONTENT_TYPE_SD_STR,
+            extension_constants::CONTENT_TYPE_AUDIO_STR,
+            extension_constants::CONTENT_TYPE_UNKNOWN_STR,
+        ];
+        if let Some(name) = policy.contentTypes.keys().find(|name| !known.contains(&name.as_str())) {
+            fpsLogError!(FPSStatus::paramErr, "Invalid policy: unknown content type \"{}\"", name);
+            returnErrorStatus!(FPSStatus::paramErr);
+        }
+
+        Ok(policy)
+    }
+
+    pub fn fromFile(path: impl AsRef<Path>) -> Result<Policy> {
+        let path = path.as_ref();
+        match std::fs::read_to_string(path) {
+            Ok(json) => Policy::fromJson(&json),
+            Err(e) => {
+                fpsLogError!(FPSStatus::paramErr, "Unable to read policy {}: {}", path.display(), e);
+                returnErrorStatus!(FPSStatus::paramErr);
+            }
+        }
+    }
+
+    /// Checks the request against the default and content type rules, and sets the
+    /// security level the CKC will require.
+    pub fn evaluate(&self, operation: &FPSOperation, serverCtx: &mut FPSServerCtx) -> Result<()> {
+        let name = operation.assetInfo.extension.contentType.name();
+        let contentRule = self.contentTypes.get(name);
+
+        self.defaults.check("defaults", operation, serverCtx)?;
+        if let Some(contentRule) = contentRule {
+            contentRule.check(&format!("content-types.{}", name), operation, serverCtx)?;
+        }
+
+        serverCtx.ckcContainer.ckcData.extension.requiredSecurityLevel = contentRule
+            .and_then(|rule| rule.securityLevel)
+            .or(self.defaults.securityLevel)
+            .map(FPSSecurityLevel::from)
+            .unwrap_or(FPSSecurityLevel::main);
+
+        Ok(())
+    }
+}
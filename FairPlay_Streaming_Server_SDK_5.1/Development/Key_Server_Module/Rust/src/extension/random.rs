@@ -0,0 +1,114 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+//! Source of the random bytes in CKCs: the CKC IV, the HDCP TLLV random value and the TLLV padding.
+//!
+//! The default `SystemRandom` is a CSPRNG. With the `deterministic_random` feature, `SeededRandom`
+//! makes CKCs reproducible for tests and golden-file comparisons. That feature does not compile
+//! in release builds, since predictable padding and IVs must never reach production.
+
+use crate::extension::structures::extension_structures::SDKExtension;
+use rand::Rng;
+use std::cell::RefCell;
+use std::sync::{Arc, RwLock};
+
+/// Fills buffers with random bytes.
+pub trait RandomSource: Send + Sync {
+    fn fill(&self, out: &mut [u8]);
+
+    /// Whether the bytes depend on the order of the calls. The operations of a batch are then
+    /// processed one at a time instead of in parallel (see `extension::parallel`).
+    fn ordered(&self) -> bool {
+        false
+    }
+}
+
+/// Thread-local CSPRNG seeded from the operating system.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemRandom;
+
+impl RandomSource for SystemRandom {
+    fn fill(&self, out: &mut [u8]) {
+        rand::thread_rng().fill(out);
+    }
+}
+
+/// Deterministic generator: the same seed gives the same bytes, in the same order.
+///
+/// Shared by every request it is installed for, so CKCs are only reproducible when the
+/// requests are processed in the same order.
+#[cfg(feature = "deterministic_random")]
+#[derive(Debug)]
+pub struct SeededRandom {
+    rng: std::sync::Mutex<rand::rngs::StdRng>,
+}
+
+#[cfg(feature = "deterministic_random")]
+impl SeededRandom {
+    pub fn new(seed: u64) -> SeededRandom {
+        use rand::SeedableRng;
+
+        SeededRandom {
+            rng: std::sync::Mutex::new(rand::rngs::StdRng::seed_from_u64(seed)),
+        }
+    }
+}
+
+#[cfg(feature = "deterministic_random")]
+impl RandomSource for SeededRandom {
+    fn fill(&self, out: &mut [u8]) {
+        self.rng.lock().unwrap_or_else(|e| e.into_inner()).fill(out);
+    }
+
+    fn ordered(&self) -> bool {
+        true
+    }
+}
+
+/// Source installed with `SDKExtension::setRandomSource`
+static RANDOM_SOURCE: RwLock<Option<Arc<dyn RandomSource>>> = RwLock::new(None);
+
+thread_local! {
+    /// Source of the `KeyServer` or `KeyRequest` currently processed on this thread
+    static SCOPED_RANDOM_SOURCE: RefCell<Option<Arc<dyn RandomSource>>> = const { RefCell::new(None) };
+}
+
+/// Restores the previously scoped source, also when the request panics.
+struct ScopedRandomGuard {
+    previous: Option<Arc<dyn RandomSource>>,
+}
+
+impl Drop for ScopedRandomGuard {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        SCOPED_RANDOM_SOURCE.with(|scoped| *scoped.borrow_mut() = previous);
+    }
+}
+
+impl SDKExtension {
+    /// Replaces the random source for requests not processed with their own.
+    pub fn setRandomSource(randomSource: Arc<dyn RandomSource>) {
+        *RANDOM_SOURCE.write().unwrap_or_else(|e| e.into_inner()) = Some(randomSource);
+    }
+
+    /// Returns the random source on the calling thread, `SystemRandom` unless one was installed.
+    pub fn randomSource() -> Arc<dyn RandomSource> {
+        if let Some(randomSource) = SCOPED_RANDOM_SOURCE.with(|scoped| scoped.borrow().clone()) {
+            return randomSource;
+        }
+
+        match RANDOM_SOURCE.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+            Some(randomSource) => randomSource.clone(),
+            None => Arc::new(SystemRandom),
+        }
+    }
+
+    /// Runs `f` with `randomSource` providing every random byte generated on this thread.
+    pub fn withRandomSource<R>(randomSource: Arc<dyn RandomSource>, f: impl FnOnce() -> R) -> R {
+        let previous = SCOPED_RANDOM_SOURCE.with(|scoped| scoped.borrow_mut().replace(randomSource));
+        let _guard = ScopedRandomGuard { previous };
+
+        f()
+    }
+}
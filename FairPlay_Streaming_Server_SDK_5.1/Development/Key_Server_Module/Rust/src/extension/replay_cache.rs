@@ -0,0 +1,275 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+//! Rejects SPCs that were already answered.
+//!
+//! Each SPC is fingerprinted by its anti-replay seed, transaction ID and R2. A fingerprint seen
+//! again within the replay window fails with `FPSStatus::replayErr`. Disabled unless configured:
+//!
+//! - `FPS_REPLAY_CACHE`: `off` (default), `memory`, or `sqlite:<path>` to share the cache
+//!   between server processes
+//! - `FPS_REPLAY_WINDOW_SECS`: how long a fingerprint is remembered (default 300)
+//! - `FPS_REPLAY_CACHE_CAPACITY`: fingerprints kept by the `memory` cache (default 100000)
+
+use crate::base::structures::base_server_structures::FPSServerSPCData;
+use crate::extension::structures::extension_structures::SDKExtension;
+use crate::validate::{FPSStatus, Result};
+use crate::{fpsLogError, returnErrorStatus};
+use openssl::sha::Sha256;
+use rusqlite::{params, Connection};
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const REPLAY_CACHE_ENV: &str = "FPS_REPLAY_CACHE";
+pub const REPLAY_WINDOW_ENV: &str = "FPS_REPLAY_WINDOW_SECS";
+pub const REPLAY_CACHE_CAPACITY_ENV: &str = "FPS_REPLAY_CACHE_CAPACITY";
+
+pub const DEFAULT_REPLAY_WINDOW: Duration = Duration::from_secs(300);
+pub const DEFAULT_REPLAY_CACHE_CAPACITY: usize = 100_000;
+
+/// SHA-256 over the values that identify one SPC.
+pub type Fingerprint = [u8; 32];
+
+/// Storage for recently seen fingerprints. Times are milliseconds since the Unix epoch.
+pub trait ReplayStore: Send + Sync {
+    /// Records `fingerprint` and returns true if it was already recorded less than `window` before `now`.
+    fn checkAndInsert(&self, fingerprint: &Fingerprint, now: u64, window: Duration) -> Result<bool>;
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// In-memory store
+////////////////////////////////////////////////////////////////////////////////
+
+/// Fingerprints kept in process memory, bounded to `capacity` entries.
+///
+/// When full, the oldest fingerprint is dropped even if it is still inside the window, so
+/// size the capacity for the expected request rate times the window.
+pub struct MemoryReplayStore {
+    capacity: usize,
+    entries: Mutex<MemoryEntries>,
+}
+
+#[derive(Default)]
+struct MemoryEntries {
+    seen: HashMap<Fingerprint, u64>,
+    order: VecDeque<(Fingerprint, u64)>,
+}
+
+impl MemoryReplayStore {
+    pub fn new(capacity: usize) -> MemoryReplayStore {
+        MemoryReplayStore {
+            capacity: capacity.max(1),
+            entries: Default::default(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner()).seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl ReplayStore for MemoryReplayStore {
+    fn checkAndInsert(&self, fingerprint: &Fingerprint, now: u64, window: Duration) -> Result<bool> {
+        let window = window.as_millis() as u64;
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let MemoryEntries { seen, order } = &mut *entries;
+
+        if let Some(&seenAt) = seen.get(fingerprint) {
+            if seenAt.saturating_add(window) > now {
+                return Ok(true);
+            }
+        }
+
+        // Entries are queued in insertion order, so expired ones are at the front. Drop those,
+        // then the oldest live ones while there is no room for the new fingerprint.
+        while let Some(&(oldest, seenAt)) = order.front() {
+            if seenAt.saturating_add(window) > now && seen.len() < self.capacity {
+                break;
+            }
+            order.pop_front();
+            if seen.get(&oldest) == Some(&seenAt) {
+                seen.remove(&oldest);
+            }
+        }
+
+        seen.insert(*fingerprint, now);
+        order.push_back((*fingerprint, now));
+        Ok(false)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// SQLite store
+////////////////////////////////////////////////////////////////////////////////
+
+/// Fingerprints kept in an SQLite database that several server processes can share.
+pub struct SqliteReplayStore {
+    connection: Mutex<Connection>,
+}
+
+fn databaseError(e: rusqlite::Error) -> FPSStatus {
+    fpsLogError!(FPSStatus::internalErr, "replay cache error: {}", e);
+    FPSStatus::internalErr
+}
+
+impl SqliteReplayStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<SqliteReplayStore> {
+        let path = path.as_ref();
+        let connection = match Connection::open(path) {
+            Ok(connection) => connection,
+            Err(e) => {
+                fpsLogError!(FPSStatus::internalErr, "Unable to open replay cache {}: {}", path.display(), e);
+                returnErrorStatus!(FPSStatus::internalErr);
+            }
+        };
+
+        // Other processes may hold the write lock briefly
+        connection.busy_timeout(Duration::from_secs(5)).map_err(databaseError)?;
+        connection
+            .execute_batch(
+                "CREATE TABLE IF NOT EXISTS replay_fingerprints (
+                     fingerprint BLOB PRIMARY KEY NOT NULL,
+                     seen_at     INTEGER NOT NULL
+                 );
+                 CREATE INDEX IF NOT EXISTS replay_fingerprints_seen_at ON replay_fingerprints (seen_at);",
+            )
+            .map_err(databaseError)?;
+
+        Ok(SqliteReplayStore {
+            connection: Mutex::new(connection),
+        })
+    }
+}
+
+impl ReplayStore for SqliteReplayStore {
+    fn checkAndInsert(&self, fingerprint: &Fingerprint, now: u64, window: Duration) -> Result<bool> {
+        let expiredBefore = now.saturating_sub(window.as_millis() as u64) as i64;
+        let mut connection = self.connection.lock().unwrap_or_else(|e| e.into_inner());
+
+        // IMMEDIATE takes the write lock up front so two processes cannot both miss the same fingerprint
+        let transaction = connection
+            .transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)
+            .map_err(databaseError)?;
+        transaction
+            .execute("DELETE FROM replay_fingerprints WHERE seen_at <= ?1", params![expiredBefore])
+            .map_err(databaseError)?;
+        let inserted = transaction
+            .execute(
+                "INSERT OR IGNORE INTO replay_fingerprints (fingerprint, seen_at) VALUES (?1, ?2)",
+                params![&fingerprint[..], now as i64],
+            )
+            .map_err(databaseError)?;
+        transaction.commit().map_err(databaseError)?;
+
+        Ok(inserted == 0)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Replay cache
+////////////////////////////////////////////////////////////////////////////////
+
+pub struct ReplayCache {
+    window: Duration,
+    store: Arc<dyn ReplayStore>,
+}
+
+impl ReplayCache {
+    pub fn new(store: Arc<dyn ReplayStore>, window: Duration) -> ReplayCache {
+        ReplayCache { window, store }
+    }
+
+    /// Builds the cache described by `FPS_REPLAY_CACHE` and related variables, or `None` if disabled.
+    pub fn fromEnvironment() -> Result<Option<ReplayCache>> {
+        let number = |var: &str| -> Result<Option<u64>> {
+            match std::env::var(var) {
+                Ok(value) => match value.parse() {
+                    Ok(number) => Ok(Some(number)),
+                    Err(_) => {
+                        fpsLogError!(FPSStatus::paramErr, "invalid {}: {}", var, value);
+                        returnErrorStatus!(FPSStatus::paramErr);
+                    }
+                },
+                Err(_) => Ok(None),
+            }
+        };
+        let window = number(REPLAY_WINDOW_ENV)?.map_or(DEFAULT_REPLAY_WINDOW, Duration::from_secs);
+        let capacity = number(REPLAY_CACHE_CAPACITY_ENV)?.map_or(DEFAULT_REPLAY_CACHE_CAPACITY, |n| n as usize);
+
+        let config = std::env::var(REPLAY_CACHE_ENV).unwrap_or_default();
+        let store: Arc<dyn ReplayStore> = match config.as_str() {
+            "" | "off" => return Ok(None),
+            "memory" => Arc::new(MemoryReplayStore::new(capacity)),
+            _ => match config.strip_prefix("sqlite:") {
+                Some(path) => Arc::new(SqliteReplayStore::open(path)?),
+                None => {
+                    fpsLogError!(FPSStatus::paramErr, "invalid {}: {}", REPLAY_CACHE_ENV, config);
+                    returnErrorStatus!(FPSStatus::paramErr);
+                }
+            },
+        };
+
+        Ok(Some(ReplayCache::new(store, window)))
+    }
+
+    pub fn fingerprint(spcData: &FPSServerSPCData) -> Fingerprint {
+        let mut hasher = Sha256::new();
+        hasher.update(&spcData.antiReplay);
+        hasher.update(&spcData.transactionId.to_be_bytes());
+        hasher.update(&spcData.r2);
+        hasher.finish()
+    }
+
+    /// Fails with `replayErr` if the same SPC was checked within the replay window.
+    pub fn check(&self, spcData: &FPSServerSPCData) -> Result<()> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64);
+
+        if self.store.checkAndInsert(&ReplayCache::fingerprint(spcData), now, self.window)? {
+            fpsLogError!(
+                FPSStatus::replayErr,
+                "SPC replayed (transaction ID 0x{:x}) within {}s window",
+                spcData.transactionId,
+                self.window.as_secs()
+            );
+            returnErrorStatus!(FPSStatus::replayErr);
+        }
+        Ok(())
+    }
+}
+
+/// Replay cache installed with `SDKExtension::setReplayCache`, or configured from the environment once first used.
+/// The inner `None` means replay detection is off.
+static REPLAY_CACHE: RwLock<Option<Option<Arc<ReplayCache>>>> = RwLock::new(None);
+
+impl SDKExtension {
+    /// Turns replay detection on (`Some`) or off (`None`) for all subsequent requests.
+    pub fn setReplayCache(replayCache: Option<Arc<ReplayCache>>) {
+        *REPLAY_CACHE.write().unwrap_or_else(|e| e.into_inner()) = Some(replayCache);
+    }
+
+    /// Returns the installed replay cache, configuring it from `FPS_REPLAY_CACHE` on first use.
+    pub fn replayCache() -> Result<Option<Arc<ReplayCache>>> {
+        if let Some(replayCache) = REPLAY_CACHE.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+            return Ok(replayCache.clone());
+        }
+
+        let mut installed = REPLAY_CACHE.write().unwrap_or_else(|e| e.into_inner());
+        if let Some(replayCache) = installed.as_ref() {
+            return Ok(replayCache.clone());
+        }
+
+        let replayCache = ReplayCache::fromEnvironment()?.map(Arc::new);
+        *installed = Some(replayCache.clone());
+
+        Ok(replayCache)
+    }
+}
@@ -0,0 +1,658 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+//! Schema of the `fairplay-streaming-request` and `fairplay-streaming-response` JSON documents.
+//!
+//! The same definitions drive strict request validation (see `SDKExtension::setStrictSchema`)
+//! and the published JSON Schemas in `schema/` (`fpssdk_local schema request|response`).
+//! Fields added by `parseAssetInfoCustom`, `parseCreateCKCOperationCustom` or
+//! `serializeCreateCKCNodeCustom` must be added here too, or strict mode rejects them.
+
+use crate::base::base_constants::{self, FPS_MAX_TITLE_ID_LENGTH, FPS_OFFLINE_CONTENTID_LENGTH};
+use crate::base::base_constants::{AES128_IV_SZ, AES128_KEY_SZ, FPS_V1_HU_SZ};
+use crate::base::base_constants::{FPS_PRODUCT_HASH_SIZE, FPS_VENDOR_HASH_SIZE};
+use crate::base::parse_json::base_parse_json_helper::invalidFieldType;
+use crate::extension::extension_constants;
+use crate::extension::structures::extension_structures::SDKExtension;
+use crate::extension::wrapped_key::KEY_WRAP_ALGORITHM_NAMES;
+use crate::validate::{FPSStatus, Result};
+use crate::{fpsLogError, returnErrorStatus};
+use serde_jsonrc::{json, Map, Value};
+use std::sync::RwLock;
+
+/// Enables strict mode when set to `1` or `true`.
+pub const STRICT_SCHEMA_ENV: &str = "FPS_STRICT_SCHEMA";
+
+const JSON_SCHEMA_DIALECT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+/// Accepted values of a JSON field.
+#[derive(Debug, Clone, Copy)]
+pub enum SchemaType {
+    object(&'static [SchemaField]),
+    array {
+        items: &'static SchemaType,
+        minItems: usize,
+        maxItems: Option<usize>,
+    },
+    /// Hex string of exactly `length` bytes, optionally prefixed with `0x` if `prefixed`
+    hexBytes {
+        length: usize,
+        prefixed: bool,
+    },
+    /// Hex string of any length
+    hex,
+    base64,
+    string,
+    /// One of these strings
+    enumeration(&'static [&'static str]),
+    boolean,
+    /// Integer in `min..=max`, also as a decimal string if `strings`
+    integer {
+        min: i128,
+        max: i128,
+        strings: bool,
+    },
+    /// The first of these whose JSON type matches
+    oneOf(&'static [SchemaType]),
+}
+
+/// A member of a JSON object.
+#[derive(Debug, Clone, Copy)]
+pub struct SchemaField {
+    pub name: &'static str,
+    pub schemaType: SchemaType,
+    pub required: bool,
+    pub description: &'static str,
+}
+
+const fn required(name: &'static str, schemaType: SchemaType, description: &'static str) -> SchemaField {
+    SchemaField {
+        name,
+        schemaType,
+        required: true,
+        description,
+    }
+}
+
+const fn optional(name: &'static str, schemaType: SchemaType, description: &'static str) -> SchemaField {
+    SchemaField {
+        name,
+        schemaType,
+        required: false,
+        description,
+    }
+}
+
+const U32: SchemaType = SchemaType::integer {
+    min: 0,
+    max: u32::MAX as i128,
+    strings: false,
+};
+const U32_OR_STRING: SchemaType = SchemaType::integer {
+    min: 0,
+    max: u32::MAX as i128,
+    strings: true,
+};
+const VERSION: SchemaType = hexBytes(4);
+
+const fn hexBytes(length: usize) -> SchemaType {
+    SchemaType::hexBytes {
+        length,
+        prefixed: false,
+    }
+}
+
+const OFFLINE_HLS: SchemaType = SchemaType::object(&[
+    optional(
+        base_constants::STREAM_ID_STR,
+        hexBytes(FPS_OFFLINE_CONTENTID_LENGTH),
+        "Unique ID of the HLS stream",
+    ),
+    optional(
+        base_constants::TITLE_ID_STR,
+        hexBytes(FPS_MAX_TITLE_ID_LENGTH),
+        "ID of the HLS title, the same for all of its streams",
+    ),
+    optional(
+        base_constants::RENTAL_DURATION_STR,
+        U32_OR_STRING,
+        "Seconds from download, 0 for none",
+    ),
+    optional(
+        base_constants::PLAYBACK_DURATION_STR,
+        U32_OR_STRING,
+        "Seconds from first playback, 0 for none",
+    ),
+    optional(
+        extension_constants::ACCOUNT_ID_STR,
+        SchemaType::string,
+        "Account the license is issued to, recorded in the offline license ledger",
+    ),
+]);
+
+const ASSET_INFO: SchemaType = SchemaType::object(&[
+    optional(
+        base_constants::CONTENT_KEY_STR,
+        SchemaType::hexBytes {
+            length: AES128_KEY_SZ,
+            prefixed: true,
+        },
+        "Content key",
+    ),
+    optional(
+        extension_constants::CONTENT_KEY_WRAPPED_STR,
+        SchemaType::oneOf(&[
+            SchemaType::string,
+            SchemaType::object(&[
+                required(
+                    extension_constants::WRAPPED_KEY_ALG_STR,
+                    SchemaType::enumeration(KEY_WRAP_ALGORITHM_NAMES),
+                    "Key wrapping algorithm",
+                ),
+                optional(
+                    extension_constants::WRAPPED_KEY_KID_STR,
+                    SchemaType::string,
+                    "ID of the key-encryption key",
+                ),
+                required(
+                    extension_constants::WRAPPED_KEY_VALUE_STR,
+                    SchemaType::base64,
+                    "Wrapped content key",
+                ),
+            ]),
+        ]),
+        "Content key wrapped with the key-encryption key, as a JWE compact string or an object, instead of content-key",
+    ),
+    optional(
+        base_constants::CONTENT_IV_STR,
+        SchemaType::hexBytes {
+            length: AES128_IV_SZ,
+            prefixed: true,
+        },
+        "Content IV",
+    ),
+    optional(
+        base_constants::LEASE_DURATION_STR,
+        U32,
+        "Seconds from SPC creation, 0 for no lease",
+    ),
+    optional(
+        base_constants::OFFLINE_HLS_STR,
+        OFFLINE_HLS,
+        "Persistent license parameters",
+    ),
+    optional(
+        base_constants::HDCP_TYPE_STR,
+        SchemaType::integer {
+            min: -1,
+            max: 1,
+            strings: true,
+        },
+        "-1 for HDCP not required, 0 (default) for type 0, 1 for type 1",
+    ),
+    optional(
+        extension_constants::CONTENT_TYPE_STR,
+        SchemaType::enumeration(&[
+            extension_constants::CONTENT_TYPE_UHD_STR,
+            extension_constants::CONTENT_TYPE_HD_STR,
+            extension_constants::CONTENT_TYPE_SD_STR,
+            extension_constants::CONTENT_TYPE_AUDIO_STR,
+            extension_constants::CONTENT_TYPE_UNKNOWN_STR,
+        ]),
+        "Content type the policy rules apply to",
+    ),
+]);
+
+const CREATE_CKC_REQUEST: SchemaType = SchemaType::object(&[
+    optional(
+        base_constants::ID_STR,
+        SchemaType::integer {
+            min: 0,
+            max: u64::MAX as i128,
+            strings: true,
+        },
+        "Returned in the result, defaults to 0",
+    ),
+    required(base_constants::SPC_STR, SchemaType::base64, "SPC sent by the client"),
+    optional(
+        base_constants::CHECK_IN_STR,
+        SchemaType::boolean,
+        "Answer an offline key check-in",
+    ),
+    optional(
+        base_constants::ASSET_INFO_STR,
+        SchemaType::array {
+            items: &ASSET_INFO,
+            minItems: 1,
+            maxItems: Some(1),
+        },
+        "Protection requirements of the requested asset",
+    ),
+]);
+
+/// `fairplay-streaming-request` document
+pub const REQUEST_SCHEMA: SchemaType = SchemaType::object(&[required(
+    extension_constants::FAIRPLAY_STREAMING_REQUEST_STR,
+    SchemaType::object(&[required(
+        base_constants::CREATE_CKC_STR,
+        SchemaType::array {
+            items: &CREATE_CKC_REQUEST,
+            minItems: 1,
+            maxItems: None,
+        },
+        "Operations to process",
+    )]),
+    "Key request",
+)]);
+
+const DEVICE_CLASS_NAME: SchemaType = SchemaType::enumeration(&[
+    "appleDesktop",
+    "appleMobile",
+    "appleWearable",
+    "appleLivingRoom",
+    "appleSpacial",
+    "Unknown",
+]);
+
+const STATUS: SchemaType = SchemaType::integer {
+    min: i32::MIN as i128,
+    max: 0,
+    strings: false,
+};
+
+const CREATE_CKC_RESULT: SchemaType = SchemaType::object(&[
+    required(
+        base_constants::ID_STR,
+        SchemaType::integer {
+            min: 0,
+            max: u64::MAX as i128,
+            strings: false,
+        },
+        "id of the operation",
+    ),
+    required(base_constants::STATUS_STR, STATUS, "FPSStatus, 0 for success"),
+    optional(
+        base_constants::ERROR_STR,
+        SchemaType::object(&[
+            required(base_constants::STATUS_STR, STATUS, "FPSStatus"),
+            required(
+                base_constants::REASON_STR,
+                SchemaType::string,
+                "Machine-readable reason",
+            ),
+            optional(
+                base_constants::MESSAGE_STR,
+                SchemaType::string,
+                "Human-readable message",
+            ),
+        ]),
+        "Why the operation failed, depending on the server configuration",
+    ),
+    optional(base_constants::HU_STR, hexBytes(FPS_V1_HU_SZ), "Client HU"),
+    optional(
+        base_constants::CHECK_IN_SERVER_CHALLENGE_STR,
+        SchemaType::integer {
+            min: 0,
+            max: u64::MAX as i128,
+            strings: true,
+        },
+        "Server challenge of a check-in",
+    ),
+    optional(base_constants::CHECK_IN_FLAGS_STR, SchemaType::hex, "Sync flags"),
+    optional(
+        base_constants::DURATION_LEFT_STR,
+        U32_OR_STRING,
+        "Seconds to rental expiry",
+    ),
+    optional(
+        base_constants::CHECK_IN_TITLE_ID_STR,
+        hexBytes(FPS_MAX_TITLE_ID_LENGTH),
+        "Title ID of a check-in",
+    ),
+    optional(
+        base_constants::CHECK_IN_STREAM_ID_STR,
+        SchemaType::array {
+            items: &hexBytes(FPS_OFFLINE_CONTENTID_LENGTH),
+            minItems: 1,
+            maxItems: None,
+        },
+        "Stream IDs of the deleted keys",
+    ),
+    optional(
+        extension_constants::CHECK_IN_VERIFIED_STR,
+        SchemaType::boolean,
+        "Whether the check-in carried a server challenge minted for this device and title",
+    ),
+    optional(base_constants::FPDI_VERSION_STR, U32, "Device identity version"),
+    optional(base_constants::DEVICE_CLASS_STR, U32, "FPSDeviceClass"),
+    optional(
+        base_constants::VENDOR_HASH_STR,
+        hexBytes(FPS_VENDOR_HASH_SIZE),
+        "Vendor hash",
+    ),
+    optional(
+        base_constants::PRODUCT_HASH_STR,
+        hexBytes(FPS_PRODUCT_HASH_SIZE),
+        "Product hash",
+    ),
+    optional(
+        base_constants::FPS_REE_VERSION_STR,
+        VERSION,
+        "FairPlay Streaming REE version",
+    ),
+    optional(
+        base_constants::FPS_TEE_VERSION_STR,
+        VERSION,
+        "FairPlay Streaming TEE version",
+    ),
+    optional(base_constants::OS_VERSION_STR, VERSION, "OS version"),
+    optional(
+        base_constants::HOST_DEVICE_CLASS_STR,
+        DEVICE_CLASS_NAME,
+        "Device class of the VM host",
+    ),
+    optional(
+        base_constants::HOST_OS_VERSION_STR,
+        VERSION,
+        "OS version of the VM host",
+    ),
+    optional(
+        base_constants::HOST_VM_PROTOCOL_VERSION,
+        U32,
+        "VM protocol version of the host",
+    ),
+    optional(
+        base_constants::GUEST_DEVICE_CLASS_STR,
+        DEVICE_CLASS_NAME,
+        "Device class of the VM guest",
+    ),
+    optional(
+        base_constants::GUEST_OS_VERSION_STR,
+        VERSION,
+        "OS version of the VM guest",
+    ),
+    optional(
+        base_constants::GUEST_VM_PROTOCOL_VERSION,
+        U32,
+        "VM protocol version of the guest",
+    ),
+    optional(base_constants::CKC_STR, SchemaType::base64, "CKC for the client"),
+]);
+
+/// `fairplay-streaming-response` document
+pub const RESPONSE_SCHEMA: SchemaType = SchemaType::object(&[required(
+    extension_constants::FAIRPLAY_STREAMING_RESPONSE_STR,
+    SchemaType::object(&[required(
+        base_constants::CREATE_CKC_STR,
+        SchemaType::array {
+            items: &CREATE_CKC_RESULT,
+            minItems: 1,
+            maxItems: None,
+        },
+        "One result per operation",
+    )]),
+    "Key response",
+)]);
+
+/// Logs a constraint violation and returns the status to fail with.
+fn outOfRange(path: &str, message: String) -> FPSStatus {
+    fpsLogError!(FPSStatus::paramErr, reason = "out-of-range", "{} {}", path, message);
+    FPSStatus::paramErr
+}
+
+fn childPath(path: &str, name: &str) -> String {
+    if path.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", path, name)
+    }
+}
+
+impl SchemaType {
+    /// Description of the accepted values, for error messages.
+    fn expected(&self) -> String {
+        match self {
+            SchemaType::object(_) => "an object".to_string(),
+            SchemaType::array { .. } => "an array".to_string(),
+            SchemaType::hexBytes { .. } | SchemaType::hex => "a hex string".to_string(),
+            SchemaType::base64 => "a base64 string".to_string(),
+            SchemaType::string | SchemaType::enumeration(_) => "a string".to_string(),
+            SchemaType::boolean => "a boolean".to_string(),
+            SchemaType::integer { strings: true, .. } => "an integer or a string".to_string(),
+            SchemaType::integer { strings: false, .. } => "an integer".to_string(),
+            SchemaType::oneOf(choices) => choices
+                .iter()
+                .map(SchemaType::expected)
+                .collect::<Vec<_>>()
+                .join(" or "),
+        }
+    }
+
+    /// True if `value` has the JSON type of this schema, whatever its contents.
+    fn acceptsType(&self, value: &Value) -> bool {
+        match self {
+            SchemaType::object(_) => value.is_object(),
+            SchemaType::array { .. } => value.is_array(),
+            SchemaType::boolean => value.is_boolean(),
+            SchemaType::integer { strings, .. } => value.is_number() || (*strings && value.is_string()),
+            SchemaType::oneOf(choices) => choices.iter().any(|choice| choice.acceptsType(value)),
+            _ => value.is_string(),
+        }
+    }
+
+    /// Checks `value`, located at `path`, and fails on its first violation.
+    pub fn validate(&self, value: &Value, path: &str) -> Result<()> {
+        match (self, value) {
+            (SchemaType::object(fields), Value::Object(object)) => {
+                for (name, member) in object {
+                    let memberPath = childPath(path, name);
+                    match fields.iter().find(|field| field.name == name) {
+                        Some(field) => field.schemaType.validate(member, &memberPath)?,
+                        None => {
+                            fpsLogError!(
+                                FPSStatus::paramErr,
+                                reason = "unknown-field",
+                                "unknown field {}",
+                                memberPath
+                            );
+                            returnErrorStatus!(FPSStatus::paramErr);
+                        }
+                    }
+                }
+                if let Some(field) = fields
+                    .iter()
+                    .find(|field| field.required && !object.contains_key(field.name))
+                {
+                    fpsLogError!(
+                        FPSStatus::paramErr,
+                        reason = "missing-field",
+                        "{} not found",
+                        childPath(path, field.name)
+                    );
+                    returnErrorStatus!(FPSStatus::paramErr);
+                }
+                Ok(())
+            }
+            (
+                SchemaType::array {
+                    items,
+                    minItems,
+                    maxItems,
+                },
+                Value::Array(array),
+            ) => {
+                if array.len() < *minItems || maxItems.is_some_and(|maxItems| array.len() > maxItems) {
+                    let limit = match maxItems {
+                        Some(maxItems) if maxItems == minItems => format!("{}", minItems),
+                        Some(maxItems) => format!("{} to {}", minItems, maxItems),
+                        None => format!("at least {}", minItems),
+                    };
+                    return Err(outOfRange(
+                        path,
+                        format!("must have {} entries, not {}", limit, array.len()),
+                    ));
+                }
+                for (index, item) in array.iter().enumerate() {
+                    items.validate(item, &format!("{}[{}]", path, index))?;
+                }
+                Ok(())
+            }
+            (SchemaType::hexBytes { length, prefixed }, Value::String(text)) => {
+                let text = match prefixed {
+                    true => text.strip_prefix("0x").unwrap_or(text),
+                    false => text,
+                };
+                match hex::decode(text) {
+                    Ok(bytes) if bytes.len() == *length => Ok(()),
+                    Ok(bytes) => Err(outOfRange(
+                        path,
+                        format!("must be {} bytes, not {}", length, bytes.len()),
+                    )),
+                    Err(e) => Err(outOfRange(path, format!("is not hex: {}", e))),
+                }
+            }
+            (SchemaType::hex, Value::String(text)) => match text.chars().all(|c| c.is_ascii_hexdigit()) {
+                true => Ok(()),
+                false => Err(outOfRange(path, "is not hex".to_string())),
+            },
+            (SchemaType::base64, Value::String(text)) => {
+                use base64::Engine;
+                match base64::engine::general_purpose::STANDARD.decode(text) {
+                    Ok(_) => Ok(()),
+                    Err(e) => Err(outOfRange(path, format!("is not base64: {}", e))),
+                }
+            }
+            (SchemaType::string, Value::String(_)) | (SchemaType::boolean, Value::Bool(_)) => Ok(()),
+            (SchemaType::enumeration(names), Value::String(text)) => match names.contains(&text.as_str()) {
+                true => Ok(()),
+                false => Err(outOfRange(
+                    path,
+                    format!("must be one of {}, not \"{}\"", names.join(", "), text),
+                )),
+            },
+            (SchemaType::integer { min, max, strings }, Value::Number(_) | Value::String(_)) => {
+                let number = match value {
+                    Value::Number(number) => number.as_i64().map(i128::from).or(number.as_u64().map(i128::from)),
+                    Value::String(text) if *strings => text.parse::<i128>().ok(),
+                    _ => return Err(invalidFieldType(path, &self.expected(), value)),
+                };
+                match number {
+                    Some(number) if (*min..=*max).contains(&number) => Ok(()),
+                    _ => Err(outOfRange(
+                        path,
+                        format!("must be an integer from {} to {}, not {}", min, max, value),
+                    )),
+                }
+            }
+            (SchemaType::oneOf(choices), _) => match choices.iter().find(|choice| choice.acceptsType(value)) {
+                Some(choice) => choice.validate(value, path),
+                None => Err(invalidFieldType(path, &self.expected(), value)),
+            },
+            _ => Err(invalidFieldType(path, &self.expected(), value)),
+        }
+    }
+
+    /// JSON Schema of the accepted values.
+    pub fn toJsonSchema(&self) -> Value {
+        let integer = |number: i128| match u64::try_from(number) {
+            Ok(number) => json!(number),
+            Err(_) => json!(number as i64),
+        };
+
+        match self {
+            SchemaType::object(fields) => {
+                let mut properties = Map::new();
+                for field in fields.iter() {
+                    let mut property = field.schemaType.toJsonSchema();
+                    property["description"] = json!(field.description);
+                    properties.insert(field.name.to_string(), property);
+                }
+                let required: Vec<&str> = fields
+                    .iter()
+                    .filter(|field| field.required)
+                    .map(|field| field.name)
+                    .collect();
+                json!({
+                    "type": "object",
+                    "properties": properties,
+                    "required": required,
+                    "additionalProperties": false,
+                })
+            }
+            SchemaType::array {
+                items,
+                minItems,
+                maxItems,
+            } => {
+                let mut schema = json!({ "type": "array", "items": items.toJsonSchema(), "minItems": minItems });
+                if let Some(maxItems) = maxItems {
+                    schema["maxItems"] = json!(maxItems);
+                }
+                schema
+            }
+            SchemaType::hexBytes { length, prefixed } => {
+                let prefix = if *prefixed { "(0x)?" } else { "" };
+                json!({ "type": "string", "pattern": format!("^{}[0-9A-Fa-f]{{{}}}$", prefix, length * 2) })
+            }
+            SchemaType::hex => json!({ "type": "string", "pattern": "^[0-9A-Fa-f]*$" }),
+            SchemaType::base64 => json!({ "type": "string", "contentEncoding": "base64" }),
+            SchemaType::string => json!({ "type": "string" }),
+            SchemaType::enumeration(names) => json!({ "type": "string", "enum": names }),
+            SchemaType::boolean => json!({ "type": "boolean" }),
+            SchemaType::integer { min, max, strings } => {
+                let number = json!({ "type": "integer", "minimum": integer(*min), "maximum": integer(*max) });
+                match strings {
+                    true => json!({ "oneOf": [number, { "type": "string", "pattern": "^-?[0-9]+$" }] }),
+                    false => number,
+                }
+            }
+            SchemaType::oneOf(choices) => {
+                json!({ "oneOf": choices.iter().map(SchemaType::toJsonSchema).collect::<Vec<_>>() })
+            }
+        }
+    }
+}
+
+/// Published JSON Schema of a document.
+pub fn jsonSchema(title: &str, schemaType: &SchemaType) -> Value {
+    let mut schema = Map::new();
+    schema.insert("$schema".to_string(), json!(JSON_SCHEMA_DIALECT));
+    schema.insert("title".to_string(), json!(title));
+    if let Value::Object(definition) = schemaType.toJsonSchema() {
+        schema.extend(definition);
+    }
+    Value::Object(schema)
+}
+
+pub fn requestJsonSchema() -> Value {
+    jsonSchema(extension_constants::FAIRPLAY_STREAMING_REQUEST_STR, &REQUEST_SCHEMA)
+}
+
+pub fn responseJsonSchema() -> Value {
+    jsonSchema(extension_constants::FAIRPLAY_STREAMING_RESPONSE_STR, &RESPONSE_SCHEMA)
+}
+
+/// Mode installed with `SDKExtension::setStrictSchema`, or read from `FPS_STRICT_SCHEMA` once first used.
+static STRICT_SCHEMA: RwLock<Option<bool>> = RwLock::new(None);
+
+impl SDKExtension {
+    /// Rejects requests that do not match `REQUEST_SCHEMA` exactly, for all threads.
+    ///
+    /// Off by default: unknown fields are ignored and invalid values fall back to defaults.
+    pub fn setStrictSchema(strict: bool) {
+        *STRICT_SCHEMA.write().unwrap_or_else(|e| e.into_inner()) = Some(strict);
+    }
+
+    pub fn strictSchema() -> bool {
+        if let Some(strict) = *STRICT_SCHEMA.read().unwrap_or_else(|e| e.into_inner()) {
+            return strict;
+        }
+
+        let strict = matches!(std::env::var(STRICT_SCHEMA_ENV).as_deref(), Ok("1") | Ok("true"));
+        *STRICT_SCHEMA
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .get_or_insert(strict)
+    }
+}
@@ -0,0 +1,132 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+//! Shares the parsed SPC between the `create-ckc` operations of one request.
+//!
+//! A client asking for several keys at once (audio and video renditions of one title) sends the
+//! same SPC in each operation. `Base::processOperations` installs one `SPCCache` per request, so
+//! that SPC is decrypted and its TLLVs parsed once; every operation still builds its own CKC.
+//! Entries are keyed by the SHA-256 of the SPC and dropped with the request. The decrypted
+//! container, with the session key and SPC payload, is shared by reference and never copied.
+//!
+//! Only `serverCtx.spcContainer` is cached: fields set elsewhere in `FPSServerCtx` by
+//! `checkSupportedFeaturesCustom` are not carried over to the other operations.
+
+use crate::base::structures::base_server_structures::{FPSServerCtx, FPSServerSPCContainer, FPSServerSPCData};
+use crate::extension::replay_cache::{Fingerprint, ReplayCache};
+use crate::extension::structures::extension_structures::SDKExtension;
+use crate::validate::Result;
+use openssl::sha::sha256;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+/// Parsed container of one SPC, `None` until an operation parsed it successfully.
+type SPCCacheEntry = Arc<Mutex<Option<Arc<FPSServerSPCContainer>>>>;
+
+/// Parsed SPCs of one request.
+#[derive(Debug, Default)]
+pub struct SPCCache {
+    /// Entry per SPC digest. Each entry is locked while its SPC is parsed, so concurrent
+    /// operations wait for the result.
+    entries: Mutex<HashMap<[u8; 32], SPCCacheEntry>>,
+    /// SPCs that passed replay detection in this request
+    replayChecked: Mutex<HashSet<Fingerprint>>,
+}
+
+impl SPCCache {
+    pub fn new() -> SPCCache {
+        SPCCache::default()
+    }
+
+    /// Number of SPCs parsed successfully.
+    pub fn len(&self) -> usize {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries
+            .values()
+            .filter(|entry| entry.lock().unwrap_or_else(|e| e.into_inner()).is_some())
+            .count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Fills `serverCtx.spcContainer` from the cache, or with `parse` the first time `spc` is seen.
+    ///
+    /// Failures are not cached: the next operation with the same SPC parses it again and logs its own error.
+    pub fn parse(
+        &self,
+        spc: &[u8],
+        serverCtx: &mut FPSServerCtx,
+        parse: impl FnOnce(&[u8], &mut FPSServerCtx) -> Result<()>,
+    ) -> Result<()> {
+        let entry = self
+            .entries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(sha256(spc))
+            .or_default()
+            .clone();
+        let mut cached = entry.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Some(spcContainer) = cached.as_ref() {
+            log::debug!("SPC already parsed by another operation of this request");
+            serverCtx.spcContainer = Arc::clone(spcContainer);
+            return Ok(());
+        }
+
+        parse(spc, serverCtx)?;
+        *cached = Some(Arc::clone(&serverCtx.spcContainer));
+
+        Ok(())
+    }
+
+    /// Runs `replayCache.check` once per SPC of the request, so operations sharing an SPC do
+    /// not reject each other as replays.
+    pub fn checkReplay(&self, replayCache: &ReplayCache, spcData: &FPSServerSPCData) -> Result<()> {
+        let fingerprint = ReplayCache::fingerprint(spcData);
+        let mut replayChecked = self.replayChecked.lock().unwrap_or_else(|e| e.into_inner());
+        if replayChecked.contains(&fingerprint) {
+            return Ok(());
+        }
+
+        replayCache.check(spcData)?;
+        replayChecked.insert(fingerprint);
+
+        Ok(())
+    }
+}
+
+thread_local! {
+    /// Cache of the request currently processed on this thread
+    static SCOPED_SPC_CACHE: RefCell<Option<Arc<SPCCache>>> = const { RefCell::new(None) };
+}
+
+/// Restores the previously scoped cache, also when the operation panics.
+struct ScopedSPCCacheGuard {
+    previous: Option<Arc<SPCCache>>,
+}
+
+impl Drop for ScopedSPCCacheGuard {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        SCOPED_SPC_CACHE.with(|scoped| *scoped.borrow_mut() = previous);
+    }
+}
+
+impl SDKExtension {
+    /// Returns the cache of the request processed on the calling thread, if any.
+    pub fn spcCache() -> Option<Arc<SPCCache>> {
+        SCOPED_SPC_CACHE.with(|scoped| scoped.borrow().clone())
+    }
+
+    /// Runs `f` with `spcCache` shared by every SPC parsed on this thread.
+    pub fn withSPCCache<R>(spcCache: Arc<SPCCache>, f: impl FnOnce() -> R) -> R {
+        let previous = SCOPED_SPC_CACHE.with(|scoped| scoped.borrow_mut().replace(spcCache));
+        let _guard = ScopedSPCCacheGuard { previous };
+
+        f()
+    }
+}
@@ -2,9 +2,13 @@
 // Copyright © 2023-2024 Apple Inc. All rights reserved.
 //
 
+use crate::extension::credentials::credential_provider::CredentialProvider;
 use crate::extension::extension_constants::ContentType;
 use crate::extension::extension_constants::FPSSecurityLevel;
+use crate::extension::offline_ledger::OfflineReservation;
+use derivative::Derivative;
 use std::fmt::Debug;
+use std::sync::Arc;
 
 #[derive(Debug, Default, Clone)]
 pub struct SDKExtension {}
@@ -15,18 +19,26 @@ pub struct FPSOperationExtension {}
 #[derive(Debug, Default, Clone)]
 pub struct AssetInfoExtension {
     pub contentType: ContentType,
+    /// `account-id` of the `offline-hls` object, recorded in the offline ledger
+    pub accountId: Option<String>,
 }
 
 #[derive(Debug, Default, Clone)]
 pub struct ServerCtxExtension {
     pub contentType: ContentType,
+    pub accountId: Option<String>,
+    /// Offline license recorded ahead of CKC generation (see `extension::offline_ledger`)
+    pub offlineReservation: Option<Arc<OfflineReservation>>,
 }
 
 #[derive(Debug, Default, Clone)]
 pub struct FPSResultsExtension {}
 
 #[derive(Debug, Default, Clone)]
-pub struct FPSResultExtension {}
+pub struct FPSResultExtension {
+    /// The check-in carried a fresh server challenge minted for its HU and title
+    pub checkInVerified: bool,
+}
 
 #[derive(Debug, Default, Clone)]
 pub struct SPCDataExtension {}
@@ -36,8 +48,13 @@ pub struct CKCDataExtension {
     pub requiredSecurityLevel: FPSSecurityLevel,
 }
 
-#[derive(Debug, Default, Clone)]
-pub struct SPCContainerExtension {}
+#[derive(Derivative, Default, Clone)]
+#[derivative(Debug)]
+pub struct SPCContainerExtension {
+    /// Credentials of the certificate named by the SPC certificate hash, once selected
+    #[derivative(Debug = "ignore")]
+    pub credentials: Option<Arc<dyn CredentialProvider>>,
+}
 
 #[derive(Debug, Default, Clone)]
 pub struct ClientFeaturesExtension {}
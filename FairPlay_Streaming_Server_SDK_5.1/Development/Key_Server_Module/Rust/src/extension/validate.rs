@@ -29,6 +29,10 @@ macro_rules! returnErrorStatus {
     };
 }
 
+use crate::extension::structures::extension_structures::SDKExtension;
+use std::cell::RefCell;
+use std::sync::RwLock;
+
 pub type Result<T> = std::result::Result<T, FPSStatus>;
 
 /// Error codes used by FairPlay Streaming.
@@ -47,6 +51,10 @@ pub enum FPSStatus {
     clientSecurityLevelErr = -42604,
     invalidCertificateErr = -42605,
     notImplementedErr = -42612,
+    /// The SPC was already answered (see `extension::replay_cache`)
+    replayErr = -42613,
+    /// The device or account reached its offline download limits (see `extension::policy`)
+    offlineLimitErr = -42614,
 }
 
 impl std::fmt::Display for FPSStatus {
@@ -54,3 +62,239 @@ impl std::fmt::Display for FPSStatus {
         write!(f, "{}", *self as i32)
     }
 }
+
+/// Error returned by the typed `KeyServer` API.
+///
+/// Carries the full `FPSErrorDetail` whatever `SDKExtension::errorDetails` is set to, since that
+/// setting only limits what is returned to JSON callers.
+#[derive(Debug, PartialEq, Clone)]
+pub struct FpsError {
+    /// Id of the request that failed
+    id: u64,
+    detail: FPSErrorDetail,
+}
+
+impl FpsError {
+    /// Error without a more specific reason or message than `status` gives.
+    pub fn new(id: u64, status: FPSStatus) -> FpsError {
+        FpsError::withDetail(id, FPSErrorDetail::new(status))
+    }
+
+    pub fn withDetail(id: u64, detail: FPSErrorDetail) -> FpsError {
+        FpsError { id, detail }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn status(&self) -> FPSStatus {
+        self.detail.status
+    }
+
+    /// Stable kebab-case reason, as in the `error` object of a `create-ckc` result
+    pub fn reason(&self) -> &str {
+        &self.detail.reason
+    }
+
+    /// Message logged where the error occurred
+    pub fn message(&self) -> &str {
+        &self.detail.message
+    }
+
+    pub fn detail(&self) -> &FPSErrorDetail {
+        &self.detail
+    }
+}
+
+impl std::fmt::Display for FpsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "request {} failed: {:?} ({})", self.id, self.status(), self.status())
+    }
+}
+
+impl std::error::Error for FpsError {}
+
+impl FPSStatus {
+    /// Machine-readable reason reported when the code that failed did not give a more specific one.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            FPSStatus::noErr => "ok",
+            FPSStatus::spcVersionErr => "unsupported-spc-version",
+            FPSStatus::parserErr => "malformed-input",
+            FPSStatus::missingRequiredTagErr => "missing-required-tag",
+            FPSStatus::paramErr => "invalid-parameter",
+            FPSStatus::memoryErr => "out-of-memory",
+            FPSStatus::versionErr => "unsupported-version",
+            FPSStatus::dupTagErr => "duplicate-tag",
+            FPSStatus::internalErr => "internal-error",
+            FPSStatus::clientSecurityLevelErr => "client-security-level",
+            FPSStatus::invalidCertificateErr => "invalid-certificate",
+            FPSStatus::notImplementedErr => "not-implemented",
+            FPSStatus::replayErr => "spc-replayed",
+            FPSStatus::offlineLimitErr => "offline-limit-reached",
+        }
+    }
+
+    /// HTTP status code answering a request that failed with this status.
+    ///
+    /// Problems with the SPC or the request are client errors; failures of the server itself are `500`.
+    pub fn httpStatus(&self) -> u16 {
+        match self {
+            FPSStatus::noErr => 200,
+            FPSStatus::spcVersionErr
+            | FPSStatus::parserErr
+            | FPSStatus::missingRequiredTagErr
+            | FPSStatus::paramErr
+            | FPSStatus::versionErr
+            | FPSStatus::dupTagErr
+            | FPSStatus::invalidCertificateErr => 400,
+            FPSStatus::clientSecurityLevelErr | FPSStatus::offlineLimitErr => 403,
+            FPSStatus::replayErr => 409,
+            FPSStatus::notImplementedErr => 501,
+            FPSStatus::memoryErr | FPSStatus::internalErr => 500,
+        }
+    }
+}
+
+impl TryFrom<i64> for FPSStatus {
+    type Error = i64;
+
+    /// Reads the `status` of a `create-ckc` result.
+    fn try_from(code: i64) -> std::result::Result<FPSStatus, i64> {
+        [
+            FPSStatus::noErr,
+            FPSStatus::spcVersionErr,
+            FPSStatus::parserErr,
+            FPSStatus::missingRequiredTagErr,
+            FPSStatus::paramErr,
+            FPSStatus::memoryErr,
+            FPSStatus::versionErr,
+            FPSStatus::dupTagErr,
+            FPSStatus::internalErr,
+            FPSStatus::clientSecurityLevelErr,
+            FPSStatus::invalidCertificateErr,
+            FPSStatus::notImplementedErr,
+            FPSStatus::replayErr,
+            FPSStatus::offlineLimitErr,
+        ]
+        .into_iter()
+        .find(|status| *status as i64 == code)
+        .ok_or(code)
+    }
+}
+
+/// Why an operation failed: the `error` object of a `create-ckc` result.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FPSErrorDetail {
+    pub status: FPSStatus,
+    /// Stable kebab-case reason, e.g. `hdcp-type-1-not-supported`
+    pub reason: String,
+    /// Message logged where the error occurred
+    pub message: String,
+}
+
+impl FPSErrorDetail {
+    /// Detail of an error returned without being logged.
+    pub fn new(status: FPSStatus) -> FPSErrorDetail {
+        FPSErrorDetail {
+            status,
+            reason: status.reason().to_string(),
+            message: format!("{:?}", status),
+        }
+    }
+}
+
+/// How much of `FPSErrorDetail` is returned in `create-ckc` results.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ErrorDetails {
+    /// No `error` object, only `status` (default, for untrusted callers)
+    #[default]
+    hidden,
+    /// `status` and `reason`
+    reason,
+    /// `status`, `reason` and `message`
+    full,
+}
+
+/// Selects `ErrorDetails` with `hidden`, `reason` or `full`.
+pub const ERROR_DETAILS_ENV: &str = "FPS_ERROR_DETAILS";
+
+/// Level installed with `SDKExtension::setErrorDetails`, or read from `FPS_ERROR_DETAILS` once first used.
+static ERROR_DETAILS: RwLock<Option<ErrorDetails>> = RwLock::new(None);
+
+thread_local! {
+    /// First error logged by the operation being processed on this thread, while one is captured
+    static CAPTURED_ERROR: RefCell<Option<Option<FPSErrorDetail>>> = const { RefCell::new(None) };
+}
+
+/// Restores the capture of an enclosing operation, also when the operation panics.
+struct ErrorCaptureGuard {
+    previous: Option<Option<FPSErrorDetail>>,
+}
+
+impl Drop for ErrorCaptureGuard {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        CAPTURED_ERROR.with(|captured| *captured.borrow_mut() = previous);
+    }
+}
+
+/// Runs `f` and returns the detail of the error it failed with, if it failed.
+///
+/// The detail is the first one `fpsLogError!` logged with the returned status, or a generic one
+/// if that status was returned without being logged.
+pub fn captureError<T>(f: impl FnOnce() -> Result<T>) -> (Result<T>, Option<FPSErrorDetail>) {
+    let previous = CAPTURED_ERROR.with(|captured| captured.borrow_mut().replace(None));
+    let guard = ErrorCaptureGuard { previous };
+
+    let result = f();
+    let captured = CAPTURED_ERROR.with(|captured| captured.borrow_mut().take()).flatten();
+    drop(guard);
+
+    let detail = match &result {
+        Ok(_) => None,
+        Err(status) => match captured {
+            Some(detail) if detail.status == *status => Some(detail),
+            _ => Some(FPSErrorDetail::new(*status)),
+        },
+    };
+    (result, detail)
+}
+
+/// Keeps the first error logged while an error is captured. Called by `fpsLogError!`.
+pub fn recordError(status: FPSStatus, reason: Option<&str>, message: &str) {
+    if status == FPSStatus::noErr {
+        return;
+    }
+
+    CAPTURED_ERROR.with(|captured| {
+        if let Some(first @ None) = captured.borrow_mut().as_mut() {
+            *first = Some(FPSErrorDetail {
+                status,
+                reason: reason.unwrap_or(status.reason()).to_string(),
+                message: message.to_string(),
+            });
+        }
+    });
+}
+
+impl SDKExtension {
+    /// Sets how much error detail `create-ckc` results carry, for all threads.
+    pub fn setErrorDetails(errorDetails: ErrorDetails) {
+        *ERROR_DETAILS.write().unwrap_or_else(|e| e.into_inner()) = Some(errorDetails);
+    }
+
+    pub fn errorDetails() -> ErrorDetails {
+        if let Some(errorDetails) = *ERROR_DETAILS.read().unwrap_or_else(|e| e.into_inner()) {
+            return errorDetails;
+        }
+
+        let errorDetails = match std::env::var(ERROR_DETAILS_ENV).as_deref() {
+            Ok("reason") => ErrorDetails::reason,
+            Ok("full") => ErrorDetails::full,
+            _ => ErrorDetails::hidden,
+        };
+        *ERROR_DETAILS.write().unwrap_or_else(|e| e.into_inner()).get_or_insert(errorDetails)
+    }
+}
@@ -0,0 +1,279 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+//! Content keys sent encrypted in `content-key-wrapped` instead of in the clear in `content-key`.
+//!
+//! The CMS wraps each content key with a key-encryption key that only it and the credential
+//! provider hold (`CredentialProvider::getKeyEncryptionKey`), so the request and every log that
+//! sees it carry nothing usable. The field is either a JWE compact serialization whose plaintext
+//! is the 16-byte content key, or an object with the key wrapped directly:
+//!
+//! ```text
+//! "content-key-wrapped": {"alg": "A256KW", "kid": "cms-2025", "value": "<base64>"}
+//! ```
+//!
+//! `alg` is one of `KEY_WRAP_ALGORITHM_NAMES` (AES key wrap with an AES key-encryption key,
+//! RSA-OAEP with an RSA one). JWE content encryption is `A128GCM` or `A256GCM`. `content-iv`
+//! is still sent in the clear.
+
+use crate::base::base_constants::AES128_KEY_SZ;
+use crate::base::structures::secret::SecretBytes;
+use crate::extension::credentials::credential_provider::KeyEncryptionKey;
+use crate::extension::extension_constants::{WRAPPED_KEY_ALG_STR, WRAPPED_KEY_KID_STR, WRAPPED_KEY_VALUE_STR};
+use crate::extension::structures::extension_structures::SDKExtension;
+use crate::validate::{FPSStatus, Result};
+use crate::{fpsLogError, returnErrorStatus};
+use base64::engine::general_purpose;
+use base64::Engine;
+use openssl::aes::{unwrap_key, AesKey};
+use openssl::encrypt::Decrypter;
+use openssl::hash::MessageDigest;
+use openssl::rsa::Padding;
+use openssl::symm::{Cipher, Crypter, Mode};
+use serde_jsonrc::Value;
+
+/// JWA names of the accepted key wrapping algorithms.
+pub const KEY_WRAP_ALGORITHM_NAMES: &[&str] = &["A128KW", "A192KW", "A256KW", "RSA-OAEP", "RSA-OAEP-256"];
+
+/// Length of the JWE initialization vector and authentication tag for AES-GCM
+const GCM_IV_SZ: usize = 12;
+const GCM_TAG_SZ: usize = 16;
+
+/// Algorithm a key was wrapped with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyWrapAlgorithm {
+    /// AES key wrap (RFC 3394) with a key-encryption key of this many bytes
+    aesKW(usize),
+    /// RSAES-OAEP with SHA-1
+    rsaOAEP,
+    /// RSAES-OAEP with SHA-256
+    rsaOAEP256,
+}
+
+/// Logs why a wrapped key was rejected and returns the status to fail with.
+fn invalidWrappedKey(message: String) -> FPSStatus {
+    fpsLogError!(
+        FPSStatus::paramErr,
+        reason = "invalid-wrapped-key",
+        "content-key-wrapped {}",
+        message
+    );
+    FPSStatus::paramErr
+}
+
+/// Logs a wrapped key that does not decrypt with the key-encryption key.
+fn unwrapFailed(what: &str) -> FPSStatus {
+    fpsLogError!(
+        FPSStatus::paramErr,
+        reason = "key-unwrap-failed",
+        "Unable to unwrap {} with the key-encryption key",
+        what
+    );
+    FPSStatus::paramErr
+}
+
+fn decodeBase64(text: &str, what: &str) -> Result<Vec<u8>> {
+    general_purpose::STANDARD
+        .decode(text)
+        .map_err(|e| invalidWrappedKey(format!("{} is not base64: {}", what, e)))
+}
+
+fn decodeBase64Url(text: &str, what: &str) -> Result<Vec<u8>> {
+    general_purpose::URL_SAFE_NO_PAD
+        .decode(text)
+        .map_err(|e| invalidWrappedKey(format!("{} is not base64url: {}", what, e)))
+}
+
+impl KeyWrapAlgorithm {
+    pub fn fromName(name: &str) -> Option<KeyWrapAlgorithm> {
+        match name {
+            "A128KW" => Some(KeyWrapAlgorithm::aesKW(16)),
+            "A192KW" => Some(KeyWrapAlgorithm::aesKW(24)),
+            "A256KW" => Some(KeyWrapAlgorithm::aesKW(32)),
+            "RSA-OAEP" => Some(KeyWrapAlgorithm::rsaOAEP),
+            "RSA-OAEP-256" => Some(KeyWrapAlgorithm::rsaOAEP256),
+            _ => None,
+        }
+    }
+
+    /// Decrypts `wrapped` with `keyEncryptionKey`, which must be of the kind the algorithm uses.
+    pub fn unwrap(&self, keyEncryptionKey: &KeyEncryptionKey, wrapped: &[u8]) -> Result<SecretBytes> {
+        match (self, keyEncryptionKey) {
+            (KeyWrapAlgorithm::aesKW(keyLength), KeyEncryptionKey::aes(key)) => {
+                if key.len() != *keyLength {
+                    return Err(invalidWrappedKey(format!(
+                        "uses a {}-byte key-encryption key, the provisioned one is {} bytes",
+                        keyLength,
+                        key.len()
+                    )));
+                }
+                // The wrapped key is one 8-byte block longer, and at least two blocks
+                if wrapped.len() < 24 || !wrapped.len().is_multiple_of(8) {
+                    return Err(invalidWrappedKey(format!(
+                        "is not AES key wrap output ({} bytes)",
+                        wrapped.len()
+                    )));
+                }
+
+                let Ok(aesKey) = AesKey::new_decrypt(key) else {
+                    fpsLogError!(FPSStatus::internalErr, "Unable to load the key-encryption key");
+                    returnErrorStatus!(FPSStatus::internalErr);
+                };
+                let mut unwrapped = SecretBytes::zeroed(wrapped.len() - 8);
+                match unwrap_key(&aesKey, None, &mut unwrapped, wrapped) {
+                    Ok(length) => unwrapped.truncate(length),
+                    Err(_) => return Err(unwrapFailed("the AES wrapped key")),
+                }
+                Ok(unwrapped)
+            }
+            (KeyWrapAlgorithm::rsaOAEP | KeyWrapAlgorithm::rsaOAEP256, KeyEncryptionKey::rsa(privateKey)) => {
+                let mut decrypter = match Decrypter::new(privateKey) {
+                    Ok(decrypter) => decrypter,
+                    Err(e) => {
+                        fpsLogError!(FPSStatus::internalErr, "Unable to load the key-encryption key: {}", e);
+                        returnErrorStatus!(FPSStatus::internalErr);
+                    }
+                };
+                let configured = decrypter.set_rsa_padding(Padding::PKCS1_OAEP).and_then(|_| match self {
+                    KeyWrapAlgorithm::rsaOAEP256 => decrypter
+                        .set_rsa_oaep_md(MessageDigest::sha256())
+                        .and_then(|_| decrypter.set_rsa_mgf1_md(MessageDigest::sha256())),
+                    _ => Ok(()),
+                });
+                let length = configured.and_then(|_| decrypter.decrypt_len(wrapped));
+                let Ok(length) = length else {
+                    return Err(unwrapFailed("the RSA wrapped key"));
+                };
+
+                let mut unwrapped = SecretBytes::zeroed(length);
+                match decrypter.decrypt(wrapped, &mut unwrapped) {
+                    Ok(length) => unwrapped.truncate(length),
+                    Err(_) => return Err(unwrapFailed("the RSA wrapped key")),
+                }
+                Ok(unwrapped)
+            }
+            (KeyWrapAlgorithm::aesKW(_), _) => Err(invalidWrappedKey(
+                "uses AES key wrap, the provisioned key-encryption key is RSA".to_string(),
+            )),
+            _ => Err(invalidWrappedKey(
+                "uses RSA-OAEP, the provisioned key-encryption key is AES".to_string(),
+            )),
+        }
+    }
+}
+
+/// Unwraps `wrapped` with the key-encryption key of the credential provider named `kid`.
+fn unwrapWith(alg: &str, kid: Option<&str>, wrapped: &[u8]) -> Result<SecretBytes> {
+    let Some(algorithm) = KeyWrapAlgorithm::fromName(alg) else {
+        return Err(invalidWrappedKey(format!(
+            "alg must be one of {}, not \"{}\"",
+            KEY_WRAP_ALGORITHM_NAMES.join(", "),
+            alg
+        )));
+    };
+    let keyEncryptionKey = SDKExtension::credentialProvider()?.getKeyEncryptionKey(kid)?;
+
+    algorithm.unwrap(&keyEncryptionKey, wrapped)
+}
+
+/// Decrypts a JWE compact serialization (`header.encryptedKey.iv.ciphertext.tag`).
+fn decryptJWE(jwe: &str) -> Result<SecretBytes> {
+    let parts: Vec<&str> = jwe.split('.').collect();
+    let [header, encryptedKey, iv, ciphertext, tag] = parts[..] else {
+        return Err(invalidWrappedKey(format!(
+            "is not a JWE compact serialization ({} parts instead of 5)",
+            parts.len()
+        )));
+    };
+
+    let headerJson = decodeBase64Url(header, "JWE header")?;
+    let Ok(Value::Object(headerFields)) = serde_jsonrc::from_slice::<Value>(&headerJson) else {
+        return Err(invalidWrappedKey("JWE header is not a JSON object".to_string()));
+    };
+    if headerFields.contains_key("zip") {
+        return Err(invalidWrappedKey("JWE must not be compressed".to_string()));
+    }
+    let (Some(alg), Some(enc)) = (
+        headerFields.get("alg").and_then(Value::as_str),
+        headerFields.get("enc").and_then(Value::as_str),
+    ) else {
+        return Err(invalidWrappedKey("JWE header needs alg and enc".to_string()));
+    };
+    let kid = headerFields.get("kid").and_then(Value::as_str);
+
+    let contentEncryptionKey = unwrapWith(alg, kid, &decodeBase64Url(encryptedKey, "JWE encrypted key")?)?;
+    let cipher = match (enc, contentEncryptionKey.len()) {
+        ("A128GCM", 16) => Cipher::aes_128_gcm(),
+        ("A256GCM", 32) => Cipher::aes_256_gcm(),
+        ("A128GCM" | "A256GCM", length) => {
+            return Err(invalidWrappedKey(format!(
+                "JWE key is {} bytes, which does not match {}",
+                length, enc
+            )));
+        }
+        _ => {
+            return Err(invalidWrappedKey(format!(
+                "JWE enc must be A128GCM or A256GCM, not \"{}\"",
+                enc
+            )))
+        }
+    };
+
+    let iv = decodeBase64Url(iv, "JWE IV")?;
+    let ciphertext = decodeBase64Url(ciphertext, "JWE ciphertext")?;
+    let tag = decodeBase64Url(tag, "JWE tag")?;
+    if iv.len() != GCM_IV_SZ || tag.len() != GCM_TAG_SZ {
+        return Err(invalidWrappedKey(format!(
+            "JWE IV and tag must be {} and {} bytes",
+            GCM_IV_SZ, GCM_TAG_SZ
+        )));
+    }
+
+    // The encoded header is the additional authenticated data
+    let mut plaintext = SecretBytes::zeroed(ciphertext.len() + cipher.block_size());
+    let decrypted = Crypter::new(cipher, Mode::Decrypt, &contentEncryptionKey, Some(&iv)).and_then(|mut crypter| {
+        crypter.aad_update(header.as_bytes())?;
+        let count = crypter.update(&ciphertext, &mut plaintext)?;
+        crypter.set_tag(&tag)?;
+        Ok(count + crypter.finalize(&mut plaintext[count..])?)
+    });
+    match decrypted {
+        Ok(length) => plaintext.truncate(length),
+        Err(_) => return Err(unwrapFailed("the JWE")),
+    }
+
+    Ok(plaintext)
+}
+
+/// Returns the content key carried by a `content-key-wrapped` value.
+pub fn unwrapContentKey(wrapped: &Value) -> Result<SecretBytes> {
+    let contentKey = match wrapped {
+        Value::String(jwe) => decryptJWE(jwe)?,
+        Value::Object(fields) => {
+            let (Some(alg), Some(value)) = (
+                fields.get(WRAPPED_KEY_ALG_STR).and_then(Value::as_str),
+                fields.get(WRAPPED_KEY_VALUE_STR).and_then(Value::as_str),
+            ) else {
+                return Err(invalidWrappedKey(format!(
+                    "needs {} and {} strings",
+                    WRAPPED_KEY_ALG_STR, WRAPPED_KEY_VALUE_STR
+                )));
+            };
+            let kid = fields.get(WRAPPED_KEY_KID_STR).and_then(Value::as_str);
+
+            unwrapWith(alg, kid, &decodeBase64(value, WRAPPED_KEY_VALUE_STR)?)?
+        }
+        _ => return Err(invalidWrappedKey("must be a JWE string or an object".to_string())),
+    };
+
+    if contentKey.len() != AES128_KEY_SZ {
+        return Err(invalidWrappedKey(format!(
+            "holds a {}-byte key instead of {}",
+            contentKey.len(),
+            AES128_KEY_SZ
+        )));
+    }
+
+    Ok(contentKey)
+}
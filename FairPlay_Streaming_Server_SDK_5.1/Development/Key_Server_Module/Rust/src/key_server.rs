@@ -0,0 +1,367 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+//! Typed Rust interface to the key server.
+//!
+//! `KeyServer::process` runs the same pipeline as a `create-ckc` entry in the
+//! `fairplay-streaming-request` JSON, without building or parsing any JSON:
+//!
+//! ```no_run
+//! use fpssdk::key_server::{AssetInfo, HDCPType, KeyRequest, KeyServer};
+//! # let spc: Vec<u8> = Vec::new();
+//!
+//! let request = KeyRequest::new(spc)
+//!     .assetInfo(AssetInfo::new().contentKey([0x3C; 16], [0xD5; 16]).hdcp(HDCPType::type1));
+//! let response = KeyServer::new().process(&request)?;
+//! println!("CKC is {} bytes", response.ckc.len());
+//! # Ok::<(), fpssdk::extension::validate::FpsError>(())
+//! ```
+//!
+//! The JSON-only hooks (`parseCreateCKCOperationCustom`, `parseAssetInfoCustom`, ...) are not
+//! called by `process`; every other extension hook runs as it does for JSON requests. A server
+//! can carry its own `FpsExtension` (see `extension::fps_extension`) and `RandomSource` (see
+//! `extension::random`).
+
+use crate::base::base_constants::{
+    self, FPSDeviceClass, FPSHDCPRequirement, FPSLicenseType, FPS_OFFLINE_CONTENTID_LENGTH,
+    KD_SYNC_SPC_FLAG_TITLEID_VALID, NO_LEASE_DURATION,
+};
+use crate::base::structures::base_fps_structures::{self, Base, FPSOperation, FPSResult};
+use crate::base::structures::base_server_structures::VMDeviceInfo;
+use crate::base::structures::secret::SecretBytes;
+use crate::extension::extension_constants::ContentType;
+use crate::extension::fps_extension::FpsExtension;
+use crate::extension::random::RandomSource;
+use crate::extension::structures::extension_structures::SDKExtension;
+use crate::validate::{self, FPSErrorDetail, FPSStatus, FpsError, Result};
+use crate::{fpsLogError, returnErrorStatus};
+use derivative::Derivative;
+use serde_jsonrc::Value;
+use std::sync::Arc;
+
+/// HDCP level the client must enforce for the content key.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum HDCPType {
+    notRequired,
+    /// Default, as for a JSON request without `hdcp-type`
+    #[default]
+    type0,
+    type1,
+}
+
+impl From<HDCPType> for FPSHDCPRequirement {
+    fn from(hdcp: HDCPType) -> FPSHDCPRequirement {
+        match hdcp {
+            HDCPType::notRequired => FPSHDCPRequirement::hdcpNotRequired,
+            HDCPType::type0 => FPSHDCPRequirement::hdcpType0,
+            HDCPType::type1 => FPSHDCPRequirement::hdcpType1,
+        }
+    }
+}
+
+/// Persistent (offline HLS) license parameters, the `offline-hls` JSON object.
+#[derive(Debug, Default, Clone)]
+pub struct OfflineLicense {
+    /// `stream-id` and `title-id` must be set together
+    pub streamId: Option<Vec<u8>>,
+    pub titleId: Option<Vec<u8>>,
+    /// Seconds from download, 0 for none
+    pub rentalDuration: u32,
+    /// Seconds from first playback, 0 for none
+    pub playbackDuration: u32,
+    /// Account the license is issued to, recorded in the offline license ledger
+    pub accountId: Option<String>,
+}
+
+/// Protection requirements for the requested asset, the `asset-info` JSON object.
+#[derive(Debug, Default, Clone)]
+pub struct AssetInfo {
+    /// 16-byte content key and IV. `None` leaves them to `queryDatabaseCustom` (e.g. the key store);
+    /// the request fails with `paramErr` if nothing provides them.
+    pub contentKey: Option<(SecretBytes, SecretBytes)>,
+    pub hdcp: HDCPType,
+    /// Seconds from SPC creation, `None` for no lease
+    pub leaseDuration: Option<u32>,
+    pub offline: Option<OfflineLicense>,
+    pub contentType: ContentType,
+}
+
+impl AssetInfo {
+    pub fn new() -> AssetInfo {
+        Default::default()
+    }
+
+    pub fn contentKey(mut self, key: impl Into<Vec<u8>>, iv: impl Into<Vec<u8>>) -> AssetInfo {
+        self.contentKey = Some((SecretBytes::from(key.into()), SecretBytes::from(iv.into())));
+        self
+    }
+
+    pub fn hdcp(mut self, hdcp: HDCPType) -> AssetInfo {
+        self.hdcp = hdcp;
+        self
+    }
+
+    pub fn leaseDuration(mut self, seconds: u32) -> AssetInfo {
+        self.leaseDuration = Some(seconds);
+        self
+    }
+
+    pub fn offline(mut self, offline: OfflineLicense) -> AssetInfo {
+        self.offline = Some(offline);
+        self
+    }
+
+    pub fn contentType(mut self, contentType: ContentType) -> AssetInfo {
+        self.contentType = contentType;
+        self
+    }
+
+    /// Converts to the structure the JSON parser fills in.
+    fn toOperationAssetInfo(&self) -> Result<base_fps_structures::AssetInfo> {
+        let mut assetInfo = base_fps_structures::AssetInfo::default();
+
+        if let Some((key, iv)) = &self.contentKey {
+            if key.len() != base_constants::AES128_KEY_SZ || iv.len() != base_constants::AES128_IV_SZ {
+                fpsLogError!(
+                    FPSStatus::paramErr,
+                    "content key and IV must be 16 bytes (got {} and {})",
+                    key.len(),
+                    iv.len()
+                );
+                returnErrorStatus!(FPSStatus::paramErr);
+            }
+            assetInfo.key = key.clone();
+            assetInfo.iv = iv.clone();
+            assetInfo.isCKProvided = true;
+        }
+
+        assetInfo.hdcpReq = FPSHDCPRequirement::from(self.hdcp) as u64;
+        assetInfo.leaseDuration = match self.leaseDuration {
+            None | Some(0) => NO_LEASE_DURATION,
+            Some(seconds) => seconds,
+        };
+
+        if let Some(offline) = &self.offline {
+            assetInfo.licenseType = FPSLicenseType::offlineHLS as u32;
+            assetInfo.streamId = offline.streamId.clone();
+            assetInfo.titleId = offline.titleId.clone();
+            assetInfo.rentalDuration = offline.rentalDuration;
+            assetInfo.playbackDuration = offline.playbackDuration;
+            assetInfo.extension.accountId = offline.accountId.clone();
+            Base::verifyOfflineHLS(&mut assetInfo)?;
+        }
+
+        assetInfo.extension.contentType = self.contentType;
+
+        Ok(assetInfo)
+    }
+}
+
+/// A single key request, the equivalent of one `create-ckc` JSON entry.
+#[derive(Debug, Default, Clone)]
+pub struct KeyRequest {
+    pub id: u64,
+    pub spc: Vec<u8>,
+    pub assetInfo: AssetInfo,
+    /// True when the SPC is a SyncSPC with check-in
+    pub isCheckIn: bool,
+    /// Seed of a `SeededRandom` used for this request only, for a reproducible CKC
+    #[cfg(feature = "deterministic_random")]
+    pub randomSeed: Option<u64>,
+}
+
+impl KeyRequest {
+    pub fn new(spc: impl Into<Vec<u8>>) -> KeyRequest {
+        KeyRequest {
+            spc: spc.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Caller chosen id, returned in `KeyResponse::id` and `FpsError::id`.
+    pub fn id(mut self, id: u64) -> KeyRequest {
+        self.id = id;
+        self
+    }
+
+    pub fn assetInfo(mut self, assetInfo: AssetInfo) -> KeyRequest {
+        self.assetInfo = assetInfo;
+        self
+    }
+
+    pub fn checkIn(mut self, isCheckIn: bool) -> KeyRequest {
+        self.isCheckIn = isCheckIn;
+        self
+    }
+
+    #[cfg(feature = "deterministic_random")]
+    pub fn randomSeed(mut self, seed: u64) -> KeyRequest {
+        self.randomSeed = Some(seed);
+        self
+    }
+
+    fn toOperation(&self) -> Result<FPSOperation> {
+        Ok(FPSOperation {
+            id: self.id,
+            spc: self.spc.clone(),
+            isCheckIn: self.isCheckIn,
+            assetInfo: self.assetInfo.toOperationAssetInfo()?,
+            parseError: None,
+            extension: Default::default(),
+        })
+    }
+}
+
+/// Device Identity TLLV values reported by the client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceIdentity {
+    pub fpdiVersion: u32,
+    pub deviceClass: FPSDeviceClass,
+    pub vendorHash: Vec<u8>,
+    pub productHash: Vec<u8>,
+    pub fpVersionREE: u32,
+    pub fpVersionTEE: u32,
+    pub osVersion: u32,
+}
+
+/// Sync TLLV values of a check-in request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncData {
+    pub serverChallenge: u64,
+    pub flags: u64,
+    /// Present when the client flagged the title ID as valid
+    pub titleId: Option<Vec<u8>>,
+    pub durationToRentalExpiry: u32,
+    /// Content IDs the client reports as deleted
+    pub deletedContentIds: Vec<Vec<u8>>,
+    /// The server challenge was minted for this device and title and is still fresh
+    pub verified: bool,
+}
+
+/// Result of a successful key request, the typed equivalent of one `create-ckc` JSON result.
+#[derive(Debug, Clone)]
+pub struct KeyResponse {
+    pub id: u64,
+    pub ckc: Vec<u8>,
+    pub hu: Vec<u8>,
+    pub sessionId: u64,
+    pub deviceIdentity: Option<DeviceIdentity>,
+    pub vmDeviceInfo: Option<VMDeviceInfo>,
+    pub sync: Option<SyncData>,
+}
+
+impl From<FPSResult> for KeyResponse {
+    fn from(result: FPSResult) -> KeyResponse {
+        let deviceIdentity = result.deviceIdentitySet.then(|| DeviceIdentity {
+            fpdiVersion: result.fpdiVersion,
+            deviceClass: FPSDeviceClass::from(result.deviceClass),
+            vendorHash: result.vendorHash.clone(),
+            productHash: result.productHash.clone(),
+            fpVersionREE: result.fpVersionREE,
+            fpVersionTEE: result.fpVersionTEE,
+            osVersion: result.osVersion,
+        });
+
+        let sync = result.isCheckIn.then(|| SyncData {
+            serverChallenge: result.syncServerChallenge,
+            flags: result.syncFlags,
+            titleId: ((result.syncFlags & KD_SYNC_SPC_FLAG_TITLEID_VALID) != 0).then(|| result.syncTitleId.clone()),
+            durationToRentalExpiry: result.durationToRentalExpiry,
+            deletedContentIds: result
+                .deletedContentIDs
+                .chunks(FPS_OFFLINE_CONTENTID_LENGTH)
+                .take(result.recordsDeleted)
+                .map(<[u8]>::to_vec)
+                .collect(),
+            verified: result.extension.checkInVerified,
+        });
+
+        KeyResponse {
+            id: result.id,
+            ckc: result.ckc,
+            hu: result.hu,
+            sessionId: result.sessionId,
+            deviceIdentity,
+            vmDeviceInfo: result.vmDeviceInfo,
+            sync,
+        }
+    }
+}
+
+/// Entry point of the typed API. Credentials, policy and stores are the ones configured on `SDKExtension`.
+#[derive(Derivative, Default, Clone)]
+#[derivative(Debug)]
+pub struct KeyServer {
+    /// Hooks for requests processed by this server, instead of `SDKExtension::fpsExtension()`
+    #[derivative(Debug = "ignore")]
+    extension: Option<Arc<dyn FpsExtension>>,
+    /// Random source for requests processed by this server, instead of `SDKExtension::randomSource()`
+    #[derivative(Debug = "ignore")]
+    randomSource: Option<Arc<dyn RandomSource>>,
+}
+
+impl KeyServer {
+    pub fn new() -> KeyServer {
+        Default::default()
+    }
+
+    /// Registers the extension that handles every hook for requests processed by this server.
+    pub fn withExtension(mut self, extension: Arc<dyn FpsExtension>) -> KeyServer {
+        self.extension = Some(extension);
+        self
+    }
+
+    /// Registers the random source for requests processed by this server (e.g. a `SeededRandom`).
+    pub fn withRandomSource(mut self, randomSource: Arc<dyn RandomSource>) -> KeyServer {
+        self.randomSource = Some(randomSource);
+        self
+    }
+
+    /// Generates the CKC for `request`.
+    pub fn process(&self, request: &KeyRequest) -> std::result::Result<KeyResponse, FpsError> {
+        let error = |status, detail: Option<FPSErrorDetail>| {
+            FpsError::withDetail(request.id, detail.unwrap_or_else(|| FPSErrorDetail::new(status)))
+        };
+
+        let process = || {
+            let (operation, detail) = validate::captureError(|| request.toOperation());
+            let mut fpsOperation = operation.map_err(|status| error(status, detail))?;
+            let mut fpsResult = FPSResult::default();
+            if let Err(status) = Base::createResults(&mut fpsOperation, &mut fpsResult) {
+                return Err(error(status, fpsResult.error.take()));
+            }
+
+            Ok(KeyResponse::from(fpsResult))
+        };
+
+        #[cfg(feature = "deterministic_random")]
+        if let Some(seed) = request.randomSeed {
+            let randomSource = Arc::new(crate::extension::random::SeededRandom::new(seed));
+            return self.run(|| SDKExtension::withRandomSource(randomSource, process));
+        }
+
+        self.run(process)
+    }
+
+    /// Processes a `fairplay-streaming-request` JSON document with this server's extension.
+    pub fn processJson(&self, json: Value) -> Result<Value> {
+        self.run(|| {
+            let mut output = Value::default();
+            Base::processOperations(json, &mut output)?;
+            Ok(output)
+        })
+    }
+
+    fn run<R>(&self, f: impl FnOnce() -> R) -> R {
+        let f = || match &self.randomSource {
+            Some(randomSource) => SDKExtension::withRandomSource(randomSource.clone(), f),
+            None => f(),
+        };
+
+        match &self.extension {
+            Some(extension) => SDKExtension::withFpsExtension(extension.clone(), f),
+            None => f(),
+        }
+    }
+}
@@ -11,13 +11,23 @@ use crate::validate::{FPSStatus, FPSStatus::noErr};
 use std::ffi::{c_char, CStr, CString};
 pub mod base;
 pub mod logging;
+pub mod metrics;
 pub mod extension;
+pub mod key_server;
 
 use crate::extension::structures::extension_structures::SDKExtension;
 use crate::extension::structures::extension_structures;
 use crate::extension::extension as Extension; // Using uppercase to avoid conflict with folder name
 use crate::extension::validate as validate;
 
+// Deterministic randomness makes CKC IVs and padding predictable
+#[cfg(all(feature = "deterministic_random", not(debug_assertions)))]
+compile_error!("the deterministic_random feature is for tests and must not be enabled in release builds");
+
+// The mock key payload backend puts content keys in the clear into CKCs
+#[cfg(all(feature = "mock_key_payload", not(debug_assertions)))]
+compile_error!("the mock_key_payload feature is for tests and must not be enabled in release builds");
+
 /// Processes the operations specified in the input json.
 ///
 /// The returned json must be disposed of with `fpsDisposeResponse`.
@@ -44,9 +54,7 @@ pub extern "C" fn fpsProcessOperations(
         };
         let s = s.as_str();
 
-        let root = Base::parseRootFromString(s);
-
-        let status = Base::processOperations(root, &mut output);
+        let status = Base::processOperationsFromString(s, &mut output);
 
         let out_string = output.to_string();
 
@@ -2,33 +2,263 @@
 // Copyright © 2023-2025 Apple Inc. All rights reserved.
 //
 
+use crate::base::base_constants::{FPSDeviceClass, FPSTLLVTagValue};
+use crate::base::structures::base_server_structures::FPSServerSPCContainer;
+use crate::extension::key_store::formatAssetId;
+use crate::extension::structures::extension_structures::SDKExtension;
+use crate::validate::{self, FPSStatus};
+use serde_jsonrc::{json, Map, Value};
 use std::cell::RefCell;
+use std::fmt;
+use std::sync::RwLock;
+
 type LogFormat = RefCell<Box< dyn Fn(u32, &str) -> String + Send + Sync>>;
 thread_local! {pub static LOG_FORMAT: LogFormat = RefCell::new(Box::new(|_line, _file| { String::new() }))}
 
+/// Selects `LogOutput::json` when set to `json`.
+pub const LOG_OUTPUT_ENV: &str = "FPS_LOG_FORMAT";
+
 /// Creates and prints an error to be logged.
 ///
 /// Output is logged to stderr.
 ///
 /// Will print in both release and debug mode.
 ///
-/// Log format can be overridden in `logInitCustom`
+/// Log format can be overridden in `logInitCustom`, or switched to JSON lines with
+/// `SDKExtension::setLogOutput`.
+///
+/// The first error logged by a `create-ckc` operation becomes the `error` object of its result
+/// (see `validate::FPSErrorDetail`). `reason = "..."` gives it a more specific reason than the status.
 #[macro_export]
 macro_rules! fpsLogError {
+    ($errorCode: expr, reason = $reason: expr, $($arg:tt)+) => {
+        $crate::logging::logError(&$errorCode, line!(), file!(), Some($reason), Some(&format!($($arg)+)));
+    };
+
     ($errorCode: expr, $($arg:tt)+) => {
-        let errorMessage = format!($($arg)+);
-        let string = format!("{},FP_ERRCODE=\"{}\",{}",
-                              $crate::logging::LOG_FORMAT.with( |a| {
-                                  a.borrow()(line!(), file!())
-                              }), $errorCode, errorMessage);
-        eprintln!("{}", string);
+        $crate::logging::logError(&$errorCode, line!(), file!(), None, Some(&format!($($arg)+)));
     };
 
     ($errorCode: expr) => {
-        let string = format!("{} FP_ERRCODE=\"{}\", {}",
-                              $crate::logging::LOG_FORMAT.with( |a| {
-                                  a.borrow()(line!(), file!())
-                              }), $errorCode);
-        eprintln!("{}", string);
+        $crate::logging::logError(&$errorCode, line!(), file!(), None, None);
     };
 }
+
+/// Format of `fpsLogError!` lines and of `log` records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogOutput {
+    /// `key="value"` lines formatted by `LOG_FORMAT`
+    text,
+    /// One JSON object per line, with the operation context
+    json,
+}
+
+/// Output installed with `SDKExtension::setLogOutput`, or read from `FPS_LOG_FORMAT` once first used.
+static LOG_OUTPUT: RwLock<Option<LogOutput>> = RwLock::new(None);
+
+impl SDKExtension {
+    /// Switches log output between text and JSON lines for all threads.
+    pub fn setLogOutput(logOutput: LogOutput) {
+        *LOG_OUTPUT.write().unwrap_or_else(|e| e.into_inner()) = Some(logOutput);
+    }
+
+    pub fn logOutput() -> LogOutput {
+        if let Some(logOutput) = *LOG_OUTPUT.read().unwrap_or_else(|e| e.into_inner()) {
+            return logOutput;
+        }
+
+        let logOutput = match std::env::var(LOG_OUTPUT_ENV).as_deref() {
+            Ok("json") => LogOutput::json,
+            _ => LogOutput::text,
+        };
+        *LOG_OUTPUT.write().unwrap_or_else(|e| e.into_inner()).get_or_insert(logOutput)
+    }
+
+    /// Changes the most verbose `log` level printed, in debug and release builds.
+    ///
+    /// The initial level comes from `RUST_LOG` (see `logInitCustom`). `fpsLogError!` always prints.
+    pub fn setLogLevel(level: log::LevelFilter) {
+        log::set_max_level(level);
+    }
+}
+
+/// Request being processed on this thread, added to every JSON log line.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LogContext {
+    /// `id` of the create-ckc operation
+    pub id: Option<u64>,
+    pub assetId: Option<String>,
+    pub transactionId: Option<u64>,
+    pub deviceClass: Option<FPSDeviceClass>,
+    pub spcVersion: Option<u32>,
+}
+
+thread_local! {
+    static LOG_CONTEXT: RefCell<LogContext> = RefCell::new(LogContext::default());
+}
+
+/// Restores the previous context when an operation ends, also when it panics.
+pub struct LogContextGuard {
+    previous: LogContext,
+}
+
+impl Drop for LogContextGuard {
+    fn drop(&mut self) {
+        let previous = std::mem::take(&mut self.previous);
+        LOG_CONTEXT.with(|context| *context.borrow_mut() = previous);
+    }
+}
+
+impl LogContext {
+    /// Starts the context of operation `id` on this thread, until the guard is dropped.
+    pub fn enter(id: u64) -> LogContextGuard {
+        let context = LogContext {
+            id: Some(id),
+            ..Default::default()
+        };
+        let previous = LOG_CONTEXT.with(|current| current.replace(context));
+        LogContextGuard { previous }
+    }
+
+    pub fn current() -> LogContext {
+        LOG_CONTEXT.with(|context| context.borrow().clone())
+    }
+
+    pub fn update(f: impl FnOnce(&mut LogContext)) {
+        LOG_CONTEXT.with(|context| f(&mut context.borrow_mut()));
+    }
+
+    /// Takes the values that were read from the SPC, even if parsing stopped early.
+    pub fn setSPC(&mut self, spcContainer: &FPSServerSPCContainer) {
+        let spcData = &spcContainer.spcData;
+        let parsedTags = &spcData.spcDataParser.parsedTagValues;
+
+        if spcContainer.version != 0 {
+            self.spcVersion = Some(spcContainer.version);
+        }
+        if parsedTags.contains(&(FPSTLLVTagValue::assetIDTag as u64)) {
+            self.assetId = Some(formatAssetId(&spcData.assetId));
+        }
+        if parsedTags.contains(&(FPSTLLVTagValue::transactionIDTag as u64)) {
+            self.transactionId = Some(spcData.transactionId);
+        }
+        if spcData.deviceIdentity.isDeviceIdentitySet {
+            self.deviceClass = Some(FPSDeviceClass::from(spcData.deviceIdentity.deviceClass));
+        }
+    }
+
+    /// Adds the known fields to a JSON log line.
+    fn addTo(&self, line: &mut Map<String, Value>) {
+        if let Some(id) = self.id {
+            line.insert("id".to_string(), json!(id));
+        }
+        if let Some(assetId) = &self.assetId {
+            line.insert("asset-id".to_string(), json!(assetId));
+        }
+        if let Some(transactionId) = self.transactionId {
+            line.insert("transaction-id".to_string(), json!(format!("0x{:016x}", transactionId)));
+        }
+        if let Some(deviceClass) = self.deviceClass {
+            line.insert("device-class".to_string(), json!(format!("{:?}", deviceClass)));
+        }
+        if let Some(spcVersion) = self.spcVersion {
+            line.insert("spc-version".to_string(), json!(spcVersion));
+        }
+    }
+}
+
+/// Error code accepted by `fpsLogError!`.
+pub trait LogErrorCode {
+    fn code(&self) -> i32;
+    fn status(&self) -> Option<FPSStatus>;
+}
+
+impl LogErrorCode for FPSStatus {
+    fn code(&self) -> i32 {
+        *self as i32
+    }
+
+    fn status(&self) -> Option<FPSStatus> {
+        Some(*self)
+    }
+}
+
+impl LogErrorCode for i32 {
+    fn code(&self) -> i32 {
+        *self
+    }
+
+    fn status(&self) -> Option<FPSStatus> {
+        None
+    }
+}
+
+/// Prints key material without its value.
+pub struct Redacted<'a>(pub &'a [u8]);
+
+impl fmt::Display for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<redacted {} bytes>", self.0.len())
+    }
+}
+
+impl fmt::Debug for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Common fields of a JSON log line.
+pub fn jsonLogLine(level: &str, file: &str, line: u32, message: &str) -> Map<String, Value> {
+    let mut jsonLine = Map::new();
+    jsonLine.insert(
+        "timestamp".to_string(),
+        json!(chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()),
+    );
+    jsonLine.insert("level".to_string(), json!(level));
+    jsonLine.insert("tool".to_string(), json!(env!("CARGO_PKG_NAME")));
+    jsonLine.insert("version".to_string(), json!(env!("CARGO_PKG_VERSION")));
+    jsonLine.insert("pid".to_string(), json!(std::process::id()));
+    jsonLine.insert("file".to_string(), json!(file));
+    jsonLine.insert("line".to_string(), json!(line));
+    LogContext::current().addTo(&mut jsonLine);
+    jsonLine.insert("message".to_string(), json!(message));
+    jsonLine
+}
+
+/// Prints an `fpsLogError!` line in the configured `LogOutput`.
+pub fn logError(errorCode: &dyn LogErrorCode, line: u32, file: &str, reason: Option<&str>, message: Option<&str>) {
+    if let Some(status) = errorCode.status() {
+        validate::recordError(status, reason, message.unwrap_or_default());
+    }
+
+    match SDKExtension::logOutput() {
+        LogOutput::text => {
+            let prefix = LOG_FORMAT.with(|a| a.borrow()(line, file));
+            match message {
+                Some(message) => eprintln!("{},FP_ERRCODE=\"{}\",{}", prefix, errorCode.code(), message),
+                None => eprintln!("{} FP_ERRCODE=\"{}\", ", prefix, errorCode.code()),
+            }
+        }
+        LogOutput::json => {
+            eprintln!("{}", Value::Object(jsonErrorLine(errorCode, line, file, reason, message)));
+        }
+    }
+}
+
+/// JSON log line of an `fpsLogError!`, with the error code, status name and reason.
+pub fn jsonErrorLine(
+    errorCode: &dyn LogErrorCode,
+    line: u32,
+    file: &str,
+    reason: Option<&str>,
+    message: Option<&str>,
+) -> Map<String, Value> {
+    let mut jsonLine = jsonLogLine("ERROR", file, line, message.unwrap_or_default());
+    jsonLine.insert("code".to_string(), json!(errorCode.code()));
+    if let Some(status) = errorCode.status() {
+        jsonLine.insert("status".to_string(), json!(format!("{:?}", status)));
+        jsonLine.insert("reason".to_string(), json!(reason.unwrap_or(status.reason())));
+    }
+    jsonLine
+}
@@ -0,0 +1,218 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+//! Counters and latency histograms of `create-ckc` operations, in the Prometheus text format.
+//!
+//! Every operation processed by `Base::createResults` (JSON requests and `KeyServer::process`)
+//! is counted in `SDKExtension::metrics()`. `fpssdk_server` serves `render()` on `GET /metrics`.
+
+use crate::base::base_constants::{FPSDeviceClass, SPCVersion};
+use crate::base::structures::base_fps_structures::{FPSOperation, FPSResult};
+use crate::base::Utils::FPSServerUtils::readBigEndianU32;
+use crate::extension::structures::extension_structures::SDKExtension;
+use crate::validate::{FPSStatus, Result};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// `Content-Type` of `MetricsRegistry::render` output.
+pub const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Upper bounds, in seconds, of the latency histogram buckets.
+pub const LATENCY_BUCKETS: [f64; 12] = [0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 1.0];
+
+/// Timed steps of a `create-ckc` operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    /// `Base::decryptSPCData`
+    spcDecrypt,
+    /// `KSMCreateKeyPayload`, or the installed `KeyPayloadBackend`
+    keyPayload,
+    /// `Base::generateCKC`
+    ckcGeneration,
+}
+
+impl Stage {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stage::spcDecrypt => "spc_decrypt",
+            Stage::keyPayload => "key_payload",
+            Stage::ckcGeneration => "ckc_generation",
+        }
+    }
+}
+
+/// Labels of the `fpssdk_create_ckc_operations_total` counter.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct OperationLabels {
+    /// `FPSStatus` name, `noErr` for success
+    pub status: String,
+    /// `content-type` of the asset
+    pub contentType: &'static str,
+    pub deviceClass: FPSDeviceClass,
+    /// `1024` or `2048` (the SPC RSA key size), `unknown` if the SPC could not be read
+    pub spcVersion: &'static str,
+    /// The SPC came from a virtual machine
+    pub vm: bool,
+    pub checkIn: bool,
+}
+
+impl OperationLabels {
+    /// Labels of an operation after `Base::createResults` returned `status`.
+    pub fn new(fpsOperation: &FPSOperation, fpsResult: &FPSResult, status: &Result<()>) -> OperationLabels {
+        let spcVersion = match readBigEndianU32(&fpsOperation.spc, 0) {
+            Ok(version) if version == SPCVersion::v1 as u32 => "1024",
+            Ok(version) if version == SPCVersion::v2 as u32 => "2048",
+            _ => "unknown",
+        };
+
+        OperationLabels {
+            status: match status {
+                Ok(()) => format!("{:?}", FPSStatus::noErr),
+                Err(e) => format!("{:?}", e),
+            },
+            contentType: fpsOperation.assetInfo.extension.contentType.name(),
+            deviceClass: FPSDeviceClass::from(fpsResult.deviceClass),
+            spcVersion,
+            vm: fpsResult.vmDeviceInfo.is_some(),
+            checkIn: fpsOperation.isCheckIn,
+        }
+    }
+
+    fn format(&self) -> String {
+        format!(
+            "status=\"{}\",content_type=\"{}\",device_class=\"{:?}\",spc_version=\"{}\",platform=\"{}\",request=\"{}\"",
+            self.status,
+            self.contentType,
+            self.deviceClass,
+            self.spcVersion,
+            if self.vm { "vm" } else { "physical" },
+            if self.checkIn { "check-in" } else { "license" },
+        )
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    /// Observations per bucket of `LATENCY_BUCKETS`, plus one for `+Inf`
+    buckets: [u64; LATENCY_BUCKETS.len() + 1],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[bucket] += 1;
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+#[derive(Debug, Default)]
+struct Metrics {
+    operations: BTreeMap<OperationLabels, u64>,
+    latencies: BTreeMap<Stage, Histogram>,
+}
+
+/// Counters and histograms shared by every thread of the process.
+#[derive(Debug)]
+pub struct MetricsRegistry {
+    metrics: Mutex<Metrics>,
+}
+
+impl Default for MetricsRegistry {
+    fn default() -> MetricsRegistry {
+        MetricsRegistry::new()
+    }
+}
+
+impl MetricsRegistry {
+    pub const fn new() -> MetricsRegistry {
+        MetricsRegistry {
+            metrics: Mutex::new(Metrics {
+                operations: BTreeMap::new(),
+                latencies: BTreeMap::new(),
+            }),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Metrics> {
+        self.metrics.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Counts one `create-ckc` operation.
+    pub fn recordOperation(&self, labels: OperationLabels) {
+        *self.lock().operations.entry(labels).or_insert(0) += 1;
+    }
+
+    /// Number of operations counted with exactly `labels`.
+    pub fn operationCount(&self, labels: &OperationLabels) -> u64 {
+        self.lock().operations.get(labels).copied().unwrap_or(0)
+    }
+
+    pub fn observe(&self, stage: Stage, duration: Duration) {
+        self.lock().latencies.entry(stage).or_default().observe(duration.as_secs_f64());
+    }
+
+    /// Number of latency observations of `stage`.
+    pub fn observationCount(&self, stage: Stage) -> u64 {
+        self.lock().latencies.get(&stage).map_or(0, |histogram| histogram.count)
+    }
+
+    /// Runs `f` and adds its duration to the histogram of `stage`, whether or not it succeeds.
+    pub fn time<R>(&self, stage: Stage, f: impl FnOnce() -> R) -> R {
+        let start = Instant::now();
+        let result = f();
+        self.observe(stage, start.elapsed());
+        result
+    }
+
+    /// Formats every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let metrics = self.lock();
+        let mut out = String::new();
+
+        out.push_str("# HELP fpssdk_create_ckc_operations_total create-ckc operations processed.\n");
+        out.push_str("# TYPE fpssdk_create_ckc_operations_total counter\n");
+        for (labels, count) in &metrics.operations {
+            let _ = writeln!(out, "fpssdk_create_ckc_operations_total{{{}}} {}", labels.format(), count);
+        }
+
+        out.push_str("# HELP fpssdk_stage_duration_seconds Duration of create-ckc processing steps.\n");
+        out.push_str("# TYPE fpssdk_stage_duration_seconds histogram\n");
+        for (stage, histogram) in &metrics.latencies {
+            let mut cumulative = 0;
+            for (i, count) in histogram.buckets.iter().enumerate() {
+                cumulative += count;
+                let bound = LATENCY_BUCKETS.get(i).map_or("+Inf".to_string(), |bound| bound.to_string());
+                let _ = writeln!(
+                    out,
+                    "fpssdk_stage_duration_seconds_bucket{{stage=\"{}\",le=\"{}\"}} {}",
+                    stage.name(),
+                    bound,
+                    cumulative
+                );
+            }
+            let _ = writeln!(out, "fpssdk_stage_duration_seconds_sum{{stage=\"{}\"}} {}", stage.name(), histogram.sum);
+            let _ = writeln!(out, "fpssdk_stage_duration_seconds_count{{stage=\"{}\"}} {}", stage.name(), histogram.count);
+        }
+
+        out
+    }
+}
+
+/// Registry of the process
+static METRICS: MetricsRegistry = MetricsRegistry::new();
+
+impl SDKExtension {
+    /// Returns the registry every `create-ckc` operation is recorded in.
+    pub fn metrics() -> &'static MetricsRegistry {
+        &METRICS
+    }
+}
@@ -0,0 +1,144 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+#![allow(nonstandard_style)]
+
+mod common;
+
+use base64::engine::general_purpose;
+use base64::Engine;
+use common::{buildSPC, requiredTLLVs, serverKey, syncTLLV};
+use fpssdk::extension::check_in_challenge::{
+    ChallengeVerification, CheckInChallenges, FORGED_CHALLENGE_WINDOW_SECS, MAX_FORGED_CHALLENGES,
+};
+use fpssdk::extension::key_payload::MockKeyPayloadBackend;
+use fpssdk::extension::offline_ledger::{LicenseHolder, OfflineLedger};
+use fpssdk::extension::structures::extension_structures::SDKExtension;
+use fpssdk::extension::validate::FPSStatus;
+use fpssdk::key_server::{AssetInfo, KeyRequest, KeyServer, OfflineLicense};
+use serde_jsonrc::json;
+use std::sync::Arc;
+use std::time::Duration;
+
+const NOW: i64 = 1_750_000_000;
+const HU: [u8; 20] = [0xAB; 20];
+const TITLE_ID: [u8; 16] = [0x71; 16];
+
+#[test]
+fn challenges_are_bound_to_device_title_and_time() {
+    let challenges = CheckInChallenges::new(vec![0x4B; 32], Duration::from_secs(600)).unwrap();
+    let challenge = challenges.mint(&HU, &TITLE_ID, NOW).unwrap();
+    let verify = |challenge, hu: &[u8], titleId: &[u8], now| challenges.verify(challenge, hu, titleId, now).unwrap();
+
+    assert_eq!(
+        verify(challenge, &HU, &TITLE_ID, NOW + 600),
+        ChallengeVerification::valid
+    );
+    assert_eq!(
+        verify(challenge, &HU, &TITLE_ID, NOW + 601),
+        ChallengeVerification::expired
+    );
+    assert_eq!(
+        verify(challenge, &[0xAC; 20], &TITLE_ID, NOW),
+        ChallengeVerification::forged
+    );
+    assert_eq!(verify(challenge, &HU, &[0x72; 16], NOW), ChallengeVerification::forged);
+    assert_eq!(verify(0, &HU, &TITLE_ID, NOW), ChallengeVerification::missing);
+
+    // Any altered bit, including the issue time, breaks the tag
+    for bit in [0, 23, 24, 31, 32, 63] {
+        assert_eq!(
+            verify(challenge ^ (1 << bit), &HU, &TITLE_ID, NOW),
+            ChallengeVerification::forged
+        );
+    }
+
+    // Other keys do not verify it, title IDs are zero-padded
+    let otherKey = CheckInChallenges::new(vec![0x4C; 32], Duration::from_secs(600)).unwrap();
+    assert_eq!(
+        otherKey.verify(challenge, &HU, &TITLE_ID, NOW).unwrap(),
+        ChallengeVerification::forged
+    );
+    let challenge = challenges.mint(&HU, &[], NOW).unwrap();
+    assert_eq!(verify(challenge, &HU, &[0; 16], NOW), ChallengeVerification::valid);
+
+    assert_eq!(
+        CheckInChallenges::new(vec![0x4B; 8], Duration::from_secs(600)).err(),
+        Some(FPSStatus::paramErr)
+    );
+}
+
+#[test]
+fn devices_sending_forged_challenges_are_throttled() {
+    let challenges = CheckInChallenges::new(vec![0x4B; 32], Duration::from_secs(600)).unwrap();
+    let challenge = challenges.mint(&HU, &TITLE_ID, NOW).unwrap();
+    let verify = |challenge, hu: &[u8], now| challenges.verifyCheckIn(challenge, hu, &TITLE_ID, now).unwrap();
+
+    for guess in 0..MAX_FORGED_CHALLENGES {
+        assert_eq!(
+            verify(challenge ^ u64::from(guess + 1), &HU, NOW),
+            ChallengeVerification::forged
+        );
+    }
+
+    // Even a genuine challenge is not checked until the window ends, other devices are not affected
+    assert_eq!(verify(challenge, &HU, NOW + 1), ChallengeVerification::throttled);
+    let otherChallenge = challenges.mint(&[0xAC; 20], &TITLE_ID, NOW).unwrap();
+    assert_eq!(verify(otherChallenge, &[0xAC; 20], NOW), ChallengeVerification::valid);
+
+    let windowEnd = NOW + FORGED_CHALLENGE_WINDOW_SECS;
+    let challenge = challenges.mint(&HU, &TITLE_ID, windowEnd).unwrap();
+    assert_eq!(verify(challenge, &HU, windowEnd), ChallengeVerification::valid);
+}
+
+#[test]
+fn only_verified_check_ins_return_licenses() {
+    serverKey();
+    SDKExtension::setKeyPayloadBackend(Arc::new(MockKeyPayloadBackend));
+    let ledger = Arc::new(OfflineLedger::openInMemory().unwrap());
+    SDKExtension::setOfflineLedger(Some(ledger.clone()));
+    let keyServer = KeyServer::new();
+
+    let offline = OfflineLicense {
+        streamId: Some(vec![0xA1; 16]),
+        titleId: Some(TITLE_ID.to_vec()),
+        ..Default::default()
+    };
+    let request = KeyRequest::new(buildSPC(serverKey(), &requiredTLLVs(b"movie")))
+        .assetInfo(AssetInfo::new().contentKey([0x3C; 16], [0xD5; 16]).offline(offline));
+    let hu = keyServer.process(&request).unwrap().hu;
+    let device = LicenseHolder::device(hu.clone());
+
+    let checkIn = |serverChallenge: u64| {
+        let mut tllvs = requiredTLLVs(b"movie");
+        tllvs.push(syncTLLV(serverChallenge, &TITLE_ID, &[vec![0xA1; 16]]));
+        KeyRequest::new(buildSPC(serverKey(), &tllvs))
+            .checkIn(true)
+    };
+
+    // A challenge minted for another device is reported and does not free the slot
+    let forged = SDKExtension::mintCheckInChallenge(&[0xAC; 20], &TITLE_ID).unwrap();
+    let sync = keyServer.process(&checkIn(forged)).unwrap().sync.unwrap();
+    assert!(!sync.verified);
+    assert_eq!(ledger.activeLicenseCount(&device), Ok(1));
+
+    let serverChallenge = SDKExtension::mintCheckInChallenge(&hu, &TITLE_ID).unwrap();
+    let request = json!({ "fairplay-streaming-request": { "create-ckc": [{
+        "id": 1,
+        "check-in": true,
+        "spc": general_purpose::STANDARD.encode(&checkIn(serverChallenge).spc)
+    }]}});
+    let output = keyServer.processJson(request).unwrap();
+    let result = &output["fairplay-streaming-response"]["create-ckc"][0];
+    assert_eq!(result["status"], 0, "{}", result);
+    assert_eq!(result["check-in-server-challenge"], serverChallenge.to_string());
+    assert_eq!(result["check-in-verified"], true);
+    assert_eq!(ledger.activeLicenseCount(&device), Ok(0));
+
+    // The same challenge cannot be used again
+    let error = keyServer.process(&checkIn(serverChallenge)).unwrap_err();
+    assert_eq!(error.status(), FPSStatus::replayErr);
+
+    SDKExtension::setOfflineLedger(None);
+}
@@ -0,0 +1,113 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+#![allow(nonstandard_style)]
+
+mod common;
+
+use common::{buildSPC, serverKey, tllv};
+use fpssdk::base::base_constants::{FPSHDCPRequirement, FPSKeyDurationType, FPSTLLVTagValue, FPS_V1_R1_SZ};
+use fpssdk::base::structures::base_fps_structures::Base;
+use fpssdk::base::structures::base_server_structures::FPSServerCtx;
+use fpssdk::extension::validate::FPSStatus;
+use std::sync::Arc;
+
+const CONTENT_KEY_TAG: u64 = 0x58b38165af0e3d5a;
+const R1: [u8; FPS_V1_R1_SZ] = [0x71; FPS_V1_R1_SZ];
+
+fn spc() -> Vec<u8> {
+    let mut playbackState = Vec::new();
+    playbackState.extend_from_slice(&0x6543_2100u32.to_be_bytes());
+    playbackState.extend_from_slice(&0xf4dee5a2u32.to_be_bytes()); // firstPlaybackCKRequired
+    playbackState.extend_from_slice(&0x77u64.to_be_bytes());
+
+    let returnRequest = [FPSTLLVTagValue::transactionIDTag as u64, FPSTLLVTagValue::assetIDTag as u64]
+        .map(u64::to_be_bytes)
+        .concat();
+
+    let tllvs = vec![
+        tllv(FPSTLLVTagValue::antiReplayTag as u64, &[0x3A; 16]),
+        tllv(FPSTLLVTagValue::assetIDTag as u64, b"ckc-asset"),
+        tllv(FPSTLLVTagValue::transactionIDTag as u64, &0x1234u64.to_be_bytes()),
+        tllv(FPSTLLVTagValue::mediaPlaybackStateTag as u64, &playbackState),
+        tllv(FPSTLLVTagValue::returnRequestTag as u64, &returnRequest),
+    ];
+    buildSPC(serverKey(), &tllvs)
+}
+
+/// Builds the CKC the server would send for `spc`, with a fixed content key payload and R1.
+fn buildCKC(spc: &[u8], tamper: impl FnOnce(&mut FPSServerCtx)) -> Vec<u8> {
+    let mut serverCtx = FPSServerCtx {
+        spcContainer: Arc::new(Base::inspectSPC(spc).spcContainer),
+        ..Default::default()
+    };
+    Base::extractReturnTags(&mut Arc::make_mut(&mut serverCtx.spcContainer).spcData).unwrap();
+
+    let ckcData = &mut serverCtx.ckcContainer.ckcData;
+    ckcData.contentKeyTLLVTag = CONTENT_KEY_TAG;
+    ckcData.contentKeyTLLVPayload = vec![0xC0; 40].into();
+    ckcData.r1 = R1.to_vec().into();
+    ckcData.hdcpTypeTLLVValue = FPSHDCPRequirement::hdcpType1 as u64;
+    ckcData.keyDuration.leaseDuration = 600;
+    ckcData.keyDuration.keyType = FPSKeyDurationType::lease as u32;
+    tamper(&mut serverCtx);
+
+    Base::generateCKC(&mut serverCtx).unwrap();
+    serverCtx.ckcContainer.ckc
+}
+
+#[test]
+fn inspect_ckc_decodes_and_verifies_every_tllv() {
+    let spc = spc();
+    let inspection = Base::inspectCKC(&spc, &buildCKC(&spc, |_| {}), Some(&R1));
+    assert_eq!(inspection.error, None);
+    assert!(inspection.returnTagCheck().isValid());
+
+    let json = inspection.toJson();
+    assert_eq!(json["header"]["version"], 1);
+    assert_eq!(json["header"]["server-information"]["language"], "rust");
+
+    let tllvs = json["tllvs"].as_array().unwrap();
+    let names: Vec<&str> = tllvs.iter().map(|tllv| tllv["name"].as_str().unwrap()).collect();
+    assert_eq!(
+        names,
+        [
+            "contentKeyPayloadTag",
+            "r1Tag",
+            "transactionIDTag",
+            "assetIDTag",
+            "hdcpInformationTag",
+            "securityLevelTag",
+            "keyDurationTag"
+        ]
+    );
+    assert_eq!(tllvs[0]["tag"], format!("0x{:016x}", CONTENT_KEY_TAG));
+    assert_eq!(tllvs[0]["decoded"]["content-key-payload"], 40);
+    assert_eq!(tllvs[4]["decoded"], "hdcpType1");
+    assert_eq!(tllvs[5]["decoded"]["security-level"], "main");
+    assert_eq!(tllvs[6]["decoded"]["lease-duration"], 600);
+    assert_eq!(tllvs[6]["decoded"]["key-type"], "lease");
+    assert_eq!(json["return-tags"]["valid"], true);
+
+    assert!(inspection.to_string().contains("return-tags: requested=0x47aa7ad3440577de (transactionIDTag)"));
+}
+
+#[test]
+fn inspect_ckc_reports_mismatched_return_tags_and_wrong_r1() {
+    let spc = spc();
+    let ckc = buildCKC(&spc, |serverCtx| {
+        Arc::make_mut(&mut serverCtx.spcContainer).spcData.returnTLLVs[0].value = 0x9999u64.to_be_bytes().to_vec();
+        Arc::make_mut(&mut serverCtx.spcContainer).spcData.returnTLLVs.pop();
+    });
+
+    let check = Base::inspectCKC(&spc, &ckc, Some(&R1)).returnTagCheck();
+    assert_eq!(check.mismatched, vec![FPSTLLVTagValue::transactionIDTag as u64]);
+    assert_eq!(check.missing, vec![FPSTLLVTagValue::assetIDTag as u64]);
+    assert!(check.unexpected.is_empty());
+
+    let inspection = Base::inspectCKC(&spc, &ckc, Some(&[0x72; FPS_V1_R1_SZ]));
+    assert_eq!(inspection.error, Some(FPSStatus::paramErr));
+    assert_eq!(inspection.ckcContainer.version, 1);
+    assert!(inspection.toJson()["return-tags"].is_null());
+}
@@ -0,0 +1,114 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+//! Builds SPCs for tests, encrypted for a generated RSA key instead of a provisioned certificate.
+
+#![allow(nonstandard_style, dead_code)]
+
+use fpssdk::base::base_constants::{FPSTLLVTagValue, KD_SYNC_SPC_FLAG_TITLEID_VALID};
+use fpssdk::base::base_constants::{FPS_V1_R2_SZ, FPS_V1_SKR1_INTEGRITY_SZ, FPS_V1_SKR1_SZ};
+use fpssdk::extension::credentials::credential_provider::MemoryCredentialProvider;
+use fpssdk::extension::structures::extension_structures::SDKExtension;
+use openssl::encrypt::Encrypter;
+use openssl::hash::MessageDigest;
+use openssl::pkey::{PKey, Private};
+use openssl::rsa::{Padding, Rsa};
+use openssl::symm::{Cipher, Crypter, Mode};
+use std::sync::{Arc, OnceLock};
+
+pub const SPC_KEY: [u8; 16] = [0x11; 16];
+pub const SPC_IV: [u8; 16] = [0x22; 16];
+pub const CERTIFICATE_HASH: [u8; 20] = [0xCE; 20];
+
+/// RSA key installed as the server's 2048-bit credentials (once per test binary).
+pub fn serverKey() -> &'static PKey<Private> {
+    static KEY: OnceLock<PKey<Private>> = OnceLock::new();
+    KEY.get_or_init(|| {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let pem = key.private_key_to_pem_pkcs8().unwrap();
+        let provider = MemoryCredentialProvider::fromPem(None, Some(&pem), vec![0xAB; 32]).unwrap();
+        SDKExtension::setCredentialProvider(Arc::new(provider));
+        key
+    })
+}
+
+/// Encodes one TLLV, padding the value to a multiple of 16 bytes.
+pub fn tllv(tag: u64, value: &[u8]) -> Vec<u8> {
+    let paddedLength = value.len().div_ceil(16) * 16;
+    let mut out = Vec::new();
+    out.extend_from_slice(&tag.to_be_bytes());
+    out.extend_from_slice(&(paddedLength as u32).to_be_bytes());
+    out.extend_from_slice(&(value.len() as u32).to_be_bytes());
+    out.extend_from_slice(value);
+    out.resize(out.len() + paddedLength - value.len(), 0xA5);
+    out
+}
+
+/// TLLVs every SPC must carry to go through `Base::parseSPC`, with `assetId` and a return
+/// request for the transaction ID.
+pub fn requiredTLLVs(assetId: &[u8]) -> Vec<Vec<u8>> {
+    vec![
+        tllv(FPSTLLVTagValue::sessionKeyR1Tag as u64, &[0x51; FPS_V1_SKR1_SZ]),
+        tllv(
+            FPSTLLVTagValue::sessionKeyR1IntegrityTag as u64,
+            &[0x52; FPS_V1_SKR1_INTEGRITY_SZ],
+        ),
+        tllv(FPSTLLVTagValue::antiReplayTag as u64, &[0x53; 16]),
+        tllv(FPSTLLVTagValue::r2tag as u64, &[0x54; FPS_V1_R2_SZ]),
+        tllv(FPSTLLVTagValue::assetIDTag as u64, assetId),
+        tllv(FPSTLLVTagValue::transactionIDTag as u64, &0x1234u64.to_be_bytes()),
+        tllv(FPSTLLVTagValue::protocolVersionUsedTag as u64, &1u32.to_be_bytes()),
+        tllv(
+            FPSTLLVTagValue::protocolVersionsSupportedTag as u64,
+            &1u32.to_be_bytes(),
+        ),
+        tllv(
+            FPSTLLVTagValue::returnRequestTag as u64,
+            &(FPSTLLVTagValue::transactionIDTag as u64).to_be_bytes(),
+        ),
+    ]
+}
+
+/// SyncTLLV v2 of a check-in for `titleId`, reporting `deletedContentIDs` as securely deleted.
+pub fn syncTLLV(serverChallenge: u64, titleId: &[u8; 16], deletedContentIDs: &[Vec<u8>]) -> Vec<u8> {
+    let mut value = Vec::new();
+    value.extend_from_slice(&2u32.to_be_bytes());
+    value.extend_from_slice(&0u32.to_be_bytes());
+    value.extend_from_slice(&serverChallenge.to_be_bytes());
+    value.extend_from_slice(&KD_SYNC_SPC_FLAG_TITLEID_VALID.to_be_bytes());
+    value.extend_from_slice(titleId);
+    value.extend_from_slice(&0u32.to_be_bytes());
+    value.extend_from_slice(&(deletedContentIDs.len() as u32).to_be_bytes());
+    value.extend_from_slice(&deletedContentIDs.concat());
+    tllv(FPSTLLVTagValue::offlineSyncTag as u64, &value)
+}
+
+/// Builds a v2 SPC carrying `tllvs`, with its key wrapped for `key`.
+pub fn buildSPC(key: &PKey<Private>, tllvs: &[Vec<u8>]) -> Vec<u8> {
+    let payload = tllvs.concat();
+
+    let mut encrypter = Encrypter::new(key).unwrap();
+    encrypter.set_rsa_padding(Padding::PKCS1_OAEP).unwrap();
+    encrypter.set_rsa_mgf1_md(MessageDigest::sha256()).unwrap();
+    encrypter.set_rsa_oaep_md(MessageDigest::sha256()).unwrap();
+    let mut wrappedKey = vec![0; encrypter.encrypt_len(&SPC_KEY).unwrap()];
+    let length = encrypter.encrypt(&SPC_KEY, &mut wrappedKey).unwrap();
+    wrappedKey.truncate(length);
+
+    let mut crypter = Crypter::new(Cipher::aes_128_cbc(), Mode::Encrypt, &SPC_KEY, Some(&SPC_IV)).unwrap();
+    crypter.pad(false);
+    let mut encrypted = vec![0; payload.len() + 16];
+    let length = crypter.update(&payload, &mut encrypted).unwrap();
+    encrypted.truncate(length);
+
+    let mut spc = Vec::new();
+    spc.extend_from_slice(&2u32.to_be_bytes());
+    spc.extend_from_slice(&[0; 4]);
+    spc.extend_from_slice(&SPC_IV);
+    spc.extend_from_slice(&wrappedKey);
+    spc.extend_from_slice(&CERTIFICATE_HASH);
+    spc.extend_from_slice(&(encrypted.len() as u32).to_be_bytes());
+    spc.extend_from_slice(&encrypted);
+    spc
+}
@@ -0,0 +1,106 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+#![allow(nonstandard_style)]
+
+mod common;
+
+use base64::engine::general_purpose;
+use base64::Engine;
+use common::{buildSPC, requiredTLLVs, serverKey, tllv};
+use fpssdk::base::base_constants::FPSTLLVTagValue;
+use fpssdk::base::structures::base_fps_structures::Base;
+use fpssdk::extension::structures::extension_structures::SDKExtension;
+use fpssdk::extension::validate::{self, ErrorDetails, FPSErrorDetail, FPSStatus, Result};
+use fpssdk::fpsLogError;
+use serde_jsonrc::{json, Value};
+
+fn failing(status: FPSStatus, logged: FPSStatus) -> Result<()> {
+    fpsLogError!(FPSStatus::noErr, "Warning! not an error");
+    fpsLogError!(logged, reason = "first-failure", "first {}", 1);
+    fpsLogError!(logged, "second");
+    Err(status)
+}
+
+#[test]
+fn capture_error_keeps_the_first_logged_error_of_the_returned_status() {
+    let (result, detail) = validate::captureError(|| failing(FPSStatus::paramErr, FPSStatus::paramErr));
+    assert_eq!(result, Err(FPSStatus::paramErr));
+    assert_eq!(
+        detail,
+        Some(FPSErrorDetail {
+            status: FPSStatus::paramErr,
+            reason: "first-failure".to_string(),
+            message: "first 1".to_string(),
+        })
+    );
+
+    // A different status than the one logged gets a generic detail
+    let (_, detail) = validate::captureError(|| failing(FPSStatus::internalErr, FPSStatus::paramErr));
+    assert_eq!(detail, Some(FPSErrorDetail::new(FPSStatus::internalErr)));
+    assert_eq!(detail.unwrap().reason, "internal-error");
+
+    // Nested captures do not see each other's errors
+    let (_, outer) = validate::captureError(|| {
+        let (_, inner) = validate::captureError(|| failing(FPSStatus::dupTagErr, FPSStatus::dupTagErr));
+        assert_eq!(inner.unwrap().status, FPSStatus::dupTagErr);
+        Err::<(), _>(FPSStatus::parserErr)
+    });
+    assert_eq!(outer, Some(FPSErrorDetail::new(FPSStatus::parserErr)));
+
+    assert_eq!(validate::captureError(|| Ok(())).1, None);
+}
+
+#[test]
+fn create_ckc_results_carry_the_configured_error_details() {
+    serverKey();
+    let mut tllvs = requiredTLLVs(b"error-asset");
+    tllvs.pop();
+    tllvs.push(tllv(FPSTLLVTagValue::returnRequestTag as u64, &0x1111u64.to_be_bytes()));
+    let spc = buildSPC(serverKey(), &tllvs);
+
+    let process = |errorDetails| {
+        SDKExtension::setErrorDetails(errorDetails);
+        let request = json!({
+            "fairplay-streaming-request": {
+                "create-ckc": [{ "id": 9, "spc": general_purpose::STANDARD.encode(&spc) }]
+            }
+        });
+        let mut output = Value::default();
+        Base::processOperations(request, &mut output).unwrap();
+        output["fairplay-streaming-response"]["create-ckc"][0].clone()
+    };
+
+    let result = process(ErrorDetails::hidden);
+    assert_eq!(result["status"], FPSStatus::missingRequiredTagErr as i32);
+    assert!(result["error"].is_null());
+
+    let result = process(ErrorDetails::reason);
+    assert_eq!(result["error"]["status"], FPSStatus::missingRequiredTagErr as i32);
+    assert_eq!(result["error"]["reason"], "return-tag-missing");
+    assert!(result["error"]["message"].is_null());
+
+    let result = process(ErrorDetails::full);
+    assert_eq!(result["error"]["message"], "Return tag missing from SPC 0x1111");
+
+    // Errors in the request itself are reported the same way
+    let request = json!({ "fairplay-streaming-request": { "create-ckc": [{ "id": 10 }] } });
+    let mut output = Value::default();
+    Base::processOperations(request, &mut output).unwrap();
+    let error = &output["fairplay-streaming-response"]["create-ckc"][0]["error"];
+    assert_eq!(error["reason"], "spc-missing");
+    assert_eq!(error["message"], "SPC not found");
+}
+
+#[test]
+fn result_status_codes_map_to_http_status_codes() {
+    assert_eq!(FPSStatus::try_from(FPSStatus::replayErr as i64), Ok(FPSStatus::replayErr));
+    assert_eq!(FPSStatus::try_from(-43003), Err(-43003));
+
+    assert_eq!(FPSStatus::noErr.httpStatus(), 200);
+    assert_eq!(FPSStatus::parserErr.httpStatus(), 400);
+    assert_eq!(FPSStatus::clientSecurityLevelErr.httpStatus(), 403);
+    assert_eq!(FPSStatus::replayErr.httpStatus(), 409);
+    assert_eq!(FPSStatus::internalErr.httpStatus(), 500);
+}
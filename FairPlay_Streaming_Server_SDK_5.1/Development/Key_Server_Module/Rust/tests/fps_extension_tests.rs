@@ -0,0 +1,68 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+#![allow(nonstandard_style)]
+
+use fpssdk::base::structures::base_fps_structures::{FPSOperation, FPSResults};
+use fpssdk::extension::extension;
+use fpssdk::extension::fps_extension::{DefaultExtension, FpsExtension};
+use fpssdk::extension::validate::{FPSStatus, FpsError, Result};
+use fpssdk::key_server::{KeyRequest, KeyServer};
+use serde_jsonrc::{Map, Value};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Refuses every request and tags the JSON response.
+#[derive(Default)]
+struct RefuseAll {
+    calls: AtomicUsize,
+}
+
+impl FpsExtension for RefuseAll {
+    fn createResultsCustom(&self, _fpsOperation: &mut FPSOperation, _keyTypeRequested: &mut u32) -> Result<()> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        Err(FPSStatus::notImplementedErr)
+    }
+
+    fn serializeResultsCustom(
+        &self,
+        fpsResults: &FPSResults,
+        ckcNode: Vec<Value>,
+        jsonResults: &mut Map<String, Value>,
+    ) -> Result<()> {
+        DefaultExtension.serializeResultsCustom(fpsResults, ckcNode, jsonResults)?;
+        jsonResults.insert("served-by".to_string(), Value::String("refuse-all".to_string()));
+        Ok(())
+    }
+}
+
+#[test]
+fn server_extension_handles_hooks() {
+    let refuseAll = Arc::new(RefuseAll::default());
+    let keyServer = KeyServer::new().withExtension(refuseAll.clone());
+    let request = KeyRequest::new([0, 0, 0, 9]).id(5);
+
+    assert_eq!(keyServer.process(&request).unwrap_err(), FpsError::new(5, FPSStatus::notImplementedErr));
+    assert_eq!(refuseAll.calls.load(Ordering::SeqCst), 1);
+
+    // Servers without an extension, and hooks called outside the server, keep the default behavior
+    assert_eq!(KeyServer::new().process(&request).unwrap_err().status(), FPSStatus::spcVersionErr);
+    assert_eq!(extension::parseHDCPTypeCustom(5), Err(FPSStatus::paramErr));
+    assert_eq!(refuseAll.calls.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn server_extension_applies_to_json_requests() {
+    let keyServer = KeyServer::new().withExtension(Arc::new(RefuseAll::default()));
+    let json: Value = r#"{ "fairplay-streaming-request": { "create-ckc": [ { "id": 3, "spc": "AAAACQ==" } ] } }"#
+        .parse()
+        .unwrap();
+
+    let output = keyServer.processJson(json).unwrap();
+    assert_eq!(output["served-by"], "refuse-all");
+
+    let result = &output["fairplay-streaming-response"]["create-ckc"][0];
+    assert_eq!(result["id"], 3);
+    assert_eq!(result["status"], FPSStatus::notImplementedErr as i32);
+}
@@ -0,0 +1,139 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+#![allow(nonstandard_style)]
+
+use fpssdk::base::structures::base_fps_structures::Base;
+use fpssdk::extension::structures::extension_structures::SDKExtension;
+use fpssdk::extension::validate::{ErrorDetails, FPSStatus};
+use serde_jsonrc::Value;
+use std::ffi::{c_char, CStr, CString};
+
+/// Returns the first `create-ckc` result for `input`.
+fn process(input: &str) -> Value {
+    SDKExtension::setErrorDetails(ErrorDetails::full);
+
+    let mut output = Value::default();
+    Base::processOperationsFromString(input, &mut output).unwrap();
+    output["fairplay-streaming-response"]["create-ckc"][0].clone()
+}
+
+fn assertError(result: &Value, status: FPSStatus, reason: &str, message: &str) {
+    assert_eq!(result["status"], status as i32, "{}", result);
+    assert_eq!(result["error"]["reason"], reason, "{}", result);
+    assert_eq!(result["error"]["message"], message, "{}", result);
+}
+
+#[test]
+fn invalid_json_is_a_parser_error_with_its_position() {
+    let result = process("{\n  \"fairplay-streaming-request\": {\n    \"create-ckc\": [ }\n}");
+    assert_eq!(result["status"], FPSStatus::parserErr as i32);
+    assert_eq!(result["error"]["reason"], "invalid-json");
+    assert!(
+        result["error"]["message"].as_str().unwrap().starts_with("Invalid JSON at line 3 column 21"),
+        "{}",
+        result
+    );
+
+    // The C entry point answers instead of panicking
+    let input = CString::new("not json").unwrap();
+    let mut out: *mut c_char = std::ptr::null_mut();
+    let mut outLength = 0;
+    let status = fpssdk::fpsProcessOperations(input.as_ptr(), 8, &mut out, &mut outLength);
+    assert_eq!(status, FPSStatus::noErr);
+    let output: Value = unsafe { CStr::from_ptr(out) }.to_str().unwrap().parse().unwrap();
+    assert_eq!(output["fairplay-streaming-response"]["create-ckc"][0]["status"], FPSStatus::parserErr as i32);
+    fpssdk::fpsDisposeResponse(out, outLength);
+}
+
+#[test]
+fn wrong_field_types_are_reported_per_field() {
+    assertError(&process("{}"), FPSStatus::paramErr, "missing-field", "fairplay-streaming-request not found");
+    assertError(
+        &process(r#"{ "fairplay-streaming-request": [] }"#),
+        FPSStatus::paramErr,
+        "invalid-field-type",
+        "fairplay-streaming-request must be an object, not an array",
+    );
+    assertError(
+        &process(r#"{ "fairplay-streaming-request": { "create-ckc": {} } }"#),
+        FPSStatus::paramErr,
+        "invalid-field-type",
+        "create-ckc must be an array, not an object",
+    );
+    assertError(
+        &process(r#"{ "fairplay-streaming-request": { "create-ckc": [ "AAAA" ] } }"#),
+        FPSStatus::paramErr,
+        "invalid-field-type",
+        "create-ckc[0] must be an object, not a string",
+    );
+    assertError(
+        &process(r#"{ "fairplay-streaming-request": { "create-ckc": [ { "spc": 12 } ] } }"#),
+        FPSStatus::paramErr,
+        "invalid-field-type",
+        "spc must be a base64 string, not a number",
+    );
+    assertError(
+        &process(r#"{ "fairplay-streaming-request": { "create-ckc": [ { "spc": "AAAA", "asset-info": {} } ] } }"#),
+        FPSStatus::paramErr,
+        "invalid-field-type",
+        "asset-info must be an array with one object, not an object",
+    );
+    assertError(
+        &process(
+            r#"{ "fairplay-streaming-request": { "create-ckc": [
+                { "spc": "AAAA", "asset-info": [ { "content-key": 5, "lease-duration": "60" } ] } ] } }"#,
+        ),
+        FPSStatus::paramErr,
+        "invalid-field-type",
+        "content-key must be a hex string, not a number",
+    );
+    assertError(
+        &process(
+            r#"{ "fairplay-streaming-request": { "create-ckc": [
+                { "spc": "AAAA", "asset-info": [ { "offline-hls": { "stream-id": "nothex" } } ] } ] } }"#,
+        ),
+        FPSStatus::paramErr,
+        "invalid-hex",
+        "unable to decode stream-id: Invalid character 'n' at position 0",
+    );
+}
+
+#[test]
+fn undecodable_keys_are_not_logged() {
+    for field in ["content-key", "content-iv"] {
+        let input = format!(
+            r#"{{ "fairplay-streaming-request": {{ "create-ckc": [
+                {{ "spc": "AAAA", "asset-info": [ {{ "{field}": "0123456789abcdef0123456789abcdeX" }} ] }} ] }} }}"#
+        );
+        let result = process(&input);
+        assert_eq!(result["status"], FPSStatus::paramErr as i32, "{}", result);
+        let message = result["error"]["message"].as_str().unwrap();
+        assert!(message.ends_with("<redacted 32 bytes>"), "{}", message);
+        assert!(!message.contains("0123456789"), "{}", message);
+    }
+}
+
+#[test]
+fn an_operation_with_a_wrong_field_type_fails_alone() {
+    SDKExtension::setErrorDetails(ErrorDetails::full);
+    let input = r#"{ "fairplay-streaming-request": { "create-ckc": [
+        { "id": 1, "spc": 12 },
+        { "id": 2, "spc": "AAAAAAAA" },
+        "AAAA",
+        { "id": 4, "spc": "AAAAAAAA", "asset-info": [ { "content-key": 5 } ] } ] } }"#;
+    let mut output = Value::default();
+    Base::processOperationsFromString(input, &mut output).unwrap();
+    let results = output["fairplay-streaming-response"]["create-ckc"].as_array().unwrap();
+
+    assert_eq!(results.len(), 4, "{}", output);
+    assertError(&results[0], FPSStatus::paramErr, "invalid-field-type", "spc must be a base64 string, not a number");
+    assert_eq!(results[0]["id"], 1);
+    // The other operations are still processed
+    assert_eq!(results[1]["id"], 2);
+    assert_ne!(results[1]["error"]["reason"], "invalid-field-type", "{}", output);
+    assertError(&results[2], FPSStatus::paramErr, "invalid-field-type", "create-ckc[2] must be an object, not a string");
+    assertError(&results[3], FPSStatus::paramErr, "invalid-field-type", "content-key must be a hex string, not a number");
+    assert_eq!(results[3]["id"], 4);
+}
@@ -0,0 +1,88 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+#![allow(nonstandard_style)]
+
+mod common;
+
+use base64::engine::general_purpose;
+use base64::Engine;
+use common::{buildSPC, requiredTLLVs, serverKey};
+use fpssdk::base::base_constants::KSMKeyPayloadContentType;
+use fpssdk::base::structures::base_fps_structures::Base;
+use fpssdk::extension::key_payload::{
+    KeyPayloadBackend, KeyPayloadRequest, MockKeyPayloadBackend, MOCK_CONTENT_KEY_PAYLOAD_MAGIC,
+    MOCK_CONTENT_KEY_TLLV_TAG,
+};
+use fpssdk::extension::structures::extension_structures::SDKExtension;
+use fpssdk::extension::validate::FPSStatus;
+use serde_jsonrc::{json, Value};
+use std::sync::Arc;
+
+const CONTENT_KEY: &str = "3C3C3C3C3C3C3C3C3C3C3C3C3C3C3C3C";
+const CONTENT_IV: &str = "D5D5D5D5D5D5D5D5D5D5D5D5D5D5D5D5";
+
+#[test]
+fn mock_backend_runs_the_whole_pipeline() {
+    serverKey();
+    SDKExtension::setKeyPayloadBackend(Arc::new(MockKeyPayloadBackend));
+
+    let spc = buildSPC(serverKey(), &requiredTLLVs(b"mock-asset"));
+    let request = json!({
+        "fairplay-streaming-request": {
+            "create-ckc": [{
+                "id": 1,
+                "spc": general_purpose::STANDARD.encode(&spc),
+                "asset-info": [{ "content-key": CONTENT_KEY, "content-iv": CONTENT_IV, "content-type": "audio" }],
+            }]
+        }
+    });
+    let mut output = Value::default();
+    Base::processOperations(request, &mut output).unwrap();
+
+    let result = &output["fairplay-streaming-response"]["create-ckc"][0];
+    assert_eq!(result["status"], FPSStatus::noErr as i32);
+    let ckc = general_purpose::STANDARD.decode(result["ckc"].as_str().unwrap()).unwrap();
+
+    // R1 is recovered through the same backend
+    let inspection = Base::inspectCKC(&spc, &ckc, None);
+    assert_eq!(inspection.error, None);
+    assert!(inspection.returnTagCheck().isValid());
+
+    let contentKeyTLLV = &inspection.tllvs[0].tllv;
+    assert_eq!(contentKeyTLLV.tag, MOCK_CONTENT_KEY_TLLV_TAG);
+    assert_eq!(&contentKeyTLLV.value[..8], MOCK_CONTENT_KEY_PAYLOAD_MAGIC);
+    assert_eq!(hex::encode_upper(&contentKeyTLLV.value[8..24]), CONTENT_KEY);
+    assert_eq!(hex::encode_upper(&contentKeyTLLV.value[24..40]), CONTENT_IV);
+    assert_eq!(contentKeyTLLV.value[40..], (KSMKeyPayloadContentType::audio as u64).to_be_bytes());
+}
+
+#[test]
+fn mock_backend_is_deterministic() {
+    let request = KeyPayloadRequest {
+        contentKey: &[0x3C; 16],
+        contentIV: &[0xD5; 16],
+        contentType: KSMKeyPayloadContentType::video,
+        skR1: &[0x51; 112],
+        r2: &[],
+        r1Integrity: &[],
+        supportedKeyFormats: &[],
+        cryptoVersionUsed: 1,
+        provisioningData: &[],
+        certHash: &[],
+    };
+
+    let first = MockKeyPayloadBackend.createKeyPayload(&request).unwrap();
+    assert_eq!(first, MockKeyPayloadBackend.createKeyPayload(&request).unwrap());
+    assert_eq!(first.hu.len(), 20);
+    assert_eq!(first.r1.len(), 44);
+
+    let otherClient = KeyPayloadRequest { skR1: &[0x61; 112], ..request };
+    let second = MockKeyPayloadBackend.createKeyPayload(&otherClient).unwrap();
+    assert_ne!(first.r1, second.r1);
+    assert_ne!(first.hu, second.hu);
+
+    let missingKey = KeyPayloadRequest { contentKey: &[], ..request };
+    assert_eq!(MockKeyPayloadBackend.createKeyPayload(&missingKey), Err(FPSStatus::paramErr));
+}
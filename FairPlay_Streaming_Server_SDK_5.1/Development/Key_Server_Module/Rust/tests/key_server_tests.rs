@@ -0,0 +1,121 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+#![allow(nonstandard_style)]
+
+mod common;
+
+use common::{buildSPC, requiredTLLVs, serverKey, syncTLLV};
+use fpssdk::base::base_constants::{FPSDeviceClass, KD_SYNC_SPC_FLAG_TITLEID_VALID};
+use fpssdk::base::structures::base_fps_structures::FPSResult;
+use fpssdk::extension::key_payload::MockKeyPayloadBackend;
+use fpssdk::extension::key_store::KeyStore;
+use fpssdk::extension::structures::extension_structures::SDKExtension;
+use fpssdk::extension::validate::{ErrorDetails, FPSStatus, FpsError};
+use fpssdk::key_server::{AssetInfo, KeyRequest, KeyResponse, KeyServer, OfflineLicense};
+use std::sync::Arc;
+
+#[test]
+fn invalid_requests_fail_with_request_id() {
+    let keyServer = KeyServer::new();
+    let spc = [0, 0, 0, 9, 0, 0, 0, 0];
+
+    // Unsupported SPC version
+    let request = KeyRequest::new(spc).id(7).assetInfo(AssetInfo::new().contentKey([1; 16], [2; 16]));
+    let error = keyServer.process(&request).unwrap_err();
+    assert_eq!((error.id(), error.status()), (7, FPSStatus::spcVersionErr));
+    assert_eq!(error.to_string(), "request 7 failed: spcVersionErr (-42580)");
+
+    // Keys must be exactly 16 bytes
+    let request = KeyRequest::new(spc).id(8).assetInfo(AssetInfo::new().contentKey([1; 15], [2; 16]));
+    let error = keyServer.process(&request).unwrap_err();
+    assert_eq!((error.id(), error.status()), (8, FPSStatus::paramErr));
+    assert_eq!(error.message(), "content key and IV must be 16 bytes (got 15 and 16)");
+
+    // Offline licenses need both stream and title IDs
+    let offline = OfflineLicense {
+        streamId: Some(vec![3; 16]),
+        ..Default::default()
+    };
+    let request = KeyRequest::new(spc).assetInfo(AssetInfo::new().offline(offline));
+    assert_eq!(keyServer.process(&request).unwrap_err().status(), FPSStatus::paramErr);
+}
+
+#[test]
+fn requests_without_a_content_key_fail() {
+    let keyServer = KeyServer::new();
+    let spc = buildSPC(serverKey(), &requiredTLLVs(b"movie"));
+    let process = || keyServer.process(&KeyRequest::new(spc.clone()).id(4).assetInfo(AssetInfo::new()));
+    let reason = |error: FpsError| (error.id(), error.status(), error.reason().to_string());
+
+    // Nothing fills in the key. The typed API keeps the reason even when JSON callers get none.
+    SDKExtension::setErrorDetails(ErrorDetails::hidden);
+    let missing = (4, FPSStatus::paramErr, "missing-content-key".to_string());
+    assert_eq!(reason(process().unwrap_err()), missing);
+
+    // The key store has no key for the asset
+    SDKExtension::setKeyStore(Arc::new(KeyStore::openInMemory().unwrap()));
+    let notStored = (4, FPSStatus::paramErr, "no-stored-content-key".to_string());
+    assert_eq!(reason(process().unwrap_err()), notStored);
+
+    // Lease renewals do not need one from the store either
+    SDKExtension::setKeyPayloadBackend(Arc::new(MockKeyPayloadBackend));
+    let renewal = KeyRequest::new(spc.clone()).assetInfo(AssetInfo::new().leaseDuration(600));
+    assert!(keyServer.process(&renewal).is_ok());
+}
+
+#[test]
+fn check_ins_and_lease_renewals_need_no_content_key() {
+    SDKExtension::setKeyPayloadBackend(Arc::new(MockKeyPayloadBackend));
+    let keyServer = KeyServer::new();
+
+    let mut tllvs = requiredTLLVs(b"movie");
+    tllvs.push(syncTLLV(0, &[0x71; 16], &[]));
+    let checkIn = KeyRequest::new(buildSPC(serverKey(), &tllvs)).id(5).checkIn(true);
+    let response = keyServer.process(&checkIn).unwrap();
+    assert!(response.sync.is_some());
+
+    let renewal = KeyRequest::new(buildSPC(serverKey(), &requiredTLLVs(b"movie")))
+        .id(6)
+        .assetInfo(AssetInfo::new().leaseDuration(600));
+    assert_eq!(keyServer.process(&renewal).unwrap().id, 6);
+}
+
+#[test]
+fn response_exposes_result_fields() {
+    let mut result = FPSResult {
+        id: 3,
+        ckc: vec![0xCC; 4],
+        hu: vec![0xAB; 20],
+        deviceIdentitySet: true,
+        deviceClass: FPSDeviceClass::appleLivingRoom as u32,
+        osVersion: 0x00120000,
+        isCheckIn: true,
+        syncServerChallenge: 99,
+        syncFlags: KD_SYNC_SPC_FLAG_TITLEID_VALID,
+        recordsDeleted: 2,
+        ..Default::default()
+    };
+    result.deletedContentIDs = [vec![1; 16], vec![2; 16]].concat();
+
+    let response = KeyResponse::from(result);
+    assert_eq!(response.id, 3);
+    assert_eq!(response.ckc, vec![0xCC; 4]);
+    assert_eq!(response.hu, vec![0xAB; 20]);
+    assert!(response.vmDeviceInfo.is_none());
+
+    let deviceIdentity = response.deviceIdentity.unwrap();
+    assert_eq!(deviceIdentity.deviceClass, FPSDeviceClass::appleLivingRoom);
+    assert_eq!(deviceIdentity.osVersion, 0x00120000);
+
+    let sync = response.sync.unwrap();
+    assert_eq!(sync.serverChallenge, 99);
+    assert_eq!(sync.titleId, Some(vec![0; 16]));
+    assert_eq!(sync.deletedContentIds, vec![vec![1; 16], vec![2; 16]]);
+
+    // Licenses without device identity or check-in leave those fields empty
+    let response = KeyResponse::from(FPSResult::default());
+    assert!(response.deviceIdentity.is_none());
+    assert!(response.sync.is_none());
+}
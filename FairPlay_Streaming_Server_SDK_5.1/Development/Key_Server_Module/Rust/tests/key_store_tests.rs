@@ -0,0 +1,107 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+#![allow(nonstandard_style)]
+
+use fpssdk::base::base_constants::{self, FPSHDCPRequirement};
+use fpssdk::base::structures::base_fps_structures::FPSOperation;
+use fpssdk::base::structures::base_server_structures::FPSServerCtx;
+use fpssdk::extension::extension;
+use fpssdk::extension::extension_constants::ContentType;
+use fpssdk::extension::key_store::{formatAssetId, parseAssetId, KeyStore};
+use fpssdk::extension::random::RandomSource;
+use fpssdk::extension::structures::extension_structures::SDKExtension;
+use fpssdk::extension::validate::{self, FPSStatus};
+use std::sync::Arc;
+
+const KEYS_JSON: &str = r#"[
+    {
+        "asset-id": "twelve",
+        "content-key": "3C3C3C3C3C3C3C3C3C3C3C3C3C3C3C3C",
+        "content-iv": "D5FBD6B82ED93E4EF98AE40931EE33B7",
+        "content-type": "uhd",
+        "hdcp-type": 1,
+        "lease-duration": 600
+    },
+    {
+        "asset-id": "0x0001ff",
+        "content-key": "0x00112233445566778899aabbccddeeff",
+        "content-iv": "00112233445566778899aabbccddeeff"
+    }
+]"#;
+
+struct ZeroRandom;
+
+impl RandomSource for ZeroRandom {
+    fn fill(&self, out: &mut [u8]) {
+        out.fill(0);
+    }
+}
+
+#[test]
+fn import_list_and_rotate() {
+    let keyStore = KeyStore::openInMemory().unwrap();
+    assert_eq!(keyStore.importJson(KEYS_JSON), Ok(2));
+
+    let records = keyStore.list().unwrap();
+    assert_eq!(records.iter().map(|r| formatAssetId(&r.assetId)).collect::<Vec<_>>(), ["0x0001ff", "twelve"]);
+    assert_eq!(records[1].version, 1);
+    assert_eq!(records[1].hdcpType, Some(1));
+    assert_eq!(records[0].leaseDuration, None);
+
+    // Rotation replaces the key and IV but keeps the asset settings
+    let rotated = keyStore.rotate(b"twelve", None, Some(vec![7; 16].into())).unwrap();
+    assert_eq!(rotated.version, 2);
+    assert_ne!(rotated.key, records[1].key);
+    assert_eq!(rotated.iv, vec![7; 16]);
+    assert_eq!(rotated.leaseDuration, Some(600));
+
+    // Generated keys do not come from the installed random source
+    let rotated =
+        SDKExtension::withRandomSource(Arc::new(ZeroRandom), || keyStore.rotate(b"twelve", None, None)).unwrap();
+    assert_ne!(rotated.key, vec![0; 16]);
+    assert_ne!(rotated.iv, vec![0; 16]);
+
+    // Bad entries are rejected and nothing from the file is stored
+    let badKey = r#"[{ "asset-id": "new", "content-key": "00", "content-iv": "00112233445566778899aabbccddeeff" }]"#;
+    assert_eq!(keyStore.importJson(badKey), Err(FPSStatus::paramErr));
+    assert!(keyStore.get(b"new").unwrap().is_none());
+    assert_eq!(keyStore.rotate(b"missing", None, None).err(), Some(FPSStatus::paramErr));
+    assert_eq!(parseAssetId("0xzz").err(), Some(FPSStatus::paramErr));
+}
+
+#[test]
+fn query_database_fills_asset_info_from_store() {
+    let keyStore = Arc::new(KeyStore::openInMemory().unwrap());
+    keyStore.importJson(KEYS_JSON).unwrap();
+    SDKExtension::setKeyStore(keyStore);
+
+    let mut fpsOperation = FPSOperation::default();
+    let mut serverCtx = FPSServerCtx::default();
+    Arc::make_mut(&mut serverCtx.spcContainer).spcData.assetId = b"twelve".to_vec();
+    extension::queryDatabaseCustom(&mut fpsOperation, &mut serverCtx).unwrap();
+
+    let assetInfo = &fpsOperation.assetInfo;
+    assert!(assetInfo.isCKProvided);
+    assert_eq!(assetInfo.key, vec![0x3C; 16]);
+    assert_eq!(assetInfo.hdcpReq, FPSHDCPRequirement::hdcpType1 as u64);
+    assert_eq!(assetInfo.leaseDuration, 600);
+    assert!(matches!(assetInfo.extension.contentType, ContentType::uhd));
+
+    // Keys passed in the request win over the store
+    let mut fpsOperation = FPSOperation::default();
+    fpsOperation.assetInfo.isCKProvided = true;
+    fpsOperation.assetInfo.key = vec![0xAA; base_constants::AES128_KEY_SZ].into();
+    extension::queryDatabaseCustom(&mut fpsOperation, &mut serverCtx).unwrap();
+    assert_eq!(fpsOperation.assetInfo.key, vec![0xAA; 16]);
+
+    // Unknown assets fail instead of continuing without a key
+    let mut fpsOperation = FPSOperation::default();
+    Arc::make_mut(&mut serverCtx.spcContainer).spcData.assetId = b"unknown".to_vec();
+    let (result, error) =
+        validate::captureError(|| extension::queryDatabaseCustom(&mut fpsOperation, &mut serverCtx));
+    assert_eq!(result, Err(FPSStatus::paramErr));
+    assert_eq!(error.unwrap().reason, "no-stored-content-key");
+    assert!(!fpsOperation.assetInfo.isCKProvided);
+}
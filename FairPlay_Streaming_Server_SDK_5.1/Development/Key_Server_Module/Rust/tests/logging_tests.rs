@@ -0,0 +1,81 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+#![allow(nonstandard_style)]
+
+mod common;
+
+use common::{buildSPC, requiredTLLVs, serverKey};
+use fpssdk::base::structures::base_fps_structures::Base;
+use fpssdk::extension::validate::FPSStatus;
+use fpssdk::logging::{jsonErrorLine, jsonLogLine, LogContext, Redacted};
+
+#[test]
+fn log_context_takes_spc_values_and_restores_previous_context() {
+    let spc = buildSPC(serverKey(), &requiredTLLVs(b"logging-asset"));
+    let spcContainer = Base::inspectSPC(&spc).spcContainer;
+
+    let outer = LogContext::enter(1);
+    {
+        let _inner = LogContext::enter(2);
+        LogContext::update(|context| context.setSPC(&spcContainer));
+
+        let context = LogContext::current();
+        assert_eq!(context.id, Some(2));
+        assert_eq!(context.assetId.as_deref(), Some("logging-asset"));
+        assert_eq!(context.transactionId, Some(0x1234));
+        assert_eq!(context.spcVersion, Some(2));
+        assert_eq!(context.deviceClass, None);
+    }
+    assert_eq!(LogContext::current(), LogContext { id: Some(1), ..Default::default() });
+
+    drop(outer);
+    assert_eq!(LogContext::current(), LogContext::default());
+}
+
+#[test]
+fn json_log_line_has_context_fields_and_redacts_key_material() {
+    let _context = LogContext::enter(42);
+    LogContext::update(|context| context.transactionId = Some(0xABCD));
+
+    let contentKey = [0x3C; 16];
+    let line = jsonLogLine("DEBUG", "src/file.rs", 7, &format!("content key {}", Redacted(&contentKey)));
+
+    assert_eq!(line["level"], "DEBUG");
+    assert_eq!(line["tool"], "fpssdk");
+    assert_eq!(line["file"], "src/file.rs");
+    assert_eq!(line["line"], 7);
+    assert_eq!(line["id"], 42);
+    assert_eq!(line["transaction-id"], "0x000000000000abcd");
+    assert!(line.get("asset-id").is_none());
+    assert_eq!(line["message"], "content key <redacted 16 bytes>");
+    assert!(line["timestamp"].as_str().unwrap().ends_with('Z'));
+}
+
+#[test]
+fn json_error_line_has_the_status_name_and_reason() {
+    let _context = LogContext::enter(7);
+
+    let line = jsonErrorLine(
+        &FPSStatus::paramErr,
+        12,
+        "src/file.rs",
+        Some("no-stored-content-key"),
+        Some("no content key stored"),
+    );
+    assert_eq!(line["level"], "ERROR");
+    assert_eq!(line["id"], 7);
+    assert_eq!(line["code"], FPSStatus::paramErr as i32);
+    assert_eq!(line["status"], "paramErr");
+    assert_eq!(line["reason"], "no-stored-content-key");
+    assert_eq!(line["message"], "no content key stored");
+
+    // Without a reason the status gives it, plain error codes have neither
+    let line = jsonErrorLine(&FPSStatus::replayErr, 12, "src/file.rs", None, None);
+    assert_eq!(line["reason"], FPSStatus::replayErr.reason());
+    assert_eq!(line["message"], "");
+    let line = jsonErrorLine(&-1, 12, "src/file.rs", None, Some("unexpected"));
+    assert_eq!(line["code"], -1);
+    assert!(line.get("status").is_none() && line.get("reason").is_none());
+}
@@ -0,0 +1,82 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+#![allow(nonstandard_style)]
+
+mod common;
+
+use common::{buildSPC, requiredTLLVs, serverKey};
+use fpssdk::base::base_constants::FPSDeviceClass;
+use fpssdk::extension::extension_constants::ContentType;
+use fpssdk::extension::key_payload::MockKeyPayloadBackend;
+use fpssdk::extension::structures::extension_structures::SDKExtension;
+use fpssdk::extension::validate::FPSStatus;
+use fpssdk::key_server::{AssetInfo, KeyRequest, KeyServer};
+use fpssdk::metrics::{MetricsRegistry, OperationLabels, Stage};
+use std::sync::Arc;
+use std::time::Duration;
+
+fn spc() -> Vec<u8> {
+    serverKey();
+    SDKExtension::setKeyPayloadBackend(Arc::new(MockKeyPayloadBackend));
+    buildSPC(serverKey(), &requiredTLLVs(b"metrics-asset"))
+}
+
+fn labels(status: FPSStatus, contentType: ContentType) -> OperationLabels {
+    OperationLabels {
+        status: format!("{:?}", status),
+        contentType: contentType.name(),
+        deviceClass: FPSDeviceClass::unknown,
+        spcVersion: "2048",
+        vm: false,
+        checkIn: false,
+    }
+}
+
+// Tests in this file share the process registry, so each one uses its own content type
+#[test]
+fn create_ckc_operations_are_counted_by_status_and_timed() {
+    let metrics = SDKExtension::metrics();
+    let success = labels(FPSStatus::noErr, ContentType::hd);
+    let countBefore = metrics.operationCount(&success);
+    let timedBefore = [Stage::spcDecrypt, Stage::keyPayload, Stage::ckcGeneration].map(|s| metrics.observationCount(s));
+
+    let assetInfo = AssetInfo::new().contentKey([0x3C; 16], [0xD5; 16]).contentType(ContentType::hd);
+    KeyServer::new().process(&KeyRequest::new(spc()).assetInfo(assetInfo.clone())).unwrap();
+    assert_eq!(metrics.operationCount(&success), countBefore + 1);
+    for (stage, before) in [Stage::spcDecrypt, Stage::keyPayload, Stage::ckcGeneration].into_iter().zip(timedBefore) {
+        assert!(metrics.observationCount(stage) > before, "{:?} was not timed", stage);
+    }
+
+    let mut truncated = spc();
+    truncated.truncate(200);
+    let error = KeyServer::new()
+        .process(&KeyRequest::new(truncated).assetInfo(assetInfo.contentType(ContentType::sd)))
+        .unwrap_err();
+    assert_eq!(metrics.operationCount(&labels(error.status(), ContentType::sd)), 1);
+
+    let text = metrics.render();
+    assert!(text.contains("# TYPE fpssdk_create_ckc_operations_total counter"));
+    assert!(text.contains(
+        "fpssdk_create_ckc_operations_total{status=\"noErr\",content_type=\"hd\",device_class=\"unknown\",\
+         spc_version=\"2048\",platform=\"physical\",request=\"license\"}"
+    ));
+    assert!(text.contains("fpssdk_stage_duration_seconds_bucket{stage=\"key_payload\",le=\"+Inf\"}"));
+}
+
+#[test]
+fn histogram_buckets_are_cumulative() {
+    let metrics = MetricsRegistry::new();
+    metrics.observe(Stage::spcDecrypt, Duration::from_micros(50));
+    metrics.observe(Stage::spcDecrypt, Duration::from_millis(3));
+    metrics.observe(Stage::spcDecrypt, Duration::from_secs(2));
+
+    let text = metrics.render();
+    assert!(text.contains("fpssdk_stage_duration_seconds_bucket{stage=\"spc_decrypt\",le=\"0.0001\"} 1\n"));
+    assert!(text.contains("fpssdk_stage_duration_seconds_bucket{stage=\"spc_decrypt\",le=\"0.005\"} 2\n"));
+    assert!(text.contains("fpssdk_stage_duration_seconds_bucket{stage=\"spc_decrypt\",le=\"1\"} 2\n"));
+    assert!(text.contains("fpssdk_stage_duration_seconds_bucket{stage=\"spc_decrypt\",le=\"+Inf\"} 3\n"));
+    assert!(text.contains("fpssdk_stage_duration_seconds_count{stage=\"spc_decrypt\"} 3\n"));
+    assert!(!text.contains("stage=\"ckc_generation\""));
+}
@@ -0,0 +1,145 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+#![allow(nonstandard_style)]
+
+mod common;
+
+use common::{buildSPC, requiredTLLVs, serverKey, syncTLLV};
+use fpssdk::extension::key_payload::MockKeyPayloadBackend;
+use fpssdk::extension::offline_ledger::{LicenseHolder, OfflineLedger, OfflineLicenseRecord};
+use fpssdk::extension::structures::extension_structures::SDKExtension;
+use fpssdk::extension::validate::{self, FPSStatus};
+use fpssdk::key_server::{AssetInfo, KeyRequest, KeyServer, OfflineLicense};
+use std::sync::Arc;
+
+const NOW: i64 = 1_750_000_000;
+
+fn license(hu: u8, streamId: u8, accountId: &str) -> OfflineLicenseRecord {
+    OfflineLicenseRecord {
+        hu: vec![hu; 20],
+        streamId: vec![streamId; 16],
+        titleId: vec![0x71; 16],
+        accountId: Some(accountId.to_string()),
+        issuedAt: NOW - 60,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn ledger_counts_active_licenses_per_device_and_account() {
+    let ledger = OfflineLedger::openInMemory().unwrap();
+    ledger.record(&license(1, 0xA1, "alice")).unwrap();
+    ledger.record(&license(1, 0xA2, "alice")).unwrap();
+    ledger.record(&license(2, 0xA1, "alice")).unwrap();
+    ledger.record(&license(3, 0xA1, "bob")).unwrap();
+    // Issuing the same stream again replaces the earlier license
+    ledger.record(&license(1, 0xA2, "alice")).unwrap();
+
+    // Rentals stop counting once they expire
+    let rental = OfflineLicenseRecord {
+        rentalDuration: 3600,
+        issuedAt: NOW - 7200,
+        ..license(2, 0xA2, "alice")
+    };
+    ledger.record(&rental).unwrap();
+    assert!(!rental.isActive(NOW));
+
+    let device = LicenseHolder::device(vec![1; 20]);
+    let account = LicenseHolder::account("alice".to_string());
+    assert_eq!(ledger.activeLicenses(&device, NOW).unwrap().len(), 2);
+    assert_eq!(ledger.activeLicenses(&account, NOW).unwrap().len(), 3);
+    assert_eq!(ledger.activeLicenses(&account, NOW - 5400).unwrap().len(), 4);
+
+    // Check-in returns the deleted licenses of that device only
+    let deleted = [vec![0xA1; 16], vec![0xA3; 16]].concat();
+    assert_eq!(ledger.checkIn(&[1; 20], 77, &deleted, NOW), Ok(1));
+    assert_eq!(
+        ledger.activeLicenses(&device, NOW).unwrap(),
+        vec![license(1, 0xA2, "alice")]
+    );
+    assert_eq!(ledger.activeLicenses(&account, NOW).unwrap().len(), 2);
+
+    // Each server challenge is accepted once
+    let (result, error) = validate::captureError(|| ledger.checkIn(&[2; 20], 77, &deleted, NOW));
+    assert_eq!(result, Err(FPSStatus::replayErr));
+    assert_eq!(error.unwrap().reason, "check-in-challenge-reused");
+    assert_eq!(ledger.activeLicenses(&account, NOW).unwrap().len(), 2);
+
+    // Without a challenge the check-in cannot be verified
+    assert_eq!(ledger.checkIn(&[2; 20], 0, &deleted, NOW), Ok(0));
+    assert_eq!(ledger.activeLicenses(&account, NOW).unwrap().len(), 2);
+}
+
+#[test]
+fn check_in_challenges_stay_used_across_restarts() {
+    let path = std::env::temp_dir().join(format!("fpssdk-offline-ledger-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let device = LicenseHolder::device(vec![1; 20]);
+
+    let ledger = OfflineLedger::open(&path).unwrap();
+    ledger.record(&license(1, 0xA1, "alice")).unwrap();
+    ledger.record(&license(1, 0xA2, "alice")).unwrap();
+    assert_eq!(ledger.checkIn(&[1; 20], 77, &[0xA1; 16], NOW), Ok(1));
+    drop(ledger);
+
+    let ledger = OfflineLedger::open(&path).unwrap();
+    assert_eq!(
+        ledger.activeLicenses(&device, NOW).unwrap(),
+        vec![license(1, 0xA2, "alice")]
+    );
+    let (result, error) = validate::captureError(|| ledger.checkIn(&[1; 20], 77, &[0xA2; 16], NOW));
+    assert_eq!(result, Err(FPSStatus::replayErr));
+    assert_eq!(error.unwrap().reason, "check-in-challenge-reused");
+    assert_eq!(ledger.activeLicenseCount(&device), Ok(1));
+
+    drop(ledger);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn offline_licenses_are_recorded_and_returned_by_check_in() {
+    serverKey();
+    SDKExtension::setKeyPayloadBackend(Arc::new(MockKeyPayloadBackend));
+    let ledger = Arc::new(OfflineLedger::openInMemory().unwrap());
+    SDKExtension::setOfflineLedger(Some(ledger.clone()));
+    let keyServer = KeyServer::new();
+
+    let streamId = vec![0xA1; 16];
+    let offline = OfflineLicense {
+        streamId: Some(streamId.clone()),
+        titleId: Some(vec![0x71; 16]),
+        rentalDuration: 86400,
+        accountId: Some("alice".to_string()),
+        ..Default::default()
+    };
+    let request = KeyRequest::new(buildSPC(serverKey(), &requiredTLLVs(b"movie")))
+        .assetInfo(AssetInfo::new().contentKey([0x3C; 16], [0xD5; 16]).offline(offline));
+    let hu = keyServer.process(&request).unwrap().hu;
+
+    let account = LicenseHolder::account("alice".to_string());
+    assert_eq!(ledger.activeLicenseCount(&LicenseHolder::device(hu.clone())), Ok(1));
+    let recorded = &ledger.activeLicenses(&account, 0).unwrap()[0];
+    assert_eq!((&recorded.hu, &recorded.streamId), (&hu, &streamId));
+    assert_eq!(recorded.rentalDuration, 86400);
+
+    // Streaming licenses are not recorded
+    let request = KeyRequest::new(buildSPC(serverKey(), &requiredTLLVs(b"movie")))
+        .assetInfo(AssetInfo::new().contentKey([0x3C; 16], [0xD5; 16]));
+    keyServer.process(&request).unwrap();
+    assert_eq!(ledger.activeLicenseCount(&account), Ok(1));
+
+    let mut tllvs = requiredTLLVs(b"movie");
+    let serverChallenge = SDKExtension::mintCheckInChallenge(&hu, &[0x71; 16]).unwrap();
+    tllvs.push(syncTLLV(serverChallenge, &[0x71; 16], std::slice::from_ref(&streamId)));
+    let checkIn = KeyRequest::new(buildSPC(serverKey(), &tllvs)).checkIn(true);
+    keyServer.process(&checkIn).unwrap();
+    assert_eq!(ledger.activeLicenseCount(&account), Ok(0));
+
+    // Replaying the check-in SPC is rejected
+    let error = keyServer.process(&checkIn).unwrap_err();
+    assert_eq!(error.status(), FPSStatus::replayErr);
+
+    SDKExtension::setOfflineLedger(None);
+}
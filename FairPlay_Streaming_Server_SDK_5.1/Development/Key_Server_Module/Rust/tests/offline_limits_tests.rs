@@ -0,0 +1,208 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+#![allow(nonstandard_style)]
+
+mod common;
+
+use common::{buildSPC, requiredTLLVs, serverKey, syncTLLV};
+use fpssdk::extension::key_payload::MockKeyPayloadBackend;
+use fpssdk::extension::offline_ledger::{LicenseHolder, OfflineLedger, OfflineLicenseRecord};
+use fpssdk::extension::policy::{OfflineLimits, Policy};
+use fpssdk::extension::structures::extension_structures::SDKExtension;
+use fpssdk::extension::validate::{self, FPSStatus};
+use fpssdk::key_server::{AssetInfo, KeyRequest, KeyServer, OfflineLicense};
+use std::sync::Arc;
+
+const NOW: i64 = 1_750_000_000;
+
+fn license(hu: u8, streamId: u8, titleId: u8) -> OfflineLicenseRecord {
+    OfflineLicenseRecord {
+        hu: vec![hu; 20],
+        streamId: vec![streamId; 16],
+        titleId: vec![titleId; 16],
+        accountId: Some("alice".to_string()),
+        issuedAt: NOW,
+        ..Default::default()
+    }
+}
+
+fn checkLimits(ledger: &OfflineLedger, limits: &OfflineLimits, license: &OfflineLicenseRecord) -> Option<String> {
+    let (result, error) = validate::captureError(|| ledger.checkLimits(limits, license));
+    assert_eq!(result.is_err(), error.is_some());
+    error.map(|error| {
+        assert_eq!(error.status, FPSStatus::offlineLimitErr);
+        error.reason
+    })
+}
+
+#[test]
+fn limits_count_licenses_per_device_and_devices_per_account_title() {
+    let policy = Policy::fromJson(
+        r#"{ "offline-limits": { "max-licenses-per-device": 2, "max-devices-per-account-title": 2 } }"#,
+    )
+    .unwrap();
+    let limits = policy.offlineLimits;
+    assert!(!limits.isEmpty() && !limits.requireCheckInBeforeRedownload);
+    assert!(Policy::fromJson(r#"{ "offline-limits": { "max-licenses": 2 } }"#).is_err());
+
+    let ledger = OfflineLedger::openInMemory().unwrap();
+    ledger.record(&license(1, 0xA1, 0x71)).unwrap();
+    ledger.record(&license(1, 0xA2, 0x71)).unwrap();
+    ledger.record(&license(2, 0xA1, 0x71)).unwrap();
+
+    // Device 1 is full, but may download a stream it holds again
+    assert_eq!(
+        checkLimits(&ledger, &limits, &license(1, 0xB1, 0x72)).as_deref(),
+        Some("offline-device-limit")
+    );
+    assert_eq!(checkLimits(&ledger, &limits, &license(1, 0xA2, 0x71)), None);
+
+    // The account holds title 0x71 on two devices already
+    assert_eq!(checkLimits(&ledger, &limits, &license(2, 0xA2, 0x71)), None);
+    assert_eq!(
+        checkLimits(&ledger, &limits, &license(3, 0xA1, 0x71)).as_deref(),
+        Some("offline-account-limit")
+    );
+    assert_eq!(checkLimits(&ledger, &limits, &license(3, 0xB1, 0x72)), None);
+    let otherAccount = OfflineLicenseRecord {
+        accountId: Some("bob".to_string()),
+        ..license(3, 0xA1, 0x71)
+    };
+    assert_eq!(checkLimits(&ledger, &limits, &otherAccount), None);
+
+    // Returning a license frees its slot
+    ledger.checkIn(&[1; 20], 1, &[0xA2; 16], NOW).unwrap();
+    assert_eq!(checkLimits(&ledger, &limits, &license(1, 0xB1, 0x72)), None);
+
+    let limits = OfflineLimits {
+        requireCheckInBeforeRedownload: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        checkLimits(&ledger, &limits, &license(1, 0xA1, 0x71)).as_deref(),
+        Some("offline-check-in-required")
+    );
+    assert_eq!(checkLimits(&ledger, &limits, &license(1, 0xA2, 0x71)), None);
+}
+
+#[test]
+fn reservations_count_against_the_limits_until_released() {
+    let ledger = Arc::new(OfflineLedger::openInMemory().unwrap());
+    let device = LicenseHolder::device(vec![1; 20]);
+    let limits = OfflineLimits {
+        maxLicensesPerDevice: Some(1),
+        ..Default::default()
+    };
+
+    // Concurrent downloads cannot all pass the limit
+    let reservations: Vec<_> = (0..8u8)
+        .map(|streamId| {
+            let ledger = ledger.clone();
+            let limits = limits.clone();
+            std::thread::spawn(move || ledger.reserve(&limits, &license(1, streamId, 0x71)).ok())
+        })
+        .collect::<Vec<_>>()
+        .into_iter()
+        .filter_map(|thread| thread.join().unwrap())
+        .collect();
+    assert_eq!(reservations.len(), 1);
+    assert_eq!(ledger.activeLicenseCount(&device), Ok(1));
+
+    // A reservation whose CKC was not generated frees its slot
+    drop(reservations);
+    assert_eq!(ledger.activeLicenseCount(&device), Ok(0));
+
+    let committed = ledger.reserve(&limits, &license(1, 0xA1, 0x71)).unwrap();
+    committed.commit();
+    drop(committed);
+    assert_eq!(ledger.activeLicenseCount(&device), Ok(1));
+
+    // Releasing a renewal restores the license it replaced
+    let renewal = OfflineLicenseRecord {
+        issuedAt: NOW + 10,
+        ..license(1, 0xA1, 0x71)
+    };
+    drop(ledger.reserve(&limits, &renewal).unwrap());
+    assert_eq!(
+        ledger.activeLicenses(&device, NOW).unwrap(),
+        vec![license(1, 0xA1, 0x71)]
+    );
+}
+
+#[test]
+fn releasing_a_reservation_keeps_a_later_one_for_the_same_stream() {
+    let ledger = Arc::new(OfflineLedger::openInMemory().unwrap());
+    let device = LicenseHolder::device(vec![1; 20]);
+    let limits = OfflineLimits::default();
+
+    // Two requests for the same stream in the same second record identical licenses
+    let first = ledger.reserve(&limits, &license(1, 0xA1, 0x71)).unwrap();
+    let second = ledger.reserve(&limits, &license(1, 0xA1, 0x71)).unwrap();
+    second.commit();
+    drop(second);
+
+    // The first CKC fails after the second was issued
+    drop(first);
+    assert_eq!(
+        ledger.activeLicenses(&device, NOW).unwrap(),
+        vec![license(1, 0xA1, 0x71)]
+    );
+
+    // Releasing the later one restores the earlier pending one, which can still be released
+    let first = ledger.reserve(&limits, &license(1, 0xB2, 0x71)).unwrap();
+    let second = ledger.reserve(&limits, &license(1, 0xB2, 0x71)).unwrap();
+    drop(second);
+    assert_eq!(ledger.activeLicenseCount(&device), Ok(2));
+    drop(first);
+    assert_eq!(
+        ledger.activeLicenses(&device, NOW).unwrap(),
+        vec![license(1, 0xA1, 0x71)]
+    );
+}
+
+#[test]
+fn requests_over_the_limits_fail_until_a_check_in_returns_a_slot() {
+    serverKey();
+    SDKExtension::setKeyPayloadBackend(Arc::new(MockKeyPayloadBackend));
+    SDKExtension::setPolicy(Arc::new(Policy {
+        offlineLimits: OfflineLimits {
+            maxLicensesPerDevice: Some(1),
+            ..Default::default()
+        },
+        ..Policy::builtIn()
+    }));
+    SDKExtension::setOfflineLedger(Some(Arc::new(OfflineLedger::openInMemory().unwrap())));
+    let keyServer = KeyServer::new();
+
+    let download = |streamId: u8| {
+        let offline = OfflineLicense {
+            streamId: Some(vec![streamId; 16]),
+            titleId: Some(vec![0x71; 16]),
+            ..Default::default()
+        };
+        let request = KeyRequest::new(buildSPC(serverKey(), &requiredTLLVs(b"movie")))
+            .assetInfo(AssetInfo::new().contentKey([0x3C; 16], [0xD5; 16]).offline(offline));
+        keyServer
+            .process(&request)
+            .map(|response| response.hu)
+            .map_err(|error| error.status())
+    };
+
+    let hu = download(0xA1).unwrap();
+    assert_eq!(download(0xA2), Err(FPSStatus::offlineLimitErr));
+    assert_eq!(FPSStatus::offlineLimitErr.httpStatus(), 403);
+
+    // Checking in stream 0xA1 frees the slot
+    let mut tllvs = requiredTLLVs(b"movie");
+    let serverChallenge = SDKExtension::mintCheckInChallenge(&hu, &[0x71; 16]).unwrap();
+    tllvs.push(syncTLLV(serverChallenge, &[0x71; 16], &[vec![0xA1; 16]]));
+    let checkIn = KeyRequest::new(buildSPC(serverKey(), &tllvs)).checkIn(true);
+    keyServer.process(&checkIn).unwrap();
+
+    assert_eq!(download(0xA2), Ok(hu));
+
+    SDKExtension::setOfflineLedger(None);
+    SDKExtension::setPolicy(Arc::new(Policy::builtIn()));
+}
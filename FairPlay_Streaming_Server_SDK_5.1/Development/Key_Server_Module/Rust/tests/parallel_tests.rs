@@ -0,0 +1,126 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+#![allow(nonstandard_style)]
+
+mod common;
+
+use base64::engine::general_purpose;
+use base64::Engine;
+use common::{buildSPC, requiredTLLVs, serverKey};
+use fpssdk::extension::fps_extension::{DefaultExtension, FpsExtension};
+use fpssdk::extension::key_payload::MockKeyPayloadBackend;
+use fpssdk::extension::parallel::processInParallel;
+#[cfg(feature = "deterministic_random")]
+use fpssdk::extension::random::SeededRandom;
+use fpssdk::extension::structures::extension_structures::SDKExtension;
+use fpssdk::key_server::KeyServer;
+use serde_jsonrc::{json, Value};
+use std::sync::{Arc, Barrier};
+use std::thread;
+
+#[test]
+fn items_run_concurrently_with_the_callers_extension_and_keep_their_order() {
+    SDKExtension::setParallelism(4);
+    let extension: Arc<dyn FpsExtension> = Arc::new(DefaultExtension);
+    // Items 0 and 1 only finish once both are running, so they must be on different threads
+    let barrier = Arc::new(Barrier::new(2));
+
+    let expected = extension.clone();
+    let results = SDKExtension::withFpsExtension(extension.clone(), || {
+        processInParallel((0..20).collect(), move |item: usize| {
+            if item < 2 {
+                barrier.wait();
+            }
+            assert!(Arc::ptr_eq(&SDKExtension::fpsExtension(), &expected));
+            (item, thread::current().id())
+        })
+    });
+
+    assert_eq!(
+        results.iter().map(|(item, _)| *item).collect::<Vec<_>>(),
+        (0..20).collect::<Vec<_>>()
+    );
+    assert_ne!(results[0].1, results[1].1);
+    assert!(results.iter().all(|(_, threadId)| *threadId != thread::current().id()));
+}
+
+#[test]
+fn requests_share_one_pool_of_worker_threads() {
+    SDKExtension::setParallelism(4);
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            thread::spawn(|| {
+                processInParallel((0..16).collect(), |_: usize| thread::current().name().map(String::from))
+            })
+        })
+        .collect();
+
+    let mut names: Vec<_> = threads
+        .into_iter()
+        .flat_map(|request| request.join().unwrap())
+        .collect();
+    names.sort();
+    names.dedup();
+    assert!(names.len() <= 4, "{:?}", names);
+    assert!(names
+        .iter()
+        .all(|name| name.as_deref().unwrap().starts_with("fpssdk-operation-")));
+
+    // A panic is resumed on the calling thread, the pool keeps working
+    let panicked = std::panic::catch_unwind(|| processInParallel((0..8).collect(), |item: usize| assert_ne!(item, 5)));
+    assert!(panicked.is_err());
+    assert_eq!(processInParallel((0..8).collect(), |item: usize| item * 2)[7], 14);
+}
+
+#[test]
+fn batched_operations_return_results_in_request_order() {
+    serverKey();
+    SDKExtension::setKeyPayloadBackend(Arc::new(MockKeyPayloadBackend));
+    SDKExtension::setParallelism(4);
+
+    let operations: Vec<Value> = (0..8u64)
+        .map(|id| {
+            let spc = buildSPC(serverKey(), &requiredTLLVs(format!("rendition-{}", id).as_bytes()));
+            json!({
+                "id": id,
+                "spc": general_purpose::STANDARD.encode(spc),
+                "asset-info": [{ "content-key": "3C3C3C3C3C3C3C3C3C3C3C3C3C3C3C3C", "content-iv": "D5D5D5D5D5D5D5D5D5D5D5D5D5D5D5D5" }]
+            })
+        })
+        .collect();
+    let request = json!({ "fairplay-streaming-request": { "create-ckc": operations } });
+
+    let process = |keyServer: &KeyServer| {
+        let output = keyServer.processJson(request.clone()).unwrap();
+        output["fairplay-streaming-response"]["create-ckc"]
+            .as_array()
+            .unwrap()
+            .clone()
+    };
+
+    let results = process(&KeyServer::new());
+    assert_eq!(results.len(), 8);
+    for (id, result) in results.iter().enumerate() {
+        assert_eq!(result["id"], id as u64);
+        assert_eq!(result["status"], 0, "{}", result);
+    }
+
+    // An operation that fails does not affect the others
+    let mut failing = request.clone();
+    failing["fairplay-streaming-request"]["create-ckc"][3]["spc"] = json!(general_purpose::STANDARD.encode([0; 64]));
+    let output = KeyServer::new().processJson(failing).unwrap();
+    let results = output["fairplay-streaming-response"]["create-ckc"].as_array().unwrap();
+    for (id, result) in results.iter().enumerate() {
+        assert_eq!(result["id"], id as u64);
+        assert_eq!(result["status"] == 0, id != 3, "{}", result);
+    }
+
+    // A seeded source is used one operation at a time, so the whole batch is reproducible
+    #[cfg(feature = "deterministic_random")]
+    {
+        let seeded = || KeyServer::new().withRandomSource(Arc::new(SeededRandom::new(3)));
+        assert_eq!(process(&seeded()), process(&seeded()));
+    }
+}
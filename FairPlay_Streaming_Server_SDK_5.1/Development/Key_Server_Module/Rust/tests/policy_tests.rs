@@ -0,0 +1,158 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+#![allow(nonstandard_style)]
+
+use fpssdk::base::base_constants::FPSHDCPRequirement;
+use fpssdk::base::structures::base_fps_structures::FPSOperation;
+use fpssdk::base::structures::base_server_structures::{FPSServerCtx, VMDeviceInfo};
+use fpssdk::extension::extension_constants::{ContentType, FPSSecurityLevel};
+use fpssdk::extension::policy::{Policy, PolicyVersion};
+use fpssdk::extension::validate::FPSStatus;
+use std::sync::Arc;
+
+fn request(contentType: ContentType, hdcpReq: FPSHDCPRequirement) -> (FPSOperation, FPSServerCtx) {
+    let mut operation = FPSOperation::default();
+    operation.assetInfo.extension.contentType = contentType;
+    operation.assetInfo.hdcpReq = hdcpReq as u64;
+    (operation, FPSServerCtx::default())
+}
+
+fn reportSecurityLevel(serverCtx: &mut FPSServerCtx, securityLevel: FPSSecurityLevel) {
+    Arc::make_mut(&mut serverCtx.spcContainer).spcData.isSecurityLevelTLLVValid = true;
+    Arc::make_mut(&mut serverCtx.spcContainer).spcData.supportedSecurityLevel = securityLevel as u64;
+}
+
+#[test]
+fn built_in_policy_matches_default_rules() {
+    let policy = Policy::builtIn();
+
+    // UHD requires Main and HDCP type 1
+    let (operation, mut serverCtx) = request(ContentType::uhd, FPSHDCPRequirement::hdcpType1);
+    reportSecurityLevel(&mut serverCtx, FPSSecurityLevel::main);
+    assert_eq!(policy.evaluate(&operation, &mut serverCtx), Ok(()));
+    assert_eq!(
+        serverCtx.ckcContainer.ckcData.extension.requiredSecurityLevel as u64,
+        FPSSecurityLevel::main as u64
+    );
+
+    reportSecurityLevel(&mut serverCtx, FPSSecurityLevel::baseline);
+    assert_eq!(policy.evaluate(&operation, &mut serverCtx), Err(FPSStatus::clientSecurityLevelErr));
+
+    let (operation, mut serverCtx) = request(ContentType::uhd, FPSHDCPRequirement::hdcpType0);
+    assert_eq!(policy.evaluate(&operation, &mut serverCtx), Err(FPSStatus::paramErr));
+
+    // Older clients without security level information only fail if they only claim Baseline
+    let (operation, mut serverCtx) = request(ContentType::uhd, FPSHDCPRequirement::hdcpType1);
+    Arc::make_mut(&mut serverCtx.spcContainer).spcData.clientFeatures.supportsSecurityLevelBaseline = true;
+    assert_eq!(policy.evaluate(&operation, &mut serverCtx), Err(FPSStatus::clientSecurityLevelErr));
+
+    // HD requires HDCP, SD does not
+    let (operation, mut serverCtx) = request(ContentType::hd, FPSHDCPRequirement::hdcpNotRequired);
+    assert_eq!(policy.evaluate(&operation, &mut serverCtx), Err(FPSStatus::paramErr));
+
+    // Custom HDCP requirement values satisfy HD, not UHD
+    let (mut operation, mut serverCtx) = request(ContentType::hd, FPSHDCPRequirement::hdcpType0);
+    operation.assetInfo.hdcpReq = 0x1234;
+    assert_eq!(policy.evaluate(&operation, &mut serverCtx), Ok(()));
+    operation.assetInfo.extension.contentType = ContentType::uhd;
+    reportSecurityLevel(&mut serverCtx, FPSSecurityLevel::main);
+    assert_eq!(policy.evaluate(&operation, &mut serverCtx), Err(FPSStatus::paramErr));
+
+    let (operation, mut serverCtx) = request(ContentType::sd, FPSHDCPRequirement::hdcpNotRequired);
+    assert_eq!(policy.evaluate(&operation, &mut serverCtx), Ok(()));
+    assert_eq!(
+        serverCtx.ckcContainer.ckcData.extension.requiredSecurityLevel as u64,
+        FPSSecurityLevel::baseline as u64
+    );
+
+    // KDL version is checked for every content type, only if the client reported one
+    Arc::make_mut(&mut serverCtx.spcContainer).spcData.clientKextDenyListVersion = 30;
+    assert_eq!(policy.evaluate(&operation, &mut serverCtx), Err(FPSStatus::clientSecurityLevelErr));
+
+    // Unknown content types get Main in the CKC without checking the client
+    let (operation, mut serverCtx) = request(ContentType::unknown, FPSHDCPRequirement::hdcpNotRequired);
+    reportSecurityLevel(&mut serverCtx, FPSSecurityLevel::baseline);
+    assert_eq!(policy.evaluate(&operation, &mut serverCtx), Ok(()));
+    assert_eq!(
+        serverCtx.ckcContainer.ckcData.extension.requiredSecurityLevel as u64,
+        FPSSecurityLevel::main as u64
+    );
+}
+
+#[test]
+fn policy_file_rules_for_devices_and_virtual_machines() {
+    let policy = Policy::fromJson(
+        r#"{
+            "defaults": { "allow-virtual-machines": true, "vm-guest-device-classes": ["apple-desktop"] },
+            "content-types": {
+                "hd": {
+                    "security-level": "baseline",
+                    "device-classes": ["apple-living-room", "partner-living-room"],
+                    "min-os-version": "17.4",
+                    "min-tee-version": "0x00020000"
+                },
+                "uhd": { "allow-virtual-machines": false }
+            }
+        }"#,
+    )
+    .unwrap();
+
+    let (operation, mut serverCtx) = request(ContentType::hd, FPSHDCPRequirement::hdcpType0);
+    let identity = &mut Arc::make_mut(&mut serverCtx.spcContainer).spcData.deviceIdentity;
+    identity.isDeviceIdentitySet = true;
+    identity.deviceClass = 128;
+    identity.fpVersionTEE = 0x00020001;
+    assert_eq!(policy.evaluate(&operation, &mut serverCtx), Ok(()));
+
+    Arc::make_mut(&mut serverCtx.spcContainer).spcData.deviceIdentity.deviceClass = 2;
+    assert_eq!(policy.evaluate(&operation, &mut serverCtx), Err(FPSStatus::clientSecurityLevelErr));
+
+    Arc::make_mut(&mut serverCtx.spcContainer).spcData.deviceIdentity.deviceClass = 1;
+    Arc::make_mut(&mut serverCtx.spcContainer).spcData.deviceIdentity.osVersion = 0x00110300;
+    assert_eq!(policy.evaluate(&operation, &mut serverCtx), Err(FPSStatus::clientSecurityLevelErr));
+
+    Arc::make_mut(&mut serverCtx.spcContainer).spcData.deviceIdentity.osVersion = 0x00110400;
+    Arc::make_mut(&mut serverCtx.spcContainer).spcData.deviceIdentity.fpVersionTEE = 0x00010000;
+    assert_eq!(policy.evaluate(&operation, &mut serverCtx), Err(FPSStatus::clientSecurityLevelErr));
+
+    // Virtual machines: allowed by default for desktop guests, never for UHD
+    let vm = VMDeviceInfo {
+        guestDeviceClass: 3.into(),
+        ..Default::default()
+    };
+    let (operation, mut serverCtx) = request(ContentType::sd, FPSHDCPRequirement::hdcpNotRequired);
+    Arc::make_mut(&mut serverCtx.spcContainer).spcData.vmDeviceInfo = Some(vm.clone());
+    assert_eq!(policy.evaluate(&operation, &mut serverCtx), Ok(()));
+
+    Arc::make_mut(&mut serverCtx.spcContainer).spcData.vmDeviceInfo = Some(VMDeviceInfo {
+        guestDeviceClass: 2.into(),
+        ..vm.clone()
+    });
+    assert_eq!(policy.evaluate(&operation, &mut serverCtx), Err(FPSStatus::clientSecurityLevelErr));
+
+    let (operation, mut serverCtx) = request(ContentType::uhd, FPSHDCPRequirement::hdcpType1);
+    Arc::make_mut(&mut serverCtx.spcContainer).spcData.vmDeviceInfo = Some(vm);
+    assert_eq!(policy.evaluate(&operation, &mut serverCtx), Err(FPSStatus::clientSecurityLevelErr));
+}
+
+#[test]
+fn policy_file_is_validated() {
+    let version = |json: &str| {
+        Policy::fromJson(&format!(r#"{{ "defaults": {{ "min-os-version": {} }} }}"#, json))
+            .map(|policy| policy.defaults.minOSVersion)
+    };
+    assert_eq!(version(r#""17.4""#), Ok(Some(PolicyVersion(0x00110400))));
+    assert_eq!(version(r#""0x00110401""#), Ok(Some(PolicyVersion(0x00110401))));
+    assert_eq!(version("1114112"), Ok(Some(PolicyVersion(0x00110000))));
+    assert_eq!(version(r#""17.x""#), Err(FPSStatus::paramErr));
+
+    // Typos must not silently disable a rule
+    assert_eq!(Policy::fromJson(r#"{ "defaults": { "min-kdl": 31 } }"#).err(), Some(FPSStatus::paramErr));
+    assert_eq!(Policy::fromJson(r#"{ "content-types": { "4k": {} } }"#).err(), Some(FPSStatus::paramErr));
+    assert_eq!(
+        Policy::fromJson(r#"{ "content-types": { "sd": { "security-level": "high" } } }"#).err(),
+        Some(FPSStatus::paramErr)
+    );
+}
@@ -0,0 +1,49 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+#![allow(nonstandard_style)]
+
+mod common;
+
+use common::{buildSPC, requiredTLLVs, serverKey};
+use fpssdk::extension::key_payload::MockKeyPayloadBackend;
+use fpssdk::extension::random::SeededRandom;
+use fpssdk::extension::structures::extension_structures::SDKExtension;
+use fpssdk::key_server::{AssetInfo, KeyRequest, KeyServer};
+use std::sync::Arc;
+
+fn request() -> KeyRequest {
+    serverKey();
+    SDKExtension::setKeyPayloadBackend(Arc::new(MockKeyPayloadBackend));
+
+    let spc = buildSPC(serverKey(), &requiredTLLVs(b"random-asset"));
+    KeyRequest::new(spc).assetInfo(AssetInfo::new().contentKey([0x3C; 16], [0xD5; 16]))
+}
+
+fn ckc(keyServer: &KeyServer, request: &KeyRequest) -> Vec<u8> {
+    keyServer.process(request).unwrap().ckc
+}
+
+#[test]
+fn seeded_server_produces_identical_ckcs() {
+    let request = request();
+    let seeded = |seed| KeyServer::new().withRandomSource(Arc::new(SeededRandom::new(seed)));
+
+    assert_eq!(ckc(&seeded(7), &request), ckc(&seeded(7), &request));
+    assert_ne!(ckc(&seeded(7), &request), ckc(&seeded(8), &request));
+
+    // The default source stays random
+    assert_ne!(ckc(&KeyServer::new(), &request), ckc(&KeyServer::new(), &request));
+}
+
+#[test]
+fn request_seed_does_not_depend_on_other_requests() {
+    let request = request().randomSeed(42);
+    let keyServer = KeyServer::new().withRandomSource(Arc::new(SeededRandom::new(1)));
+
+    let first = ckc(&keyServer, &request);
+    ckc(&keyServer, &self::request());
+    assert_eq!(ckc(&keyServer, &request), first);
+    assert_eq!(ckc(&KeyServer::new(), &request), first);
+}
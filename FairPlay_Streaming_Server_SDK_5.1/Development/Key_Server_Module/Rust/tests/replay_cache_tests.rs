@@ -0,0 +1,67 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+#![allow(nonstandard_style)]
+
+use fpssdk::base::structures::base_server_structures::FPSServerSPCData;
+use fpssdk::extension::replay_cache::{MemoryReplayStore, ReplayCache, ReplayStore, SqliteReplayStore};
+use fpssdk::extension::validate::FPSStatus;
+use std::sync::Arc;
+use std::time::Duration;
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+#[test]
+fn duplicate_spcs_are_rejected() {
+    let replayCache = ReplayCache::new(Arc::new(MemoryReplayStore::new(16)), WINDOW);
+
+    let mut spcData = FPSServerSPCData {
+        antiReplay: vec![1; 16].into(),
+        transactionId: 42,
+        ..Default::default()
+    };
+    assert_eq!(replayCache.check(&spcData), Ok(()));
+    assert_eq!(replayCache.check(&spcData), Err(FPSStatus::replayErr));
+
+    // Any of the fingerprinted values makes it a different SPC
+    spcData.transactionId = 43;
+    assert_eq!(replayCache.check(&spcData), Ok(()));
+    spcData.r2 = vec![2; spcData.r2.len()];
+    assert_eq!(replayCache.check(&spcData), Ok(()));
+}
+
+#[test]
+fn memory_store_expires_and_stays_bounded() {
+    let store = MemoryReplayStore::new(2);
+    let [a, b, c] = [[1u8; 32], [2u8; 32], [3u8; 32]];
+
+    assert_eq!(store.checkAndInsert(&a, 0, WINDOW), Ok(false));
+    assert_eq!(store.checkAndInsert(&a, 59_999, WINDOW), Ok(true));
+    assert_eq!(store.checkAndInsert(&a, 60_000, WINDOW), Ok(false));
+
+    assert_eq!(store.checkAndInsert(&b, 60_001, WINDOW), Ok(false));
+    assert_eq!(store.checkAndInsert(&c, 60_002, WINDOW), Ok(false));
+    assert_eq!(store.len(), 2);
+
+    // `a` was the oldest entry and made room for `c`
+    assert_eq!(store.checkAndInsert(&b, 60_003, WINDOW), Ok(true));
+    assert_eq!(store.checkAndInsert(&a, 60_004, WINDOW), Ok(false));
+}
+
+#[test]
+fn sqlite_store_is_shared_between_instances() {
+    let path = std::env::temp_dir().join(format!("fpssdk-replay-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let first = SqliteReplayStore::open(&path).unwrap();
+    let second = SqliteReplayStore::open(&path).unwrap();
+    let fingerprint = [7u8; 32];
+
+    assert_eq!(first.checkAndInsert(&fingerprint, 1_000, WINDOW), Ok(false));
+    assert_eq!(second.checkAndInsert(&fingerprint, 2_000, WINDOW), Ok(true));
+    assert_eq!(second.checkAndInsert(&fingerprint, 61_000, WINDOW), Ok(false));
+
+    drop((first, second));
+    let _ = std::fs::remove_file(&path);
+}
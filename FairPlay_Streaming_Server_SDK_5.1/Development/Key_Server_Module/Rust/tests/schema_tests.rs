@@ -0,0 +1,85 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+#![allow(nonstandard_style)]
+
+use fpssdk::base::structures::base_fps_structures::Base;
+use fpssdk::extension::schema::{self, REQUEST_SCHEMA};
+use fpssdk::extension::structures::extension_structures::SDKExtension;
+use fpssdk::extension::validate::{self, ErrorDetails, FPSStatus};
+use serde_jsonrc::{json, Value};
+
+/// Returns the reason and message of validating `assetInfo` against `REQUEST_SCHEMA`.
+fn validateAssetInfo(assetInfo: Value) -> (String, String) {
+    let request = json!({
+        "fairplay-streaming-request": { "create-ckc": [{ "id": 1, "spc": "AAAA", "asset-info": [assetInfo] }] }
+    });
+    let (result, detail) = validate::captureError(|| REQUEST_SCHEMA.validate(&request, ""));
+    assert_eq!(result, Err(FPSStatus::paramErr));
+    let detail = detail.unwrap();
+    (detail.reason, detail.message)
+}
+
+#[test]
+fn strict_mode_rejects_unknown_fields_and_out_of_range_values() {
+    let path = "fairplay-streaming-request.create-ckc[0].asset-info[0]";
+    assert_eq!(
+        validateAssetInfo(json!({ "lease_duration": 60 })),
+        (
+            "unknown-field".to_string(),
+            format!("unknown field {}.lease_duration", path)
+        )
+    );
+    assert_eq!(
+        validateAssetInfo(json!({ "content-key": "0x3C3C3C3C3C3C3C3C3C3C3C3C3C3C3C" })),
+        (
+            "out-of-range".to_string(),
+            format!("{}.content-key must be 16 bytes, not 15", path)
+        )
+    );
+    assert_eq!(
+        validateAssetInfo(json!({ "offline-hls": { "rental-duration": "4294967296" } })),
+        (
+            "out-of-range".to_string(),
+            format!(
+                "{}.offline-hls.rental-duration must be an integer from 0 to 4294967295, not \"4294967296\"",
+                path
+            )
+        )
+    );
+    assert_eq!(validateAssetInfo(json!({ "hdcp-type": true })).0, "invalid-field-type");
+
+    // The same request is only rejected by the parser in strict mode
+    SDKExtension::setErrorDetails(ErrorDetails::reason);
+    let request = json!({
+        "fairplay-streaming-request": {
+            "create-ckc": [{ "id": 2, "spc": "AAAA", "asset-info": [{ "lease_duration": 60 }] }]
+        }
+    });
+    let process = |strict| {
+        SDKExtension::setStrictSchema(strict);
+        let mut output = Value::default();
+        Base::processOperations(request.clone(), &mut output).unwrap();
+        output["fairplay-streaming-response"]["create-ckc"][0]["error"]["reason"].clone()
+    };
+    assert_ne!(process(false), "unknown-field");
+    assert_eq!(process(true), "unknown-field");
+    SDKExtension::setStrictSchema(false);
+}
+
+#[test]
+fn published_schemas_match_the_definitions() {
+    let published = |name: &str| -> Value {
+        let path = format!("{}/schema/{}.schema.json", env!("CARGO_MANIFEST_DIR"), name);
+        std::fs::read_to_string(path).unwrap().parse().unwrap()
+    };
+    assert_eq!(published("fairplay-streaming-request"), schema::requestJsonSchema());
+    assert_eq!(published("fairplay-streaming-response"), schema::responseJsonSchema());
+
+    let request = schema::requestJsonSchema();
+    let assetInfo = &request["properties"]["fairplay-streaming-request"]["properties"]["create-ckc"]["items"]
+        ["properties"]["asset-info"]["items"];
+    assert_eq!(assetInfo["additionalProperties"], false);
+    assert_eq!(assetInfo["properties"]["lease-duration"]["maximum"], u32::MAX);
+}
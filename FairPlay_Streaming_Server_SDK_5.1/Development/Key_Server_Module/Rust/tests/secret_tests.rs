@@ -0,0 +1,63 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+#![allow(nonstandard_style)]
+
+use fpssdk::base::structures::base_server_structures::FPSServerCtx;
+use fpssdk::base::structures::secret::SecretBytes;
+use fpssdk::extension::key_store::KeyRecord;
+use fpssdk::key_server::AssetInfo;
+use std::sync::Arc;
+
+#[test]
+fn secret_bytes_compare_and_redact_their_value() {
+    let secret = SecretBytes::from(vec![0x3C; 16]);
+
+    assert_eq!(secret, vec![0x3C; 16]);
+    assert_eq!(secret, SecretBytes::from(&[0x3C; 16][..]));
+    assert_ne!(secret, SecretBytes::from(vec![0x3C; 15]));
+    assert_ne!(secret, SecretBytes::zeroed(16));
+    assert_eq!(format!("{:?}", secret), "<redacted 16 bytes>");
+    assert_eq!(hex::encode(&secret), "3c".repeat(16));
+}
+
+#[test]
+fn hex_secrets_are_decoded_at_their_final_size() {
+    let short = SecretBytes::fromHex("abc", 4).unwrap();
+    assert_eq!(short, vec![0x0A, 0xBC, 0, 0]);
+    assert_eq!(short.capacity(), 4);
+
+    assert_eq!(SecretBytes::fromHex(&"3c".repeat(20), 16).unwrap(), vec![0x3C; 16]);
+    assert_eq!(SecretBytes::fromHex("", 2).unwrap(), SecretBytes::zeroed(2));
+    assert!(SecretBytes::fromHex("3g", 16).is_none());
+    assert!(SecretBytes::fromHex(&format!("{}zz", "3c".repeat(16)), 16).is_none());
+}
+
+#[test]
+fn debug_dumps_do_not_print_key_material() {
+    let mut serverCtx = FPSServerCtx::default();
+    Arc::make_mut(&mut serverCtx.spcContainer).spcDecryptedData = vec![0x3C; 64].into();
+    Arc::make_mut(&mut serverCtx.spcContainer).spcData.sk = vec![0x3C; 16].into();
+    Arc::make_mut(&mut serverCtx.spcContainer).spcData.skR1 = vec![0x3C; 112].into();
+    serverCtx.ckcContainer.ckcData.ck = vec![0x3C; 16].into();
+    serverCtx.ckcContainer.ckcData.r1 = vec![0x3C; 44].into();
+    serverCtx.ckcContainer.ckcData.contentKeyTLLVPayload = vec![0x3C; 48].into();
+
+    let record = KeyRecord {
+        assetId: b"asset".to_vec(),
+        key: vec![0x3C; 16].into(),
+        iv: vec![0x3C; 16].into(),
+        ..Default::default()
+    };
+    let assetInfo = AssetInfo::new().contentKey([0x3C; 16], [0x3C; 16]);
+
+    for dump in [
+        format!("{:?}", serverCtx),
+        format!("{:?}", record),
+        format!("{:?}", assetInfo),
+    ] {
+        assert!(dump.contains("<redacted"), "{}", dump);
+        assert!(!dump.contains("60, 60"), "{}", dump);
+    }
+}
@@ -0,0 +1,110 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+#![allow(nonstandard_style)]
+
+mod common;
+
+use base64::engine::general_purpose;
+use base64::Engine;
+use common::{buildSPC, requiredTLLVs, serverKey};
+use fpssdk::base::structures::base_server_structures::{FPSServerCtx, FPSServerSPCContainer};
+use fpssdk::extension::fps_extension::{DefaultExtension, FpsExtension};
+use fpssdk::extension::key_payload::MockKeyPayloadBackend;
+use fpssdk::extension::replay_cache::{MemoryReplayStore, ReplayCache};
+use fpssdk::extension::spc_cache::SPCCache;
+use fpssdk::extension::structures::extension_structures::SDKExtension;
+use fpssdk::extension::validate::{FPSStatus, Result};
+use fpssdk::key_server::KeyServer;
+use serde_jsonrc::{json, Value};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Counts the SPCs whose container is parsed.
+#[derive(Default)]
+struct CountingExtension {
+    parsed: AtomicUsize,
+}
+
+impl FpsExtension for CountingExtension {
+    fn selectCredentialsCustom(&self, spcContainer: &mut FPSServerSPCContainer) -> Result<()> {
+        self.parsed.fetch_add(1, Ordering::SeqCst);
+        DefaultExtension.selectCredentialsCustom(spcContainer)
+    }
+}
+
+#[test]
+fn operations_sharing_an_spc_parse_it_once_and_get_their_own_ckc() {
+    serverKey();
+    SDKExtension::setKeyPayloadBackend(Arc::new(MockKeyPayloadBackend));
+    SDKExtension::setReplayCache(Some(Arc::new(ReplayCache::new(
+        Arc::new(MemoryReplayStore::new(16)),
+        Duration::from_secs(60),
+    ))));
+
+    let spc = general_purpose::STANDARD.encode(buildSPC(serverKey(), &requiredTLLVs(b"shared-spc")));
+    let operation = |id: u64, key: &str| json!({ "id": id, "spc": spc, "asset-info": [{ "content-key": key, "content-iv": "D5D5D5D5D5D5D5D5D5D5D5D5D5D5D5D5" }] });
+    let request = json!({
+        "fairplay-streaming-request": {
+            "create-ckc": [
+                operation(1, "11111111111111111111111111111111"),
+                operation(2, "22222222222222222222222222222222"),
+                operation(3, "33333333333333333333333333333333"),
+            ]
+        }
+    });
+
+    let extension = Arc::new(CountingExtension::default());
+    let keyServer = KeyServer::new().withExtension(extension.clone());
+    let process = || -> Vec<Value> {
+        let output = keyServer.processJson(request.clone()).unwrap();
+        output["fairplay-streaming-response"]["create-ckc"]
+            .as_array()
+            .unwrap()
+            .clone()
+    };
+
+    let results = process();
+    assert_eq!(extension.parsed.load(Ordering::SeqCst), 1);
+    for result in &results {
+        assert_eq!(result["status"], 0, "{}", result);
+    }
+    assert_ne!(results[0]["ckc"], results[1]["ckc"]);
+    assert_ne!(results[1]["ckc"], results[2]["ckc"]);
+
+    // The SPC is still used up for later requests
+    for result in process() {
+        assert_eq!(result["status"], FPSStatus::replayErr as i32);
+    }
+    SDKExtension::setReplayCache(None);
+}
+
+#[test]
+fn failed_parses_are_not_cached() {
+    let spcCache = SPCCache::new();
+    let mut serverCtx = FPSServerCtx::default();
+
+    let status = spcCache.parse(b"spc", &mut serverCtx, |_, _| Err(FPSStatus::parserErr));
+    assert_eq!(status, Err(FPSStatus::parserErr));
+    assert!(spcCache.is_empty());
+
+    let parse = |_: &[u8], serverCtx: &mut FPSServerCtx| {
+        Arc::make_mut(&mut serverCtx.spcContainer).version = 2;
+        Ok(())
+    };
+    spcCache.parse(b"spc", &mut serverCtx, parse).unwrap();
+    let mut other = FPSServerCtx::default();
+    spcCache
+        .parse(b"spc", &mut other, |_, _| panic!("parsed twice"))
+        .unwrap();
+    assert_eq!(other.spcContainer.version, 2);
+    assert_eq!(spcCache.len(), 1);
+
+    // Operations share the decrypted SPC instead of copying it
+    assert!(Arc::ptr_eq(&serverCtx.spcContainer, &other.spcContainer));
+
+    spcCache.parse(b"other spc", &mut other, parse).unwrap();
+    assert_eq!(spcCache.len(), 2);
+}
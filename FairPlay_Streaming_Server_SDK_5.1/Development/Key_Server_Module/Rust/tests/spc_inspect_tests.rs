@@ -0,0 +1,85 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+#![allow(nonstandard_style)]
+
+mod common;
+
+use common::{buildSPC, serverKey, tllv, CERTIFICATE_HASH};
+use fpssdk::base::base_constants::{FPSTLLVTagValue, FPS_CAPABILITY_HDCP_TYPE1_ENFORCEMENT_SUPPORTED};
+use fpssdk::base::structures::base_fps_structures::Base;
+use fpssdk::extension::validate::FPSStatus;
+use openssl::pkey::PKey;
+use openssl::rsa::Rsa;
+
+const UNKNOWN_TAG: u64 = 0x0123456789abcdef;
+
+fn tllvs() -> Vec<Vec<u8>> {
+    let mut capabilities = [0u8; 16];
+    capabilities[8..].copy_from_slice(&FPS_CAPABILITY_HDCP_TYPE1_ENFORCEMENT_SUPPORTED.to_be_bytes());
+
+    let mut deviceIdentity = Vec::new();
+    deviceIdentity.extend_from_slice(&1u32.to_be_bytes());
+    deviceIdentity.extend_from_slice(&2u32.to_be_bytes()); // appleMobile
+    deviceIdentity.extend_from_slice(&[0x0A; 8]);
+    deviceIdentity.extend_from_slice(&[0x0B; 8]);
+    deviceIdentity.extend_from_slice(&[0; 8]);
+    deviceIdentity.extend_from_slice(&0x00110200u32.to_be_bytes());
+
+    vec![
+        tllv(FPSTLLVTagValue::assetIDTag as u64, b"inspect-me"),
+        tllv(FPSTLLVTagValue::transactionIDTag as u64, &0x1234u64.to_be_bytes()),
+        tllv(FPSTLLVTagValue::returnRequestTag as u64, &(FPSTLLVTagValue::transactionIDTag as u64).to_be_bytes()),
+        tllv(FPSTLLVTagValue::capabilitiesTag as u64, &capabilities),
+        // Duplicate tag, reported but does not stop the inspection
+        tllv(FPSTLLVTagValue::transactionIDTag as u64, &0x5678u64.to_be_bytes()),
+        tllv(FPSTLLVTagValue::deviceIdentityTag as u64, &deviceIdentity),
+        tllv(UNKNOWN_TAG, &[1, 2, 3]),
+    ]
+}
+
+#[test]
+fn inspect_spc_decodes_every_tllv() {
+    let spc = buildSPC(serverKey(), &tllvs());
+    let inspection = Base::inspectSPC(&spc);
+    assert_eq!(inspection.error, None);
+
+    let json = inspection.toJson();
+    assert_eq!(json["header"]["version"], 2);
+    assert_eq!(json["header"]["certificate-hash"], hex::encode_upper(CERTIFICATE_HASH));
+    assert_eq!(json["header"]["wrapped-key-size"], 256);
+
+    let tllvs = json["tllvs"].as_array().unwrap();
+    assert_eq!(tllvs.len(), 7);
+    assert_eq!(tllvs[0]["name"], "assetIDTag");
+    assert_eq!(tllvs[0]["decoded"], "inspect-me");
+    assert_eq!(tllvs[1]["decoded"], "0x0000000000001234");
+    assert_eq!(tllvs[4]["error"], "dupTagErr (-42591)");
+    assert!(tllvs[6]["name"].is_null());
+    assert_eq!(tllvs[6]["value"], "010203");
+
+    assert_eq!(json["return-request"][0], "0x47aa7ad3440577de (transactionIDTag)");
+    assert_eq!(json["capabilities"]["hdcp-type1"], true);
+    assert_eq!(json["capabilities"]["check-in"], false);
+    assert_eq!(json["device-identity"]["device-class"], "appleMobile");
+    assert_eq!(json["device-identity"]["os-version"], "00110200");
+    assert!(json["vm-device-info"].is_null());
+    assert_eq!(inspection.unknownTags(), vec![UNKNOWN_TAG]);
+
+    let text = inspection.to_string();
+    assert!(text.contains("0x1bf7f53f5d5d5a1f (assetIDTag) length=10 inspect-me"));
+    assert!(text.contains("unknown-tags: 0x0123456789abcdef"));
+}
+
+#[test]
+fn inspect_spc_reports_header_when_payload_cannot_be_decrypted() {
+    serverKey();
+    let otherKey = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let inspection = Base::inspectSPC(&buildSPC(&otherKey, &tllvs()));
+
+    assert_eq!(inspection.error, Some(FPSStatus::invalidCertificateErr));
+    assert_eq!(inspection.spcContainer.certificateHash, CERTIFICATE_HASH);
+    assert!(inspection.tllvs.is_empty());
+    assert!(inspection.toJson()["capabilities"].is_null());
+}
@@ -0,0 +1,178 @@
+//
+// Copyright © 2025 Apple Inc. All rights reserved.
+//
+
+#![allow(nonstandard_style)]
+
+use base64::engine::general_purpose;
+use base64::Engine;
+use fpssdk::base::structures::base_fps_structures::{AssetInfo, Base};
+use fpssdk::extension::credentials::credential_provider::{KeyEncryptionKey, MemoryCredentialProvider};
+use fpssdk::extension::schema::REQUEST_SCHEMA;
+use fpssdk::extension::structures::extension_structures::SDKExtension;
+use fpssdk::extension::validate::{self, FPSStatus};
+use openssl::aes::{wrap_key, AesKey};
+use openssl::encrypt::Encrypter;
+use openssl::hash::MessageDigest;
+use openssl::pkey::{PKey, Private};
+use openssl::rsa::{Padding, Rsa};
+use openssl::symm::{encrypt_aead, Cipher};
+use serde_jsonrc::{json, Value};
+use std::sync::Arc;
+
+const CONTENT_KEY: [u8; 16] = [0x3C; 16];
+const AES_KEK: [u8; 32] = [0x4B; 32];
+
+/// Installs an AES key-encryption key under kid `cms` and an RSA one as the default.
+fn installKeyEncryptionKeys() -> PKey<Private> {
+    let rsaKek = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let provider = MemoryCredentialProvider::new(None, None, vec![0xAB; 32])
+        .withKeyEncryptionKey(Some("cms"), KeyEncryptionKey::aes(AES_KEK.to_vec().into()))
+        .withKeyEncryptionKey(None, KeyEncryptionKey::rsa(rsaKek.clone()));
+    SDKExtension::setCredentialProvider(Arc::new(provider));
+    rsaKek
+}
+
+fn aesWrapped(key: &[u8]) -> String {
+    let mut wrapped = vec![0; key.len() + 8];
+    wrap_key(&AesKey::new_encrypt(&AES_KEK).unwrap(), None, &mut wrapped, key).unwrap();
+    general_purpose::STANDARD.encode(wrapped)
+}
+
+/// JWE with RSA-OAEP-256 and A256GCM, as a CMS would produce it.
+fn jwe(rsaKek: &PKey<Private>, plaintext: &[u8]) -> String {
+    let encode = |bytes: &[u8]| general_purpose::URL_SAFE_NO_PAD.encode(bytes);
+    let header = encode(br#"{"alg":"RSA-OAEP-256","enc":"A256GCM"}"#);
+    let contentEncryptionKey = [0x11; 32];
+    let iv = [0x22; 12];
+
+    let mut encrypter = Encrypter::new(rsaKek).unwrap();
+    encrypter.set_rsa_padding(Padding::PKCS1_OAEP).unwrap();
+    encrypter.set_rsa_oaep_md(MessageDigest::sha256()).unwrap();
+    encrypter.set_rsa_mgf1_md(MessageDigest::sha256()).unwrap();
+    let mut encryptedKey = vec![0; encrypter.encrypt_len(&contentEncryptionKey).unwrap()];
+    let length = encrypter.encrypt(&contentEncryptionKey, &mut encryptedKey).unwrap();
+    encryptedKey.truncate(length);
+
+    let mut tag = [0; 16];
+    let ciphertext = encrypt_aead(
+        Cipher::aes_256_gcm(),
+        &contentEncryptionKey,
+        Some(&iv),
+        header.as_bytes(),
+        plaintext,
+        &mut tag,
+    )
+    .unwrap();
+
+    [
+        header,
+        encode(&encryptedKey),
+        encode(&iv),
+        encode(&ciphertext),
+        encode(&tag),
+    ]
+    .join(".")
+}
+
+fn parseAssetInfo(assetInfoJson: Value) -> (Result<AssetInfo, FPSStatus>, Option<String>) {
+    let mut assetInfo = AssetInfo::default();
+    let (result, detail) = validate::captureError(|| Base::parseAssetInfo(&assetInfoJson, &mut assetInfo));
+    (result.map(|_| assetInfo), detail.map(|detail| detail.reason))
+}
+
+#[test]
+fn wrapped_content_keys_are_unwrapped_with_the_key_encryption_key() {
+    let rsaKek = installKeyEncryptionKeys();
+
+    let wrappedAssetInfo = json!({
+        "content-key-wrapped": { "alg": "A256KW", "kid": "cms", "value": aesWrapped(&CONTENT_KEY) },
+        "content-iv": "d5d5d5d5d5d5d5d5d5d5d5d5d5d5d5d5",
+    });
+    let (assetInfo, _) = parseAssetInfo(wrappedAssetInfo.clone());
+    let assetInfo = assetInfo.unwrap();
+    assert!(assetInfo.isCKProvided);
+    assert_eq!(assetInfo.key, CONTENT_KEY.to_vec());
+
+    let (assetInfo, _) = parseAssetInfo(json!({
+        "content-key-wrapped": jwe(&rsaKek, &CONTENT_KEY),
+        "content-iv": "d5d5d5d5d5d5d5d5d5d5d5d5d5d5d5d5",
+    }));
+    assert_eq!(assetInfo.unwrap().key, CONTENT_KEY.to_vec());
+
+    // Both forms are part of the strict request schema
+    let request = json!({ "fairplay-streaming-request": { "create-ckc": [{
+        "spc": "AAAA",
+        "asset-info": [wrappedAssetInfo],
+    }]}});
+    assert_eq!(REQUEST_SCHEMA.validate(&request, ""), Ok(()));
+}
+
+#[test]
+fn wrapped_content_keys_that_do_not_unwrap_are_rejected() {
+    let rsaKek = installKeyEncryptionKeys();
+    let iv = "d5d5d5d5d5d5d5d5d5d5d5d5d5d5d5d5";
+
+    // Altered in transit
+    let mut tampered = general_purpose::STANDARD.decode(aesWrapped(&CONTENT_KEY)).unwrap();
+    tampered[3] ^= 1;
+    let (result, reason) = parseAssetInfo(json!({
+        "content-key-wrapped": { "alg": "A256KW", "kid": "cms", "value": general_purpose::STANDARD.encode(tampered) },
+        "content-iv": iv,
+    }));
+    assert_eq!(result.err(), Some(FPSStatus::paramErr));
+    assert_eq!(reason.as_deref(), Some("key-unwrap-failed"));
+
+    // A kid the provider has no key for is not replaced by the default key
+    let (result, reason) = parseAssetInfo(json!({
+        "content-key-wrapped": { "alg": "A256KW", "kid": "cms-old", "value": aesWrapped(&CONTENT_KEY) },
+        "content-iv": iv,
+    }));
+    assert_eq!(result.err(), Some(FPSStatus::paramErr));
+    assert_eq!(reason.as_deref(), Some("unknown-kid"));
+
+    // The default key-encryption key is RSA
+    let (result, reason) = parseAssetInfo(json!({
+        "content-key-wrapped": { "alg": "A128KW", "value": aesWrapped(&CONTENT_KEY) },
+        "content-iv": iv,
+    }));
+    assert_eq!(result.err(), Some(FPSStatus::paramErr));
+    assert_eq!(reason.as_deref(), Some("invalid-wrapped-key"));
+
+    // Only content keys are accepted
+    let (result, reason) =
+        parseAssetInfo(json!({ "content-key-wrapped": jwe(&rsaKek, &[0x3C; 32]), "content-iv": iv }));
+    assert_eq!(result.err(), Some(FPSStatus::paramErr));
+    assert_eq!(reason.as_deref(), Some("invalid-wrapped-key"));
+
+    // A JWE whose authentication tag or ciphertext was altered does not decrypt
+    let token = jwe(&rsaKek, &CONTENT_KEY);
+    let mut parts: Vec<String> = token.split('.').map(String::from).collect();
+    let mut tag = general_purpose::URL_SAFE_NO_PAD.decode(&parts[4]).unwrap();
+    tag[0] ^= 1;
+    parts[4] = general_purpose::URL_SAFE_NO_PAD.encode(tag);
+    let (result, reason) = parseAssetInfo(json!({ "content-key-wrapped": parts.join("."), "content-iv": iv }));
+    assert_eq!(result.err(), Some(FPSStatus::paramErr));
+    assert_eq!(reason.as_deref(), Some("key-unwrap-failed"));
+
+    let mut parts: Vec<String> = token.split('.').map(String::from).collect();
+    let mut ciphertext = general_purpose::URL_SAFE_NO_PAD.decode(&parts[3]).unwrap();
+    ciphertext[0] ^= 1;
+    parts[3] = general_purpose::URL_SAFE_NO_PAD.encode(ciphertext);
+    let (result, reason) = parseAssetInfo(json!({ "content-key-wrapped": parts.join("."), "content-iv": iv }));
+    assert_eq!(result.err(), Some(FPSStatus::paramErr));
+    assert_eq!(reason.as_deref(), Some("key-unwrap-failed"));
+
+    // Truncated JWE
+    let truncated = token.rsplit_once('.').unwrap().0;
+    let (result, reason) = parseAssetInfo(json!({ "content-key-wrapped": truncated, "content-iv": iv }));
+    assert_eq!(result.err(), Some(FPSStatus::paramErr));
+    assert_eq!(reason.as_deref(), Some("invalid-wrapped-key"));
+
+    let (result, _) = parseAssetInfo(json!({
+        "content-key": hex::encode(CONTENT_KEY),
+        "content-key-wrapped": jwe(&rsaKek, &CONTENT_KEY),
+        "content-iv": iv,
+    }));
+    assert_eq!(result.err(), Some(FPSStatus::paramErr));
+}